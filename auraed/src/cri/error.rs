@@ -50,7 +50,34 @@ impl From<RuntimeServiceError> for Status {
             }
             RuntimeServiceError::KillError { .. } => Status::internal(msg),
             RuntimeServiceError::ClientError(e) => match e {
-                ClientError::ConnectionError(_) => Status::unavailable(msg),
+                ClientError::ConnectionError(_)
+                | ClientError::ConnectTimeout { .. }
+                | ClientError::DnsTimeout { .. }
+                | ClientError::CaFetchFailed { .. } => Status::unavailable(msg),
+                ClientError::ConnectionBudgetExhausted { .. }
+                | ClientError::LoadShed { .. } => {
+                    Status::resource_exhausted(msg)
+                }
+                ClientError::ServerIdentityMismatch { .. }
+                | ClientError::TrustDomainMismatch { .. }
+                | ClientError::MissingIpSan { .. }
+                | ClientError::ChainTooDeep { .. }
+                | ClientError::TlsVerification { .. } => {
+                    Status::unauthenticated(msg)
+                }
+                ClientError::DeadlineExceeded => Status::deadline_exceeded(msg),
+                ClientError::KeyDecryptFailed => Status::unauthenticated(msg),
+                ClientError::KeyAlgorithmRejected { .. }
+                | ClientError::NoTrustAnchors => {
+                    Status::failed_precondition(msg)
+                }
+                ClientError::ReadOnlyViolation { .. }
+                | ClientError::MethodNotPermitted { .. } => {
+                    Status::permission_denied(msg)
+                }
+                ClientError::VersionIncompatible { .. } => {
+                    Status::failed_precondition(msg)
+                }
                 ClientError::Other(_) => Status::unknown(msg),
             },
         }
@@ -13,10 +13,20 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
+use crate::cells::CellService;
+use crate::cri::runtime_service::RuntimeService;
+use crate::observe::ObserveService;
+use crate::vms::VmService;
+use proto::cells::cell_service_server::CellServiceServer;
+use proto::cri::runtime_service_server::RuntimeServiceServer;
 use proto::discovery::{
-    discovery_service_server, DiscoverRequest, DiscoverResponse,
+    discovery_service_server::{self, DiscoveryServiceServer},
+    DiscoverRequest, DiscoverResponse, HelloRequest, HelloResponse,
 };
+use proto::observe::observe_service_server::ObserveServiceServer;
+use proto::vms::vm_service_server::VmServiceServer;
 use thiserror::Error;
+use tonic::server::NamedService;
 use tonic::{Request, Response, Status};
 use tracing::error;
 
@@ -24,6 +34,23 @@ pub(crate) type Result<T> = std::result::Result<T, DiscoveryServiceError>;
 
 const VERSION: Option<&str> = option_env!("CARGO_PKG_VERSION");
 
+/// The version of the Aurae API packages served, as in `aurae.cells.v0`.
+const API_VERSION: &str = "v0";
+
+/// The gRPC services auraed serves, as added to the server in `lib.rs`.
+const SERVICES: &[&str] = &[
+    "grpc.health.v1.Health",
+    <CellServiceServer<CellService> as NamedService>::NAME,
+    <DiscoveryServiceServer<DiscoveryService> as NamedService>::NAME,
+    <ObserveServiceServer<ObserveService> as NamedService>::NAME,
+    <RuntimeServiceServer<RuntimeService> as NamedService>::NAME,
+    <VmServiceServer<VmService> as NamedService>::NAME,
+];
+
+/// The largest message auraed decodes, tonic's default, which the server
+/// does not raise.
+const MAX_MESSAGE_SIZE: u64 = 4 * 1024 * 1024;
+
 #[derive(Debug, Error)]
 pub(crate) enum DiscoveryServiceError {
     #[error(transparent)]
@@ -53,6 +80,23 @@ impl DiscoveryService {
         Ok(DiscoverResponse {
             healthy: true,
             version: VERSION.unwrap_or("unknown").into(),
+            targets: vec![],
+            api_version: API_VERSION.into(),
+            services: SERVICES
+                .iter()
+                .map(|service| service.to_string())
+                .collect(),
+        })
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn hello(&self, request: HelloRequest) -> Result<HelloResponse> {
+        Ok(HelloResponse {
+            version: VERSION.unwrap_or("unknown").into(),
+            features: vec![],
+            // the server is built without accepting compressed messages
+            compression: vec![],
+            max_message_size: MAX_MESSAGE_SIZE,
         })
     }
 }
@@ -66,11 +110,19 @@ impl discovery_service_server::DiscoveryService for DiscoveryService {
         let request = request.into_inner();
         Ok(Response::new(self.discover(request)?))
     }
+
+    async fn hello(
+        &self,
+        request: Request<HelloRequest>,
+    ) -> std::result::Result<Response<HelloResponse>, Status> {
+        let request = request.into_inner();
+        Ok(Response::new(self.hello(request)?))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use proto::discovery::DiscoverRequest;
+    use proto::discovery::{DiscoverRequest, HelloRequest};
 
     use crate::discovery::{DiscoveryService, VERSION};
 
@@ -83,5 +135,21 @@ mod tests {
 
         assert!(resp.healthy);
         assert_eq!(resp.version, VERSION.expect("valid version"));
+        assert_eq!(resp.api_version, "v0");
+        assert!(resp
+            .services
+            .iter()
+            .any(|service| service == "aurae.discovery.v0.DiscoveryService"));
+    }
+
+    #[test]
+    fn test_hello() {
+        let resp = DiscoveryService::new()
+            .hello(HelloRequest::default())
+            .expect("hello succeeds");
+
+        assert_eq!(resp.version, VERSION.expect("valid version"));
+        assert!(resp.compression.is_empty());
+        assert_eq!(resp.max_message_size, 4 * 1024 * 1024);
     }
 }
\ No newline at end of file
@@ -18,7 +18,8 @@ use backoff::{
     ExponentialBackoffBuilder, SystemClock,
 };
 use client::{
-    AuraeConfig, AuraeSocket, AuthConfig, Client, ClientError, SystemConfig,
+    AuraeConfig, AuraeSocket, AuthConfig, Client, ClientError, ConnectOptions,
+    SystemConfig,
 };
 use once_cell::sync::Lazy;
 use std::{future::Future, net::SocketAddr, time::Duration};
@@ -76,10 +77,20 @@ async fn run_auraed() -> Client {
             ca_crt: "/etc/aurae/pki/ca.crt".to_string(),
             client_crt: "/etc/aurae/pki/_signed.client.nova.crt".to_string(),
             client_key: "/etc/aurae/pki/client.nova.key".to_string(),
+            enforce_secure_paths: false,
+            strict: false,
+            expected_server_cn: None,
+            expected_server_spiffe: None,
+            expected_server_trust_domain: None,
+            client_key_passphrase: None,
+            ca_fetch: Default::default(),
+            pkcs11: None,
         },
         system: SystemConfig {
             socket: AuraeSocket::Path(socket.clone().into()),
+            ssh_jump: None,
         },
+        connect: ConnectOptions::default(),
     };
 
     tokio::spawn(async move {
@@ -130,8 +141,20 @@ pub async fn remote_auraed_client(
             ca_crt: "/etc/aurae/pki/ca.crt".to_string(),
             client_crt: "/etc/aurae/pki/_signed.client.nova.crt".to_string(),
             client_key: "/etc/aurae/pki/client.nova.key".to_string(),
+            enforce_secure_paths: false,
+            strict: false,
+            expected_server_cn: None,
+            expected_server_spiffe: None,
+            expected_server_trust_domain: None,
+            client_key_passphrase: None,
+            ca_fetch: Default::default(),
+            pkcs11: None,
         },
-        system: SystemConfig { socket: AuraeSocket::Addr(addr) },
+        system: SystemConfig {
+            socket: AuraeSocket::Addr(addr),
+            ssh_jump: None,
+        },
+        connect: ConnectOptions::default(),
     };
     Client::new(client_config.clone()).await
 }
@@ -45,13 +45,19 @@ pub(crate) fn service(input: TokenStream) -> TokenStream {
 
     let (_, proto) = proto_reader::parse(&file_path);
 
-    let service = proto
+    let (package, service) = proto
         .file_descriptors
         .iter()
-        .flat_map(|x| &x.service)
-        .find(|x| matches!(x.name(), n if service_name == n))
+        .flat_map(|x| x.service.iter().map(move |s| (x.package(), s)))
+        .find(|(_, x)| matches!(x.name(), n if service_name == n))
         .expect("failed to find service");
 
+    // the gRPC path of a method, e.g. "/aurae.cells.v0.CellService/Allocate"
+    let method_path = |method: &str| match package {
+        "" => format!("/{service_name}/{method}"),
+        package => format!("/{package}.{service_name}/{method}"),
+    };
+
     let client_namespace = Ident::new(
         &format!("{}_client", service_name.to_string().to_snake_case()),
         service_name.span(),
@@ -112,11 +118,44 @@ pub(crate) fn service(input: TokenStream) -> TokenStream {
     let rpc_implementations: Vec<_> = rpc_signatures
         .iter()
         .zip(fn_name_idents)
-        .map(|(signature, name)| {
-            quote! {
-                #signature {
-                    let mut client = ::proto::#module::#client_namespace::#client_ident::new(self.channel.clone());
-                    client.#name(req).await
+        .zip(&service.method)
+        .map(|((signature, name), m)| {
+            let path = method_path(m.name());
+            let call = quote! {
+                self.deadline_guard()?;
+                let mut client = ::proto::#module::#client_namespace::#client_ident::new(channel);
+                if let Some(encoding) = self.compression_encoding() {
+                    client = client.send_compressed(encoding).accept_compressed(encoding);
+                }
+                self.refresh_credentials().await?;
+                self.log_payload(#path, "request", &req);
+                let response = self.until_deadline(client.#name(self.request(req)?)).await;
+                if response.is_ok() {
+                    self.record_success(#path);
+                }
+                response
+            };
+
+            if m.server_streaming.unwrap_or(false) {
+                quote! {
+                    #signature {
+                        self.method_guard(#path)?;
+                        let channel = self.channel();
+                        #call
+                    }
+                }
+            } else {
+                quote! {
+                    #signature {
+                        self.method_guard(#path)?;
+                        let response = self.with_retries(#path, req, |req| {
+                            self.hedged(#path, req, |req, channel| async move { #call })
+                        }).await;
+                        if let Ok(response) = &response {
+                            self.log_payload(#path, "response", response.get_ref());
+                        }
+                        response
+                    }
                 }
             }
         }).collect();
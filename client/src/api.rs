@@ -0,0 +1,110 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! A trait over the connection level operations of a [`Client`], so code
+//! depending on auraed can take a fake in its tests.
+//!
+//! RPCs are already behind one trait per service, such as
+//! [`crate::cells::cell_service::CellServiceClient`], all implemented by
+//! [`Client`]. Code generic over [`AuraeApi`] and the service traits it calls
+//! can be handed a fake implementing just those, without a channel.
+
+use crate::config::X509Details;
+use crate::diagnostics::ClientState;
+use crate::{Client, ClientError};
+
+#[tonic::async_trait]
+pub trait AuraeApi: Send + Sync {
+    /// Whether auraed answers on the current connection. A server without
+    /// the health service still counts as answering.
+    async fn health_check(&self) -> Result<(), ClientError>;
+
+    /// See [`Client::state`].
+    fn state(&self) -> ClientState;
+
+    /// See [`Client::client_cert_details`].
+    fn client_cert_details(&self) -> Option<X509Details>;
+
+    /// See [`Client::is_read_only`].
+    fn is_read_only(&self) -> bool;
+}
+
+#[tonic::async_trait]
+impl AuraeApi for Client {
+    async fn health_check(&self) -> Result<(), ClientError> {
+        Client::health_check(self)
+            .await
+            .map_err(|e| ClientError::Other(anyhow::anyhow!(e)))
+    }
+
+    fn state(&self) -> ClientState {
+        Client::state(self)
+    }
+
+    fn client_cert_details(&self) -> Option<X509Details> {
+        Client::client_cert_details(self)
+    }
+
+    fn is_read_only(&self) -> bool {
+        Client::is_read_only(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::ConnectionState;
+
+    struct Fake {
+        healthy: bool,
+    }
+
+    #[tonic::async_trait]
+    impl AuraeApi for Fake {
+        async fn health_check(&self) -> Result<(), ClientError> {
+            match self.healthy {
+                true => Ok(()),
+                false => Err(ClientError::Other(anyhow::anyhow!("down"))),
+            }
+        }
+
+        fn state(&self) -> ClientState {
+            ClientState {
+                connection: ConnectionState::Connected,
+                last_error: None,
+            }
+        }
+
+        fn client_cert_details(&self) -> Option<X509Details> {
+            None
+        }
+
+        fn is_read_only(&self) -> bool {
+            true
+        }
+    }
+
+    /// Code under test, taking any implementation.
+    async fn ready(api: &dyn AuraeApi) -> bool {
+        api.health_check().await.is_ok()
+            && api.state().connection != ConnectionState::Failed
+    }
+
+    #[tokio::test]
+    async fn fakes_stand_in_for_the_client() {
+        assert!(ready(&Fake { healthy: true }).await);
+        assert!(!ready(&Fake { healthy: false }).await);
+    }
+}
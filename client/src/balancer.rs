@@ -0,0 +1,910 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Spreading RPCs across the replicas behind a DNS name.
+
+use crate::cert_store::CertStore;
+use crate::config::{ConnectOptions, LbPolicy};
+use crate::connector::{self, ConnectState, DnsTimeout, TcpOptions};
+use crate::cooldown::{CooldownPolicy, Cooldowns};
+use crate::events::ConnectionEvent;
+use crate::hedge::HedgePolicy;
+use crate::{AuraeSocket, Client, ClientError};
+use anyhow::anyhow;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tonic::transport::{Channel, Uri};
+use tracing::{debug, warn};
+
+type Result<T> = std::result::Result<T, crate::ClientError>;
+
+/// How often [`Balancer::replace_draining`] checks that the balancer is
+/// still alive while no events come in.
+const DRAIN_WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The channels a [`crate::Client`] picks from for each RPC.
+#[derive(Debug)]
+pub(crate) struct Balancer {
+    policy: LbPolicy,
+    weights: Weights,
+    /// `None` unless round robin RPCs are hedged.
+    hedge: Option<HedgePolicy>,
+    /// `None` unless replicas cool down after failed connects, see
+    /// [`ConnectOptions::endpoint_cooldown`].
+    cooldowns: Option<Cooldowns>,
+    channels: RwLock<Vec<(Option<SocketAddr>, Channel)>>,
+    next: AtomicUsize,
+    /// When the last RPC was picked, in milliseconds since `created`.
+    last_used: AtomicU64,
+    created: Instant,
+}
+
+impl Balancer {
+    /// A balancer over a single connection, used for every transport other
+    /// than a round robin URI.
+    pub(crate) fn single(channel: Channel) -> Self {
+        Self {
+            policy: LbPolicy::PickFirst,
+            weights: Weights::default(),
+            hedge: None,
+            cooldowns: None,
+            channels: RwLock::new(vec![(None, channel)]),
+            next: AtomicUsize::new(0),
+            last_used: AtomicU64::new(0),
+            created: Instant::now(),
+        }
+    }
+
+    /// The channel to issue the next RPC on.
+    pub(crate) fn pick(&self) -> Channel {
+        let channels =
+            self.channels.read().expect("balancer channels lock poisoned");
+        channels[self.next_index(&channels)].1.clone()
+    }
+
+    /// The channel to issue the next RPC on, followed by up to `spares`
+    /// channels to other replicas, in round robin order from it.
+    pub(crate) fn pick_with_spares(&self, spares: usize) -> Vec<Channel> {
+        let channels =
+            self.channels.read().expect("balancer channels lock poisoned");
+        let index = self.next_index(&channels);
+        (0..channels.len())
+            .take(1 + spares)
+            .map(|offset| channels[(index + offset) % channels.len()].1.clone())
+            .collect()
+    }
+
+    fn next_index(&self, channels: &[(Option<SocketAddr>, Channel)]) -> usize {
+        self.last_used.store(self.elapsed_ms(), Ordering::Relaxed);
+        match self.policy {
+            LbPolicy::PickFirst => 0,
+            LbPolicy::RoundRobin => {
+                let n = self.next.fetch_add(1, Ordering::Relaxed);
+                self.skip_cooling(self.weights.index(n, channels), channels)
+            }
+        }
+    }
+
+    /// `index`, or the first replica after it that is not cooling down.
+    /// Stays at `index` when every replica is, as they all fail fast.
+    fn skip_cooling(
+        &self,
+        index: usize,
+        channels: &[(Option<SocketAddr>, Channel)],
+    ) -> usize {
+        let Some(cooldowns) =
+            self.cooldowns.as_ref().filter(|cooldowns| !cooldowns.is_empty())
+        else {
+            return index;
+        };
+        (0..channels.len())
+            .map(|offset| (index + offset) % channels.len())
+            .find(|&i| {
+                channels[i]
+                    .0
+                    .and_then(|addr| cooldowns.remaining(addr))
+                    .is_none()
+            })
+            .unwrap_or(index)
+    }
+
+    pub(crate) fn hedge_policy(&self) -> Option<&HedgePolicy> {
+        self.hedge.as_ref()
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        u64::try_from(self.created.elapsed().as_millis()).unwrap_or(u64::MAX)
+    }
+
+    /// How long it has been since the last RPC was picked.
+    fn idle_for(&self) -> Duration {
+        let last_used = self.last_used.load(Ordering::Relaxed);
+        Duration::from_millis(self.elapsed_ms().saturating_sub(last_used))
+    }
+
+    /// Swap in `channel` for the one connected to `addr`.
+    fn replace(&self, addr: Option<SocketAddr>, channel: Channel) {
+        let mut channels =
+            self.channels.write().expect("balancer channels lock poisoned");
+        if let Some(entry) =
+            channels.iter_mut().find(|(known, _)| *known == addr)
+        {
+            entry.1 = channel;
+        }
+    }
+
+    fn snapshot(&self) -> Vec<(Option<SocketAddr>, Channel)> {
+        self.channels.read().expect("balancer channels lock poisoned").clone()
+    }
+
+    /// Connect to every address `uri` resolves to, and keep re-resolving it
+    /// in the background for as long as the balancer is alive.
+    pub(crate) async fn round_robin(
+        uri: &Uri,
+        certs: Option<Arc<CertStore>>,
+        options: &ConnectOptions,
+        connect_state: ConnectState,
+    ) -> Result<Arc<Self>> {
+        let weights = Weights::new(&options.replica_weights)?;
+        let addrs =
+            resolve(uri, options).await.map_err(
+                |e| match connector::find_cause::<DnsTimeout>(&e) {
+                    Some(timeout) => ClientError::DnsTimeout {
+                        host: timeout.host.clone(),
+                        timeout: timeout.timeout,
+                    },
+                    None => anyhow::Error::from(e).into(),
+                },
+            )?;
+        let channels =
+            connect_all(&addrs, &[], &certs, options, &connect_state).await?;
+
+        let balancer = Arc::new(Self {
+            policy: LbPolicy::RoundRobin,
+            weights,
+            hedge: HedgePolicy::new(options),
+            cooldowns: CooldownPolicy::new(options)
+                .map(|_| connect_state.cooldowns.clone()),
+            channels: RwLock::new(channels),
+            next: AtomicUsize::new(0),
+            last_used: AtomicU64::new(0),
+            created: Instant::now(),
+        });
+
+        let known = addrs_of(&balancer.snapshot());
+        let _ = tokio::spawn(re_resolve(
+            Arc::downgrade(&balancer),
+            uri.clone(),
+            Some(known),
+            certs,
+            options.clone(),
+            connect_state,
+        ));
+
+        Ok(balancer)
+    }
+
+    /// Keep re-resolving `uri`, the socket of a single channel, and move to
+    /// a fresh connection whenever the addresses behind it change, for as
+    /// long as the balancer is alive. See [`ConnectOptions::follow_dns`].
+    pub(crate) fn follow_dns(
+        self: &Arc<Self>,
+        uri: Uri,
+        certs: Option<Arc<CertStore>>,
+        options: ConnectOptions,
+        connect_state: ConnectState,
+    ) {
+        let _ = tokio::spawn(re_resolve(
+            Arc::downgrade(self),
+            uri,
+            None,
+            certs,
+            options,
+            connect_state,
+        ));
+    }
+
+    /// Replace every channel with a fresh connection each `age`, for as long
+    /// as the balancer is alive. `socket` is what a single channel was
+    /// connected over, replicas are reconnected by address.
+    pub(crate) fn rotate_every(
+        self: &Arc<Self>,
+        age: Duration,
+        socket: AuraeSocket,
+        certs: Option<Arc<CertStore>>,
+        options: ConnectOptions,
+        connect_state: ConnectState,
+    ) {
+        let _ = tokio::spawn(rotate(
+            Arc::downgrade(self),
+            age,
+            socket,
+            certs,
+            options,
+            connect_state,
+        ));
+    }
+
+    /// Close the connections once no RPC was made for `timeout`, for as long
+    /// as the balancer is alive. They are replaced with channels that only
+    /// connect again on the next RPC, over `socket` or the replica address.
+    pub(crate) fn close_when_idle(
+        self: &Arc<Self>,
+        timeout: Duration,
+        socket: AuraeSocket,
+        certs: Option<Arc<CertStore>>,
+        options: ConnectOptions,
+        connect_state: ConnectState,
+    ) {
+        let _ = tokio::spawn(close_idle(
+            Arc::downgrade(self),
+            timeout,
+            socket,
+            certs,
+            options,
+            connect_state,
+        ));
+    }
+
+    /// Replace the channels whose connection the server drains with GOAWAY,
+    /// see [`ConnectionEvent::Draining`], for as long as the balancer is
+    /// alive.
+    pub(crate) fn replace_draining(
+        self: &Arc<Self>,
+        socket: AuraeSocket,
+        certs: Option<Arc<CertStore>>,
+        options: ConnectOptions,
+        connect_state: ConnectState,
+    ) {
+        let _ = tokio::spawn(replace_draining(
+            Arc::downgrade(self),
+            socket,
+            certs,
+            options,
+            connect_state,
+        ));
+    }
+
+    /// Replace every channel with a fresh connection, keeping the old one
+    /// for any that fails to connect. Replicas are reconnected by address,
+    /// a single channel over `socket`. Fails when no channel could be
+    /// replaced.
+    pub(crate) async fn reconnect_all(
+        &self,
+        socket: &AuraeSocket,
+        certs: &Option<Arc<CertStore>>,
+        options: &ConnectOptions,
+        connect_state: &ConnectState,
+    ) -> Result<()> {
+        let current = self.snapshot();
+        let mut channels = Vec::with_capacity(current.len());
+        let mut reconnected = false;
+        let mut last_err = None;
+
+        for (addr, channel) in current {
+            let socket = addr.map(AuraeSocket::Addr).unwrap_or(socket.clone());
+            match Client::connect_chan(
+                socket,
+                certs.clone(),
+                options,
+                connect_state.clone(),
+            )
+            .await
+            {
+                Ok(fresh) => {
+                    reconnected = true;
+                    channels.push((addr, fresh));
+                }
+                Err(e) => {
+                    warn!(
+                        "failed to reconnect, keeping the old connection: {e}"
+                    );
+                    last_err = Some(e);
+                    channels.push((addr, channel));
+                }
+            }
+        }
+
+        *self.channels.write().expect("balancer channels lock poisoned") =
+            channels;
+        match last_err {
+            Some(e) if !reconnected => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// See [`ConnectOptions::replica_weights`].
+#[derive(Debug, Default)]
+struct Weights {
+    by_addr: HashMap<SocketAddr, u32>,
+    by_ip: HashMap<IpAddr, u32>,
+}
+
+impl Weights {
+    fn new(weights: &BTreeMap<String, u32>) -> Result<Self> {
+        let mut parsed = Self::default();
+        for (replica, &weight) in weights {
+            if weight == 0 {
+                return Err(anyhow!(
+                    "connect.replica_weights for '{replica}' is 0, weights must be positive"
+                )
+                .into());
+            }
+            if let Ok(addr) = replica.parse::<SocketAddr>() {
+                let _ = parsed.by_addr.insert(addr, weight);
+            } else if let Ok(ip) = replica.parse::<IpAddr>() {
+                let _ = parsed.by_ip.insert(ip, weight);
+            } else {
+                return Err(anyhow!(
+                    "connect.replica_weights key '{replica}' is not an IP address or IP address and port"
+                )
+                .into());
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn weight(&self, addr: Option<SocketAddr>) -> u64 {
+        let Some(addr) = addr else {
+            return 1;
+        };
+        let weight = self
+            .by_addr
+            .get(&addr)
+            .or_else(|| self.by_ip.get(&addr.ip()))
+            .copied()
+            .unwrap_or(1);
+        u64::from(weight)
+    }
+
+    /// The channel the `n`th RPC goes to. Every replica gets a run of
+    /// consecutive RPCs as long as its weight, in the order of `channels`.
+    fn index(
+        &self,
+        n: usize,
+        channels: &[(Option<SocketAddr>, Channel)],
+    ) -> usize {
+        if self.by_addr.is_empty() && self.by_ip.is_empty() {
+            return n % channels.len();
+        }
+
+        let total: u64 =
+            channels.iter().map(|(addr, _)| self.weight(*addr)).sum();
+        let mut slot = n as u64 % total;
+        channels
+            .iter()
+            .position(|(addr, _)| {
+                let weight = self.weight(*addr);
+                let hit = slot < weight;
+                slot = slot.saturating_sub(weight);
+                hit
+            })
+            .unwrap_or(0)
+    }
+}
+
+async fn rotate(
+    balancer: Weak<Balancer>,
+    age: Duration,
+    socket: AuraeSocket,
+    certs: Option<Arc<CertStore>>,
+    options: ConnectOptions,
+    connect_state: ConnectState,
+) {
+    loop {
+        tokio::time::sleep(age).await;
+
+        let Some(balancer) = balancer.upgrade() else {
+            return;
+        };
+
+        if let Some(certs) = &certs {
+            if let Err(e) = certs.reload().await {
+                warn!("failed to re-read certs before rotating connections, keeping the current ones: {e}");
+            }
+        }
+
+        let _ = balancer
+            .reconnect_all(&socket, &certs, &options, &connect_state)
+            .await;
+        debug!("rotated connections after {age:?}");
+        connect_state.events.publish(ConnectionEvent::Rotated);
+    }
+}
+
+async fn replace_draining(
+    balancer: Weak<Balancer>,
+    socket: AuraeSocket,
+    certs: Option<Arc<CertStore>>,
+    options: ConnectOptions,
+    connect_state: ConnectState,
+) {
+    let mut events = connect_state.events.subscribe();
+
+    loop {
+        // wake up now and then to notice the balancer is gone, the events
+        // outlive it as long as this task holds `connect_state`
+        let event =
+            tokio::time::timeout(DRAIN_WATCH_INTERVAL, events.recv()).await;
+        if balancer.strong_count() == 0 {
+            return;
+        }
+        let peer = match event {
+            Ok(Ok(ConnectionEvent::Draining { peer })) => peer,
+            Ok(Err(broadcast::error::RecvError::Closed)) => return,
+            // a missed GOAWAY still closes its connection once drained, and
+            // tonic reconnects that channel on its next RPC
+            _ => continue,
+        };
+
+        let Some(balancer) = balancer.upgrade() else {
+            return;
+        };
+        let draining: Vec<_> = balancer
+            .snapshot()
+            .into_iter()
+            .filter(|(addr, _)| addr.is_none() || *addr == peer)
+            .collect();
+
+        for (addr, _) in draining {
+            let target = addr.map(AuraeSocket::Addr).unwrap_or(socket.clone());
+            match Client::connect_chan(
+                target,
+                certs.clone(),
+                &options,
+                connect_state.clone(),
+            )
+            .await
+            {
+                Ok(fresh) => balancer.replace(addr, fresh),
+                Err(e) => warn!(
+                    "failed to replace a draining connection, tonic reconnects it on the next RPC: {e}"
+                ),
+            }
+        }
+    }
+}
+
+async fn close_idle(
+    balancer: Weak<Balancer>,
+    timeout: Duration,
+    socket: AuraeSocket,
+    certs: Option<Arc<CertStore>>,
+    options: ConnectOptions,
+    connect_state: ConnectState,
+) {
+    let mut closed = false;
+
+    loop {
+        let idle_for = match balancer.upgrade() {
+            Some(balancer) => balancer.idle_for(),
+            None => return,
+        };
+        if idle_for < timeout {
+            closed = false;
+            tokio::time::sleep(timeout - idle_for).await;
+            continue;
+        }
+        if closed {
+            tokio::time::sleep(timeout).await;
+            continue;
+        }
+
+        let Some(balancer) = balancer.upgrade() else {
+            return;
+        };
+        let channels = balancer
+            .snapshot()
+            .into_iter()
+            .map(|(addr, _)| {
+                let socket =
+                    addr.map(AuraeSocket::Addr).unwrap_or(socket.clone());
+                let channel = Client::connect_chan_lazy(
+                    socket,
+                    certs.clone(),
+                    &options,
+                    connect_state.clone(),
+                );
+                (addr, channel)
+            })
+            .collect();
+        *balancer.channels.write().expect("balancer channels lock poisoned") =
+            channels;
+        closed = true;
+        debug!("closed connections after {timeout:?} without RPCs");
+        connect_state.events.publish(ConnectionEvent::ClosedIdle);
+    }
+}
+
+/// Periodically re-resolve `uri`, swapping in new channels when the
+/// addresses behind it change: one per replica when load balancing, a
+/// single fresh connection otherwise. `known` is the set the current
+/// channels were connected to, `None` to take it from the first resolve.
+///
+/// Replaced channels are only dropped from the balancer. RPCs already
+/// running on them hold their own handle and finish there, and the old
+/// connection closes once the last of them is done.
+///
+/// Only a weak reference is held between ticks, so the task ends once the
+/// last client using the balancer is dropped.
+async fn re_resolve(
+    balancer: Weak<Balancer>,
+    uri: Uri,
+    known: Option<Vec<SocketAddr>>,
+    certs: Option<Arc<CertStore>>,
+    options: ConnectOptions,
+    connect_state: ConnectState,
+) {
+    let mut interval = tokio::time::interval(options.resolve_interval);
+    let _ = interval.tick().await;
+    let mut known = match known {
+        Some(known) => known,
+        None => resolve(&uri, &options).await.unwrap_or_default(),
+    };
+
+    loop {
+        let _ = interval.tick().await;
+
+        if balancer.strong_count() == 0 {
+            return;
+        }
+
+        let addrs = match resolve(&uri, &options).await {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                warn!("failed to re-resolve {uri}, keeping replicas: {e}");
+                continue;
+            }
+        };
+
+        if addrs == known {
+            continue;
+        }
+
+        let Some((policy, current)) =
+            balancer.upgrade().map(|b| (b.policy, b.snapshot()))
+        else {
+            return;
+        };
+
+        let channels = match policy {
+            LbPolicy::RoundRobin => {
+                connect_all(&addrs, &current, &certs, &options, &connect_state)
+                    .await
+            }
+            LbPolicy::PickFirst => Client::connect_chan(
+                AuraeSocket::Uri(uri.clone()),
+                certs.clone(),
+                &options,
+                connect_state.clone(),
+            )
+            .await
+            .map(|channel| vec![(None, channel)]),
+        };
+        let Some(balancer) = balancer.upgrade() else {
+            return;
+        };
+
+        match channels {
+            Ok(channels) => {
+                debug!("addresses behind {uri} changed to {addrs:?}");
+                known = match policy {
+                    LbPolicy::RoundRobin => addrs_of(&channels),
+                    LbPolicy::PickFirst => addrs.clone(),
+                };
+                *balancer
+                    .channels
+                    .write()
+                    .expect("balancer channels lock poisoned") = channels;
+                connect_state
+                    .events
+                    .publish(ConnectionEvent::Migrated { addrs });
+            }
+            Err(e) => {
+                warn!("no address behind {uri} is reachable, keeping the current connections: {e}");
+            }
+        }
+    }
+}
+
+/// The replica addresses of `channels`.
+fn addrs_of(channels: &[(Option<SocketAddr>, Channel)]) -> Vec<SocketAddr> {
+    channels.iter().filter_map(|(addr, _)| *addr).collect()
+}
+
+/// Build a channel per address, reusing those in `existing`. Addresses that
+/// fail to connect are skipped, as long as at least one succeeds.
+async fn connect_all(
+    addrs: &[SocketAddr],
+    existing: &[(Option<SocketAddr>, Channel)],
+    certs: &Option<Arc<CertStore>>,
+    options: &ConnectOptions,
+    connect_state: &ConnectState,
+) -> Result<Vec<(Option<SocketAddr>, Channel)>> {
+    let mut channels = Vec::with_capacity(addrs.len());
+    let mut last_err = None;
+
+    for addr in addrs {
+        if let Some((_, channel)) =
+            existing.iter().find(|(existing, _)| *existing == Some(*addr))
+        {
+            channels.push((Some(*addr), channel.clone()));
+            continue;
+        }
+
+        let socket = AuraeSocket::Addr(*addr);
+        match Client::connect_chan(
+            socket,
+            certs.clone(),
+            options,
+            connect_state.clone(),
+        )
+        .await
+        {
+            Ok(channel) => channels.push((Some(*addr), channel)),
+            Err(e) => {
+                warn!("skipping unreachable replica {addr}: {e}");
+                last_err = Some(e);
+            }
+        }
+    }
+
+    match last_err {
+        Some(e) if channels.is_empty() => Err(e),
+        _ => Ok(channels),
+    }
+}
+
+/// Resolve the host of `uri` to the addresses `connect.ip_family` selects,
+/// sorted so changes in the set can be detected.
+async fn resolve(
+    uri: &Uri,
+    options: &ConnectOptions,
+) -> io::Result<Vec<SocketAddr>> {
+    let (host, port) = connector::host_port(uri)?;
+    let mut addrs =
+        connector::resolve(&host, port, TcpOptions::from(options)).await?;
+    addrs.sort();
+    addrs.dedup();
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grpc::health::health::HealthClient;
+    use crate::testing::in_memory_channel;
+    use proto::grpc::health::health_check_response::ServingStatus;
+    use proto::grpc::health::health_server::{Health, HealthServer};
+    use proto::grpc::health::{HealthCheckRequest, HealthCheckResponse};
+    use tonic::transport::Server;
+    use tonic::{Request, Response, Status};
+
+    /// Counts the health checks it answers.
+    #[derive(Clone, Default)]
+    struct Counting(Arc<AtomicUsize>);
+
+    #[tonic::async_trait]
+    impl Health for Counting {
+        async fn check(
+            &self,
+            _request: Request<HealthCheckRequest>,
+        ) -> std::result::Result<Response<HealthCheckResponse>, Status>
+        {
+            let _ = self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(Response::new(HealthCheckResponse {
+                status: ServingStatus::Serving.into(),
+            }))
+        }
+
+        type WatchStream = futures_util::stream::Empty<
+            std::result::Result<HealthCheckResponse, Status>,
+        >;
+
+        async fn watch(
+            &self,
+            _request: Request<HealthCheckRequest>,
+        ) -> std::result::Result<Response<Self::WatchStream>, Status> {
+            Err(Status::unimplemented("watch"))
+        }
+    }
+
+    fn balancer(
+        policy: LbPolicy,
+        channels: Vec<(Option<SocketAddr>, Channel)>,
+    ) -> Balancer {
+        Balancer {
+            policy,
+            weights: Weights::default(),
+            hedge: None,
+            cooldowns: None,
+            channels: RwLock::new(channels),
+            next: AtomicUsize::new(0),
+            last_used: AtomicU64::new(0),
+            created: Instant::now(),
+        }
+    }
+
+    async fn check(channel: Channel) -> std::result::Result<(), Status> {
+        HealthClient::new(channel)
+            .check(HealthCheckRequest { service: String::new() })
+            .await
+            .map(|_| ())
+    }
+
+    fn replicas(addrs: &[&str]) -> Vec<(Option<SocketAddr>, Channel)> {
+        addrs
+            .iter()
+            .map(|addr| {
+                let channel =
+                    Channel::from_static("http://127.0.0.1:1").connect_lazy();
+                (Some(addr.parse().unwrap()), channel)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn replicas_get_rpcs_in_proportion_to_their_weight() {
+        let channels =
+            replicas(&["10.0.0.3:8080", "10.0.0.4:8080", "10.0.0.5:8080"]);
+        let weights = Weights::new(&BTreeMap::from([
+            ("10.0.0.3".to_string(), 6),
+            ("10.0.0.4:8080".to_string(), 3),
+        ]))
+        .unwrap();
+
+        let mut picked = [0usize; 3];
+        for n in 0..10_000 {
+            picked[weights.index(n, &channels)] += 1;
+        }
+
+        // 6:3:1, the last replica is not listed and weighs 1
+        for (count, expected) in picked.into_iter().zip([6000, 3000, 1000]) {
+            assert!(count.abs_diff(expected) <= 100, "{picked:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn without_weights_every_replica_takes_turns() {
+        let channels = replicas(&["10.0.0.3:8080", "10.0.0.4:8080"]);
+        let weights = Weights::default();
+
+        let picked: Vec<_> =
+            (0..4).map(|n| weights.index(n, &channels)).collect();
+
+        assert_eq!(picked, [0, 1, 0, 1]);
+    }
+
+    #[tokio::test]
+    async fn replicas_cooling_down_are_skipped() {
+        let channels = replicas(&["10.0.0.3:8080", "10.0.0.4:8080"]);
+        let cooldowns = Cooldowns::default();
+        let balancer = Balancer {
+            cooldowns: Some(cooldowns.clone()),
+            ..balancer(LbPolicy::RoundRobin, channels.clone())
+        };
+        let policy = CooldownPolicy::new(&ConnectOptions {
+            endpoint_cooldown: Some(Duration::from_secs(60)),
+            ..ConnectOptions::default()
+        })
+        .unwrap();
+
+        cooldowns.failed(channels[0].0.unwrap(), policy);
+        let picked: Vec<_> =
+            (0..4).map(|_| balancer.next_index(&channels)).collect();
+        assert_eq!(picked, [1, 1, 1, 1]);
+
+        cooldowns.failed(channels[1].0.unwrap(), policy);
+        let picked: Vec<_> =
+            (0..2).map(|_| balancer.next_index(&channels)).collect();
+        assert_eq!(picked, [0, 1]);
+    }
+
+    #[tokio::test]
+    async fn round_robin_spreads_rpcs_across_replicas() {
+        let (first, second) = (Counting::default(), Counting::default());
+        let replica = |health: &Counting, addr: &str| {
+            let router = Server::builder()
+                .add_service(HealthServer::new(health.clone()));
+            (Some(addr.parse().unwrap()), in_memory_channel(router))
+        };
+        let balancer = balancer(
+            LbPolicy::RoundRobin,
+            vec![
+                replica(&first, "10.0.0.3:8080"),
+                replica(&second, "10.0.0.4:8080"),
+            ],
+        );
+
+        for _ in 0..6 {
+            check(balancer.pick()).await.unwrap();
+        }
+        assert_eq!(first.0.load(Ordering::SeqCst), 3);
+        assert_eq!(second.0.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn re_resolving_moves_to_new_addresses() {
+        let health = Counting::default();
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming =
+            futures_util::stream::unfold(listener, |listener| async move {
+                let accepted =
+                    listener.accept().await.map(|(stream, _)| stream);
+                Some((accepted, listener))
+            });
+        let _server = tokio::spawn(
+            Server::builder()
+                .add_service(HealthServer::new(health.clone()))
+                .serve_with_incoming(incoming),
+        );
+
+        // connected to a replica no longer behind the URI
+        let gone = Channel::from_static("http://127.0.0.1:1").connect_lazy();
+        let balancer = Arc::new(balancer(
+            LbPolicy::RoundRobin,
+            vec![(Some("127.0.0.1:1".parse().unwrap()), gone)],
+        ));
+        let options = ConnectOptions {
+            resolve_interval: Duration::from_millis(10),
+            ..ConnectOptions::default()
+        };
+        let connect_state = ConnectState::default();
+        let mut events = connect_state.events.subscribe();
+        let _task = tokio::spawn(re_resolve(
+            Arc::downgrade(&balancer),
+            format!("http://{addr}").parse().unwrap(),
+            Some(vec!["127.0.0.1:1".parse().unwrap()]),
+            None,
+            options,
+            connect_state,
+        ));
+
+        let migrated = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Ok(ConnectionEvent::Migrated { addrs }) =
+                    events.recv().await
+                {
+                    return addrs;
+                }
+            }
+        })
+        .await
+        .expect("the balancer did not move to the new address");
+        assert_eq!(migrated, [addr]);
+
+        assert_eq!(addrs_of(&balancer.snapshot()), [addr]);
+        check(balancer.pick()).await.unwrap();
+        assert_eq!(health.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn weights_must_be_positive_and_keyed_by_address() {
+        let zero = Weights::new(&BTreeMap::from([("10.0.0.3".into(), 0)]));
+        assert!(zero.unwrap_err().to_string().contains("must be positive"));
+
+        let host =
+            Weights::new(&BTreeMap::from([("auraed.example.com".into(), 2)]));
+        assert!(host.is_err());
+    }
+}
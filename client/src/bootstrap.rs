@@ -0,0 +1,148 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Connecting through a bootstrap endpoint, see
+//! [`Client::connect_via_bootstrap`].
+
+use crate::connector::ConnectState;
+use crate::discovery::discovery_service::DiscoveryServiceClient;
+use crate::{AuraeConfig, Client, ClientError};
+use proto::discovery::{DiscoverRequest, DiscoverTarget};
+use std::future::Future;
+use std::panic::Location;
+use tracing::{info, warn};
+
+impl Client {
+    /// Connect to the daemon a bootstrap endpoint points at, for topologies
+    /// where clients only know a well-known address.
+    ///
+    /// Connects with `bootstrap` and asks it where to go with the
+    /// `Discover` RPC. The targets it returns are tried in order with the
+    /// auth and connect options of `bootstrap`, overriding the server name
+    /// and pinned certificates where the target sets them, and the first
+    /// one that connects and answers a health check is returned. Fails
+    /// with the error of the last target when none does. A bootstrap that
+    /// returns no targets serves clients itself, and is returned as is.
+    #[track_caller]
+    pub fn connect_via_bootstrap(
+        bootstrap: AuraeConfig,
+    ) -> impl Future<Output = Result<Self, ClientError>> {
+        let created_at = Location::caller();
+        async move {
+            let client = Self::new_at(
+                bootstrap.clone(),
+                created_at,
+                ConnectState::default(),
+            )
+            .await?;
+            let targets = client
+                .discover(DiscoverRequest {})
+                .await
+                .map_err(|status| {
+                    anyhow::anyhow!(
+                        "bootstrap discovery failed: {}",
+                        status.message()
+                    )
+                })?
+                .into_inner()
+                .targets;
+            if targets.is_empty() {
+                return Ok(client);
+            }
+            drop(client);
+
+            let mut last_error = None;
+            for target in &targets {
+                match connect_target(&bootstrap, target, created_at).await {
+                    Ok(client) => {
+                        info!(socket = %target.socket, "connected via bootstrap");
+                        return Ok(client);
+                    }
+                    Err(e) => {
+                        warn!(socket = %target.socket, "bootstrap target failed: {e}");
+                        last_error = Some(e);
+                    }
+                }
+            }
+            Err(last_error.expect("targets is not empty"))
+        }
+    }
+}
+
+async fn connect_target(
+    bootstrap: &AuraeConfig,
+    target: &DiscoverTarget,
+    created_at: &'static Location<'static>,
+) -> Result<Client, ClientError> {
+    let config = target_config(bootstrap, target)?;
+    let client =
+        Client::new_at(config, created_at, ConnectState::default()).await?;
+    client.health_check().await.map_err(|e| anyhow::anyhow!(e))?;
+    Ok(client)
+}
+
+/// `bootstrap`, pointed at `target`.
+fn target_config(
+    bootstrap: &AuraeConfig,
+    target: &DiscoverTarget,
+) -> Result<AuraeConfig, ClientError> {
+    let mut config = bootstrap.clone();
+    config.system.socket = target.socket.parse().map_err(|e| {
+        anyhow::anyhow!("bootstrap returned an unusable target: {e}")
+    })?;
+    if !target.server_name.is_empty() {
+        config.connect.server_name = Some(target.server_name.clone());
+    }
+    if !target.pinned_server_sha256.is_empty() {
+        config.connect.pinned_server_sha256 =
+            target.pinned_server_sha256.clone();
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AuraeSocket;
+
+    #[test]
+    fn targets_override_the_socket_and_identity_only() {
+        let mut bootstrap = AuraeConfig::from_options(
+            "ca.crt",
+            "client.crt",
+            "client.key",
+            "/var/run/aurae/bootstrap.sock",
+        );
+        bootstrap.connect.server_name = Some("bootstrap.aurae.io".into());
+        bootstrap.connect.pinned_server_sha256 = vec!["aa".into()];
+
+        let target = DiscoverTarget {
+            socket: "10.0.0.3:8080".into(),
+            server_name: "node-3.aurae.io".into(),
+            pinned_server_sha256: vec![],
+        };
+        let config = target_config(&bootstrap, &target).unwrap();
+        assert!(matches!(config.system.socket, AuraeSocket::Addr(_)));
+        assert_eq!(
+            config.connect.server_name.as_deref(),
+            Some("node-3.aurae.io")
+        );
+        assert_eq!(config.connect.pinned_server_sha256, vec!["aa"]);
+        assert_eq!(config.auth.client_crt, "client.crt");
+
+        let target = DiscoverTarget { socket: String::new(), ..target };
+        assert!(target_config(&bootstrap, &target).is_err());
+    }
+}
@@ -0,0 +1,601 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! The TLS identity a client makes new connections with, and hot reloading
+//! of it when the cert files change on disk or the process gets SIGHUP.
+//!
+//! Reloading only affects connections made afterwards. Established HTTP/2
+//! connections keep the identity they were opened with until they drop and
+//! are re-established.
+
+use crate::cert_bundle::CertBundle;
+use crate::config::{
+    ca_fetch, AuthConfig, CaFetchOptions, ClientCertDetails, Pkcs11Key,
+    X509Details,
+};
+#[cfg(feature = "pkcs11")]
+use crate::config::{cert_material, CertMaterial};
+use crate::events::{ConnectionEvent, Events};
+use crate::tls::{TlsConnect, TlsOptions};
+use crate::Client;
+#[cfg(feature = "pkcs11")]
+use crate::{pkcs11, tls};
+use anyhow::{anyhow, Context, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+#[cfg(feature = "pkcs11")]
+use std::time::SystemTime;
+use tokio::runtime::Handle;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Quiet period after a change before the certs are re-read. Rotation by
+/// atomic rename produces a burst of events, which this coalesces.
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub(crate) struct CertStore {
+    auth: AuthConfig,
+    options: TlsOptions,
+    reread: Reread,
+    current: RwLock<Loaded>,
+}
+
+/// What [`CertStore::reload`] reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reread {
+    Checked,
+    /// Only build the TLS config, see [`Client::new_minimal`].
+    Minimal,
+    /// Nothing, the TLS config came from [`Client::new_with_rustls`].
+    Never,
+}
+
+#[derive(Debug)]
+struct Loaded {
+    tls: TlsConnect,
+    /// `None` for a minimal store, which never parses the certs.
+    details: Option<ClientCertDetails>,
+    ca: Option<X509Details>,
+}
+
+impl CertStore {
+    pub(crate) async fn load(
+        auth: AuthConfig,
+        options: TlsOptions,
+    ) -> Result<Self> {
+        let current = RwLock::new(Self::read(&auth, &options).await?);
+        Ok(Self { auth, options, reread: Reread::Checked, current })
+    }
+
+    /// Read the certs into a TLS config without parsing or checking them.
+    pub(crate) async fn load_minimal(
+        auth: AuthConfig,
+        options: TlsOptions,
+    ) -> Result<Self> {
+        let current = RwLock::new(Self::read_minimal(&auth, &options).await?);
+        Ok(Self { auth, options, reread: Reread::Minimal, current })
+    }
+
+    /// Start from a bundle loaded by the caller. Reloads read the files of
+    /// `auth` again.
+    pub(crate) fn from_bundle(
+        auth: AuthConfig,
+        options: TlsOptions,
+        bundle: &CertBundle,
+    ) -> Result<Self> {
+        let current = RwLock::new(Self::check(&auth, &options, bundle)?);
+        Ok(Self { auth, options, reread: Reread::Checked, current })
+    }
+
+    /// Always connect with `tls`, which has no cert files behind it.
+    pub(crate) fn fixed(tls: TlsConnect, details: Option<X509Details>) -> Self {
+        let auth = AuthConfig {
+            ca_crt: String::new(),
+            client_crt: String::new(),
+            client_key: String::new(),
+            enforce_secure_paths: false,
+            strict: false,
+            expected_server_cn: None,
+            expected_server_spiffe: None,
+            expected_server_trust_domain: None,
+            client_key_passphrase: None,
+            ca_fetch: CaFetchOptions::default(),
+            pkcs11: None,
+        };
+        let loaded =
+            Loaded { tls, details: details.map(ClientCertDetails), ca: None };
+        Self {
+            auth,
+            options: TlsOptions::default(),
+            reread: Reread::Never,
+            current: RwLock::new(loaded),
+        }
+    }
+
+    async fn read(auth: &AuthConfig, options: &TlsOptions) -> Result<Loaded> {
+        if let Some(token) = &auth.pkcs11 {
+            return Self::read_token(auth, token, options).await;
+        }
+        if !auth.has_client_identity() {
+            return Self::read_server_only(auth, options).await;
+        }
+
+        let bundle = CertBundle::load_during_rotation(
+            auth,
+            options.clock_skew_tolerance,
+            options.rotation_retries,
+            options.rotation_retry_delay,
+        )
+        .await?;
+        Self::check(auth, options, &bundle)
+    }
+
+    fn check(
+        auth: &AuthConfig,
+        options: &TlsOptions,
+        bundle: &CertBundle,
+    ) -> Result<Loaded> {
+        let (details, ca) = bundle.details();
+        check_ca(auth, bundle.ca_bundle_details())?;
+        check_client_auth(auth, &details)?;
+        options.check_key_algorithm(&details)?;
+        let tls = TlsConnect::new(bundle.material(), auth, options)?;
+        Ok(Loaded { tls, details: Some(details), ca: Some(ca) })
+    }
+
+    /// Read and check the CA of an `auth` without a client identity.
+    async fn read_server_only(
+        auth: &AuthConfig,
+        options: &TlsOptions,
+    ) -> Result<Loaded> {
+        let material = auth.to_cert_material().await?;
+        let ca = material.get_server_ca_details()?;
+        check_ca(auth, &material.get_server_ca_bundle_details()?)?;
+        let tls = TlsConnect::new(&material, auth, options)?;
+        Ok(Loaded { tls, details: None, ca: Some(ca) })
+    }
+
+    /// Read and check the certs of an `auth` whose key is on a token. The
+    /// key is only used through the token.
+    #[cfg(feature = "pkcs11")]
+    async fn read_token(
+        auth: &AuthConfig,
+        token: &Pkcs11Key,
+        options: &TlsOptions,
+    ) -> Result<Loaded> {
+        let opened = {
+            let token = token.clone();
+            tokio::task::spawn_blocking(move || pkcs11::open(&token))
+                .await
+                .context("PKCS#11 token task failed")??
+        };
+        let client_cert = match (auth.client_crt.is_empty(), opened.cert_pem) {
+            (false, _) => cert_material::read_client_cert(auth).await?,
+            (true, Some(pem)) => pem,
+            (true, None) => {
+                return Err(anyhow!(
+                    "no certificate with CKA_ID {} on the PKCS#11 token, set auth.client_crt",
+                    token.key_id
+                ))
+            }
+        };
+        let material = CertMaterial {
+            server_root_ca_cert: cert_material::read_ca(auth).await?,
+            client_cert,
+            client_key: Vec::new(),
+        };
+
+        let details = material.get_client_cert_details()?;
+        let ca = material.get_server_ca_details()?;
+        check_ca(auth, &material.get_server_ca_bundle_details()?)?;
+        check_client_auth(auth, &details)?;
+        options.check_key_algorithm(&details)?;
+        tls::verify_client_chain(
+            &material,
+            SystemTime::now(),
+            options.clock_skew_tolerance,
+        )?;
+        let tls =
+            TlsConnect::with_signing_key(&material, opened.key, auth, options)?;
+        Ok(Loaded { tls, details: Some(details), ca: Some(ca) })
+    }
+
+    #[cfg(not(feature = "pkcs11"))]
+    async fn read_token(
+        _auth: &AuthConfig,
+        _token: &Pkcs11Key,
+        _options: &TlsOptions,
+    ) -> Result<Loaded> {
+        Err(anyhow!(
+            "auth.pkcs11 is set, but the client was built without the pkcs11 feature"
+        ))
+    }
+
+    async fn read_minimal(
+        auth: &AuthConfig,
+        options: &TlsOptions,
+    ) -> Result<Loaded> {
+        if let Some(token) = &auth.pkcs11 {
+            return Self::read_token(auth, token, options).await;
+        }
+        let material = auth.to_cert_material().await?;
+        let tls = TlsConnect::new(&material, auth, options)?;
+        Ok(Loaded { tls, details: None, ca: None })
+    }
+
+    /// Whether [`CertStore::reload`] has cert files to read.
+    pub(crate) fn has_files(&self) -> bool {
+        self.reread != Reread::Never
+    }
+
+    /// The TLS config for the next connection.
+    pub(crate) fn tls(&self) -> TlsConnect {
+        self.current.read().expect("cert store lock poisoned").tls.clone()
+    }
+
+    pub(crate) fn details(&self) -> Option<X509Details> {
+        let current = self.current.read().expect("cert store lock poisoned");
+        current.details.as_ref().map(|details| (**details).clone())
+    }
+
+    pub(crate) fn ca(&self) -> Option<X509Details> {
+        self.current.read().expect("cert store lock poisoned").ca.clone()
+    }
+
+    /// Re-read the cert files, returning the fingerprint of the new client
+    /// certificate (`None` for a minimal store). On error the current
+    /// identity is kept.
+    pub(crate) async fn reload(&self) -> Result<Option<String>> {
+        let loaded = match self.reread {
+            Reread::Checked => Self::read(&self.auth, &self.options).await?,
+            Reread::Minimal => {
+                Self::read_minimal(&self.auth, &self.options).await?
+            }
+            Reread::Never => {
+                return Err(anyhow!(
+                    "the TLS config was supplied by the caller, there are no cert files to reload"
+                ))
+            }
+        };
+        let fingerprint = loaded
+            .details
+            .as_ref()
+            .map(|details| details.sha256_fingerprint.clone());
+        *self.current.write().expect("cert store lock poisoned") = loaded;
+        Ok(fingerprint)
+    }
+}
+
+/// Keeps a cert watcher started by [`Client::watch_certs`] running. Dropping
+/// the handle stops the watcher.
+#[derive(Debug)]
+pub struct CertWatcherHandle {
+    _watcher: RecommendedWatcher,
+    task: JoinHandle<()>,
+}
+
+impl Drop for CertWatcherHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Keeps the reloads installed by [`Client::install_sighup_reload`] running.
+/// Dropping the handle stops them.
+#[derive(Debug)]
+pub struct SighupReloadHandle {
+    task: JoinHandle<()>,
+}
+
+impl Drop for SighupReloadHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Client {
+    /// Re-read the cert files this client was configured with, so that new
+    /// connections use the rotated identity.
+    pub async fn reload_certs(&self) -> crate::client::Result<()> {
+        let certs = self.cert_store().ok_or_else(|| {
+            anyhow!(
+                "client was created without TLS, there are no certs to reload"
+            )
+        })?;
+        let fingerprint = certs.reload().await?;
+        reloaded(fingerprint, &self.event_publisher());
+        Ok(())
+    }
+
+    /// Details of the server root CA the connection is verified against,
+    /// `None` for clients created without TLS.
+    pub fn server_ca_details(&self) -> Option<X509Details> {
+        self.cert_store().and_then(|certs| certs.ca())
+    }
+
+    /// Watch the cert files and [`Client::reload_certs`] whenever they change.
+    ///
+    /// Changes are debounced, so a rotation touching all three files causes a
+    /// single reload. Failed reloads are logged and the previous identity is
+    /// kept. The watcher runs until the returned handle is dropped.
+    ///
+    /// Reloads run on the Tokio runtime this is called from, called outside
+    /// of one it fails instead.
+    pub fn watch_certs(&self) -> crate::client::Result<CertWatcherHandle> {
+        let certs = self.cert_store().ok_or_else(|| {
+            anyhow!(
+                "client was created without TLS, there are no certs to watch"
+            )
+        })?;
+        let runtime = runtime("watch certs")?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(
+            move |res: notify::Result<notify::Event>| {
+                if res.is_ok() {
+                    let _ = tx.send(());
+                }
+            },
+        )
+        .context("failed to create cert watcher")?;
+
+        for dir in watch_dirs(&certs.auth) {
+            watcher.watch(&dir, RecursiveMode::NonRecursive).with_context(
+                || format!("failed to watch '{}'", dir.display()),
+            )?;
+        }
+
+        let task =
+            runtime.spawn(reload_on_change(certs, rx, self.event_publisher()));
+
+        Ok(CertWatcherHandle { _watcher: watcher, task })
+    }
+
+    /// [`Client::reload_certs`] whenever the process receives SIGHUP, the
+    /// conventional way to tell a daemon to pick up rotated files.
+    ///
+    /// Failed reloads are logged and the previous identity is kept. Reloads
+    /// run until the returned handle is dropped. The config itself is not
+    /// re-read, moving to a changed config takes [`Client::switch_config`].
+    ///
+    /// Use either this or [`Client::watch_certs`]: with both, a rotation
+    /// followed by SIGHUP reads the certs twice. Once installed, SIGHUP no
+    /// longer terminates the process, even after the handle is dropped.
+    ///
+    /// Like [`Client::watch_certs`], this fails outside of a Tokio runtime.
+    pub fn install_sighup_reload(
+        &self,
+    ) -> crate::client::Result<SighupReloadHandle> {
+        let certs = self.cert_store().ok_or_else(|| {
+            anyhow!(
+                "client was created without TLS, there are no certs to reload"
+            )
+        })?;
+        let runtime = runtime("reload certs on SIGHUP")?;
+        let hangups = signal(SignalKind::hangup())
+            .context("failed to install a SIGHUP handler")?;
+
+        let task = runtime.spawn(reload_on_sighup(
+            certs,
+            hangups,
+            self.event_publisher(),
+        ));
+
+        Ok(SighupReloadHandle { task })
+    }
+}
+
+/// Flag a self-signed leaf certificate configured as a server root CA,
+/// such as a server certificate pinned in place of the CA that issued it.
+/// Every certificate of the bundle is checked; self-signed roots that may
+/// sign certificates are what a CA bundle is made of and pass. It is an
+/// error under `auth.strict`, and otherwise a warning in release builds.
+pub(crate) fn check_ca(auth: &AuthConfig, cas: &[X509Details]) -> Result<()> {
+    for ca in cas.iter().filter(|ca| ca.self_signed) {
+        let Some(reason) = ca.not_a_ca() else {
+            continue;
+        };
+
+        if auth.strict {
+            return Err(anyhow!(
+                "server root CA '{}' is a self-signed leaf certificate ({reason}), which strict mode does not allow",
+                ca.subject_common_name
+            ));
+        }
+
+        if !cfg!(debug_assertions) {
+            warn!(
+                "server root CA '{}' is a self-signed leaf certificate ({reason}); configure the CA that issues the server certificates instead",
+                ca.subject_common_name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Flag a client certificate that may not authenticate a TLS client, such
+/// as a server certificate used by mistake, which the server would reject
+/// during the handshake. It is an error under `auth.strict`, and otherwise
+/// a warning.
+pub(crate) fn check_client_auth(
+    auth: &AuthConfig,
+    client: &X509Details,
+) -> Result<()> {
+    if client.is_client_auth_capable() {
+        return Ok(());
+    }
+
+    if auth.strict {
+        return Err(anyhow!(
+            "client certificate '{}' lacks the clientAuth extended key usage, which strict mode requires",
+            client.subject_common_name
+        ));
+    }
+
+    warn!(
+        "client certificate '{}' lacks the clientAuth extended key usage; auraed will likely reject it",
+        client.subject_common_name
+    );
+    Ok(())
+}
+
+/// The runtime background tasks to `what` are spawned on, an error in
+/// place of the panic of [`tokio::spawn`] outside of one.
+fn runtime(what: &str) -> Result<Handle> {
+    Handle::try_current()
+        .with_context(|| format!("cannot {what} outside of a Tokio runtime"))
+}
+
+/// The directories holding the cert files. Directories are watched rather
+/// than the files, as rotation usually replaces the files. A fetched CA is
+/// not watched, see [`crate::CaFetchOptions::cache_ttl`].
+fn watch_dirs(auth: &AuthConfig) -> BTreeSet<PathBuf> {
+    [&auth.ca_crt, &auth.client_crt, &auth.client_key]
+        .into_iter()
+        .filter(|path| !path.is_empty() && !ca_fetch::is_url(path))
+        .map(|path| match Path::new(path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => {
+                parent.to_path_buf()
+            }
+            _ => PathBuf::from("."),
+        })
+        .collect()
+}
+
+/// Log a reload, and tell subscribers about the new identity when it is
+/// known.
+fn reloaded(fingerprint: Option<String>, events: &Events) {
+    let Some(fingerprint) = fingerprint else {
+        info!("reloaded aurae client certificates");
+        return;
+    };
+    info!(%fingerprint, "reloaded aurae client certificates");
+    events.publish(ConnectionEvent::CertReloaded { fingerprint });
+}
+
+async fn reload_on_change(
+    certs: Arc<CertStore>,
+    mut changes: mpsc::UnboundedReceiver<()>,
+    events: Events,
+) {
+    while changes.recv().await.is_some() {
+        // Wait for the burst of events to settle.
+        while let Ok(Some(())) =
+            tokio::time::timeout(RELOAD_DEBOUNCE, changes.recv()).await
+        {
+        }
+
+        match certs.reload().await {
+            Ok(fingerprint) => reloaded(fingerprint, &events),
+            Err(e) => {
+                warn!("failed to reload aurae client certificates: {e:#}")
+            }
+        }
+    }
+}
+
+async fn reload_on_sighup(
+    certs: Arc<CertStore>,
+    mut hangups: Signal,
+    events: Events,
+) {
+    while hangups.recv().await.is_some() {
+        info!("received SIGHUP, reloading aurae client certificates");
+        match certs.reload().await {
+            Ok(fingerprint) => reloaded(fingerprint, &events),
+            Err(e) => {
+                warn!("failed to reload aurae client certificates: {e:#}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_ca;
+
+    #[test]
+    fn watch_dirs_are_deduplicated() {
+        let auth = AuthConfig {
+            ca_crt: "/etc/aurae/pki/ca.crt".into(),
+            client_crt: "/etc/aurae/pki/client.crt".into(),
+            client_key: "client.key".into(),
+            enforce_secure_paths: false,
+            strict: false,
+            expected_server_cn: None,
+            expected_server_spiffe: None,
+            expected_server_trust_domain: None,
+            client_key_passphrase: None,
+            ca_fetch: CaFetchOptions::default(),
+            pkcs11: None,
+        };
+
+        let dirs: Vec<_> = watch_dirs(&auth).into_iter().collect();
+
+        assert_eq!(dirs, [PathBuf::from("."), PathBuf::from("/etc/aurae/pki")]);
+    }
+
+    #[test]
+    fn watching_needs_a_runtime() {
+        let err = runtime("watch certs").unwrap_err();
+        assert!(err.to_string().contains("outside of a Tokio runtime"));
+
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        assert!(rt.block_on(async { runtime("watch certs") }).is_ok());
+    }
+
+    #[test]
+    fn only_self_signed_leaves_are_flagged_as_cas() {
+        let auth = AuthConfig {
+            ca_crt: "ca.crt".into(),
+            client_crt: String::new(),
+            client_key: String::new(),
+            enforce_secure_paths: false,
+            strict: true,
+            expected_server_cn: None,
+            expected_server_spiffe: None,
+            expected_server_trust_domain: None,
+            client_key_passphrase: None,
+            ca_fetch: CaFetchOptions::default(),
+            pkcs11: None,
+        };
+        let bundle = |pems: &[String]| {
+            CertMaterial {
+                server_root_ca_cert: pems.concat().into_bytes(),
+                client_cert: vec![],
+                client_key: vec![],
+            }
+            .get_server_ca_bundle_details()
+            .unwrap()
+        };
+        let root = test_ca("root").serialize_pem().unwrap();
+        let leaf = rcgen::generate_simple_self_signed(vec!["auraed".into()])
+            .unwrap()
+            .serialize_pem()
+            .unwrap();
+
+        check_ca(&auth, &bundle(&[root.clone()])).unwrap();
+        let err = check_ca(&auth, &bundle(&[root, leaf])).unwrap_err();
+        assert!(err.to_string().contains("self-signed leaf"), "{err}");
+    }
+}
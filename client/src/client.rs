@@ -18,101 +18,2017 @@
 //! Manages authenticating with remote Aurae instances, as well as searching
 //! the local filesystem for configuration and authentication material.
 
-use crate::config::{AuraeConfig, CertMaterial, ClientCertDetails};
-use crate::AuraeSocket;
+use crate::balancer::Balancer;
+use crate::cert_bundle::CertBundle;
+use crate::cert_store::CertStore;
+use crate::concurrency::{
+    InFlight, InFlightSnapshot, Limited, Priority, RpcChannel, RpcLimiter,
+};
+use crate::config::{
+    AuraeConfig, AuthConfig, CompressionMode, ConnectOptions, KeyAlgorithm,
+    LbPolicy, MethodPolicy, RetryOptions, RpcLogOptions, TransportMode,
+    X509Details,
+};
+use crate::connection_tracker::{BudgetSlot, ConnectionTracker};
+use crate::connector::{
+    self, BoxedIo, ConnectInfo, ConnectPhase, ConnectState, Connector,
+    DnsTimeout, PhaseTimeout, PhaseTimeouts, TcpOptions,
+};
+use crate::credentials::Credentials;
+use crate::events::{ConnectionEvent, Events};
+use crate::grpc::health::health::HealthClient;
+use crate::grpc_web::Transport;
+use crate::hello::{ClientInfo, ServerCapabilities};
+use crate::identity::{IdentityPool, IdentitySet};
+use crate::interceptor::InterceptorChain;
+use crate::metadata::CallMetadata;
+use crate::namespace::Namespace;
+use crate::read_only::ReadOnly;
+use crate::retry::{RetryBudget, RetryPredicate};
+use crate::rpc_log::Logged;
+use crate::server_info::{ServerInfo, API_VERSION_CHECK_TIMEOUT};
+use crate::ssh_tunnel::SshTunnel;
+use crate::tls::{
+    self, ChainTooDeep, IdentityMismatch, MissingIpSan, PresentedCert,
+    TlsConnect, TlsDebugInfo, TlsOptions, TrustDomainMismatch, UntrustedChain,
+    DEFAULT_SERVER_NAME,
+};
+use crate::{AuraeSocket, ParseSocketError, SocketKind};
+use futures_util::future::join_all;
+use proto::grpc::health::HealthCheckRequest;
+use std::future::Future;
+use std::net::IpAddr;
+use std::panic::Location;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tokio::net::{TcpStream, UnixStream};
-use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity, Uri};
+use tokio::sync::{broadcast, OnceCell};
+use tokio_rustls::rustls::{ClientConfig, ServerName};
+use tonic::codec::CompressionEncoding;
+use tonic::transport::{Channel, Uri};
+use tonic::Code;
 use tower::service_fn;
+use tower::util::ServiceFn;
+use tracing::{debug, debug_span, field, info, warn, Instrument};
 
 const KNOWN_IGNORED_SOCKET_ADDR: &str = "hxxp://null";
 
-type Result<T> = std::result::Result<T, ClientError>;
+/// One connection attempt of a channel's connector.
+type Dial = Pin<Box<dyn Future<Output = std::io::Result<BoxedIo>> + Send>>;
+
+/// What tonic calls to open each connection of a channel.
+type DialService = ServiceFn<Box<dyn FnMut(Uri) -> Dial + Send>>;
+
+/// Limit on the health check [`Client::ensure_connected`] uses to decide
+/// whether the current connection is still usable.
+const ENSURE_CONNECTED_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub(crate) type Result<T> = std::result::Result<T, ClientError>;
 
 #[derive(Error, Debug)]
 pub enum ClientError {
     #[error(transparent)]
     ConnectionError(#[from] tonic::transport::Error),
+    #[error("{phase} timed out after {timeout:?}")]
+    ConnectTimeout { phase: ConnectPhase, timeout: Duration },
+    #[error("resolving {host} timed out after {timeout:?}")]
+    DnsTimeout { host: String, timeout: Duration },
+    #[error(
+        "server identity mismatch: presented {found}, expected {expected}"
+    )]
+    ServerIdentityMismatch { expected: String, found: String },
+    #[error("server SPIFFE IDs {found:?} are not in trust domain '{expected}', as auth.expected_server_trust_domain requires")]
+    TrustDomainMismatch { expected: String, found: Vec<String> },
+    #[error("server certificate has no IP SAN for {ip}, which connect.strict_hostname requires")]
+    MissingIpSan { ip: IpAddr },
+    #[error("server presented a chain of {depth} certificates, more than connect.max_chain_depth of {max}")]
+    ChainTooDeep { depth: usize, max: usize },
+    /// `chain` lists the certificates the server presented, end entity
+    /// first. It is only recorded with the `custom-verifier` feature, and
+    /// empty without.
+    #[error("server certificate verification failed: {reason}; presented chain: {}", tls::dump(.chain))]
+    TlsVerification { reason: String, chain: Vec<PresentedCert> },
+    #[error("{method} changes state, refused in read only mode")]
+    ReadOnlyViolation { method: String },
+    #[error("{method} is not permitted by connect.method_policy")]
+    MethodNotPermitted { method: String },
+    #[error(
+        "server root CA file contains no PEM certificates, so no server can be trusted"
+    )]
+    NoTrustAnchors,
+    #[error(
+        "failed to decrypt the client key, check auth.client_key_passphrase"
+    )]
+    KeyDecryptFailed,
+    #[error("failed to fetch the server root CA from {url}: {reason}")]
+    CaFetchFailed { url: String, reason: String },
+    #[error("the call deadline has already passed")]
+    DeadlineExceeded,
+    #[error("client certificate key algorithm {found} is not one of the accepted {accepted:?}")]
+    KeyAlgorithmRejected { found: String, accepted: Vec<KeyAlgorithm> },
+    #[error("all {max} connections of AURAE_MAX_CONNECTIONS are in use")]
+    ConnectionBudgetExhausted { max: usize },
+    #[error("{method} shed: all {max} RPCs of connect.max_concurrent_rpcs are in flight")]
+    LoadShed { method: String, max: usize },
+    #[error("this client speaks Aurae API {client_version} but auraed serves {server_version}; upgrade the older of the two, or set connect.allow_api_version_skew if they are known to be compatible")]
+    VersionIncompatible { client_version: String, server_version: String },
     #[error(transparent)]
-    Other(#[from] anyhow::Error),
+    Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for ClientError {
+    /// Errors raised as a [`ClientError`] deep in the crate keep their
+    /// variant, anything else is [`ClientError::Other`].
+    fn from(e: anyhow::Error) -> Self {
+        e.downcast::<ClientError>().unwrap_or_else(ClientError::Other)
+    }
 }
 
 /// Instance of a single client for an Aurae consumer.
 #[derive(Debug, Clone)]
 pub struct Client {
-    /// The channel used for gRPC connections before encryption is handled.
-    pub(crate) channel: Channel,
-    #[allow(unused)]
-    client_cert_details: Option<ClientCertDetails>,
+    /// The channels used for gRPC connections, one per replica when load
+    /// balancing.
+    balancer: Arc<Balancer>,
+    /// The TLS identity, `None` for clients created without TLS.
+    certs: Option<Arc<CertStore>>,
+    /// Compression applied to RPCs made through this client.
+    compression: CompressionMode,
+    /// Native gRPC or gRPC-Web framing for RPCs made through this client.
+    transport_mode: TransportMode,
+    /// Extra headers sent with RPCs made through this handle.
+    metadata: CallMetadata,
+    /// Run on every RPC made through this handle, after `metadata`.
+    interceptors: InterceptorChain,
+    /// Whether this handle rejects mutating RPCs.
+    read_only: ReadOnly,
+    /// Which RPCs this handle may make at all.
+    method_policy: Arc<MethodPolicy>,
+    /// Sent as the namespace of RPCs made through this handle.
+    namespace: Option<Namespace>,
+    /// When RPCs made through this handle must have finished.
+    deadline: Option<Instant>,
+    /// Whether RPCs made through this handle are shed under load.
+    priority: Priority,
+    /// Shared by all clones, so retries are throttled client wide.
+    retry: Arc<RetryBudget>,
+    /// Retries failures beyond the built in rules, see
+    /// [`Client::with_retry_predicate`].
+    retry_predicate: Option<RetryPredicate>,
+    /// Shared by all clones, `None` when RPCs are not limited.
+    limiter: Option<Arc<RpcLimiter>>,
+    /// Shared by all clones.
+    in_flight: Arc<InFlight>,
+    /// `None` unless `connect.rpc_log` is enabled.
+    rpc_log: Option<Arc<RpcLogOptions>>,
+    /// Updated by the connector on every (re)connect.
+    connect_state: ConnectState,
+    /// How the channels are connected again, `None` for clients from
+    /// [`Client::from_channel`].
+    redial: Option<Arc<Redial>>,
+    /// Shared by all clones, `None` unless set with
+    /// [`Client::with_identities`].
+    identities: Option<Arc<IdentityPool>>,
+    /// Shared by all clones, `None` unless set with
+    /// [`Client::with_credential_provider`].
+    credentials: Option<Arc<Credentials>>,
+    /// Shared by all clones, filled by the first [`Client::server_info`].
+    server_info: Arc<OnceCell<ServerInfo>>,
+    /// Shared by all clones, filled by the hello of `connect.hello`.
+    session: Arc<OnceCell<ServerCapabilities>>,
+    /// Shared by all clones, so concurrent [`Client::reconnect_now`] calls
+    /// reconnect once.
+    reconnects: Arc<ReconnectGate>,
+    /// Counts this connection as live until the last clone is dropped.
+    _tracker: Arc<ConnectionTracker>,
+    /// Keeps the SSH forward alive when connected through a jump host.
+    _tunnel: Option<Arc<SshTunnel>>,
+    /// What this client was built from, so the connection can be rebuilt.
+    origin: Arc<Origin>,
+    created_at: &'static Location<'static>,
+}
+
+#[derive(Debug)]
+enum Origin {
+    Config(AuraeConfig),
+    /// From [`Client::new_minimal`].
+    Minimal(AuraeConfig),
+    NoTls(AuraeSocket),
+    /// From [`Client::new_with_rustls`], the TLS config is in the cert
+    /// store.
+    Rustls(AuraeSocket),
+    /// Built by the caller, along with the identity it presents, if any.
+    Channel(Option<X509Details>),
+}
+
+/// Where [`Client::connect_config`] takes the TLS identity from.
+pub(crate) enum CertSource {
+    /// The cert files of the config, checked with [`CertBundle::load`].
+    Files,
+    Bundle(CertBundle),
+    /// Loaded already, see [`Client::prepare`].
+    Prepared(Arc<CertStore>),
+}
+
+/// Whether `changed` differs from `current` in no more than the options
+/// [`Client::clone_with`] applies to a shared connection.
+fn per_rpc_changes_only(
+    current: &ConnectOptions,
+    changed: &ConnectOptions,
+) -> bool {
+    let connection = ConnectOptions {
+        compression: current.compression,
+        transport_mode: current.transport_mode,
+        read_only: current.read_only,
+        mutating_methods: current.mutating_methods.clone(),
+        method_policy: current.method_policy.clone(),
+        default_namespace: current.default_namespace.clone(),
+        rpc_log: current.rpc_log.clone(),
+        ..changed.clone()
+    };
+    // compared as serialized, like AuraeConfig::checksum
+    serde_json::to_value(current).ok() == serde_json::to_value(connection).ok()
+}
+
+/// The socket the channels were connected over (the local end of an SSH
+/// forward, if any) and the options they were connected with.
+#[derive(Debug)]
+struct Redial {
+    socket: AuraeSocket,
+    options: ConnectOptions,
+}
+
+/// Lets concurrent reconnects share the one already running, see
+/// [`Client::reconnect_now`].
+#[derive(Debug, Default)]
+struct ReconnectGate {
+    /// Reconnects finished so far.
+    done: AtomicU64,
+    /// Held while reconnecting, with the error of the last reconnect.
+    last_error: tokio::sync::Mutex<Option<String>>,
+}
+
+impl ReconnectGate {
+    /// Run `reconnect`, unless one started before this call is running:
+    /// then wait for it and return its outcome instead.
+    async fn run<F, Fut>(&self, reconnect: F) -> Result<()>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let seen = self.done.load(Ordering::SeqCst);
+        let mut last_error = self.last_error.lock().await;
+        if self.done.load(Ordering::SeqCst) != seen {
+            return match &*last_error {
+                None => Ok(()),
+                Some(e) => Err(ClientError::Other(anyhow::anyhow!("{e}"))),
+            };
+        }
+
+        let result = reconnect().await;
+        *last_error = result.as_ref().err().map(ToString::to_string);
+        let _ = self.done.fetch_add(1, Ordering::SeqCst);
+        result
+    }
 }
 
 impl Client {
-    pub async fn default() -> Result<Self> {
-        Self::new(AuraeConfig::try_default()?).await
+    #[track_caller]
+    pub fn default() -> impl Future<Output = Result<Self>> {
+        let created_at = Location::caller();
+        async move {
+            Self::new_at(
+                AuraeConfig::try_default()?,
+                created_at,
+                ConnectState::default(),
+            )
+            .await
+        }
+    }
+
+    /// Create a new Client from the named profile, see
+    /// [`AuraeConfig::list_profiles()`].
+    #[track_caller]
+    pub fn from_profile(name: &str) -> impl Future<Output = Result<Self>> {
+        let created_at = Location::caller();
+        let config = AuraeConfig::load_profile(name);
+        async move {
+            Self::new_at(config?, created_at, ConnectState::default()).await
+        }
     }
 
     /// Create a new Client.
     ///
     /// Note: A new client is required for every independent execution of this process.
-    pub async fn new(
-        AuraeConfig { auth, system }: AuraeConfig,
+    #[track_caller]
+    pub fn new(config: AuraeConfig) -> impl Future<Output = Result<Self>> {
+        Self::new_at(config, Location::caller(), ConnectState::default())
+    }
+
+    /// Create a new Client that opens its transports with `connector`
+    /// instead of dialing `system.socket` itself, e.g. over QUIC or a
+    /// tunnel, or over in-memory streams in tests. The rest of `config`,
+    /// including the TLS handshake on top of each transport, applies as for
+    /// [`Client::new`].
+    #[track_caller]
+    pub fn new_with_connector<C: Connector>(
+        config: AuraeConfig,
+        connector: C,
+    ) -> impl Future<Output = Result<Self>> {
+        Self::new_at(
+            config,
+            Location::caller(),
+            ConnectState::with_connector(Arc::new(connector)),
+        )
+    }
+
+    /// Create a new Client that only reads the certs, builds the TLS config
+    /// and connects. A fast path for benchmarks and trusted automation
+    /// where identity introspection is not needed.
+    ///
+    /// The certificates are never parsed, so [`Client::client_cert_details`]
+    /// and [`Client::server_ca_details`] return `None`, and the self-signed
+    /// CA check (and with it `auth.strict`) is skipped. The server is still
+    /// verified against the CA and any expected identity.
+    ///
+    /// Nothing else [`Client::new`] does on connecting is done either: the
+    /// connect is not logged, no slot of `AURAE_MAX_CONNECTIONS` is taken,
+    /// a single connection is opened whatever `connect.load_balance` says,
+    /// and `connect.follow_dns`, `connect.idle_timeout`,
+    /// `connect.max_connection_age`, `connect.warm_up`, `connect.hello` and
+    /// the API version check are skipped. Without a hello, messages are
+    /// never compressed.
+    #[track_caller]
+    pub fn new_minimal(
+        config: AuraeConfig,
+    ) -> impl Future<Output = Result<Self>> {
+        Self::connect_minimal(
+            config,
+            Location::caller(),
+            ConnectState::default(),
+        )
+    }
+
+    /// Create a new Client that connects with `bundle` instead of reading
+    /// the cert files of `config.auth`. The rest of `config` applies as for
+    /// [`Client::new`].
+    ///
+    /// Only the first connection uses `bundle`: [`Client::reload_certs`],
+    /// the cert watcher and rebuilds by [`Client::ensure_connected`] read the
+    /// files of `config.auth`.
+    #[track_caller]
+    pub fn from_bundle(
+        config: AuraeConfig,
+        bundle: CertBundle,
+    ) -> impl Future<Output = Result<Self>> {
+        Self::connect_config(
+            config,
+            CertSource::Bundle(bundle),
+            Location::caller(),
+            ConnectState::default(),
+        )
+    }
+
+    /// Create a new Client even when auraed cannot be reached, for tools
+    /// such as status dashboards that have to come up while it is down.
+    ///
+    /// Connecting is tried as for [`Client::new`]. When that fails, a client
+    /// is returned anyway, with the failure recorded in [`Client::state`] as
+    /// [`ConnectionState::Failed`](crate::ConnectionState::Failed), instead
+    /// of the error. Its RPCs connect on demand and fail with the connect
+    /// error for as long as auraed is unreachable. As the rest of the
+    /// fallback, it uses a single connection whatever
+    /// `connect.load_balance` says, and it skips `connect.warm_up`. When the
+    /// certs could not be read or the SSH jump could not be opened, RPCs
+    /// keep failing until [`Client::ensure_connected`] rebuilds the client
+    /// from `config`, which also restores everything else.
+    ///
+    /// Only a config that can never connect, such as one with an invalid
+    /// `connect.default_namespace`, is still an error.
+    #[track_caller]
+    pub fn new_allow_disconnected(
+        config: AuraeConfig,
+    ) -> impl Future<Output = Result<Self>> {
+        let created_at = Location::caller();
+        async move {
+            let state = ConnectState::default();
+            match Self::new_at(config.clone(), created_at, state.clone()).await
+            {
+                Ok(client) => Ok(client),
+                Err(e) => {
+                    warn!(
+                        "auraed is unreachable, continuing disconnected: {e}"
+                    );
+                    state.failed(&e);
+                    Self::disconnected(config, &e, created_at, state).await
+                }
+            }
+        }
+    }
+
+    /// The fallback of [`Client::new_allow_disconnected`] once connecting
+    /// failed with `error`.
+    async fn disconnected(
+        config: AuraeConfig,
+        error: &ClientError,
+        created_at: &'static Location<'static>,
+        connect_state: ConnectState,
+    ) -> Result<Self> {
+        let origin = Arc::new(Origin::Config(config.clone()));
+        let AuraeConfig { auth, system, connect } = config;
+
+        let tls = TlsOptions::new(&connect)?;
+        let namespace = connect
+            .default_namespace
+            .as_deref()
+            .map(Namespace::new)
+            .transpose()?;
+        let socket = system.socket.normalized().map_err(anyhow::Error::from)?;
+        let certs = match system.ssh_jump {
+            // connections to the socket would bypass the jump host
+            Some(_) => None,
+            None => CertStore::load(auth, tls).await.ok().map(Arc::new),
+        };
+        let channel = match &certs {
+            Some(certs) => Self::connect_chan_lazy(
+                socket.clone(),
+                Some(certs.clone()),
+                &connect,
+                connect_state.clone(),
+            ),
+            None => Self::failing_chan(error),
+        };
+
+        Ok(Self {
+            balancer: Arc::new(Balancer::single(channel)),
+            certs,
+            compression: connect.compression,
+            transport_mode: connect.transport_mode,
+            metadata: CallMetadata::default(),
+            interceptors: InterceptorChain::default(),
+            read_only: ReadOnly::new(
+                connect.read_only,
+                connect.mutating_methods.clone(),
+            ),
+            method_policy: Arc::new(connect.method_policy.clone()),
+            namespace,
+            deadline: None,
+            priority: Priority::default(),
+            retry: Arc::new(RetryBudget::new(connect.retry.clone())),
+            retry_predicate: None,
+            limiter: connect.max_concurrent_rpcs.map(|max| {
+                Arc::new(RpcLimiter::new(max.get(), connect.max_queued_rpcs))
+            }),
+            in_flight: Arc::default(),
+            rpc_log: connect
+                .rpc_log
+                .enabled
+                .then(|| Arc::new(connect.rpc_log.clone())),
+            connect_state,
+            redial: Some(Arc::new(Redial { socket, options: connect })),
+            identities: None,
+            credentials: None,
+            server_info: Arc::default(),
+            session: Arc::default(),
+            reconnects: Arc::default(),
+            _tracker: Arc::new(ConnectionTracker::new(created_at, None)),
+            _tunnel: None,
+            origin,
+            created_at,
+        })
+    }
+
+    pub(crate) async fn new_at(
+        config: AuraeConfig,
+        created_at: &'static Location<'static>,
+        connect_state: ConnectState,
+    ) -> Result<Self> {
+        Self::connect_config(
+            config,
+            CertSource::Files,
+            created_at,
+            connect_state,
+        )
+        .await
+    }
+
+    /// The connect of [`Client::new_minimal`].
+    async fn connect_minimal(
+        config: AuraeConfig,
+        created_at: &'static Location<'static>,
+        connect_state: ConnectState,
+    ) -> Result<Self> {
+        let origin = Arc::new(Origin::Minimal(config.clone()));
+        let AuraeConfig { auth, system, connect } = config;
+
+        let tls = TlsOptions::new(&connect)?;
+        let namespace = connect
+            .default_namespace
+            .as_deref()
+            .map(Namespace::new)
+            .transpose()?;
+        let certs = Arc::new(CertStore::load_minimal(auth, tls).await?);
+
+        let _tunnel = match &system.ssh_jump {
+            Some(jump) => Some(Arc::new(SshTunnel::open(jump).await?)),
+            None => None,
+        };
+        let socket = match &_tunnel {
+            Some(tunnel) => AuraeSocket::Path(tunnel.local_socket().into()),
+            None => system.socket.normalized().map_err(anyhow::Error::from)?,
+        };
+        let channel = Self::connect_chan(
+            socket.clone(),
+            Some(certs.clone()),
+            &connect,
+            connect_state.clone(),
+        )
+        .await?;
+
+        Ok(Self {
+            balancer: Arc::new(Balancer::single(channel)),
+            certs: Some(certs),
+            compression: connect.compression,
+            transport_mode: connect.transport_mode,
+            metadata: CallMetadata::default(),
+            interceptors: InterceptorChain::default(),
+            read_only: ReadOnly::new(
+                connect.read_only,
+                connect.mutating_methods.clone(),
+            ),
+            method_policy: Arc::new(connect.method_policy.clone()),
+            namespace,
+            deadline: None,
+            priority: Priority::default(),
+            retry: Arc::new(RetryBudget::new(connect.retry.clone())),
+            retry_predicate: None,
+            limiter: connect.max_concurrent_rpcs.map(|max| {
+                Arc::new(RpcLimiter::new(max.get(), connect.max_queued_rpcs))
+            }),
+            in_flight: Arc::default(),
+            rpc_log: connect
+                .rpc_log
+                .enabled
+                .then(|| Arc::new(connect.rpc_log.clone())),
+            connect_state,
+            redial: Some(Arc::new(Redial { socket, options: connect })),
+            identities: None,
+            credentials: None,
+            server_info: Arc::default(),
+            session: Arc::default(),
+            reconnects: Arc::default(),
+            _tracker: Arc::new(ConnectionTracker::new(created_at, None)),
+            _tunnel,
+            origin,
+            created_at,
+        })
+    }
+
+    pub(crate) async fn connect_config(
+        config: AuraeConfig,
+        source: CertSource,
+        created_at: &'static Location<'static>,
+        connect_state: ConnectState,
     ) -> Result<Self> {
-        let cert_material = auth.to_cert_material().await?;
-        let client_cert_details =
-            Some(cert_material.get_client_cert_details()?);
+        let origin = Arc::new(Origin::Config(config.clone()));
+        let AuraeConfig { auth, system, connect } = config;
+
+        // Paths only, so the effective settings can be read off a failed
+        // connect without the logs ever holding key material.
+        info!(
+            socket = %system.socket.endpoint(),
+            transport = %system.socket.kind(),
+            ssh_jump = system.ssh_jump.as_ref().map(|jump| jump.host.as_str()),
+            ca_crt = %auth.ca_crt,
+            client_crt = %auth.client_crt,
+            client_key = %auth.client_key,
+            server_name = connect.server_name.as_deref().unwrap_or(DEFAULT_SERVER_NAME),
+            transport_mode = ?connect.transport_mode,
+            "connecting to auraed"
+        );
+        debug!(options = ?connect, "connect options");
 
-        let CertMaterial { server_root_ca_cert, client_cert, client_key } =
-            cert_material;
+        if connect.resolve_interval.is_zero() {
+            return Err(ClientError::Other(anyhow::anyhow!(
+                "connect.resolve_interval must not be 0"
+            )));
+        }
+        if let (Some(encoding), false) =
+            (connect.compression.name(), connect.hello)
+        {
+            return Err(ClientError::Other(anyhow::anyhow!(
+                "connect.compression is {encoding}, which needs connect.hello to learn whether auraed accepts it"
+            )));
+        }
+        let slot =
+            BudgetSlot::acquire(&connect, &connect_state.handover).await?;
+        let tls = TlsOptions::new(&connect)?;
+        let namespace = connect
+            .default_namespace
+            .as_deref()
+            .map(Namespace::new)
+            .transpose()?;
+        let certs = match source {
+            CertSource::Files => Arc::new(CertStore::load(auth, tls).await?),
+            CertSource::Bundle(bundle) => {
+                Arc::new(CertStore::from_bundle(auth, tls, &bundle)?)
+            }
+            CertSource::Prepared(certs) => certs,
+        };
+
+        let _tunnel = match &system.ssh_jump {
+            Some(jump) => Some(Arc::new(SshTunnel::open(jump).await?)),
+            None => None,
+        };
+
+        let socket = match &_tunnel {
+            Some(tunnel) => AuraeSocket::Path(tunnel.local_socket().into()),
+            None => system.socket.normalized().map_err(anyhow::Error::from)?,
+        };
 
-        let tls_config = ClientTlsConfig::new()
-            // TODO: get this from the config or the cert information somehow
-            .domain_name("server.unsafe.aurae.io")
-            .ca_certificate(Certificate::from_pem(server_root_ca_cert))
-            .identity(Identity::from_pem(client_cert, client_key));
+        let redial = Arc::new(Redial {
+            socket: socket.clone(),
+            options: connect.clone(),
+        });
+        let balancer = match socket {
+            AuraeSocket::Uri(uri)
+                if connect.load_balance == LbPolicy::RoundRobin =>
+            {
+                Balancer::round_robin(
+                    &uri,
+                    Some(certs.clone()),
+                    &connect,
+                    connect_state.clone(),
+                )
+                .await?
+            }
+            socket => {
+                let balancer = Arc::new(Balancer::single(
+                    Self::connect_chan(
+                        socket.clone(),
+                        Some(certs.clone()),
+                        &connect,
+                        connect_state.clone(),
+                    )
+                    .await?,
+                ));
+                if let (AuraeSocket::Uri(uri), true) =
+                    (socket, connect.follow_dns)
+                {
+                    balancer.follow_dns(
+                        uri,
+                        Some(certs.clone()),
+                        connect.clone(),
+                        connect_state.clone(),
+                    );
+                }
+                balancer
+            }
+        };
+        // an inherited socket cannot be connected a second time
+        if !matches!(redial.socket, AuraeSocket::Inherited(_)) {
+            balancer.replace_draining(
+                redial.socket.clone(),
+                Some(certs.clone()),
+                connect.clone(),
+                connect_state.clone(),
+            );
+        }
+        if let Some(timeout) = connect.idle_timeout {
+            balancer.close_when_idle(
+                timeout,
+                redial.socket.clone(),
+                Some(certs.clone()),
+                connect.clone(),
+                connect_state.clone(),
+            );
+        }
+        if let Some(age) = connect.max_connection_age {
+            balancer.rotate_every(
+                age,
+                redial.socket.clone(),
+                Some(certs.clone()),
+                connect.clone(),
+                connect_state.clone(),
+            );
+        }
 
-        let channel =
-            Self::connect_chan(system.socket.clone(), Some(tls_config)).await?;
-        Ok(Self { channel, client_cert_details })
+        let _tracker = Arc::new(ConnectionTracker::new(created_at, Some(slot)));
+        let client = Self {
+            balancer,
+            certs: Some(certs),
+            compression: connect.compression,
+            transport_mode: connect.transport_mode,
+            metadata: CallMetadata::default(),
+            interceptors: InterceptorChain::default(),
+            read_only: ReadOnly::new(
+                connect.read_only,
+                connect.mutating_methods.clone(),
+            ),
+            method_policy: Arc::new(connect.method_policy.clone()),
+            namespace,
+            deadline: None,
+            priority: Priority::default(),
+            retry: Arc::new(RetryBudget::new(connect.retry.clone())),
+            retry_predicate: None,
+            limiter: connect.max_concurrent_rpcs.map(|max| {
+                Arc::new(RpcLimiter::new(max.get(), connect.max_queued_rpcs))
+            }),
+            in_flight: Arc::default(),
+            rpc_log: connect
+                .rpc_log
+                .enabled
+                .then(|| Arc::new(connect.rpc_log.clone())),
+            connect_state,
+            redial: Some(redial),
+            identities: None,
+            credentials: None,
+            server_info: Arc::default(),
+            session: Arc::default(),
+            reconnects: Arc::default(),
+            _tracker,
+            _tunnel,
+            origin,
+            created_at,
+        };
+        client.warm_up(connect.warm_up).await;
+        if connect.hello {
+            client.send_hello(ClientInfo::new(&connect)).await;
+        }
+        let timeout =
+            connect.overall_timeout.unwrap_or(API_VERSION_CHECK_TIMEOUT);
+        client
+            .check_api_version(connect.allow_api_version_skew, timeout)
+            .await?;
+        Ok(client)
     }
 
     /// Create a new Client without TLS, remote server should also expect no TLS.
     ///
     /// Note: A new client is required for every independent execution of this process.
-    pub async fn new_no_tls(socket: AuraeSocket) -> Result<Self> {
-        let channel = Self::connect_chan(socket, None).await?;
-        let client_cert_details = None;
-        Ok(Self { channel, client_cert_details })
+    #[track_caller]
+    pub fn new_no_tls(
+        socket: AuraeSocket,
+    ) -> impl Future<Output = Result<Self>> {
+        Self::new_socket_at(
+            socket,
+            None,
+            Location::caller(),
+            ConnectState::default(),
+        )
+    }
+
+    /// Connect to `endpoint` with a rustls config built by the caller, e.g.
+    /// with a custom verifier, crypto provider or session storage, instead
+    /// of one assembled from cert files.
+    ///
+    /// The caller owns all TLS correctness here: `rustls_config` alone
+    /// decides which servers are trusted and which identity is presented,
+    /// and none of the checks of [`AuthConfig`] or [`ConnectOptions`] apply.
+    /// auraed only speaks HTTP/2, so the config should offer `h2` over ALPN.
+    /// The server is verified as the host of a URI `endpoint`, and as
+    /// `server.unsafe.aurae.io` for any other socket. `client_cert` is only
+    /// reported back through [`Client::client_cert_details`].
+    ///
+    /// The connection is rebuilt and reconnected with the same config, there
+    /// are no cert files to reload.
+    #[track_caller]
+    pub fn new_with_rustls(
+        rustls_config: Arc<ClientConfig>,
+        endpoint: AuraeSocket,
+        client_cert: Option<X509Details>,
+    ) -> impl Future<Output = Result<Self>> {
+        let created_at = Location::caller();
+        async move {
+            let host = match &endpoint {
+                AuraeSocket::Uri(uri) => uri.host(),
+                _ => None,
+            };
+            let server_name = host
+                .and_then(|host| ServerName::try_from(host).ok())
+                .unwrap_or_else(|| {
+                    ServerName::try_from(DEFAULT_SERVER_NAME)
+                        .expect("the default server name is a DNS name")
+                });
+            let tls = TlsConnect::from_rustls(
+                rustls_config,
+                server_name,
+                client_cert.as_ref(),
+            );
+            let certs = Arc::new(CertStore::fixed(tls, client_cert));
+            Self::new_socket_at(
+                endpoint,
+                Some(certs),
+                created_at,
+                ConnectState::default(),
+            )
+            .await
+        }
     }
 
-    async fn connect_chan(
+    /// Connect to `socket` without a config, over TLS only when `certs` is
+    /// set. Like connecting from a config, this fails on a daemon serving
+    /// another API version, see [`ConnectOptions::allow_api_version_skew`].
+    async fn new_socket_at(
         socket: AuraeSocket,
-        tls_config: Option<ClientTlsConfig>,
-    ) -> Result<Channel> {
-        let endpoint = Channel::from_static(KNOWN_IGNORED_SOCKET_ADDR);
-        let endpoint = match tls_config {
-            None => endpoint,
-            Some(tls_config) => endpoint.tls_config(tls_config)?,
-        };
-
-        // If the system socket looks like a SocketAddr, bind to it directly.  Otherwise,
-        // connect as a UNIX socket (assume it's a file path).
-        let channel = match socket {
-            AuraeSocket::Path(path) => {
-                endpoint
-                    .connect_with_connector(service_fn(move |_: Uri| {
-                        UnixStream::connect(path.clone())
-                    }))
+        certs: Option<Arc<CertStore>>,
+        created_at: &'static Location<'static>,
+        connect_state: ConnectState,
+    ) -> Result<Self> {
+        let origin = Arc::new(match certs {
+            Some(_) => Origin::Rustls(socket.clone()),
+            None => Origin::NoTls(socket.clone()),
+        });
+        let redial = Arc::new(Redial {
+            socket: socket.clone(),
+            options: ConnectOptions::default(),
+        });
+        let slot =
+            BudgetSlot::acquire(&redial.options, &connect_state.handover)
+                .await?;
+        let channel = Self::connect_chan(
+            socket,
+            certs.clone(),
+            &redial.options,
+            connect_state.clone(),
+        )
+        .await?;
+        let balancer = Arc::new(Balancer::single(channel));
+        let _tracker = Arc::new(ConnectionTracker::new(created_at, Some(slot)));
+        let _tunnel = None;
+        let allow_skew = redial.options.allow_api_version_skew;
+        let check_timeout =
+            redial.options.overall_timeout.unwrap_or(API_VERSION_CHECK_TIMEOUT);
+        let client = Self {
+            balancer,
+            certs,
+            compression: CompressionMode::None,
+            transport_mode: TransportMode::Grpc,
+            metadata: CallMetadata::default(),
+            interceptors: InterceptorChain::default(),
+            read_only: ReadOnly::default(),
+            method_policy: Arc::default(),
+            namespace: None,
+            deadline: None,
+            priority: Priority::default(),
+            retry: Arc::new(RetryBudget::new(RetryOptions::default())),
+            retry_predicate: None,
+            limiter: None,
+            in_flight: Arc::default(),
+            rpc_log: None,
+            connect_state,
+            redial: Some(redial),
+            identities: None,
+            credentials: None,
+            server_info: Arc::default(),
+            session: Arc::default(),
+            reconnects: Arc::default(),
+            _tracker,
+            _tunnel,
+            origin,
+            created_at,
+        };
+        client.check_api_version(allow_skew, check_timeout).await?;
+        Ok(client)
+    }
+
+    /// Wrap a channel the caller built themselves, e.g. over a custom
+    /// transport or an in-memory duplex in tests.
+    ///
+    /// No connection is made and no certs are read. The caller is
+    /// responsible for TLS and client identity on `channel`; `x509_details`
+    /// is only reported back through [`Client::client_cert_details`].
+    #[track_caller]
+    pub fn from_channel(
+        channel: Channel,
+        x509_details: Option<X509Details>,
+    ) -> Self {
+        let created_at = Location::caller();
+        Self {
+            balancer: Arc::new(Balancer::single(channel)),
+            certs: None,
+            compression: CompressionMode::None,
+            transport_mode: TransportMode::Grpc,
+            metadata: CallMetadata::default(),
+            interceptors: InterceptorChain::default(),
+            read_only: ReadOnly::default(),
+            method_policy: Arc::default(),
+            namespace: None,
+            deadline: None,
+            priority: Priority::default(),
+            retry: Arc::new(RetryBudget::new(RetryOptions::default())),
+            retry_predicate: None,
+            limiter: None,
+            in_flight: Arc::default(),
+            rpc_log: None,
+            connect_state: ConnectState::default(),
+            redial: None,
+            identities: None,
+            credentials: None,
+            server_info: Arc::default(),
+            session: Arc::default(),
+            reconnects: Arc::default(),
+            _tracker: Arc::new(ConnectionTracker::new(created_at, None)),
+            _tunnel: None,
+            origin: Arc::new(Origin::Channel(x509_details)),
+            created_at,
+        }
+    }
+
+    /// The transport the endpoint string `s` would be reached over, see
+    /// [`AuraeSocket::classify`] for the rules.
+    pub fn classify_endpoint(
+        s: &str,
+    ) -> std::result::Result<SocketKind, ParseSocketError> {
+        AuraeSocket::classify(s)
+    }
+
+    /// Subscribe to the connection lifecycle of this client and its clones.
+    ///
+    /// Only events after subscribing are received. A subscriber that falls
+    /// behind misses the oldest events (and is told so by a `Lagged` error)
+    /// rather than holding up the connection.
+    pub fn events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.connect_state.events.subscribe()
+    }
+
+    /// Publisher for [`Client::events`] subscribers.
+    pub(crate) fn event_publisher(&self) -> Events {
+        self.connect_state.events.clone()
+    }
+
+    /// The addresses of the most recent connection, `None` for clients from
+    /// [`Client::from_channel`]. When load balancing, this is whichever
+    /// replica was connected to last.
+    pub fn connect_info(&self) -> Option<ConnectInfo> {
+        self.connect_state.get()
+    }
+
+    /// Details of the client certificate this client authenticates with.
+    pub fn client_cert_details(&self) -> Option<X509Details> {
+        match (&self.certs, &*self.origin) {
+            (Some(certs), _) => certs.details(),
+            (None, Origin::Channel(details)) => details.clone(),
+            (None, _) => None,
+        }
+    }
+
+    /// What the TLS config of the next connection was built from: the
+    /// names the server is verified against, the trust anchors, the client
+    /// certificate chain, protocol versions and ALPN. Only names and counts,
+    /// never key material. `None` for clients connecting without TLS or
+    /// from [`Client::from_channel`].
+    pub fn tls_debug(&self) -> Option<TlsDebugInfo> {
+        let certs = self.certs.as_ref()?;
+        Some((*certs.tls().debug).clone())
+    }
+
+    /// Make sure this client has a usable connection.
+    ///
+    /// When the current connection answers a health check it is kept as is,
+    /// which costs a single unary RPC. Otherwise the connection is rebuilt
+    /// from the config the client was created with, re-reading the cert
+    /// files so rotated certs are picked up. Clones made before a rebuild
+    /// keep the old connection. The new connection takes over the
+    /// `AURAE_MAX_CONNECTIONS` slot of the old one rather than waiting for
+    /// another. Clients from [`Client::from_channel`] cannot be rebuilt, and
+    /// return an error instead.
+    pub async fn ensure_connected(&mut self) -> Result<()> {
+        match self.health_check().await {
+            Ok(()) => return Ok(()),
+            Err(e) => self.connect_state.record_error(&e),
+        }
+
+        let compression = self.compression;
+        let metadata = self.metadata.clone();
+        let read_only = self.read_only.clone();
+        let namespace = self.namespace.clone();
+        let deadline = self.deadline;
+        let interceptors = self.interceptors.clone();
+        let priority = self.priority;
+        // keep the event channel, so subscribers see the rebuild
+        let state = self.connect_state.clone();
+        // and the budget slot, so the rebuild does not wait for a second one
+        state.handover.give(self._tracker.take_slot());
+        let reconnecting = ConnectionEvent::Reconnecting { attempt: 1 };
+        let rebuilt = match &*self.origin {
+            Origin::Config(config) => {
+                state.publish(reconnecting);
+                Self::new_at(config.clone(), self.created_at, state.clone())
                     .await
             }
-            AuraeSocket::Addr(addr) => {
-                endpoint
-                    .connect_with_connector(service_fn(move |_: Uri| {
-                        TcpStream::connect(addr)
+            Origin::Minimal(config) => {
+                state.publish(reconnecting);
+                Self::connect_minimal(
+                    config.clone(),
+                    self.created_at,
+                    state.clone(),
+                )
+                .await
+            }
+            Origin::NoTls(socket) => {
+                state.publish(reconnecting);
+                Self::new_socket_at(
+                    socket.clone(),
+                    None,
+                    self.created_at,
+                    state.clone(),
+                )
+                .await
+            }
+            Origin::Rustls(socket) => {
+                state.publish(reconnecting);
+                Self::new_socket_at(
+                    socket.clone(),
+                    self.certs.clone(),
+                    self.created_at,
+                    state.clone(),
+                )
+                .await
+            }
+            Origin::Channel(_) => {
+                return Err(ClientError::Other(anyhow::anyhow!(
+                    "client wraps a caller-provided channel, which cannot be rebuilt"
+                )))
+            }
+        };
+        let rebuilt = rebuilt.map_err(|e| {
+            self._tracker.restore_slot(state.handover.take());
+            state.failed(&e);
+            e
+        })?;
+
+        let identities = self.identities.clone();
+        let credentials = self.credentials.clone();
+        let retry_predicate = self.retry_predicate.clone();
+        *self = Self {
+            compression,
+            metadata,
+            interceptors,
+            read_only,
+            namespace,
+            deadline,
+            priority,
+            identities,
+            credentials,
+            retry_predicate,
+            ..rebuilt
+        };
+        Ok(())
+    }
+
+    /// Move this client to `config`, e.g. to follow auraed from one endpoint
+    /// to another during a blue/green migration.
+    ///
+    /// A complete new client (connection, TLS config and cert details) is
+    /// built from `config` and has to answer a health check before it
+    /// replaces this one. On any failure this client is left as it was.
+    /// Only the metadata added with [`Client::with_metadata`] carries over,
+    /// everything else comes from `config`. Clones made before the switch
+    /// keep the old connection. Subscribers of [`Client::events`] keep
+    /// receiving events, starting with [`ConnectionEvent::ConfigSwitched`].
+    pub async fn switch_config(&mut self, config: AuraeConfig) -> Result<()> {
+        let state = self.connect_state.sharing_events();
+        state.handover.give(self._tracker.take_slot());
+        let switched = Self::new_at(config, self.created_at, state.clone())
+            .await
+            .map_err(|e| {
+                self._tracker.restore_slot(state.handover.take());
+                e
+            })?;
+        if let Err(e) = switched.health_check().await {
+            self._tracker.restore_slot(switched._tracker.take_slot());
+            return Err(ClientError::Other(anyhow::anyhow!(
+                "new config is not usable: {e}"
+            )));
+        }
+
+        let metadata = self.metadata.clone();
+        let identities =
+            self.identities.as_ref().map(|pool| Arc::new(pool.fresh()));
+        *self = Self { metadata, identities, ..switched };
+        state.publish(ConnectionEvent::ConfigSwitched);
+        Ok(())
+    }
+
+    /// Replace the connections of this client and its clones right away,
+    /// e.g. after rotating the certs or restarting auraed, instead of
+    /// waiting for them to fail. The cert files are read again first, and
+    /// a failure to read them fails the call without touching the
+    /// connections. Connections that cannot be replaced are kept, the call
+    /// fails only when none could be. Publishes
+    /// [`ConnectionEvent::Reconnected`] on success.
+    ///
+    /// Calls made while a reconnect is running wait for it and share its
+    /// outcome, rather than reconnecting once more. Clients from
+    /// [`Client::from_channel`] cannot reconnect, and return an error.
+    pub async fn reconnect_now(&self) -> Result<()> {
+        self.reconnects
+            .run(|| async {
+                if self.cert_store().is_some_and(|certs| certs.has_files()) {
+                    self.reload_certs().await?;
+                }
+                self.reconnect().await?;
+                self.connect_state.publish(ConnectionEvent::Reconnected);
+                Ok(())
+            })
+            .await
+    }
+
+    /// Replace the channels shared by this client and its clones with fresh
+    /// connections, for connections that stopped answering without closing.
+    pub(crate) async fn reconnect(&self) -> Result<()> {
+        let redial = self.redial.as_ref().ok_or_else(|| {
+            ClientError::Other(anyhow::anyhow!(
+                "client wraps a caller-provided channel, which cannot be reconnected"
+            ))
+        })?;
+        self.balancer
+            .reconnect_all(
+                &redial.socket,
+                &self.certs,
+                &redial.options,
+                &self.connect_state,
+            )
+            .await
+    }
+
+    /// Prime the connection with `rpcs` concurrent health checks, see
+    /// [`ConnectOptions::warm_up`].
+    async fn warm_up(&self, rpcs: usize) {
+        if rpcs == 0 {
+            return;
+        }
+
+        let span = debug_span!("warm_up", rpcs, duration = field::Empty);
+        let started = Instant::now();
+        let checks = (0..rpcs).map(|_| self.health_check());
+        let failed = join_all(checks)
+            .instrument(span.clone())
+            .await
+            .into_iter()
+            .filter(Result::is_err)
+            .count();
+
+        let elapsed = started.elapsed();
+        let _ = span.record("duration", field::debug(elapsed));
+        self.connect_state.set_warm_up(elapsed);
+        span.in_scope(|| debug!(failed, "connection warmed up"));
+    }
+
+    /// Whether auraed answers on the current connection. A server without
+    /// the health service still proves the connection works.
+    pub(crate) async fn health_check(&self) -> std::result::Result<(), String> {
+        let req = HealthCheckRequest { service: String::new() };
+        match tokio::time::timeout(
+            ENSURE_CONNECTED_CHECK_TIMEOUT,
+            self.check(req),
+        )
+        .await
+        {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(status)) if status.code() == Code::Unimplemented => Ok(()),
+            Ok(Err(status)) => {
+                Err(format!("health check failed: {}", status.message()))
+            }
+            Err(_) => Err(format!(
+                "health check timed out after {ENSURE_CONNECTED_CHECK_TIMEOUT:?}"
+            )),
+        }
+    }
+
+    /// A handle to the same connection that compresses its RPCs with
+    /// `compression` instead of the configured mode, when auraed accepted
+    /// it in answer to the hello.
+    pub fn with_compression(&self, compression: CompressionMode) -> Self {
+        Self { compression, ..self.clone() }
+    }
+
+    /// A handle to the same connection that, when `read_only` is set,
+    /// rejects mutating RPCs locally instead of sending them, see
+    /// [`ConnectOptions::read_only`].
+    pub fn with_read_only(&self, read_only: bool) -> Self {
+        let read_only =
+            ReadOnly { enabled: read_only, ..self.read_only.clone() };
+        Self { read_only, ..self.clone() }
+    }
+
+    /// A handle to the same connection whose RPCs must finish by `at`, e.g.
+    /// the deadline of the request being served.
+    ///
+    /// Each RPC sends the time left as its `grpc-timeout`, so auraed can
+    /// give up as well, and fails with `DEADLINE_EXCEEDED` once `at` passes.
+    /// For streaming RPCs this bounds getting the response, not reading the
+    /// stream. Once `at` has passed, RPCs fail without being sent, with the
+    /// message of [`ClientError::DeadlineExceeded`].
+    pub fn with_deadline_instant(&self, at: Instant) -> Self {
+        Self { deadline: Some(at), ..self.clone() }
+    }
+
+    /// A handle to the same connection whose RPCs have `priority` when
+    /// `connect.max_concurrent_rpcs` is saturated, see [`Priority`] for
+    /// which calls are shed. Without the limit nothing is shed.
+    pub fn with_priority(&self, priority: Priority) -> Self {
+        Self { priority, ..self.clone() }
+    }
+
+    /// The RPCs in flight on this client and its clones right now, counted
+    /// from when they are issued, including time spent queued for
+    /// `connect.max_concurrent_rpcs`, until their response or stream is
+    /// dropped. Clients rebuilt by [`Client::ensure_connected`] or
+    /// [`Client::switch_config`] start counting anew.
+    pub fn in_flight(&self) -> InFlightSnapshot {
+        self.in_flight.snapshot()
+    }
+
+    /// A handle to the same connection that sends `pairs` as extra metadata
+    /// with each of its RPCs, on top of any this handle already sends.
+    ///
+    /// Fails if a name is not a valid ASCII header name, or a value contains
+    /// anything other than visible ASCII.
+    pub fn with_metadata(&self, pairs: Vec<(String, String)>) -> Result<Self> {
+        let metadata = self.metadata.extend(pairs)?;
+        Ok(Self { metadata, ..self.clone() })
+    }
+
+    /// A handle to the same connection that runs `chain` on each of its
+    /// RPCs, replacing any chain this handle had.
+    pub fn with_interceptors(&self, chain: InterceptorChain) -> Self {
+        Self { interceptors: chain, ..self.clone() }
+    }
+
+    /// This client and its clones, able to act as any identity in
+    /// `identities`, see [`Client::as_identity`]. Replaces any identities
+    /// set before.
+    pub fn with_identities(&self, identities: IdentitySet) -> Self {
+        let identities = Some(Arc::new(IdentityPool::new(identities)));
+        Self { identities, ..self.clone() }
+    }
+
+    /// A handle to the same connection that scopes its RPCs to
+    /// `namespace` instead of `connect.default_namespace`. Metadata added
+    /// with [`Client::with_metadata`] under `x-aurae-namespace` still takes
+    /// precedence.
+    ///
+    /// Fails if `namespace` is empty or not a valid header value.
+    pub fn with_namespace(&self, namespace: &str) -> Result<Self> {
+        let namespace = Some(Namespace::new(namespace)?);
+        Ok(Self { namespace, ..self.clone() })
+    }
+
+    /// A client with the options this one was created with, changed by
+    /// `f`, e.g. for a second client to the same auraed with another
+    /// timeout or namespace.
+    ///
+    /// When `f` only changes options that apply per RPC (`compression`,
+    /// `transport_mode`, `read_only`, `mutating_methods`, `method_policy`,
+    /// `default_namespace` and `rpc_log`), the new client shares the
+    /// connection, retry budget and RPC limit of this one.
+    /// Any other change connects anew from the config with the changed
+    /// options, re-reading the certs, which clients from
+    /// [`Client::new_no_tls`] and [`Client::from_channel`] cannot do.
+    ///
+    /// Either way the metadata, interceptors and deadline of this handle
+    /// carry over, while the settings above come from the changed options,
+    /// replacing e.g. a namespace set with [`Client::with_namespace`].
+    pub async fn clone_with<F: FnOnce(&mut ConnectOptions)>(
+        &self,
+        f: F,
+    ) -> Result<Self> {
+        let config = match &*self.origin {
+            Origin::Config(config) | Origin::Minimal(config) => Some(config),
+            Origin::NoTls(_) | Origin::Rustls(_) | Origin::Channel(_) => None,
+        };
+        let current = match (config, &self.redial) {
+            (Some(config), _) => config.connect.clone(),
+            (None, Some(redial)) => redial.options.clone(),
+            (None, None) => ConnectOptions::default(),
+        };
+        let mut options = current.clone();
+        f(&mut options);
+
+        if per_rpc_changes_only(&current, &options) {
+            let origin = match &*self.origin {
+                Origin::Config(config) => {
+                    Arc::new(Origin::Config(AuraeConfig {
+                        connect: options.clone(),
+                        ..config.clone()
                     }))
-                    .await
+                }
+                Origin::Minimal(config) => {
+                    Arc::new(Origin::Minimal(AuraeConfig {
+                        connect: options.clone(),
+                        ..config.clone()
+                    }))
+                }
+                Origin::NoTls(_) | Origin::Rustls(_) | Origin::Channel(_) => {
+                    self.origin.clone()
+                }
+            };
+            return Ok(Self {
+                compression: options.compression,
+                transport_mode: options.transport_mode,
+                read_only: ReadOnly::new(
+                    options.read_only,
+                    options.mutating_methods.clone(),
+                ),
+                method_policy: Arc::new(options.method_policy.clone()),
+                namespace: options
+                    .default_namespace
+                    .as_deref()
+                    .map(Namespace::new)
+                    .transpose()?,
+                rpc_log: options
+                    .rpc_log
+                    .enabled
+                    .then(|| Arc::new(options.rpc_log.clone())),
+                origin,
+                ..self.clone()
+            });
+        }
+
+        let Some(config) = config else {
+            return Err(ClientError::Other(anyhow::anyhow!(
+                "client was not created from a config, only options applied per RPC can change"
+            )));
+        };
+        let config = AuraeConfig { connect: options, ..config.clone() };
+        let rebuilt = self.connect_like(config).await?;
+        Ok(Self {
+            metadata: self.metadata.clone(),
+            interceptors: self.interceptors.clone(),
+            deadline: self.deadline,
+            priority: self.priority,
+            retry_predicate: self.retry_predicate.clone(),
+            credentials: self.credentials.clone(),
+            ..rebuilt
+        })
+    }
+
+    /// The namespace RPCs made through this handle are scoped to, if any.
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_ref().map(Namespace::as_str)
+    }
+
+    /// Wrap `message` with this handle's metadata and run its interceptors.
+    /// Used by the generated service clients.
+    pub(crate) fn request<T>(
+        &self,
+        message: T,
+    ) -> std::result::Result<tonic::Request<T>, tonic::Status> {
+        let mut req = self.metadata.request(message);
+        if let Some(namespace) = &self.namespace {
+            namespace.apply(&mut req);
+        }
+        if let Some(deadline) = self.deadline {
+            req.set_timeout(deadline.saturating_duration_since(Instant::now()));
+        }
+        if let Some(credentials) = &self.credentials {
+            credentials.apply(&mut req);
+        }
+        let _ = req.extensions_mut().insert(self.priority);
+        self.interceptors.apply(req)
+    }
+
+    /// The channel for the next RPC. Used by the generated service clients.
+    pub(crate) fn channel(&self) -> RpcChannel {
+        self.rpc_channel(self.balancer.pick())
+    }
+
+    /// `channel`, from the balancer, with the layers every RPC of this
+    /// handle goes through.
+    pub(crate) fn rpc_channel(&self, channel: Channel) -> RpcChannel {
+        Logged::new(
+            Limited::new(
+                Transport::new(channel, self.transport_mode),
+                self.limiter.clone(),
+                self.in_flight.clone(),
+            ),
+            self.rpc_log.clone(),
+        )
+    }
+
+    pub(crate) fn balancer(&self) -> &Balancer {
+        &self.balancer
+    }
+
+    pub(crate) fn server_info_cell(&self) -> &OnceCell<ServerInfo> {
+        &self.server_info
+    }
+
+    pub(crate) fn session(&self) -> &OnceCell<ServerCapabilities> {
+        &self.session
+    }
+
+    pub(crate) fn identities(&self) -> Option<&IdentityPool> {
+        self.identities.as_deref()
+    }
+
+    /// A new client from the config this one was created with, but
+    /// authenticating as `auth`.
+    pub(crate) async fn connect_as(&self, auth: AuthConfig) -> Result<Self> {
+        let config = match &*self.origin {
+            Origin::Config(config) | Origin::Minimal(config) => config,
+            Origin::NoTls(_) | Origin::Rustls(_) | Origin::Channel(_) => {
+                return Err(ClientError::Other(anyhow::anyhow!(
+                    "client was not created from a config with TLS, it has no identity to switch"
+                )))
+            }
+        };
+        let config = AuraeConfig { auth, ..config.clone() };
+        self.connect_like(config).await
+    }
+
+    /// A new client connected with `config` the way this one was, through
+    /// [`Client::new_minimal`] or [`Client::new`].
+    async fn connect_like(&self, config: AuraeConfig) -> Result<Self> {
+        let state = ConnectState::default();
+        match &*self.origin {
+            Origin::Minimal(_) => {
+                Self::connect_minimal(config, self.created_at, state).await
+            }
+            _ => Self::new_at(config, self.created_at, state).await,
+        }
+    }
+
+    /// `client`, with the metadata, interceptors, credentials, read only
+    /// mode, method policy, namespace, deadline, priority and retry
+    /// predicate of this handle.
+    pub(crate) fn with_settings_on(&self, client: Self) -> Self {
+        Self {
+            metadata: self.metadata.clone(),
+            interceptors: self.interceptors.clone(),
+            credentials: self.credentials.clone(),
+            read_only: self.read_only.clone(),
+            method_policy: self.method_policy.clone(),
+            namespace: self.namespace.clone(),
+            deadline: self.deadline,
+            priority: self.priority,
+            retry_predicate: self.retry_predicate.clone(),
+            ..client
+        }
+    }
+
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub(crate) fn read_only(&self) -> &ReadOnly {
+        &self.read_only
+    }
+
+    pub(crate) fn method_policy(&self) -> &MethodPolicy {
+        &self.method_policy
+    }
+
+    pub(crate) fn retry_budget(&self) -> &RetryBudget {
+        &self.retry
+    }
+
+    pub(crate) fn retry_predicate(&self) -> Option<&RetryPredicate> {
+        self.retry_predicate.as_ref()
+    }
+
+    pub(crate) fn with_retry_predicate_set(
+        &self,
+        retry_predicate: Option<RetryPredicate>,
+    ) -> Self {
+        Self { retry_predicate, ..self.clone() }
+    }
+
+    pub(crate) fn with_credentials(
+        &self,
+        credentials: Arc<Credentials>,
+    ) -> Self {
+        Self { credentials: Some(credentials), ..self.clone() }
+    }
+
+    pub(crate) fn credentials(&self) -> Option<&Arc<Credentials>> {
+        self.credentials.as_ref()
+    }
+
+    pub(crate) fn cert_store(&self) -> Option<Arc<CertStore>> {
+        self.certs.clone()
+    }
+
+    /// The socket this client was created for, `None` for clients from
+    /// [`Client::from_channel`].
+    pub(crate) fn socket(&self) -> Option<&AuraeSocket> {
+        match &*self.origin {
+            Origin::Config(config) | Origin::Minimal(config) => {
+                Some(&config.system.socket)
+            }
+            Origin::NoTls(socket) | Origin::Rustls(socket) => Some(socket),
+            Origin::Channel(_) => None,
+        }
+    }
+
+    pub(crate) fn connect_state(&self) -> &ConnectState {
+        &self.connect_state
+    }
+
+    /// The encoding the generated service clients compress with: that of
+    /// `compression`, if auraed accepted it in answer to the hello.
+    pub(crate) fn compression_encoding(&self) -> Option<CompressionEncoding> {
+        let name = self.compression.name()?;
+        let capabilities = self.server_capabilities()?;
+        match capabilities.accepts_compression(name) {
+            true => self.compression.encoding(),
+            false => None,
+        }
+    }
+
+    /// Connect a channel over `socket`, running the TLS handshake ourselves
+    /// when `certs` is set so each phase can be timed out on its own. The
+    /// identity is read from `certs` on every (re)connect, so reloaded certs
+    /// apply to new connections.
+    pub(crate) async fn connect_chan(
+        socket: AuraeSocket,
+        certs: Option<Arc<CertStore>>,
+        options: &ConnectOptions,
+        connect_state: ConnectState,
+    ) -> Result<Channel> {
+        let connect = Channel::from_static(KNOWN_IGNORED_SOCKET_ADDR)
+            .connect_with_connector(Self::connector(
+                socket,
+                certs,
+                options,
+                connect_state,
+            ));
+
+        let res = match options.overall_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connect)
+                .await
+                .map_err(|_| ClientError::ConnectTimeout {
+                    phase: ConnectPhase::Overall,
+                    timeout,
+                })?,
+            None => connect.await,
+        };
+
+        res.map_err(|e| {
+            if let Some(timeout) = connector::find_cause::<PhaseTimeout>(&e) {
+                ClientError::ConnectTimeout {
+                    phase: timeout.phase,
+                    timeout: timeout.timeout,
+                }
+            } else if let Some(timeout) =
+                connector::find_cause::<DnsTimeout>(&e)
+            {
+                ClientError::DnsTimeout {
+                    host: timeout.host.clone(),
+                    timeout: timeout.timeout,
+                }
+            } else if let Some(mismatch) =
+                connector::find_cause::<IdentityMismatch>(&e)
+            {
+                ClientError::ServerIdentityMismatch {
+                    expected: mismatch.expected.clone(),
+                    found: mismatch.found.clone(),
+                }
+            } else if let Some(mismatch) =
+                connector::find_cause::<TrustDomainMismatch>(&e)
+            {
+                ClientError::TrustDomainMismatch {
+                    expected: mismatch.expected.clone(),
+                    found: mismatch.found.clone(),
+                }
+            } else if let Some(missing) =
+                connector::find_cause::<MissingIpSan>(&e)
+            {
+                ClientError::MissingIpSan { ip: missing.ip }
+            } else if let Some(too_deep) =
+                connector::find_cause::<ChainTooDeep>(&e)
+            {
+                ClientError::ChainTooDeep {
+                    depth: too_deep.depth,
+                    max: too_deep.max,
+                }
+            } else if let Some(untrusted) =
+                connector::find_cause::<UntrustedChain>(&e)
+            {
+                ClientError::TlsVerification {
+                    reason: tls::reason(&untrusted.invalid),
+                    chain: untrusted.chain.clone(),
+                }
+            } else {
+                ClientError::ConnectionError(e)
+            }
+        })
+    }
+
+    /// As [`Client::connect_chan`], but only connecting on the first RPC.
+    pub(crate) fn connect_chan_lazy(
+        socket: AuraeSocket,
+        certs: Option<Arc<CertStore>>,
+        options: &ConnectOptions,
+        connect_state: ConnectState,
+    ) -> Channel {
+        Channel::from_static(KNOWN_IGNORED_SOCKET_ADDR)
+            .connect_with_connector_lazy(Self::connector(
+                socket,
+                certs,
+                options,
+                connect_state,
+            ))
+    }
+
+    /// A channel whose every connect fails with the message of `error`.
+    fn failing_chan(error: &ClientError) -> Channel {
+        let message = error.to_string();
+        let connector: DialService = service_fn(Box::new(move |_: Uri| {
+            let error = std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                message.clone(),
+            );
+            let dial: Dial = Box::pin(async move { Err(error) });
+            dial
+        }));
+        Channel::from_static(KNOWN_IGNORED_SOCKET_ADDR)
+            .connect_with_connector_lazy(connector)
+    }
+
+    fn connector(
+        socket: AuraeSocket,
+        certs: Option<Arc<CertStore>>,
+        options: &ConnectOptions,
+        connect_state: ConnectState,
+    ) -> DialService {
+        let timeouts = PhaseTimeouts::from(options);
+        let tcp = TcpOptions::from(options);
+        let deadline = options.reconnect_deadline;
+
+        // tonic calls the connector again whenever the connection drops.
+        // Zero until the first connect, then the attempts since the last
+        // successful one.
+        let attempts = Arc::new(AtomicU32::new(0));
+        // When the current run of failed reconnects began.
+        let failing_since = Arc::new(Mutex::new(None::<Instant>));
+        // Set once `deadline` passed, for good: a new channel, as
+        // `reconnect_now` builds, starts over.
+        let gave_up = Arc::new(AtomicBool::new(false));
+
+        service_fn(Box::new(move |_: Uri| {
+            if gave_up.load(Ordering::Relaxed) {
+                let error = std::io::Error::new(
+                    std::io::ErrorKind::NotConnected,
+                    "gave up reconnecting after connect.reconnect_deadline, call reconnect_now to try again",
+                );
+                let dial: Dial = Box::pin(async move { Err(error) });
+                return dial;
+            }
+
+            let attempt = attempts.fetch_add(1, Ordering::Relaxed);
+            if attempt > 0 {
+                connect_state
+                    .publish(ConnectionEvent::Reconnecting { attempt });
             }
-        }?;
 
-        Ok(channel)
+            let tls = certs.as_ref().map(|certs| certs.tls());
+            let connect = connector::connect(
+                socket.clone(),
+                tls,
+                timeouts,
+                tcp,
+                connect_state.clone(),
+            );
+            let attempts = attempts.clone();
+            let failing_since = failing_since.clone();
+            let gave_up = gave_up.clone();
+            let connect_state = connect_state.clone();
+            let dial: Dial = Box::pin(async move {
+                let stream = connect.await.map_err(|e| {
+                    if attempt == 0 {
+                        connect_state.failed(&e);
+                        return e;
+                    }
+                    let since = *failing_since
+                        .lock()
+                        .expect("reconnect lock poisoned")
+                        .get_or_insert_with(Instant::now);
+                    match deadline {
+                        Some(deadline) if since.elapsed() >= deadline => {
+                            gave_up.store(true, Ordering::Relaxed);
+                            connect_state.failed(&e);
+                            connect_state.publish(ConnectionEvent::GaveUp);
+                        }
+                        _ => connect_state.record_error(&e),
+                    }
+                    e
+                })?;
+                attempts.store(1, Ordering::Relaxed);
+                *failing_since.lock().expect("reconnect lock poisoned") = None;
+                Ok(stream)
+            });
+            dial
+        }))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> Client {
+        Client::from_channel(
+            Channel::from_static("http://[::]:50051").connect_lazy(),
+            None,
+        )
+    }
+
+    #[tokio::test]
+    async fn per_rpc_changes_share_the_connection() {
+        let client = client();
+
+        let derived = client
+            .clone_with(|options| {
+                options.compression = CompressionMode::Gzip;
+                options.default_namespace = Some("staging".into());
+            })
+            .await
+            .unwrap();
+
+        assert!(Arc::ptr_eq(&derived.balancer, &client.balancer));
+        assert_eq!(derived.compression, CompressionMode::Gzip);
+        assert_eq!(derived.namespace(), Some("staging"));
+        assert_eq!(client.namespace(), None);
+    }
+
+    #[tokio::test]
+    async fn connection_changes_need_a_config() {
+        let err = client()
+            .clone_with(|options| options.server_name = Some("other".into()))
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("not created from a config"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn concurrent_reconnects_are_coalesced() {
+        let gate = ReconnectGate::default();
+        let runs = AtomicU32::new(0);
+        let reconnect = || async {
+            let _ = runs.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Err(ClientError::Other(anyhow::anyhow!("auraed is down")))
+        };
+
+        let (first, second) =
+            tokio::join!(gate.run(reconnect), gate.run(reconnect));
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(first.unwrap_err().to_string(), "auraed is down");
+        assert_eq!(second.unwrap_err().to_string(), "auraed is down");
+
+        // a later call reconnects again
+        assert!(gate.run(reconnect).await.is_err());
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn channel_clients_cannot_reconnect() {
+        let err = client().reconnect_now().await.unwrap_err();
+
+        assert!(err.to_string().contains("cannot be reconnected"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn unreachable_daemon_still_gives_a_client() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let config = AuraeConfig::parse_from_toml(&format!(
+            r#"
+[auth]
+ca_crt = "{0}/ca.crt"
+client_crt = "{0}/client.crt"
+client_key = "{0}/client.key"
+
+[system]
+socket = "{0}/aurae.sock"
+"#,
+            dir.display()
+        ))
+        .unwrap();
+
+        assert!(Client::new(config.clone()).await.is_err());
+
+        let client = Client::new_allow_disconnected(config).await.unwrap();
+        let state = client.state();
+        assert_eq!(state.connection, crate::ConnectionState::Failed);
+        assert!(state.last_error.is_some());
+        assert!(client.health_check().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn compression_needs_a_hello() {
+        let mut config = AuraeConfig::from_options(
+            "ca.crt",
+            "client.crt",
+            "client.key",
+            "/nonexistent/aurae.sock",
+        );
+        config.connect.compression = CompressionMode::Gzip;
+
+        let err = Client::new(config).await.unwrap_err();
+
+        assert!(err.to_string().contains("connect.hello"), "{err}");
+    }
+
+    /// Fails the first health check, as a connection that stopped
+    /// answering would, and answers the others.
+    struct FailsOnce(AtomicU32);
+
+    #[tonic::async_trait]
+    impl proto::grpc::health::health_server::Health for FailsOnce {
+        async fn check(
+            &self,
+            _request: tonic::Request<HealthCheckRequest>,
+        ) -> std::result::Result<
+            tonic::Response<proto::grpc::health::HealthCheckResponse>,
+            tonic::Status,
+        > {
+            match self.0.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(tonic::Status::unavailable("restarting")),
+                _ => Ok(tonic::Response::new(Default::default())),
+            }
+        }
+
+        type WatchStream = futures_util::stream::Empty<
+            std::result::Result<
+                proto::grpc::health::HealthCheckResponse,
+                tonic::Status,
+            >,
+        >;
+
+        async fn watch(
+            &self,
+            _request: tonic::Request<HealthCheckRequest>,
+        ) -> std::result::Result<
+            tonic::Response<Self::WatchStream>,
+            tonic::Status,
+        > {
+            Err(tonic::Status::unimplemented("watch"))
+        }
+    }
+
+    /// A client without TLS to a health service on a fresh unix socket
+    /// that fails its first check.
+    async fn rebuilt_on_first_check(dir: &std::path::Path) -> Client {
+        let path = dir.join("aurae.sock");
+        let router = tonic::transport::Server::builder().add_service(
+            proto::grpc::health::health_server::HealthServer::new(FailsOnce(
+                AtomicU32::new(0),
+            )),
+        );
+        crate::testing::serve_at(router, &path);
+        Client::new_no_tls(AuraeSocket::Path(path)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn clients_without_a_config_check_the_api_version() {
+        use proto::discovery::discovery_service_server::DiscoveryServiceServer;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aurae.sock");
+        let discovery = crate::testing::Discovery::answering(
+            proto::discovery::DiscoverResponse {
+                api_version: "v1".into(),
+                ..Default::default()
+            },
+        );
+        crate::testing::serve_at(
+            tonic::transport::Server::builder()
+                .add_service(DiscoveryServiceServer::new(discovery)),
+            &path,
+        );
+
+        let err =
+            Client::new_no_tls(AuraeSocket::Path(path)).await.unwrap_err();
+
+        assert!(
+            matches!(err, ClientError::VersionIncompatible { .. }),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn minimal_clients_only_connect() {
+        use crate::testing::{client_material, serve_health_at, test_ca};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let ca = test_ca("test ca");
+        let material = client_material(&ca, "bench");
+        let file = |name: &str, contents: &[u8]| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        let config = AuraeConfig::from_options(
+            file("ca.crt", &material.server_root_ca_cert),
+            file("client.crt", &material.client_cert),
+            file("client.key", &material.client_key),
+            dir.join("aurae.sock").to_string_lossy(),
+        );
+        serve_health_at(&ca, &dir.join("aurae.sock"));
+
+        let client = Client::new_minimal(config.clone()).await.unwrap();
+        client.health_check().await.unwrap();
+        assert!(client.client_cert_details().is_none());
+        assert!(client._tracker.take_slot().is_none());
+
+        let full = Client::new(config).await.unwrap();
+        assert!(full._tracker.take_slot().is_some());
+    }
+
+    #[tokio::test]
+    async fn rebuilds_keep_the_interceptors() {
+        let dir = tempfile::tempdir().unwrap();
+        let seen = Arc::new(AtomicU32::new(0));
+        let chain =
+            InterceptorChain::new().with(crate::InterceptorStage::Logging, {
+                let seen = seen.clone();
+                move |req| {
+                    let _ = seen.fetch_add(1, Ordering::SeqCst);
+                    Ok(req)
+                }
+            });
+        let mut client =
+            rebuilt_on_first_check(dir.path()).await.with_interceptors(chain);
+
+        client.ensure_connected().await.unwrap();
+        let before = seen.load(Ordering::SeqCst);
+        client.health_check().await.unwrap();
+
+        assert_eq!(seen.load(Ordering::SeqCst), before + 1);
+    }
+
+    #[tokio::test]
+    async fn rebuilds_keep_the_priority() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = rebuilt_on_first_check(dir.path())
+            .await
+            .with_priority(Priority::High);
+
+        client.ensure_connected().await.unwrap();
+
+        assert_eq!(client.priority, Priority::High);
+    }
+
+    #[tokio::test]
+    async fn reconnects_give_up_after_the_deadline() {
+        use tower::{Service, ServiceExt};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("aurae.sock");
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let options = ConnectOptions {
+            reconnect_deadline: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let state = ConnectState::default();
+        let mut events = state.events.subscribe();
+        let mut connector = Client::connector(
+            AuraeSocket::Path(path.clone()),
+            None,
+            &options,
+            state.clone(),
+        );
+        let uri = Uri::from_static(KNOWN_IGNORED_SOCKET_ADDR);
+
+        let stream = connector.ready().await.unwrap().call(uri.clone()).await;
+        assert!(stream.is_ok());
+
+        // the daemon goes away and never comes back
+        drop(listener);
+        std::fs::remove_file(&path).unwrap();
+        assert!(connector
+            .ready()
+            .await
+            .unwrap()
+            .call(uri.clone())
+            .await
+            .is_err());
+        assert_eq!(state.health().0, crate::ConnectionState::Failed);
+
+        let err =
+            connector.ready().await.unwrap().call(uri).await.err().unwrap();
+        assert!(err.to_string().contains("gave up reconnecting"), "{err}");
+        assert_eq!(state.health().0, crate::ConnectionState::Failed);
+
+        let mut seen = vec![];
+        while let Ok(event) = events.try_recv() {
+            seen.push(event);
+        }
+        assert_eq!(
+            seen,
+            [
+                ConnectionEvent::Connected,
+                ConnectionEvent::Reconnecting { attempt: 1 },
+                ConnectionEvent::GaveUp,
+            ]
+        );
     }
 }
\ No newline at end of file
@@ -0,0 +1,83 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! The time source of the retry machinery, so tests can drive backoff
+//! schedules without waiting them out.
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+pub(crate) type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Where retries read the time and wait. [`TokioClock`] outside of tests.
+pub(crate) trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+/// `tokio::time`, which follows `tokio::time::pause` and `advance`.
+#[derive(Debug, Default)]
+pub(crate) struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// A clock whose sleeps return right away, moving its time forward and
+/// recording how long they were asked to last.
+#[cfg(test)]
+#[derive(Debug)]
+pub(crate) struct MockClock {
+    state: std::sync::Mutex<(Instant, Vec<Duration>)>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub(crate) fn new() -> Self {
+        Self { state: std::sync::Mutex::new((Instant::now(), vec![])) }
+    }
+
+    /// Every sleep so far, in order.
+    pub(crate) fn sleeps(&self) -> Vec<Duration> {
+        self.state.lock().unwrap().1.clone()
+    }
+
+    pub(crate) fn advance(&self, duration: Duration) {
+        self.state.lock().unwrap().0 += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.state.lock().unwrap().0
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let mut state = self.state.lock().unwrap();
+        state.0 += duration;
+        state.1.push(duration);
+        Box::pin(async {})
+    }
+}
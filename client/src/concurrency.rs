@@ -0,0 +1,442 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Client side limit on concurrent RPCs, so that bursts queue up (or fail)
+//! here instead of running into the server's `max_concurrent_streams`.
+//! Under that limit, calls of a [`Priority`] below normal are shed first.
+//! Every call is tracked while in flight, see [`InFlightSnapshot`].
+
+use crate::client::ClientError;
+use crate::grpc_web::Transport;
+use crate::rpc_log::Logged;
+use std::collections::{BTreeMap, HashMap};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::body::BoxBody;
+use tonic::codegen::{http, Body, BoxFuture, StdError};
+use tonic::metadata::MetadataValue;
+use tonic::{Code, Status};
+use tower::{Service, ServiceExt};
+
+/// Metadata key on the `RESOURCE_EXHAUSTED` status of calls shed by the
+/// client, see [`is_load_shed`].
+const LOAD_SHED_KEY: &str = "x-aurae-load-shed";
+
+/// Whether `status` fails a [`Priority::Low`] call the client shed itself,
+/// with the message of [`ClientError::LoadShed`], rather than a
+/// `RESOURCE_EXHAUSTED` returned by auraed.
+pub fn is_load_shed(status: &Status) -> bool {
+    status.code() == Code::ResourceExhausted
+        && status.metadata().contains_key(LOAD_SHED_KEY)
+}
+
+/// What the generated service clients issue RPCs over.
+pub(crate) type RpcChannel = Logged<Limited<Transport>>;
+
+/// How a call fares once `connect.max_concurrent_rpcs` RPCs are in flight,
+/// see [`Client::with_priority`](crate::Client::with_priority).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Background work such as periodic scrapes. Shed with
+    /// `RESOURCE_EXHAUSTED` instead of queueing, see [`is_load_shed`].
+    Low,
+    /// Queues for a free slot, failing with `RESOURCE_EXHAUSTED` once
+    /// `connect.max_queued_rpcs` calls are already waiting.
+    #[default]
+    Normal,
+    /// Interactive work. Queues for a free slot even when the queue is
+    /// full.
+    High,
+}
+
+/// Permits for in-flight RPCs, shared by a client and its clones.
+#[derive(Debug)]
+pub(crate) struct RpcLimiter {
+    max_concurrent: usize,
+    max_queued: usize,
+    permits: Arc<Semaphore>,
+    queued: AtomicUsize,
+}
+
+impl RpcLimiter {
+    pub(crate) fn new(max_concurrent: usize, max_queued: usize) -> Self {
+        Self {
+            max_concurrent,
+            max_queued,
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wait for a free slot, or fail with `RESOURCE_EXHAUSTED` when
+    /// `max_queued` calls are already waiting or a [`Priority::Low`] call
+    /// finds no free slot.
+    async fn acquire(
+        &self,
+        priority: Priority,
+        method: &str,
+    ) -> Result<OwnedSemaphorePermit, Status> {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        if priority == Priority::Low {
+            let shed = ClientError::LoadShed {
+                method: method.into(),
+                max: self.max_concurrent,
+            };
+            let mut status = Status::resource_exhausted(shed.to_string());
+            let _ = status
+                .metadata_mut()
+                .insert(LOAD_SHED_KEY, MetadataValue::from_static("client"));
+            return Err(status);
+        }
+
+        let queued = self.queued.fetch_add(1, Ordering::SeqCst);
+        if queued >= self.max_queued && priority < Priority::High {
+            let _ = self.queued.fetch_sub(1, Ordering::SeqCst);
+            return Err(Status::resource_exhausted(format!(
+                "{} RPCs in flight and {} queued, the client side limit",
+                self.max_concurrent, self.max_queued
+            )));
+        }
+
+        // leaves the queue even when the caller gives up on the call
+        let _queued = Queued(&self.queued);
+        Ok(self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("rpc limiter semaphore is never closed"))
+    }
+}
+
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        let _ = self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The RPCs in flight on a client and its clones at one point in time, see
+/// [`Client::in_flight`](crate::Client::in_flight).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InFlightSnapshot {
+    pub total: usize,
+    /// Calls in flight by full method path (`/package.Service/Method`).
+    pub by_method: BTreeMap<String, usize>,
+    /// How long the longest running call has been in flight.
+    pub oldest: Option<Duration>,
+}
+
+/// The RPCs in flight, shared by a client and its clones.
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    next_id: AtomicU64,
+    calls: Mutex<HashMap<u64, (String, Instant)>>,
+}
+
+impl InFlight {
+    fn start(self: &Arc<Self>, method: &str) -> InFlightCall {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .calls
+            .lock()
+            .expect("in flight lock poisoned")
+            .insert(id, (method.to_string(), Instant::now()));
+        InFlightCall { in_flight: self.clone(), id }
+    }
+
+    pub(crate) fn snapshot(&self) -> InFlightSnapshot {
+        let calls = self.calls.lock().expect("in flight lock poisoned");
+        let mut by_method = BTreeMap::new();
+        for (method, _) in calls.values() {
+            *by_method.entry(method.clone()).or_insert(0) += 1;
+        }
+        InFlightSnapshot {
+            total: calls.len(),
+            by_method,
+            oldest: calls.values().map(|(_, at)| at.elapsed()).max(),
+        }
+    }
+}
+
+/// Counts its RPC as in flight until dropped.
+#[derive(Debug)]
+struct InFlightCall {
+    in_flight: Arc<InFlight>,
+    id: u64,
+}
+
+impl Drop for InFlightCall {
+    fn drop(&mut self) {
+        let _ = self
+            .in_flight
+            .calls
+            .lock()
+            .expect("in flight lock poisoned")
+            .remove(&self.id);
+    }
+}
+
+/// A service that tracks each call in [`InFlight`] and takes a permit from
+/// the [`RpcLimiter`], if any, before it. Both are held until the response
+/// body is dropped, so a server streaming RPC counts for as long as its
+/// stream is open. Calls waiting for a permit count as in flight already.
+#[derive(Debug, Clone)]
+pub(crate) struct Limited<S> {
+    inner: S,
+    limiter: Option<Arc<RpcLimiter>>,
+    in_flight: Arc<InFlight>,
+}
+
+impl<S> Limited<S> {
+    pub(crate) fn new(
+        inner: S,
+        limiter: Option<Arc<RpcLimiter>>,
+        in_flight: Arc<InFlight>,
+    ) -> Self {
+        Self { inner, limiter, in_flight }
+    }
+}
+
+impl<S, B> Service<http::Request<BoxBody>> for Limited<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<B>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    S::Error: Into<StdError>,
+    B: Body + Send + 'static,
+{
+    type Response = http::Response<LimitedBody<B>>;
+    type Error = StdError;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        // readiness of the inner service is awaited once a permit is held
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<BoxBody>) -> Self::Future {
+        let limiter = self.limiter.clone();
+        let inner = self.inner.clone();
+        let call = self.in_flight.start(req.uri().path());
+        Box::pin(async move {
+            let permit = match limiter {
+                Some(limiter) => {
+                    let priority = req
+                        .extensions()
+                        .get::<Priority>()
+                        .copied()
+                        .unwrap_or_default();
+                    Some(limiter.acquire(priority, req.uri().path()).await?)
+                }
+                None => None,
+            };
+            let response = inner.oneshot(req).await.map_err(Into::into)?;
+            Ok(response.map(|inner| LimitedBody {
+                inner,
+                _permit: permit,
+                _call: call,
+            }))
+        })
+    }
+}
+
+/// A response body holding on to the permit and in flight entry of its RPC.
+#[derive(Debug)]
+pub(crate) struct LimitedBody<B> {
+    inner: B,
+    _permit: Option<OwnedSemaphorePermit>,
+    _call: InFlightCall,
+}
+
+impl<B: Body + Unpin> Body for LimitedBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::time::Duration;
+    use tonic::body::empty_body;
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn in_flight_calls_never_exceed_the_limit() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let service = {
+            let in_flight = in_flight.clone();
+            let peak = peak.clone();
+            tower::service_fn(move |_req: http::Request<BoxBody>| {
+                let in_flight = in_flight.clone();
+                let peak = peak.clone();
+                async move {
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    let _ = peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    let _ = in_flight.fetch_sub(1, Ordering::SeqCst);
+                    Ok::<_, Infallible>(http::Response::new(empty_body()))
+                }
+            })
+        };
+        let limited = Limited::new(
+            service,
+            Some(Arc::new(RpcLimiter::new(3, 100))),
+            Arc::default(),
+        );
+
+        let calls: Vec<_> = (0..20)
+            .map(|_| {
+                tokio::spawn(
+                    limited.clone().oneshot(http::Request::new(empty_body())),
+                )
+            })
+            .collect();
+        for call in calls {
+            assert!(call.await.unwrap().is_ok());
+        }
+
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn calls_beyond_the_queue_fail_fast() {
+        let limiter = Arc::new(RpcLimiter::new(1, 1));
+        let held = limiter.acquire(Priority::Normal, "/test").await.unwrap();
+
+        let queued = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(Priority::Normal, "/test").await.map(drop) }
+        });
+        while limiter.queued.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+        let err = limiter.acquire(Priority::Normal, "/test").await.unwrap_err();
+        assert_eq!(err.code(), Code::ResourceExhausted);
+        assert!(!is_load_shed(&err));
+
+        drop(held);
+        assert!(queued.await.unwrap().is_ok());
+        assert_eq!(limiter.queued.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn low_priority_calls_are_shed_when_saturated() {
+        let limiter = Arc::new(RpcLimiter::new(1, 0));
+        let limited = Limited::new(
+            tower::service_fn(|_req: http::Request<BoxBody>| async {
+                Ok::<_, Infallible>(http::Response::new(empty_body()))
+            }),
+            Some(limiter.clone()),
+            Arc::default(),
+        );
+        let call = |priority: Priority| {
+            let mut req = http::Request::new(empty_body());
+            let _ = req.extensions_mut().insert(priority);
+            limited.clone().oneshot(req)
+        };
+
+        assert!(call(Priority::Low).await.is_ok());
+
+        let held = limiter.acquire(Priority::Normal, "/test").await.unwrap();
+        let shed = call(Priority::Low).await.unwrap_err();
+        let shed = shed.downcast::<Status>().unwrap();
+        assert!(is_load_shed(&shed), "{shed}");
+        assert!(!is_load_shed(&Status::resource_exhausted("quota")));
+        assert!(call(Priority::Normal).await.is_err());
+
+        let high = tokio::spawn(call(Priority::High));
+        while limiter.queued.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+        drop(held);
+        assert!(high.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn calls_are_in_flight_until_their_body_is_dropped() {
+        let release = Arc::new(tokio::sync::Notify::new());
+        let in_flight = Arc::new(InFlight::default());
+        let limited = Limited::new(
+            tower::service_fn({
+                let release = release.clone();
+                move |_req: http::Request<BoxBody>| {
+                    let release = release.clone();
+                    async move {
+                        release.notified().await;
+                        Ok::<_, Infallible>(http::Response::new(empty_body()))
+                    }
+                }
+            }),
+            None,
+            in_flight.clone(),
+        );
+        assert_eq!(in_flight.snapshot(), InFlightSnapshot::default());
+
+        let req = http::Request::builder()
+            .uri("/aurae.cells.v0.CellService/Allocate")
+            .body(empty_body())
+            .unwrap();
+        let call = tokio::spawn(limited.oneshot(req));
+        while in_flight.snapshot().total == 0 {
+            tokio::task::yield_now().await;
+        }
+        let during = in_flight.snapshot();
+        assert_eq!(
+            during.by_method.get("/aurae.cells.v0.CellService/Allocate"),
+            Some(&1)
+        );
+        assert!(during.oldest.is_some());
+
+        release.notify_one();
+        let response = call.await.unwrap().unwrap();
+        assert_eq!(in_flight.snapshot().total, 1);
+        drop(response);
+        assert_eq!(in_flight.snapshot(), InFlightSnapshot::default());
+    }
+}
@@ -13,26 +13,135 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
+use crate::config::ca_fetch::{self, CaFetchOptions};
 use crate::config::cert_material::CertMaterial;
-use serde::Deserialize;
+use secrecy::SecretString;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
 
 /// Authentication material for an AuraeScript client.
 ///
 /// This material is read from disk many times during runtime.
 /// Changing this material during a process will impact the currently
 /// running process.
-#[derive(Debug, Clone, Deserialize)]
+///
+/// Relative cert paths in a config file are resolved against the directory
+/// of that file, so once loaded through
+/// [`crate::AuraeConfig::parse_from_toml_file`] the paths here are the
+/// absolute ones that are read. Paths starting with `~` are left as is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(deny_unknown_fields)]
 pub struct AuthConfig {
-    /// The same CA certificate the server has.
+    /// The same CA certificate the server has. An `https://` URL is fetched
+    /// as set in `ca_fetch` instead of read from disk.
     pub ca_crt: String,
     /// The unique client certificate signed by the server.
+    ///
+    /// Leave it out together with `client_key` to connect with server TLS
+    /// only, presenting no client identity, to endpoints that serve
+    /// anonymous clients, such as a read-only proxy. auraed itself requires
+    /// a client certificate on every connection and fails the handshake, so
+    /// every RPC, without one.
+    #[serde(default)]
     pub client_crt: String,
-    /// The client secret key.
+    /// The client secret key, left out with `client_crt`.
+    #[serde(default)]
     pub client_key: String,
+    /// Require that the cert paths (after following symlinks) are owned by
+    /// the current user or root, and are not writable by group or others.
+    #[serde(default)]
+    pub enforce_secure_paths: bool,
+    /// Reject cert setups that are only fit for development, such as a
+    /// self-signed leaf certificate configured as the CA, instead of warning
+    /// about them.
+    #[serde(default)]
+    pub strict: bool,
+    /// Common name the server certificate must have, checked after the
+    /// handshake. Unset accepts any cert signed by the CA.
+    #[serde(default)]
+    pub expected_server_cn: Option<String>,
+    /// SPIFFE ID (a URI SAN, e.g. "spiffe://aurae.io/auraed") the server
+    /// certificate must have, checked after the handshake.
+    #[serde(default)]
+    pub expected_server_spiffe: Option<String>,
+    /// SPIFFE trust domain (e.g. "aurae.io") the SPIFFE ID of the server
+    /// certificate must belong to, checked after the handshake. Unlike
+    /// `expected_server_spiffe` it accepts any workload of the domain.
+    #[serde(default)]
+    pub expected_server_trust_domain: Option<String>,
+    /// Passphrase of an encrypted PKCS#8 client key (`BEGIN ENCRYPTED
+    /// PRIVATE KEY`). The key is decrypted in memory whenever the certs are
+    /// read, and never written out.
+    #[serde(default, skip_serializing)]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
+    pub client_key_passphrase: Option<SecretString>,
+    /// How an `https://` `ca_crt` is fetched and cached.
+    #[serde(default)]
+    pub ca_fetch: CaFetchOptions,
+    /// Sign with a private key on a PKCS#11 token (an HSM or smartcard)
+    /// instead of reading `client_key`. The client certificate is read from
+    /// `client_crt` when set, and from the token otherwise. Needs the
+    /// `pkcs11` feature.
+    ///
+    /// Any PKCS#11 2.40 module works, such as SoftHSM 2, OpenSC, the
+    /// YubiKey PIV module (`libykcs11.so`) or the client library of a
+    /// network HSM. The key must be an EC key on P-256 or P-384, or an RSA
+    /// key the token can sign RSA-PSS with.
+    #[serde(default)]
+    pub pkcs11: Option<Pkcs11Key>,
+}
+
+/// A client key held on a PKCS#11 token, see [`AuthConfig::pkcs11`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct Pkcs11Key {
+    /// Path of the PKCS#11 module of the token, e.g.
+    /// `/usr/lib/softhsm/libsofthsm2.so`.
+    pub module: String,
+    /// ID of the slot the token is in.
+    pub slot: u64,
+    /// `CKA_ID` of the private key, as hex. A certificate with the same ID
+    /// is the client certificate when `auth.client_crt` is not set.
+    pub key_id: String,
+    /// User PIN to log in to the token with. Left out for tokens that allow
+    /// signing without a login.
+    #[serde(default, skip_serializing)]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
+    pub pin: Option<SecretString>,
 }
 
 impl AuthConfig {
+    /// Whether a client certificate or key is set. Without either the
+    /// client connects with server TLS only, see [`AuthConfig::client_crt`].
+    pub fn has_client_identity(&self) -> bool {
+        !self.client_crt.is_empty()
+            || !self.client_key.is_empty()
+            || self.pkcs11.is_some()
+    }
+
+    /// Join the relative cert paths onto `dir`.
+    pub(crate) fn resolve_relative_to(&mut self, dir: &Path) {
+        for path in
+            [&mut self.ca_crt, &mut self.client_crt, &mut self.client_key]
+        {
+            resolve_relative(path, dir);
+        }
+    }
+
     pub async fn to_cert_material(&self) -> anyhow::Result<CertMaterial> {
         CertMaterial::from_config(self).await
     }
+}
+/// Join `path` onto `dir` if it is a relative cert path, leaving `~` paths
+/// and CA URLs alone.
+pub(crate) fn resolve_relative(path: &mut String, dir: &Path) {
+    if !path.is_empty()
+        && Path::new(path.as_str()).is_relative()
+        && !path.starts_with('~')
+        && !ca_fetch::is_url(path)
+    {
+        *path = dir.join(&*path).to_string_lossy().into();
+    }
 }
\ No newline at end of file
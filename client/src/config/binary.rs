@@ -0,0 +1,166 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! The `config-binary` encoding of [`AuraeConfig`], for configs baked into
+//! an image at build time.
+//!
+//! The config is carried as the tree of values it serializes to, so the
+//! binary form reads back through the same serde attributes, defaults and
+//! unknown field checks as a config file. The encoding starts with
+//! [`MAGIC`] and a little endian `u16` [`FORMAT_VERSION`], which is bumped
+//! whenever the layout of the tree changes.
+
+use super::AuraeConfig;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+const MAGIC: &[u8; 8] = b"AURAECFG";
+pub(crate) const FORMAT_VERSION: u16 = 1;
+
+#[derive(Serialize, Deserialize)]
+enum Value {
+    Null,
+    Bool(bool),
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(value) => Value::Bool(value),
+            serde_json::Value::Number(number) => {
+                if let Some(value) = number.as_u64() {
+                    Value::Unsigned(value)
+                } else if let Some(value) = number.as_i64() {
+                    Value::Signed(value)
+                } else {
+                    Value::Float(number.as_f64().unwrap_or_default())
+                }
+            }
+            serde_json::Value::String(value) => Value::String(value),
+            serde_json::Value::Array(values) => {
+                Value::Array(values.into_iter().map(Value::from).collect())
+            }
+            serde_json::Value::Object(map) => Value::Object(
+                map.into_iter().map(|(k, v)| (k, Value::from(v))).collect(),
+            ),
+        }
+    }
+}
+
+impl From<Value> for serde_json::Value {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => serde_json::Value::Null,
+            Value::Bool(value) => value.into(),
+            Value::Unsigned(value) => value.into(),
+            Value::Signed(value) => value.into(),
+            Value::Float(value) => value.into(),
+            Value::String(value) => value.into(),
+            Value::Array(values) => serde_json::Value::Array(
+                values.into_iter().map(serde_json::Value::from).collect(),
+            ),
+            Value::Object(entries) => serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k, serde_json::Value::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+pub(crate) fn encode(config: &AuraeConfig) -> Vec<u8> {
+    let value = serde_json::to_value(config)
+        .expect("aurae config always serializes to JSON");
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut bytes, &Value::from(value))
+        .expect("aurae config always serializes to bincode");
+    bytes
+}
+
+pub(crate) fn decode(bytes: &[u8]) -> Result<AuraeConfig> {
+    let body = bytes
+        .strip_prefix(MAGIC.as_slice())
+        .ok_or_else(|| anyhow!("not a binary aurae config"))?;
+    let (version, body) = match body {
+        [low, high, body @ ..] => (u16::from_le_bytes([*low, *high]), body),
+        _ => return Err(anyhow!("binary aurae config is truncated")),
+    };
+    if version != FORMAT_VERSION {
+        return Err(anyhow!(
+            "binary aurae config has format version {version}, this client \
+             reads version {FORMAT_VERSION}; re-encode it with a matching \
+             client"
+        ));
+    }
+
+    let value: Value =
+        bincode::deserialize(body).context("binary aurae config is corrupt")?;
+    serde_json::from_value(value.into()).context("invalid aurae config")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> AuraeConfig {
+        AuraeConfig::parse_from_toml(
+            r#"
+            [auth]
+            ca_crt = "/etc/aurae/pki/ca.crt"
+            client_crt = "/etc/aurae/pki/_signed.client.nova.crt"
+            client_key = "/etc/aurae/pki/client.nova.key"
+
+            [system]
+            socket = "10.0.0.7:8443"
+
+            [connect]
+            overall_timeout = "3s"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let config = config();
+
+        let decoded = decode(&encode(&config)).unwrap();
+        assert_eq!(decoded.checksum(), config.checksum());
+        assert_eq!(
+            decoded.connect.overall_timeout,
+            Some(std::time::Duration::from_secs(3))
+        );
+    }
+
+    #[test]
+    fn rejects_other_format_versions() {
+        let mut bytes = encode(&config());
+        bytes[MAGIC.len()..MAGIC.len() + 2]
+            .copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+
+        let error = decode(&bytes).unwrap_err().to_string();
+        assert!(error.contains("format version 2"), "{error}");
+        assert!(decode(b"[auth]").is_err());
+    }
+}
@@ -0,0 +1,333 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! A config and its certs shipped as one tar archive, see
+//! [`AuraeConfig::from_bundle`].
+//!
+//! The archive holds `config.toml`, the cert files it names and a
+//! `SHA256SUMS` manifest in `sha256sum` format. Every other entry must be
+//! listed in the manifest with a matching digest, which detects accidental
+//! corruption in transit. The manifest is not signed, so it is no defence
+//! against a bundle altered on purpose. Nothing is extracted to disk.
+
+use super::cert_material::{decrypt_key, CertMaterial, GZIP_MAGIC};
+use super::{ca_fetch, AuraeConfig};
+use crate::cert_bundle::CertBundle;
+use crate::client::Result;
+use anyhow::{anyhow, Context};
+use flate2::read::MultiGzDecoder;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+use x509_certificate::DigestAlgorithm;
+
+const CONFIG_ENTRY: &str = "config.toml";
+const MANIFEST_ENTRY: &str = "SHA256SUMS";
+
+/// The most a bundle may hold once decompressed, and the most any one entry
+/// may hold, so a small gzip bomb cannot exhaust memory.
+const MAX_BUNDLE_BYTES: u64 = 16 * 1024 * 1024;
+const MAX_ENTRY_BYTES: u64 = 1024 * 1024;
+
+impl AuraeConfig {
+    /// Read a config and its certs from the tar archive at `path`, which
+    /// may be gzipped. See [`AuraeConfig::from_bundle_bytes`].
+    pub fn from_bundle<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<(AuraeConfig, CertBundle)> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).with_context(|| {
+            format!("failed to read config bundle '{}'", path.display())
+        })?;
+        Self::from_bundle_bytes(&bytes)
+    }
+
+    /// Read a config and its certs from a tar archive, which may be
+    /// gzipped, for passing to [`Client::from_bundle`](crate::Client::from_bundle).
+    ///
+    /// The archive must hold `config.toml`, a `SHA256SUMS` manifest covering
+    /// every other entry against accidental corruption, and the cert files `auth.ca_crt`,
+    /// `auth.client_crt` and `auth.client_key` name, as paths inside the
+    /// archive. An encrypted key is decrypted with
+    /// `auth.client_key_passphrase`. Zip archives are not supported, nor are
+    /// bundles over 16 MiB decompressed or with entries over 1 MiB.
+    pub fn from_bundle_bytes(
+        bytes: &[u8],
+    ) -> Result<(AuraeConfig, CertBundle)> {
+        let entries = read_entries(bytes)?;
+        verify_manifest(&entries)?;
+
+        let config_toml = std::str::from_utf8(entry(&entries, CONFIG_ENTRY)?)
+            .context("config.toml in the bundle is not UTF-8")?;
+        let config = Self::parse_from_toml(config_toml)?;
+
+        let auth = &config.auth;
+        if ca_fetch::is_url(&auth.ca_crt) {
+            return Err(anyhow!(
+                "auth.ca_crt of a bundled config must name a file in the bundle, not a URL"
+            )
+            .into());
+        }
+        let material = CertMaterial {
+            server_root_ca_cert: entry(&entries, &auth.ca_crt)?.to_vec(),
+            client_cert: entry(&entries, &auth.client_crt)?.to_vec(),
+            client_key: decrypt_key(
+                entry(&entries, &auth.client_key)?.to_vec(),
+                auth.client_key_passphrase.as_ref(),
+            )?,
+        };
+        let bundle = CertBundle::from_material(
+            material,
+            config.connect.clock_skew_tolerance,
+        )?;
+
+        Ok((config, bundle))
+    }
+}
+
+/// The regular files of the archive by their path, without a leading `./`.
+fn read_entries(bytes: &[u8]) -> anyhow::Result<BTreeMap<String, Vec<u8>>> {
+    let reader: Box<dyn Read + '_> = match bytes.starts_with(&GZIP_MAGIC) {
+        true => Box::new(MultiGzDecoder::new(bytes)),
+        false => Box::new(bytes),
+    };
+    let mut tar = Vec::new();
+    let _ = reader
+        .take(MAX_BUNDLE_BYTES + 1)
+        .read_to_end(&mut tar)
+        .context("failed to read config bundle")?;
+    if tar.len() as u64 > MAX_BUNDLE_BYTES {
+        return Err(anyhow!(
+            "config bundle is over {MAX_BUNDLE_BYTES} bytes decompressed"
+        ));
+    }
+
+    let mut entries = BTreeMap::new();
+    let mut archive = tar::Archive::new(tar.as_slice());
+    for entry in archive.entries().context("failed to read config bundle")? {
+        let mut entry = entry.context("failed to read config bundle")?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let name = normalize(&name).to_string();
+        if entry.header().size()? > MAX_ENTRY_BYTES {
+            return Err(anyhow!(
+                "'{name}' of the bundle is over {MAX_ENTRY_BYTES} bytes"
+            ));
+        }
+
+        let mut data = Vec::new();
+        let _ = (&mut entry)
+            .take(MAX_ENTRY_BYTES)
+            .read_to_end(&mut data)
+            .with_context(|| {
+                format!("failed to read '{name}' of the bundle")
+            })?;
+        if entries.insert(name.clone(), data).is_some() {
+            return Err(anyhow!("config bundle holds '{name}' twice"));
+        }
+    }
+    Ok(entries)
+}
+
+/// Check every entry against `SHA256SUMS`, and that every file it lists is
+/// there.
+fn verify_manifest(entries: &BTreeMap<String, Vec<u8>>) -> anyhow::Result<()> {
+    let manifest = std::str::from_utf8(entry(entries, MANIFEST_ENTRY)?)
+        .context("SHA256SUMS in the bundle is not UTF-8")?;
+
+    let mut listed = BTreeMap::new();
+    for line in manifest.lines().filter(|line| !line.trim().is_empty()) {
+        // `sha256sum` marks files read in binary mode with a `*`.
+        let (digest, name) = line
+            .split_once(char::is_whitespace)
+            .map(|(digest, name)| {
+                (digest, name.trim_start().trim_start_matches('*'))
+            })
+            .ok_or_else(|| anyhow!("malformed SHA256SUMS line '{line}'"))?;
+        let _ = listed.insert(normalize(name), digest);
+    }
+
+    for (name, data) in entries {
+        if name == MANIFEST_ENTRY {
+            continue;
+        }
+        let Some(expected) = listed.remove(name.as_str()) else {
+            return Err(anyhow!("'{name}' of the bundle is not in SHA256SUMS"));
+        };
+        let found: String = DigestAlgorithm::Sha256
+            .digest_data(data)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        if !found.eq_ignore_ascii_case(expected) {
+            return Err(anyhow!(
+                "checksum of '{name}' does not match SHA256SUMS of the bundle"
+            ));
+        }
+    }
+    match listed.into_keys().next() {
+        Some(missing) => Err(anyhow!(
+            "'{missing}' is listed in SHA256SUMS but missing from the bundle"
+        )),
+        None => Ok(()),
+    }
+}
+
+fn entry<'a>(
+    entries: &'a BTreeMap<String, Vec<u8>>,
+    name: &str,
+) -> anyhow::Result<&'a [u8]> {
+    entries
+        .get(normalize(name))
+        .map(Vec::as_slice)
+        .ok_or_else(|| anyhow!("config bundle has no '{name}'"))
+}
+
+fn normalize(name: &str) -> &str {
+    name.trim_start_matches("./")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa};
+
+    const CONFIG: &str = r#"
+[auth]
+ca_crt = "pki/ca.crt"
+client_crt = "pki/client.crt"
+client_key = "pki/client.key"
+
+[system]
+socket = "/var/run/aurae/aurae.sock"
+"#;
+
+    fn files() -> Vec<(&'static str, Vec<u8>)> {
+        let mut params = CertificateParams::new(vec![]);
+        params.distinguished_name.push(DnType::CommonName, "bundle ca");
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(params).unwrap();
+
+        let mut params = CertificateParams::default();
+        params.distinguished_name.push(DnType::CommonName, "bundle client");
+        let client = rcgen::Certificate::from_params(params).unwrap();
+
+        vec![
+            ("config.toml", CONFIG.as_bytes().to_vec()),
+            ("pki/ca.crt", ca.serialize_pem().unwrap().into_bytes()),
+            (
+                "pki/client.crt",
+                client.serialize_pem_with_signer(&ca).unwrap().into_bytes(),
+            ),
+            ("pki/client.key", client.serialize_private_key_pem().into_bytes()),
+        ]
+    }
+
+    fn sums(files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        files
+            .iter()
+            .map(|(name, data)| {
+                let digest: String = DigestAlgorithm::Sha256
+                    .digest_data(data)
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect();
+                format!("{digest}  ./{name}\n")
+            })
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    fn tar(files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o600);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data.as_slice()).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn bundle_loads_from_memory() {
+        let mut files = files();
+        let manifest = sums(&files);
+        files.push(("SHA256SUMS", manifest));
+
+        let (config, bundle) =
+            AuraeConfig::from_bundle_bytes(&tar(&files)).unwrap();
+
+        assert_eq!(config.auth.client_crt, "pki/client.crt");
+        assert_eq!(
+            bundle.client_cert_details().subject_common_name,
+            "bundle client"
+        );
+        assert_eq!(bundle.server_ca_details().subject_common_name, "bundle ca");
+    }
+
+    #[test]
+    fn tampered_bundle_is_rejected() {
+        let mut files = files();
+        let manifest = sums(&files);
+        files.push(("SHA256SUMS", manifest));
+        files[1].1.push(b'\n');
+
+        let err = AuraeConfig::from_bundle_bytes(&tar(&files)).unwrap_err();
+        assert!(err.to_string().contains("pki/ca.crt"), "{err}");
+
+        let mut files = self::files();
+        let manifest = sums(&files[..3]);
+        files.push(("SHA256SUMS", manifest));
+        let err = AuraeConfig::from_bundle_bytes(&tar(&files)).unwrap_err();
+        assert!(err.to_string().contains("not in SHA256SUMS"), "{err}");
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::best(),
+        );
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn oversized_bundles_are_rejected() {
+        let mut files = files();
+        files.push(("padding", vec![0; MAX_ENTRY_BYTES as usize + 1]));
+        let err =
+            AuraeConfig::from_bundle_bytes(&gzip(&tar(&files))).unwrap_err();
+        assert!(
+            err.to_string().contains("'padding' of the bundle is over"),
+            "{err}"
+        );
+
+        let names: Vec<String> =
+            (0..20).map(|i| format!("padding{i}")).collect();
+        let mut files: Vec<(&str, Vec<u8>)> = self::files();
+        for name in &names {
+            files.push((name, vec![0; MAX_ENTRY_BYTES as usize]));
+        }
+        let bomb = gzip(&tar(&files));
+        assert!((bomb.len() as u64) < MAX_ENTRY_BYTES);
+        let err = AuraeConfig::from_bundle_bytes(&bomb).unwrap_err();
+        assert!(err.to_string().contains("decompressed"), "{err}");
+    }
+}
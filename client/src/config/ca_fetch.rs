@@ -0,0 +1,487 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Fetching the server root CA over HTTPS, for an `auth.ca_crt` that is an
+//! `https://` URL rather than a path.
+//!
+//! The fetched PEM is cached under `auth.ca_fetch.cache_dir` and reused
+//! until it is older than `auth.ca_fetch.cache_ttl`, so reconnects and cert
+//! reloads do not hit the URL each time. The cache is a trust anchor, so it
+//! is only readable by the current user, only reused when that user owns
+//! it, and kept apart for each `pinned_sha256` it was fetched with.
+
+use super::duration;
+use crate::ClientError;
+use anyhow::Context;
+use hyper::body::HttpBody;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::os::unix::fs::{
+    DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt,
+};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+#[cfg(feature = "custom-verifier")]
+use tokio_rustls::rustls::client::{ServerCertVerified, ServerCertVerifier};
+#[cfg(feature = "custom-verifier")]
+use tokio_rustls::rustls::Error;
+use tokio_rustls::rustls::{
+    Certificate, ClientConfig, ConfigBuilder, RootCertStore, ServerName,
+    WantsVerifier,
+};
+use tokio_rustls::TlsConnector;
+use tonic::codegen::http::{header, Request, Uri};
+use tracing::{debug, warn};
+use x509_certificate::DigestAlgorithm;
+
+/// The most a CA bundle fetch reads, a guard against pointing `ca_crt` at
+/// something that is not one.
+const MAX_CA_BYTES: usize = 1024 * 1024;
+
+/// Permissions of the cache directory and of the cached files.
+const CACHE_DIR_MODE: u32 = 0o700;
+const CACHE_FILE_MODE: u32 = 0o600;
+
+/// How an `https://` [`crate::AuthConfig::ca_crt`] is fetched.
+///
+/// Read from the optional `[auth.ca_fetch]` table of the config file, and
+/// ignored when `ca_crt` is a path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, deny_unknown_fields)]
+pub struct CaFetchOptions {
+    /// SHA-256 fingerprint of the certificate the HTTPS server presents, as
+    /// hex with or without `:` between the bytes. When set, the server is
+    /// trusted by this pin alone, for bootstrapping before any CA is known.
+    /// Needs the `custom-verifier` feature. Unset verifies it against the
+    /// system trust store.
+    pub pinned_sha256: Option<String>,
+    /// How long a fetched CA is reused from the cache before it is fetched
+    /// again. Zero fetches it on every load, without caching.
+    #[serde(
+        deserialize_with = "duration::deserialize",
+        serialize_with = "duration::serialize"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "duration::DurationSchema")
+    )]
+    pub cache_ttl: Duration,
+    /// Limit on connecting to the URL and reading the response.
+    #[serde(
+        deserialize_with = "duration::deserialize",
+        serialize_with = "duration::serialize"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "duration::DurationSchema")
+    )]
+    pub timeout: Duration,
+    /// Where fetched CAs are cached. Defaults to `$XDG_CACHE_HOME/aurae/ca`,
+    /// or `$HOME/.cache/aurae/ca`.
+    pub cache_dir: Option<String>,
+}
+
+impl Default for CaFetchOptions {
+    fn default() -> Self {
+        Self {
+            pinned_sha256: None,
+            cache_ttl: Duration::from_secs(60 * 60),
+            timeout: Duration::from_secs(10),
+            cache_dir: None,
+        }
+    }
+}
+
+/// Whether `ca_crt` names a URL to fetch rather than a file to read.
+pub(crate) fn is_url(ca_crt: &str) -> bool {
+    ca_crt.starts_with("https://")
+}
+
+/// The PEM behind `url`, from the cache while it is fresh, failing with
+/// [`ClientError::CaFetchFailed`].
+pub(crate) async fn read(
+    url: &str,
+    options: &CaFetchOptions,
+) -> Result<Vec<u8>, ClientError> {
+    let failed =
+        |reason: String| ClientError::CaFetchFailed { url: url.into(), reason };
+
+    let cached = cache_path(url, options);
+    if let Some(path) = cached.as_ref().filter(|_| !options.cache_ttl.is_zero())
+    {
+        if let Some(pem) = fresh(path, options.cache_ttl) {
+            debug!(url, path = %path.display(), "server root CA read from cache");
+            return Ok(pem);
+        }
+    }
+
+    let pem = tokio::time::timeout(options.timeout, fetch(url, options))
+        .await
+        .map_err(|_| failed(format!("timed out after {:?}", options.timeout)))?
+        .map_err(|e| failed(format!("{e:#}")))?;
+
+    if let Some(path) = cached.filter(|_| !options.cache_ttl.is_zero()) {
+        if let Err(e) = store(&path, &pem) {
+            warn!(url, path = %path.display(), "failed to cache the server root CA: {e}");
+        }
+    }
+    Ok(pem)
+}
+
+/// The cached PEM behind `url` however old it is, without fetching.
+pub(crate) async fn cached(
+    url: &str,
+    options: &CaFetchOptions,
+) -> Option<Vec<u8>> {
+    fresh(&cache_path(url, options)?, Duration::MAX)
+}
+
+/// The cache file for `url` fetched with the pin of `options`, so a CA
+/// fetched under another pin, or none, is not picked up once it changes.
+fn cache_path(url: &str, options: &CaFetchOptions) -> Option<PathBuf> {
+    let dir = match &options.cache_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .map(|home| PathBuf::from(home).join(".cache"))
+            })?
+            .join("aurae/ca"),
+    };
+    let pin = options.pinned_sha256.as_deref().unwrap_or_default();
+    let key = format!("{url}\n{}", pin.trim().to_ascii_lowercase());
+    Some(dir.join(format!(
+        "{}.crt",
+        hex(&DigestAlgorithm::Sha256.digest_data(key.as_bytes()))
+    )))
+}
+
+/// The cached PEM at `path`, unless it is older than `ttl`, is not owned by
+/// the current user alone, or no longer holds a certificate.
+///
+/// Cached files are small, so they are read without leaving the runtime.
+fn fresh(path: &Path, ttl: Duration) -> Option<Vec<u8>> {
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(nix::libc::O_NOFOLLOW)
+        .open(path)
+        .ok()?;
+    let metadata = file.metadata().ok()?;
+
+    let euid = nix::unistd::geteuid().as_raw();
+    if metadata.uid() != euid || metadata.mode() & 0o077 != 0 {
+        warn!(
+            path = %path.display(),
+            "ignoring the cached server root CA: not private to uid {euid}"
+        );
+        return None;
+    }
+
+    let modified = metadata.modified().ok()?;
+    let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+    if age >= ttl {
+        return None;
+    }
+
+    let mut pem = Vec::new();
+    let _ = file.read_to_end(&mut pem).ok()?;
+    match check_pem(&pem) {
+        Ok(()) => Some(pem),
+        Err(e) => {
+            warn!(path = %path.display(), "ignoring the cached server root CA: {e:#}");
+            None
+        }
+    }
+}
+
+/// Replace the cached PEM at `path`, so a concurrent reader never sees a
+/// partial file.
+fn store(path: &Path, pem: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(CACHE_DIR_MODE)
+            .create(dir)?;
+    }
+    let partial = path.with_extension(format!("{}.tmp", std::process::id()));
+    let _ = std::fs::remove_file(&partial);
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(CACHE_FILE_MODE)
+        .open(&partial)?;
+    file.write_all(pem)?;
+    // the mode passed to open is masked by the umask
+    file.set_permissions(std::fs::Permissions::from_mode(CACHE_FILE_MODE))?;
+    drop(file);
+    std::fs::rename(&partial, path)
+}
+
+/// Check that `pem` holds at least one certificate, and nothing that does
+/// not parse as one.
+fn check_pem(pem: &[u8]) -> anyhow::Result<()> {
+    let certs = rustls_pemfile::certs(&mut &*pem)
+        .context("response is not a PEM CA bundle")?;
+    if certs.is_empty() {
+        return Err(anyhow::anyhow!(
+            "response holds no PEM certificates, so it is not a CA bundle"
+        ));
+    }
+    for cert in &certs {
+        let _ = x509_parser::parse_x509_certificate(cert).map_err(|e| {
+            anyhow::anyhow!("response holds an invalid certificate: {e}")
+        })?;
+    }
+    Ok(())
+}
+
+async fn fetch(url: &str, options: &CaFetchOptions) -> anyhow::Result<Vec<u8>> {
+    let uri: Uri = url.parse()?;
+    let host = uri
+        .host()
+        .ok_or_else(|| anyhow::anyhow!("URL has no host"))?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let port = uri.port_u16().unwrap_or(443);
+
+    let config = ClientConfig::builder().with_safe_defaults();
+    let config = match &options.pinned_sha256 {
+        Some(pin) => pinned(config, pin)?,
+        None => {
+            config.with_root_certificates(system_roots()?).with_no_client_auth()
+        }
+    };
+
+    let stream = tokio::net::TcpStream::connect((host, port)).await?;
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from(host)?, stream)
+        .await?;
+    let (mut sender, connection) =
+        hyper::client::conn::handshake(stream).await?;
+    let _connection = tokio::spawn(connection);
+
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    let request = Request::get(path)
+        .header(header::HOST, uri.authority().map_or(host, |a| a.as_str()))
+        .body(hyper::Body::empty())?;
+    let response = sender.send_request(request).await?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("server responded {}", response.status()));
+    }
+
+    // read chunk by chunk, to stop at the limit rather than after the body
+    let mut body = response.into_body();
+    let mut pem = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if pem.len() + chunk.len() > MAX_CA_BYTES {
+            return Err(anyhow::anyhow!(
+                "response is larger than the {MAX_CA_BYTES} bytes of a CA bundle"
+            ));
+        }
+        pem.extend_from_slice(&chunk);
+    }
+    check_pem(&pem)?;
+    Ok(pem)
+}
+
+/// The certificates of the platform trust store.
+fn system_roots() -> anyhow::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_native_certs::load_native_certs()? {
+        // skip the odd certificate rustls cannot parse, like browsers do
+        let _ = roots.add(&Certificate(cert.0));
+    }
+    Ok(roots)
+}
+
+/// `config` trusting only the server certificate with the fingerprint `pin`.
+#[cfg(feature = "custom-verifier")]
+fn pinned(
+    config: ConfigBuilder<ClientConfig, WantsVerifier>,
+    pin: &str,
+) -> anyhow::Result<ClientConfig> {
+    let verifier = Arc::new(Pinned(parse_pin(pin)?));
+    Ok(config.with_custom_certificate_verifier(verifier).with_no_client_auth())
+}
+
+#[cfg(not(feature = "custom-verifier"))]
+fn pinned(
+    _config: ConfigBuilder<ClientConfig, WantsVerifier>,
+    _pin: &str,
+) -> anyhow::Result<ClientConfig> {
+    Err(anyhow::anyhow!(
+        "auth.ca_fetch.pinned_sha256 needs the client built with the custom-verifier feature"
+    ))
+}
+
+/// A SHA-256 fingerprint as lowercase hex, optionally with `:` between the
+/// bytes.
+#[cfg(feature = "custom-verifier")]
+fn parse_pin(pin: &str) -> anyhow::Result<String> {
+    let hex: String = pin.trim().chars().filter(|c| *c != ':').collect();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow::anyhow!(
+            "auth.ca_fetch.pinned_sha256 '{pin}' is not a SHA-256 fingerprint of 64 hex digits"
+        ));
+    }
+    Ok(hex.to_ascii_lowercase())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Trusts the one server certificate with the pinned fingerprint.
+#[cfg(feature = "custom-verifier")]
+struct Pinned(String);
+
+#[cfg(feature = "custom-verifier")]
+impl ServerCertVerifier for Pinned {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let found = hex(&DigestAlgorithm::Sha256.digest_data(&end_entity.0));
+        match found == self.0 {
+            true => Ok(ServerCertVerified::assertion()),
+            false => Err(Error::General(format!(
+                "server certificate SHA-256 {found} does not match auth.ca_fetch.pinned_sha256"
+            ))),
+        }
+    }
+}
+
+// every test fetches from a self-signed server, trusted by its pin
+#[cfg(all(test, feature = "custom-verifier"))]
+mod tests {
+    use super::*;
+    use crate::testing::test_ca;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::rustls::{PrivateKey, ServerConfig};
+    use tokio_rustls::TlsAcceptor;
+
+    /// An HTTPS server answering `count` requests with `body`, and the
+    /// fingerprint of its certificate.
+    async fn serve(count: usize, body: String) -> (String, String) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])
+            .unwrap();
+        let der = cert.serialize_der().unwrap();
+        let pin = hex(&DigestAlgorithm::Sha256.digest_data(&der));
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(der)],
+                PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let _server = tokio::spawn(async move {
+            for _ in 0..count {
+                let (stream, _) = listener.accept().await.unwrap();
+                let Ok(mut stream) = acceptor.accept(stream).await else {
+                    continue;
+                };
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await.unwrap();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (format!("https://localhost:{port}/ca.crt"), pin)
+    }
+
+    fn options(pin: &str, cache_dir: &std::path::Path) -> CaFetchOptions {
+        CaFetchOptions {
+            pinned_sha256: Some(pin.into()),
+            cache_dir: Some(cache_dir.to_string_lossy().into()),
+            ..CaFetchOptions::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn fetched_ca_is_cached() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let ca_pem = test_ca("fetched ca").serialize_pem().unwrap();
+        let (url, pin) = serve(1, ca_pem.clone()).await;
+        let options = options(&pin, cache_dir.path());
+
+        assert_eq!(read(&url, &options).await.unwrap(), ca_pem.as_bytes());
+        let path = cache_path(&url, &options).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().mode() & 0o777;
+        assert_eq!(mode, CACHE_FILE_MODE);
+        // the server is gone after one request
+        assert_eq!(read(&url, &options).await.unwrap(), ca_pem.as_bytes());
+
+        let uncached =
+            CaFetchOptions { cache_ttl: Duration::ZERO, ..options.clone() };
+        let err = read(&url, &uncached).await.unwrap_err();
+        assert!(matches!(err, ClientError::CaFetchFailed { .. }), "{err}");
+
+        // a cache others may write to is not trusted, so it is fetched again
+        assert!(fresh(&path, Duration::MAX).is_some());
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o666))
+            .unwrap();
+        assert_eq!(fresh(&path, Duration::MAX), None);
+        let err = read(&url, &options).await.unwrap_err();
+        assert!(matches!(err, ClientError::CaFetchFailed { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn responses_without_certificates_are_not_cached() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let (url, pin) = serve(1, "<html>sign in</html>".into()).await;
+        let options = options(&pin, cache_dir.path());
+
+        let err = read(&url, &options).await.unwrap_err();
+
+        assert!(err.to_string().contains("no PEM certificates"), "{err}");
+        assert!(!cache_path(&url, &options).unwrap().exists());
+    }
+
+    #[tokio::test]
+    async fn mismatched_pin_fails_the_fetch() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let ca_pem = test_ca("fetched ca").serialize_pem().unwrap();
+        let (url, _) = serve(1, ca_pem).await;
+
+        let err = read(&url, &options(&"ab".repeat(32), cache_dir.path()))
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(&err, ClientError::CaFetchFailed { url: failed, .. } if *failed == url),
+            "{err}"
+        );
+        assert_eq!(std::fs::read_dir(cache_dir.path()).unwrap().count(), 0);
+    }
+}
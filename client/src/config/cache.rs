@@ -0,0 +1,133 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! An opt-in, process-wide cache of parsed config files, see
+//! [`AuraeConfig::cached_from_path`].
+
+use super::AuraeConfig;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Parsed configs by absolute path. Only filled by
+/// [`AuraeConfig::cached_from_path`].
+static CACHE: Mutex<BTreeMap<PathBuf, Entry>> = Mutex::new(BTreeMap::new());
+
+struct Entry {
+    /// The mtime and size of the file when it was parsed.
+    modified: SystemTime,
+    len: u64,
+    config: AuraeConfig,
+}
+
+impl AuraeConfig {
+    /// As [`AuraeConfig::parse_from_toml_file`], but reusing the config
+    /// parsed from the same file earlier in the process, for tools that
+    /// create many clients from one config. The file is parsed again once
+    /// its mtime or size changes, so edits are picked up on the next call.
+    ///
+    /// Entries are keyed by absolute path, relative ones are taken relative
+    /// to the working directory. Nothing is cached unless this is called,
+    /// and [`AuraeConfig::clear_cache`] empties the cache. The ownership
+    /// checks of `auth.enforce_secure_paths` run when the file is parsed,
+    /// not on every hit.
+    pub fn cached_from_path<P: AsRef<Path>>(path: P) -> Result<AuraeConfig> {
+        let path = absolute(path.as_ref())?;
+        let metadata = std::fs::metadata(&path).with_context(|| {
+            format!("failed to read config {}", path.display())
+        })?;
+        let modified = metadata.modified()?;
+        let len = metadata.len();
+
+        if let Some(entry) =
+            CACHE.lock().expect("config cache lock poisoned").get(&path)
+        {
+            if entry.modified == modified && entry.len == len {
+                return Ok(entry.config.clone());
+            }
+        }
+
+        let config = AuraeConfig::parse_from_toml_file(&path)?;
+        let _ = CACHE
+            .lock()
+            .expect("config cache lock poisoned")
+            .insert(path, Entry { modified, len, config: config.clone() });
+        Ok(config)
+    }
+
+    /// Forget every config [`AuraeConfig::cached_from_path`] parsed.
+    pub fn clear_cache() {
+        CACHE.lock().expect("config cache lock poisoned").clear();
+    }
+}
+
+fn absolute(path: &Path) -> Result<PathBuf> {
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+    let cwd =
+        std::env::current_dir().context("failed to resolve the config path")?;
+    Ok(cwd.join(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::Duration;
+
+    const CONFIG: &str = r#"
+[auth]
+ca_crt = "/nonexistent/ca.crt"
+client_crt = "/nonexistent/client.crt"
+client_key = "/nonexistent/client.key"
+
+[system]
+socket = "#;
+
+    #[test]
+    fn edits_invalidate_the_cached_config() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("config.toml");
+        std::fs::write(&path, format!("{CONFIG}\"10.0.0.2:8080\"")).unwrap();
+
+        let first = AuraeConfig::cached_from_path(&path).unwrap();
+        assert_eq!(first.system.socket.endpoint(), "10.0.0.2:8080");
+
+        // a hit does not read the file, so changing it in place without
+        // moving its mtime or size goes unnoticed
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::write(&path, format!("{CONFIG}\"10.0.0.3:8080\"")).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let cached = AuraeConfig::cached_from_path(&path).unwrap();
+        assert_eq!(cached.system.socket.endpoint(), "10.0.0.2:8080");
+
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        let edited = AuraeConfig::cached_from_path(&path).unwrap();
+        assert_eq!(edited.system.socket.endpoint(), "10.0.0.3:8080");
+    }
+}
@@ -0,0 +1,745 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use super::duration;
+use crate::hedge::HEDGED_METHODS;
+use crate::read_only::MUTATING_METHODS;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::time::Duration;
+use tonic::codec::CompressionEncoding;
+
+/// Tuning for how the client connects to and talks with auraed.
+///
+/// Read from the optional `[connect]` table of the config file. Every field
+/// has a default, so the table can be omitted entirely.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectOptions {
+    /// Compression applied to request and response messages, once auraed
+    /// said it accepts the encoding in answer to `hello`.
+    pub compression: CompressionMode,
+    /// Whether RPCs are framed as native gRPC or as gRPC-Web.
+    pub transport_mode: TransportMode,
+    /// The DNS name the server certificate is verified against. Defaults to
+    /// `server.unsafe.aurae.io`.
+    pub server_name: Option<String>,
+    /// The DNS name sent as SNI in the ClientHello, for SNI routing proxies
+    /// in front of auraed. The certificate is still verified against
+    /// `server_name`. Defaults to `server_name`. Differing from it needs the
+    /// `custom-verifier` feature.
+    pub sni_hostname: Option<String>,
+    /// When the socket names an IP address (`10.0.0.3:8080`, or a URI with
+    /// an IP host), verify the server certificate against that address
+    /// instead of `server_name`: it must carry a matching IP SAN, or the
+    /// connect fails with [`crate::ClientError::MissingIpSan`]. No SNI is
+    /// sent on such connections. Host names and unix sockets are verified
+    /// against `server_name` as usual.
+    pub strict_hostname: bool,
+    /// The most certificates the server may present, its own and the
+    /// intermediates it sends, for deployments that know how long their
+    /// chain is. A longer chain fails the handshake with
+    /// [`crate::ClientError::ChainTooDeep`]. Unset, only the limits of the
+    /// TLS library apply.
+    pub max_chain_depth: Option<usize>,
+    /// Log the certificate the server presents on every TLS connection as
+    /// an [`AuditRecord`](crate::AuditRecord), one line of JSON at info
+    /// level on the `aurae::audit` target, for shipping to a SIEM.
+    pub audit_log: bool,
+    /// SHA-256 fingerprints of the server certificates to accept, as hex
+    /// with or without `:` between the bytes. When set, the server leaf
+    /// certificate must match one of them on top of passing CA
+    /// verification, so a rotation can list the old and the new cert
+    /// during the overlap.
+    pub pinned_server_sha256: Vec<String>,
+    /// Key algorithms the client certificate may use, e.g. to allow only
+    /// `ecdsa` and `ed25519`. Checked whenever the certs are loaded, except
+    /// by [`crate::Client::new_minimal`], which never parses them. `None`
+    /// accepts any.
+    pub accepted_key_algorithms: Option<Vec<KeyAlgorithm>>,
+    /// How far the clock may be off when checking the validity period of
+    /// the client certificate, so a certificate issued by a host whose clock
+    /// is slightly ahead is not rejected. Defaults to 5 minutes.
+    #[serde(
+        deserialize_with = "duration::deserialize",
+        serialize_with = "duration::serialize"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "duration::DurationSchema")
+    )]
+    pub clock_skew_tolerance: Duration,
+    /// How often to read the cert files again when the client key does not
+    /// belong to the client certificate, as happens when a rotation is
+    /// caught between replacing the one and the other. Defaults to 3.
+    pub cert_rotation_retries: u32,
+    /// How long to wait before each of the `cert_rotation_retries`.
+    /// Defaults to 100 milliseconds.
+    #[serde(
+        deserialize_with = "duration::deserialize",
+        serialize_with = "duration::serialize"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "duration::DurationSchema")
+    )]
+    pub cert_rotation_retry_delay: Duration,
+    /// Limit on opening the unix or TCP socket.
+    #[serde(
+        deserialize_with = "duration::deserialize_option",
+        serialize_with = "duration::serialize_option"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Option<duration::DurationSchema>")
+    )]
+    pub tcp_connect_timeout: Option<Duration>,
+    /// Limit on the TLS handshake, once the socket is open.
+    #[serde(
+        deserialize_with = "duration::deserialize_option",
+        serialize_with = "duration::serialize_option"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Option<duration::DurationSchema>")
+    )]
+    pub tls_handshake_timeout: Option<Duration>,
+    /// Limit on the whole connect, including HTTP/2 setup.
+    #[serde(
+        deserialize_with = "duration::deserialize_option",
+        serialize_with = "duration::serialize_option"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Option<duration::DurationSchema>")
+    )]
+    pub overall_timeout: Option<Duration>,
+    /// When `AURAE_MAX_CONNECTIONS` clients of this process are already
+    /// connected, wait for one of them to be dropped, up to
+    /// `overall_timeout`, instead of failing right away with
+    /// [`crate::ClientError::ConnectionBudgetExhausted`]. Defaults to
+    /// `true`.
+    pub wait_for_connection_budget: bool,
+    /// Limit on resolving the host of a URI socket, failing with
+    /// [`crate::ClientError::DnsTimeout`] when exceeded. Counts towards
+    /// `tcp_connect_timeout` as well.
+    #[serde(
+        deserialize_with = "duration::deserialize_option",
+        serialize_with = "duration::serialize_option"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Option<duration::DurationSchema>")
+    )]
+    pub dns_timeout: Option<Duration>,
+    /// How long the addresses a host resolved to are reused, by every
+    /// client of the process, before it is looked up again. Saves lookups
+    /// when reconnecting, but also delays noticing address changes when
+    /// re-resolving every `resolve_interval`. Defaults to 0, looking up
+    /// the host on every connect.
+    #[serde(
+        deserialize_with = "duration::deserialize",
+        serialize_with = "duration::serialize"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "duration::DurationSchema")
+    )]
+    pub dns_cache_ttl: Duration,
+    /// How RPCs are spread across the addresses a URI socket resolves to.
+    pub load_balance: LbPolicy,
+    /// Relative share of the RPCs each replica gets under round robin, by
+    /// `ip` or `ip:port`, e.g. `{ "10.0.0.3" = 9, "10.0.0.4" = 1 }` to
+    /// keep most traffic on a local replica. Replicas not listed weigh 1,
+    /// weights must be positive. Unreachable replicas are left out whatever
+    /// their weight.
+    pub replica_weights: BTreeMap<String, u32>,
+    /// Under round robin, send a duplicate of a read RPC to another replica
+    /// when it has not answered within `hedge_delay`, take whichever answer
+    /// comes first and cancel the rest. This cuts tail latency at the cost
+    /// of extra load, as every hedge is a full RPC on another replica, so
+    /// hedges are paid from the retry budget of `retry` and stop when it
+    /// runs dry. Only the RPCs of `hedged_methods` are hedged, and never
+    /// those in `mutating_methods` or streaming RPCs. `None` turns hedging
+    /// off.
+    #[serde(
+        deserialize_with = "duration::deserialize_option",
+        serialize_with = "duration::serialize_option"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Option<duration::DurationSchema>")
+    )]
+    pub hedge_delay: Option<Duration>,
+    /// Hedges sent per RPC under `hedge_delay`, one each `hedge_delay`
+    /// while none has answered, and never more than there are other
+    /// replicas.
+    pub max_hedges: u32,
+    /// Full method paths (`/package.Service/Method`) hedged under
+    /// `hedge_delay`. Defaults to the unary read RPCs of the services
+    /// shipped with this client. Only list RPCs that are safe to run more
+    /// than once, as a hedged RPC may run on every replica.
+    pub hedged_methods: Vec<String>,
+    /// After a TCP address fails to connect, skip it for this long before
+    /// connecting to it again, doubling with each failure in a row up to
+    /// `max_endpoint_cooldown`. Keeps a flapping replica from being
+    /// redialed in a tight loop: pick first moves on to the next address,
+    /// round robin sends RPCs to the other replicas, and a connect with
+    /// every address cooling down fails right away. An address recovers
+    /// on its first successful connect. `None` turns cooldowns off.
+    #[serde(
+        deserialize_with = "duration::deserialize_option",
+        serialize_with = "duration::serialize_option"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Option<duration::DurationSchema>")
+    )]
+    pub endpoint_cooldown: Option<Duration>,
+    /// The longest `endpoint_cooldown` grows to.
+    #[serde(
+        deserialize_with = "duration::deserialize",
+        serialize_with = "duration::serialize"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "duration::DurationSchema")
+    )]
+    pub max_endpoint_cooldown: Duration,
+    /// Which of the addresses a URI socket resolves to are used, and in
+    /// what order.
+    pub ip_family: IpFamily,
+    /// Local address TCP connections are bound to before connecting, for
+    /// egress from a chosen interface or source IP on multi-homed hosts.
+    /// Port `0` picks any free port. Only addresses of the same family as
+    /// this one are connected to. Ignored for unix sockets.
+    pub bind_address: Option<SocketAddr>,
+    /// Disable Nagle's algorithm on TCP connections, so small writes, such
+    /// as the keystrokes of an exec session, go out right away instead of
+    /// waiting to be batched. Turning it off trades latency for slightly
+    /// fewer packets on bulk transfers. Ignored for unix sockets.
+    pub tcp_nodelay: bool,
+    /// `SO_SNDBUF` of TCP connections, set before connecting. Larger
+    /// buffers help throughput on high latency links, at the cost of memory
+    /// per connection and of queueing delay for interactive traffic. The
+    /// kernel may round or double it. `None` keeps the system default, which
+    /// autotunes on Linux. Ignored for unix sockets.
+    pub send_buffer_size: Option<u32>,
+    /// `SO_RCVBUF` of TCP connections, as for `send_buffer_size`. Setting
+    /// it turns off receive buffer autotuning on Linux.
+    pub recv_buffer_size: Option<u32>,
+    /// How often a round robin URI, or a URI with `follow_dns`, is
+    /// re-resolved to pick up addresses that were added or went away. Must
+    /// not be 0.
+    #[serde(
+        deserialize_with = "duration::deserialize_nonzero",
+        serialize_with = "duration::serialize"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "duration::DurationSchema")
+    )]
+    pub resolve_interval: Duration,
+    /// Re-resolve a pick first URI socket every `resolve_interval`, and move
+    /// new RPCs to a fresh connection when the addresses behind it change,
+    /// e.g. for a blue/green flip by DNS. Round robin URIs always do this.
+    pub follow_dns: bool,
+    /// Retrying of unary RPCs that fail with `UNAVAILABLE`.
+    pub retry: RetryOptions,
+    /// Logging of every RPC made through the client.
+    pub rpc_log: RpcLogOptions,
+    /// Replace the connections once they are this old, re-reading the
+    /// certs first, to pick up rotated server certs or rebalance. RPCs in
+    /// flight finish on the old connection. `None` keeps connections for as
+    /// long as they work.
+    #[serde(
+        deserialize_with = "duration::deserialize_option",
+        serialize_with = "duration::serialize_option"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Option<duration::DurationSchema>")
+    )]
+    pub max_connection_age: Option<Duration>,
+    /// Close the connections once no RPC was made for this long, freeing
+    /// them on the server, and connect again on the next RPC, which then
+    /// pays for the handshake. This client sends no HTTP/2 keepalive
+    /// pings, so an idle connection otherwise stays open until auraed or a
+    /// proxy in between drops it. Pick a value below their idle timeouts to
+    /// close connections cleanly first. RPCs still running keep their
+    /// connection until they finish.
+    #[serde(
+        deserialize_with = "duration::deserialize_option",
+        serialize_with = "duration::serialize_option"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Option<duration::DurationSchema>")
+    )]
+    pub idle_timeout: Option<Duration>,
+    /// Stop reconnecting once reconnect attempts kept failing for this
+    /// long since the first of them failed. The connection then stays
+    /// [`Failed`](crate::ConnectionState::Failed), failing RPCs without
+    /// dialing, and [`crate::ConnectionEvent::GaveUp`] is published.
+    /// [`crate::Client::reconnect_now`] starts over. `None` reconnects for
+    /// as long as it takes.
+    #[serde(
+        deserialize_with = "duration::deserialize_option",
+        serialize_with = "duration::serialize_option"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Option<duration::DurationSchema>")
+    )]
+    pub reconnect_deadline: Option<Duration>,
+    /// Limit on RPCs in flight at once across a client and its clones, to
+    /// stay below the server's `max_concurrent_streams`. A server streaming
+    /// RPC counts until its stream is dropped. `None` leaves RPCs unlimited.
+    /// Once saturated, calls are admitted by their
+    /// [`Priority`](crate::Priority). Must not be 0.
+    pub max_concurrent_rpcs: Option<NonZeroUsize>,
+    /// RPCs that wait for a free slot once `max_concurrent_rpcs` are in
+    /// flight. Calls beyond that fail with `RESOURCE_EXHAUSTED`, so `0`
+    /// fails fast instead of queuing.
+    pub max_queued_rpcs: usize,
+    /// Health checks issued at once right after connecting, before the
+    /// client is returned, so the first real RPCs find the HTTP/2 streams
+    /// and flow control windows already set up. `0` disables the warm-up.
+    /// Failed checks do not fail the connect.
+    pub warm_up: usize,
+    /// Send a hello right after connecting, telling auraed the client
+    /// version, the protocol features it understands, the compression it
+    /// accepts and its message size limit, and keep what auraed answers
+    /// about itself, see [`crate::Client::server_capabilities`]. Daemons
+    /// that do not implement hello are connected to as without it.
+    pub hello: bool,
+    /// Connect to daemons serving another Aurae API version than this
+    /// client, logging a warning instead of failing with
+    /// [`crate::ClientError::VersionIncompatible`]. Only for skew known to
+    /// be compatible; daemons that do not report their API version, or do
+    /// not answer within `overall_timeout` (10 seconds when unset), are
+    /// always connected to.
+    pub allow_api_version_skew: bool,
+    /// Reject the RPCs matching `mutating_methods` locally, with
+    /// [`crate::ClientError::ReadOnlyViolation`], so a session can inspect
+    /// auraed without risk of changing it.
+    pub read_only: bool,
+    /// Full method path prefixes (`/package.Service/Method`) refused in read
+    /// only mode. Defaults to the mutating RPCs of the services shipped
+    /// with this client. A prefix ending in `/` covers a whole service.
+    pub mutating_methods: Vec<String>,
+    /// Which RPCs this client may make at all, checked before the read only
+    /// mode.
+    pub method_policy: MethodPolicy,
+    /// Namespace (tenant) every RPC is scoped to, sent as the
+    /// `x-aurae-namespace` header. Must be a valid header value. A handle
+    /// can scope to another one with [`crate::Client::with_namespace`].
+    pub default_namespace: Option<String>,
+    /// Accept any server certificate. Only exists with the
+    /// `dangerous-no-verify` feature, and is refused unless
+    /// `AURAE_I_KNOW_THIS_IS_INSECURE=1` is set as well, and in release
+    /// builds `AURAE_ALLOW_INSECURE_RELEASE=1` too. Every connect logs a
+    /// warning while it is active.
+    #[cfg(feature = "dangerous-no-verify")]
+    pub dangerous_no_verify: bool,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            compression: CompressionMode::default(),
+            transport_mode: TransportMode::default(),
+            server_name: None,
+            sni_hostname: None,
+            strict_hostname: false,
+            max_chain_depth: None,
+            audit_log: false,
+            pinned_server_sha256: Vec::new(),
+            accepted_key_algorithms: None,
+            clock_skew_tolerance: Duration::from_secs(5 * 60),
+            cert_rotation_retries: 3,
+            cert_rotation_retry_delay: Duration::from_millis(100),
+            tcp_connect_timeout: None,
+            tls_handshake_timeout: None,
+            overall_timeout: None,
+            wait_for_connection_budget: true,
+            dns_timeout: None,
+            dns_cache_ttl: Duration::ZERO,
+            load_balance: LbPolicy::default(),
+            replica_weights: BTreeMap::new(),
+            hedge_delay: None,
+            max_hedges: 1,
+            hedged_methods: HEDGED_METHODS
+                .iter()
+                .map(|method| method.to_string())
+                .collect(),
+            endpoint_cooldown: None,
+            max_endpoint_cooldown: Duration::from_secs(60),
+            ip_family: IpFamily::default(),
+            bind_address: None,
+            tcp_nodelay: true,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            resolve_interval: Duration::from_secs(30),
+            follow_dns: false,
+            retry: RetryOptions::default(),
+            rpc_log: RpcLogOptions::default(),
+            max_connection_age: None,
+            idle_timeout: None,
+            reconnect_deadline: None,
+            max_concurrent_rpcs: None,
+            max_queued_rpcs: 1024,
+            warm_up: 0,
+            hello: false,
+            allow_api_version_skew: false,
+            read_only: false,
+            mutating_methods: MUTATING_METHODS
+                .iter()
+                .map(|method| method.to_string())
+                .collect(),
+            method_policy: MethodPolicy::default(),
+            default_namespace: None,
+            #[cfg(feature = "dangerous-no-verify")]
+            dangerous_no_verify: false,
+        }
+    }
+}
+
+/// Retries of unary RPCs, read from the `[connect.retry]` table.
+///
+/// Only `UNAVAILABLE` failures are retried, as those mean the request did
+/// not reach auraed, and `RESOURCE_EXHAUSTED` ones that carry a
+/// `grpc-retry-pushback-ms` or `retry-after` header. A pushback header
+/// replaces the backoff with the delay the server asked for, up to
+/// `max_pushback`, and a malformed one stops retrying. Methods matching
+/// `connect.mutating_methods` are never retried. Retries are disabled by
+/// default.
+///
+/// All retries made through a client (and its clones) draw from a shared
+/// budget, so that a partial outage does not multiply the load on auraed.
+/// Each successful call adds `retry_ratio` retries to the budget, and
+/// `min_retries_per_sec` retries are always available on top of that. When
+/// the budget is spent, calls fail without retrying.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryOptions {
+    /// Attempts per call, including the first. `1` disables retries.
+    pub max_attempts: u32,
+    /// Pause before the first retry, growing linearly with each attempt.
+    #[serde(
+        deserialize_with = "duration::deserialize",
+        serialize_with = "duration::serialize"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "duration::DurationSchema")
+    )]
+    pub backoff: Duration,
+    /// Retries earned per successful call.
+    pub retry_ratio: f64,
+    /// Retries allowed per second regardless of successes.
+    pub min_retries_per_sec: u32,
+    /// Longest server pushback honored, longer ones are cut to this.
+    #[serde(
+        deserialize_with = "duration::deserialize",
+        serialize_with = "duration::serialize"
+    )]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "duration::DurationSchema")
+    )]
+    pub max_pushback: Duration,
+}
+
+impl Default for RetryOptions {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff: Duration::from_millis(100),
+            retry_ratio: 0.1,
+            min_retries_per_sec: 10,
+            max_pushback: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Logging of RPCs through `tracing`, read from the `[connect.rpc_log]`
+/// table. Disabled by default.
+///
+/// Each call is logged once it completes, with its full method path,
+/// duration and gRPC status code. This covers the steady state RPC flow,
+/// connecting is traced separately.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcLogOptions {
+    pub enabled: bool,
+    pub level: RpcLogLevel,
+    /// Include the request and response sizes in bytes, as sent on the
+    /// wire (including the gRPC message framing).
+    pub summarize_payloads: bool,
+    /// Also log the request and response messages of each call as JSON,
+    /// at trace level whatever `level` says, with the `redact` fields
+    /// masked. Messages of server streams are not logged. Needs the
+    /// `payload-log` feature, ignored without it.
+    pub log_payloads: bool,
+    /// Fields masked as `***` in logged payloads, as `.` separated paths of
+    /// field names in their proto or JSON spelling. `*` stands for any
+    /// number of fields, including none, so `*.token` masks every `token`
+    /// field and `cell.*` everything below `cell`. Lists are looked
+    /// through. Defaults to `*.token`, `*.secret` and `*.password`.
+    pub redact: Vec<String>,
+}
+
+impl Default for RpcLogOptions {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            level: RpcLogLevel::default(),
+            summarize_payloads: true,
+            log_payloads: false,
+            redact: ["*.token", "*.secret", "*.password"]
+                .iter()
+                .map(|path| path.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Globs over full method paths (`/package.Service/Method`) restricting the
+/// RPCs a client makes, read from the `[connect.method_policy]` table. `*`
+/// matches any run of characters, so `/aurae.cells.v0.*` covers every cell
+/// RPC.
+///
+/// A method matching any `deny` glob is refused, even when it matches an
+/// `allow` glob as well. When `allow` is not empty, methods matching none of
+/// its globs are refused too. Refused RPCs fail locally with
+/// [`crate::ClientError::MethodNotPermitted`]. Both lists are empty by
+/// default, permitting everything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, deny_unknown_fields)]
+pub struct MethodPolicy {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+/// The algorithm family of a client certificate key, see
+/// [`ConnectOptions::accepted_key_algorithms`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAlgorithm {
+    Rsa,
+    /// Any curve.
+    Ecdsa,
+    Ed25519,
+}
+
+impl KeyAlgorithm {
+    /// The family of an [`crate::X509Details::key_algorithm`].
+    pub(crate) fn from_details_name(name: &str) -> Option<Self> {
+        match name {
+            "RSA" => Some(Self::Rsa),
+            "ED25519" => Some(Self::Ed25519),
+            name if name.starts_with("ECDSA") => Some(Self::Ecdsa),
+            _ => None,
+        }
+    }
+}
+
+/// The level RPCs are logged at, see [`RpcLogOptions`].
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum RpcLogLevel {
+    Trace,
+    #[default]
+    Debug,
+    Info,
+}
+
+/// Load balancing across the replicas behind a URI socket.
+///
+/// Only applies when [`crate::SystemConfig::socket`] is a URI. Unix sockets
+/// and plain socket addresses always have a single connection.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum LbPolicy {
+    /// Use a single connection to the first address that resolves.
+    #[default]
+    PickFirst,
+    /// Connect to every resolved address and rotate RPCs across them,
+    /// weighted by [`ConnectOptions::replica_weights`]. Replicas that stop
+    /// resolving are dropped on the next re-resolve, and unreachable ones
+    /// are skipped.
+    RoundRobin,
+}
+
+/// The framing RPCs are sent in.
+///
+/// gRPC-Web lets auraed sit behind a gRPC-Web proxy (like Envoy's
+/// `grpc_web` filter), e.g. one shared with a web dashboard. Its streaming
+/// semantics differ from native gRPC: trailers arrive in the response body,
+/// server streaming messages may be buffered by the proxy instead of
+/// arriving as they are sent, and client or bidirectional streaming is not
+/// possible at all. The connection to the proxy is still HTTP/2.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum TransportMode {
+    /// Native gRPC over HTTP/2.
+    #[default]
+    Grpc,
+    /// gRPC-Web, as understood by `tonic-web` and gRPC-Web proxies.
+    GrpcWeb,
+}
+
+/// Address family selection for URI sockets that resolve to both IPv4 and
+/// IPv6 addresses, for networks where one of them is broken.
+///
+/// Addresses are tried one after the other, in order, until one connects.
+/// When load balancing, only the selected addresses get a connection.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum IpFamily {
+    /// Use every address, in the order the resolver returned them.
+    #[default]
+    Auto,
+    V4Only,
+    V6Only,
+    /// Use every address, IPv6 ones first.
+    PreferV6,
+}
+
+impl IpFamily {
+    /// Filter and order resolved addresses. Within a family, the order of
+    /// the resolver is kept.
+    pub(crate) fn select(self, mut addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        match self {
+            IpFamily::Auto => {}
+            IpFamily::V4Only => addrs.retain(SocketAddr::is_ipv4),
+            IpFamily::V6Only => addrs.retain(SocketAddr::is_ipv6),
+            IpFamily::PreferV6 => addrs.sort_by_key(SocketAddr::is_ipv4),
+        }
+        addrs
+    }
+}
+
+/// Message compression for RPCs.
+///
+/// Compression trades CPU for bandwidth, which only pays off over network
+/// transports. Over a local unix socket it is pure overhead. Messages are
+/// only compressed once the server accepted the encoding in its answer to
+/// the hello, so any mode but `none` needs `connect.hello`. zstd is not
+/// offered as the tonic version in use only implements gzip.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionMode {
+    #[default]
+    None,
+    Gzip,
+}
+
+impl CompressionMode {
+    pub(crate) fn encoding(self) -> Option<CompressionEncoding> {
+        match self {
+            CompressionMode::None => None,
+            CompressionMode::Gzip => Some(CompressionEncoding::Gzip),
+        }
+    }
+
+    /// The name of the encoding in the hello, `None` for no compression.
+    pub(crate) fn name(self) -> Option<&'static str> {
+        match self {
+            CompressionMode::None => None,
+            CompressionMode::Gzip => Some("gzip"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a resolver could return for a dual stack host.
+    fn mixed() -> Vec<SocketAddr> {
+        ["10.0.0.1:8080", "[fd00::1]:8080", "10.0.0.2:8080", "[fd00::2]:8080"]
+            .into_iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect()
+    }
+
+    fn select(family: IpFamily) -> Vec<String> {
+        family.select(mixed()).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn ip_family_filters_and_orders_addresses() {
+        assert_eq!(
+            select(IpFamily::Auto),
+            [
+                "10.0.0.1:8080",
+                "[fd00::1]:8080",
+                "10.0.0.2:8080",
+                "[fd00::2]:8080"
+            ]
+        );
+        assert_eq!(
+            select(IpFamily::V4Only),
+            ["10.0.0.1:8080", "10.0.0.2:8080"]
+        );
+        assert_eq!(
+            select(IpFamily::V6Only),
+            ["[fd00::1]:8080", "[fd00::2]:8080"]
+        );
+        assert_eq!(
+            select(IpFamily::PreferV6),
+            [
+                "[fd00::1]:8080",
+                "[fd00::2]:8080",
+                "10.0.0.1:8080",
+                "10.0.0.2:8080"
+            ]
+        );
+    }
+
+    #[test]
+    fn transport_mode_defaults_to_native_grpc() {
+        let options: ConnectOptions = toml::from_str("").unwrap();
+        assert_eq!(options.transport_mode, TransportMode::Grpc);
+
+        let options: ConnectOptions =
+            toml::from_str(r#"transport_mode = "grpc_web""#).unwrap();
+        assert_eq!(options.transport_mode, TransportMode::GrpcWeb);
+    }
+}
@@ -0,0 +1,198 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Deserialization of human friendly durations for config files.
+//!
+//! Accepts either a whole number of seconds (`30`) or a string with a unit
+//! suffix (`"250ms"`, `"30s"`, `"5m"`, `"1h"`). Durations serialize back as
+//! such a string, in seconds when whole and milliseconds otherwise.
+
+use serde::de::{Error, Visitor};
+use serde::{Deserializer, Serializer};
+use std::fmt::Formatter;
+use std::time::Duration;
+
+pub(crate) fn parse(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("duration '{value}' is missing a unit"))?;
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("duration '{value}' must start with a number"))?;
+
+    let secs = |per_unit: u64| {
+        amount
+            .checked_mul(per_unit)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("duration '{value}' is too long"))
+    };
+    match unit.trim() {
+        "ms" => Ok(Duration::from_millis(amount)),
+        "s" => secs(1),
+        "m" => secs(60),
+        "h" => secs(60 * 60),
+        unit => Err(format!(
+            "duration '{value}' has unknown unit '{unit}', expected ms, s, m or h"
+        )),
+    }
+}
+
+/// The schema of a duration in a config file, see the module docs.
+#[cfg(feature = "schema")]
+pub(crate) struct DurationSchema;
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for DurationSchema {
+    fn schema_name() -> String {
+        "Duration".into()
+    }
+
+    fn json_schema(
+        _: &mut schemars::gen::SchemaGenerator,
+    ) -> schemars::schema::Schema {
+        serde_json::from_value(serde_json::json!({
+            "description": "A whole number of seconds, or a number with a unit suffix of ms, s, m or h.",
+            "anyOf": [
+                { "type": "integer", "minimum": 0 },
+                { "type": "string", "pattern": "^\\s*[0-9]+\\s*(ms|s|m|h)\\s*$" }
+            ]
+        }))
+        .expect("duration schema is valid")
+    }
+}
+
+pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(DurationVisitor)
+}
+
+/// As [`deserialize`], rejecting `0` for intervals that must tick.
+pub(crate) fn deserialize_nonzero<'de, D>(
+    deserializer: D,
+) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match deserialize(deserializer)? {
+        duration if duration.is_zero() => {
+            Err(D::Error::custom("duration must not be 0"))
+        }
+        duration => Ok(duration),
+    }
+}
+
+pub(crate) fn deserialize_option<'de, D>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize(deserializer).map(Some)
+}
+
+pub(crate) fn serialize<S>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match duration.subsec_millis() {
+        0 => serializer.collect_str(&format_args!("{}s", duration.as_secs())),
+        _ => {
+            serializer.collect_str(&format_args!("{}ms", duration.as_millis()))
+        }
+    }
+}
+
+pub(crate) fn serialize_option<S>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match duration {
+        Some(duration) => serialize(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+struct DurationVisitor;
+
+impl<'de> Visitor<'de> for DurationVisitor {
+    type Value = Duration;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("a number of seconds or a string like \"500ms\"")
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        Ok(Duration::from_secs(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        u64::try_from(v)
+            .map(Duration::from_secs)
+            .map_err(|_| E::custom("duration must not be negative"))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        parse(v).map_err(E::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_duration_units() {
+        assert_eq!(parse("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse("1h"), Ok(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn parse_duration_rejects_bad_input() {
+        assert!(parse("30").is_err());
+        assert!(parse("ms").is_err());
+        assert!(parse("3 days").is_err());
+    }
+
+    #[test]
+    fn parse_duration_rejects_overflow() {
+        let hours = format!("{}h", u64::MAX / 60);
+        assert_eq!(
+            parse(&hours),
+            Err(format!("duration '{hours}' is too long"))
+        );
+        assert!(parse(&format!("{}m", u64::MAX / 60)).is_ok());
+    }
+}
@@ -0,0 +1,433 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Configs assembled from several layers, remembering which layer set each
+//! field, see [`ConfigLayers`].
+
+use super::auth_config::resolve_relative;
+use super::secure_path::{open_resolved, Resolved};
+use super::{config_dir, AuraeConfig};
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Separates the levels of a field in the name of an environment variable
+/// read by [`ConfigLayers::env`].
+const ENV_PREFIX: &str = "AURAE__";
+
+/// Where a field of a [`ConfigLayers`] config came from, in increasing
+/// precedence.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigSource {
+    /// Left unset by every layer.
+    Default,
+    File(PathBuf),
+    Include(PathBuf),
+    /// The name of the environment variable.
+    Env(String),
+    /// Set with [`ConfigLayers::set`].
+    Override,
+}
+
+impl ConfigSource {
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Default => 0,
+            Self::File(_) => 1,
+            Self::Include(_) => 2,
+            Self::Env(_) => 3,
+            Self::Override => 4,
+        }
+    }
+
+    fn file(&self) -> Option<&Path> {
+        match self {
+            Self::File(path) | Self::Include(path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::File(path) => write!(f, "file {}", path.display()),
+            Self::Include(path) => write!(f, "include {}", path.display()),
+            Self::Env(name) => write!(f, "env {name}"),
+            Self::Override => write!(f, "override"),
+        }
+    }
+}
+
+/// The dotted path of a config field, such as `connect.overall_timeout`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FieldPath(String);
+
+impl FieldPath {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for FieldPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl PartialEq<&str> for FieldPath {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+/// Builds an [`AuraeConfig`] from layers of partial config, and explains
+/// which layer each field was taken from.
+///
+/// Whatever order they are added in, layers apply by precedence: defaults,
+/// then [`ConfigLayers::file`], [`ConfigLayers::include`],
+/// [`ConfigLayers::env`] and [`ConfigLayers::set`]. Layers of the same kind
+/// apply in the order they were added. A layer replaces single values and
+/// merges tables, so an include setting `connect.overall_timeout` keeps the
+/// rest of `[connect]` from the file.
+///
+/// Relative cert paths are taken relative to the file that set them.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigLayers {
+    layers: Vec<(ConfigSource, toml::Table)>,
+    /// The files read, checked once the merged config is known to enforce
+    /// secure paths.
+    files: Vec<Resolved>,
+}
+
+impl ConfigLayers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The main config file, read like
+    /// [`AuraeConfig::parse_from_toml_file`].
+    pub fn file(self, path: impl AsRef<Path>) -> Result<Self> {
+        self.read(path.as_ref(), ConfigSource::File)
+    }
+
+    /// A file applied on top of every [`ConfigLayers::file`], such as a
+    /// site wide snippet.
+    pub fn include(self, path: impl AsRef<Path>) -> Result<Self> {
+        self.read(path.as_ref(), ConfigSource::Include)
+    }
+
+    /// Environment variables named `AURAE__<TABLE>__<FIELD>`, such as
+    /// `AURAE__CONNECT__OVERALL_TIMEOUT=3s` for `connect.overall_timeout`.
+    /// Values are read as TOML, and taken as a string when they are not
+    /// valid TOML, so `3s` needs no quotes.
+    pub fn env(self) -> Self {
+        self.env_from(std::env::vars())
+    }
+
+    /// Set the field at the dotted `path` to `value`, read like an
+    /// environment variable of [`ConfigLayers::env`].
+    pub fn set(mut self, path: &str, value: &str) -> Self {
+        let keys: Vec<_> = path.split('.').map(str::to_owned).collect();
+        self.layers.push((ConfigSource::Override, nested(&keys, value)));
+        self
+    }
+
+    /// The config of all layers merged.
+    pub fn build(&self) -> Result<AuraeConfig> {
+        self.resolve().map(|(config, _)| config)
+    }
+
+    /// Every field of the merged config, with the layer that set it, sorted
+    /// by path.
+    pub fn explain(&self) -> Result<Vec<(FieldPath, ConfigSource)>> {
+        let (config, mut sources) = self.resolve()?;
+        let value = serde_json::to_value(&config)
+            .expect("aurae config always serializes to JSON");
+        let mut fields = vec![];
+        leaves(&value, String::new(), &mut fields);
+
+        let mut explained: BTreeMap<_, _> = fields
+            .into_iter()
+            .map(|path| {
+                let source = sources.remove(&path);
+                (path, source.unwrap_or(ConfigSource::Default))
+            })
+            .collect();
+        // set, but left out when serializing, like auth.client_key_passphrase
+        explained.extend(sources);
+        Ok(explained
+            .into_iter()
+            .map(|(path, source)| (FieldPath(path), source))
+            .collect())
+    }
+
+    fn read(
+        mut self,
+        path: &Path,
+        source: fn(PathBuf) -> ConfigSource,
+    ) -> Result<Self> {
+        let (mut file, resolved) = open_resolved(path)?;
+        let mut contents = String::new();
+        let _ = file
+            .read_to_string(&mut contents)
+            .with_context(|| format!("could not read {}", path.display()))?;
+        let table = toml::from_str(&contents).with_context(|| {
+            format!("invalid aurae config {}", path.display())
+        })?;
+        self.layers.push((source(path.to_path_buf()), table));
+        self.files.push(resolved);
+        Ok(self)
+    }
+
+    fn env_from(
+        mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Self {
+        let mut vars: Vec<_> = vars
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect();
+        vars.sort();
+        for (name, value) in vars {
+            let keys: Vec<_> = name[ENV_PREFIX.len()..]
+                .split("__")
+                .map(str::to_lowercase)
+                .collect();
+            self.layers.push((ConfigSource::Env(name), nested(&keys, &value)));
+        }
+        self
+    }
+
+    fn resolve(&self) -> Result<(AuraeConfig, BTreeMap<String, ConfigSource>)> {
+        let mut layers: Vec<_> = self.layers.iter().collect();
+        layers.sort_by_key(|(source, _)| source.rank());
+
+        let mut merged = toml::Table::new();
+        let mut sources = BTreeMap::new();
+        for (source, table) in layers {
+            merge(&mut merged, table, "", source, &mut sources);
+        }
+
+        let config_toml = toml::to_string(&merged)
+            .context("failed to merge the config layers")?;
+        let mut config = AuraeConfig::parse_from_toml(&config_toml)?;
+
+        let auth = &mut config.auth;
+        for (field, path) in [
+            ("auth.ca_crt", &mut auth.ca_crt),
+            ("auth.client_crt", &mut auth.client_crt),
+            ("auth.client_key", &mut auth.client_key),
+        ] {
+            if let Some(file) = sources.get(field).and_then(ConfigSource::file)
+            {
+                resolve_relative(path, &config_dir(file)?);
+            }
+        }
+        if config.auth.enforce_secure_paths {
+            for file in &self.files {
+                file.check()?;
+            }
+        }
+
+        Ok((config, sources))
+    }
+}
+
+/// `value` at the end of the tables named by `keys`.
+fn nested(keys: &[String], value: &str) -> toml::Table {
+    let mut value = parse_value(value);
+    for key in keys.iter().rev() {
+        let mut table = toml::Table::new();
+        let _ = table.insert(key.clone(), value);
+        value = toml::Value::Table(table);
+    }
+    match value {
+        toml::Value::Table(table) => table,
+        _ => toml::Table::new(),
+    }
+}
+
+fn parse_value(raw: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {raw}"))
+        .ok()
+        .filter(|table| table.len() == 1)
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.into()))
+}
+
+fn merge(
+    into: &mut toml::Table,
+    layer: &toml::Table,
+    prefix: &str,
+    source: &ConfigSource,
+    sources: &mut BTreeMap<String, ConfigSource>,
+) {
+    for (key, value) in layer {
+        let path = match prefix {
+            "" => key.clone(),
+            prefix => format!("{prefix}.{key}"),
+        };
+        match (into.get_mut(key), value) {
+            (Some(toml::Value::Table(into)), toml::Value::Table(layer)) => {
+                merge(into, layer, &path, source, sources);
+            }
+            (_, value) => {
+                let nested = format!("{path}.");
+                sources.retain(|field, _| !field.starts_with(&nested));
+                record(value, &path, source, sources);
+                let _ = into.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn record(
+    value: &toml::Value,
+    path: &str,
+    source: &ConfigSource,
+    sources: &mut BTreeMap<String, ConfigSource>,
+) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                record(value, &format!("{path}.{key}"), source, sources);
+            }
+        }
+        _ => {
+            let _ = sources.insert(path.to_owned(), source.clone());
+        }
+    }
+}
+
+/// The dotted paths of the values in `value` that are not objects.
+fn leaves(value: &serde_json::Value, path: String, into: &mut Vec<String>) {
+    match value {
+        serde_json::Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                let path = match path.as_str() {
+                    "" => key.clone(),
+                    prefix => format!("{prefix}.{key}"),
+                };
+                leaves(value, path, into);
+            }
+        }
+        _ => into.push(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn write(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn source_of<'a>(
+        explained: &'a [(FieldPath, ConfigSource)],
+        field: &str,
+    ) -> &'a ConfigSource {
+        &explained.iter().find(|(path, _)| *path == field).unwrap().1
+    }
+
+    #[test]
+    fn later_layers_take_precedence() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let file = write(
+            dir,
+            "config.toml",
+            r#"
+            [auth]
+            ca_crt = "ca.crt"
+            client_crt = "client.crt"
+            client_key = "client.key"
+
+            [system]
+            socket = "/var/run/aurae/aurae.sock"
+
+            [connect]
+            overall_timeout = "1s"
+            max_queued_rpcs = 10
+            "#,
+        );
+        let include = write(
+            dir,
+            "include.toml",
+            r#"
+            [connect]
+            overall_timeout = "2s"
+            max_queued_rpcs = 20
+            "#,
+        );
+
+        // added out of order, applied by precedence
+        let layers = ConfigLayers::new()
+            .set("connect.max_queued_rpcs", "40")
+            .env_from([
+                ("AURAE__CONNECT__MAX_QUEUED_RPCS".into(), "30".into()),
+                ("AURAE__CONNECT__OVERALL_TIMEOUT".into(), "3s".into()),
+                ("HOME".into(), "/root".into()),
+            ])
+            .include(&include)
+            .unwrap()
+            .file(&file)
+            .unwrap();
+
+        let config = layers.build().unwrap();
+        assert_eq!(
+            config.connect.overall_timeout,
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(config.connect.max_queued_rpcs, 40);
+        assert_eq!(config.auth.ca_crt, dir.join("ca.crt").to_string_lossy());
+
+        let explained = layers.explain().unwrap();
+        assert_eq!(
+            source_of(&explained, "connect.max_queued_rpcs"),
+            &ConfigSource::Override
+        );
+        assert_eq!(
+            source_of(&explained, "connect.overall_timeout"),
+            &ConfigSource::Env("AURAE__CONNECT__OVERALL_TIMEOUT".into())
+        );
+        assert_eq!(
+            source_of(&explained, "auth.ca_crt"),
+            &ConfigSource::File(file)
+        );
+        assert_eq!(
+            source_of(&explained, "connect.follow_dns"),
+            &ConfigSource::Default
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_still_rejected() {
+        let layers = ConfigLayers::new()
+            .set("auth.ca_crt", "ca.crt")
+            .set("system.socket", "/var/run/aurae/aurae.sock")
+            .set("connect.overal_timeout", "1s");
+
+        let error = format!("{:#}", layers.build().unwrap_err());
+        assert!(error.contains("overall_timeout"), "{error}");
+    }
+}
@@ -0,0 +1,188 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Adopting a socket handed over through the systemd `LISTEN_FDS`
+//! convention, for clients started by a supervisor that already holds a
+//! connection to auraed.
+
+use super::{AuraeConfig, AuraeSocket, InheritedSocket};
+use anyhow::{anyhow, Context, Result};
+use nix::sys::socket::{
+    getpeername, getsockopt, sockopt, AddressFamily, SockType, SockaddrLike,
+    SockaddrStorage,
+};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd};
+
+/// The first descriptor passed under the convention, `SD_LISTEN_FDS_START`.
+const LISTEN_FDS_START: RawFd = 3;
+
+impl AuraeConfig {
+    /// Connect over the socket passed in through `LISTEN_FDS`, when the
+    /// environment carries one for this process. The config is returned
+    /// unchanged when `LISTEN_FDS` is not set.
+    ///
+    /// `LISTEN_PID` must name the current process, so sockets meant for a
+    /// parent are not picked up by mistake. Only the first descriptor is
+    /// used, and it must be a connected unix or TCP socket. The socket is
+    /// duplicated, the original descriptor stays open.
+    ///
+    /// Like `sd_listen_fds(1)`, this unsets `LISTEN_PID`, `LISTEN_FDS` and
+    /// `LISTEN_FDNAMES` once read, so child processes do not adopt the
+    /// same socket.
+    ///
+    /// The handed over stream backs a single connection: once it drops, the
+    /// client can not reconnect.
+    pub fn adopt_listen_fds(mut self) -> Result<Self> {
+        let pid = std::env::var("LISTEN_PID").ok();
+        let fds = std::env::var("LISTEN_FDS").ok();
+        if fds.is_some() {
+            for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+                std::env::remove_var(var);
+            }
+        }
+        let Some(fd) =
+            listen_fd(pid.as_deref(), fds.as_deref(), std::process::id())?
+        else {
+            return Ok(self);
+        };
+
+        // SAFETY: the convention hands descriptors from LISTEN_FDS_START on
+        // to this process, and the borrow only lives for the dup. A closed
+        // descriptor fails the dup rather than being adopted.
+        let socket = unsafe { BorrowedFd::borrow_raw(fd) }
+            .try_clone_to_owned()
+            .with_context(|| {
+                format!("LISTEN_FDS passed fd {fd}, but it is not open")
+            })?;
+        check_connected_stream(socket.as_fd()).with_context(|| {
+            format!("LISTEN_FDS passed fd {fd}, which cannot be adopted")
+        })?;
+
+        self.system.socket =
+            AuraeSocket::Inherited(InheritedSocket::new(socket));
+        Ok(self)
+    }
+}
+
+/// The descriptor to adopt given the `LISTEN_PID` and `LISTEN_FDS` values,
+/// `None` when nothing was passed.
+fn listen_fd(
+    pid: Option<&str>,
+    fds: Option<&str>,
+    current_pid: u32,
+) -> Result<Option<RawFd>> {
+    let Some(fds) = fds else {
+        return Ok(None);
+    };
+
+    let pid = pid.ok_or_else(|| {
+        anyhow!("LISTEN_FDS is set without LISTEN_PID, cannot tell who the sockets are for")
+    })?;
+    let pid: u32 = pid.trim().parse().map_err(|_| {
+        anyhow!("LISTEN_PID is '{pid}', which is not a process id")
+    })?;
+    if pid != current_pid {
+        return Err(anyhow!(
+            "LISTEN_PID is {pid}, the sockets were passed to another process than this one ({current_pid})"
+        ));
+    }
+
+    let count: u32 = fds.trim().parse().map_err(|_| {
+        anyhow!("LISTEN_FDS is '{fds}', which is not a number of descriptors")
+    })?;
+    if count == 0 {
+        return Err(anyhow!("LISTEN_FDS is 0, no socket was passed"));
+    }
+
+    Ok(Some(LISTEN_FDS_START))
+}
+
+/// Fail unless `fd` is a unix or TCP stream socket connected to a peer,
+/// rather than leaving whatever it is to fail the first RPC.
+fn check_connected_stream(fd: BorrowedFd<'_>) -> Result<()> {
+    let kind = getsockopt(&fd, sockopt::SockType)
+        .map_err(|e| anyhow!("it is not a socket: {e}"))?;
+    if kind != SockType::Stream {
+        return Err(anyhow!("it is a {kind:?} socket, not a stream socket"));
+    }
+
+    let peer = getpeername::<SockaddrStorage>(fd.as_raw_fd())
+        .map_err(|e| anyhow!("the socket is not connected: {e}"))?;
+    match peer.family() {
+        Some(
+            AddressFamily::Unix | AddressFamily::Inet | AddressFamily::Inet6,
+        ) => Ok(()),
+        family => Err(anyhow!(
+            "the socket is connected over {family:?}, not unix or TCP"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
+
+    #[test]
+    fn nothing_passed_without_listen_fds() {
+        assert_eq!(listen_fd(None, None, 42).unwrap(), None);
+        assert_eq!(listen_fd(Some("42"), None, 42).unwrap(), None);
+    }
+
+    #[test]
+    fn first_fd_is_adopted() {
+        assert_eq!(listen_fd(Some("42"), Some("1"), 42).unwrap(), Some(3));
+        assert_eq!(listen_fd(Some("42"), Some("2"), 42).unwrap(), Some(3));
+    }
+
+    #[test]
+    fn sockets_for_another_process_are_rejected() {
+        let err = listen_fd(Some("7"), Some("1"), 42).unwrap_err();
+        assert!(err.to_string().contains("another process"), "{err}");
+    }
+
+    #[test]
+    fn malformed_environment_is_rejected() {
+        for (pid, fds, expected) in [
+            (None, "1", "without LISTEN_PID"),
+            (Some("self"), "1", "not a process id"),
+            (Some("42"), "one", "not a number"),
+            (Some("42"), "-1", "not a number"),
+            (Some("42"), "0", "no socket"),
+        ] {
+            let err = listen_fd(pid, Some(fds), 42).unwrap_err();
+            assert!(err.to_string().contains(expected), "{err}");
+        }
+    }
+
+    #[test]
+    fn only_connected_stream_sockets_are_adopted() {
+        let (stream, _peer) = UnixStream::pair().unwrap();
+        check_connected_stream(stream.as_fd()).unwrap();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let listener = UnixListener::bind(tmp.path().join("l.sock")).unwrap();
+        let (datagram, _peer) = UnixDatagram::pair().unwrap();
+        let file = std::fs::File::create(tmp.path().join("file")).unwrap();
+        for (fd, expected) in [
+            (listener.as_fd(), "not connected"),
+            (datagram.as_fd(), "not a stream socket"),
+            (file.as_fd(), "not a socket"),
+        ] {
+            let err = check_connected_stream(fd).unwrap_err();
+            assert!(err.to_string().contains(expected), "{err}");
+        }
+    }
+}
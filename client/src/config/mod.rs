@@ -18,40 +18,101 @@
 //! [`AuraeConfig::try_default()`] follows an ordered priority for searching for
 //! configuration on a client's machine.
 //!
-//! 1. ${HOME}/.aurae/config
-//! 2. /etc/aurae/config
-//! 3. /var/lib/aurae/config
+//! 1. `$AURAE_CONFIG`, where `-` reads the config from stdin
+//! 2. ${HOME}/.aurae/config
+//! 3. /etc/aurae/config
+//! 4. /var/lib/aurae/config
+//! 5. the certs of [`AuraeConfig::default()`], if they all exist
+//!
+//! Named profiles can sit next to the default config as
+//! `${HOME}/.aurae/<name>.toml`, see [`AuraeConfig::list_profiles()`].
+//!
+//! Inside a Kubernetes pod, [`AuraeConfig::in_cluster()`] assembles the config
+//! from well-known secret mounts instead.
+//!
+//! Symlinks in the config and cert paths are followed. Setting
+//! `auth.enforce_secure_paths` additionally requires every link and the final
+//! target to be owned by the current user or root.
 
 pub use self::{
-    auth_config::AuthConfig, cert_material::CertMaterial,
-    client_cert_details::ClientCertDetails, system_config::AuraeSocket,
-    system_config::SystemConfig,
+    auth_config::AuthConfig, auth_config::Pkcs11Key, ca_fetch::CaFetchOptions,
+    cert_material::CertMaterial, client_cert_details::ClientCertDetails,
+    connect_options::CompressionMode, connect_options::ConnectOptions,
+    connect_options::IpFamily, connect_options::KeyAlgorithm,
+    connect_options::LbPolicy, connect_options::MethodPolicy,
+    connect_options::RetryOptions, connect_options::RpcLogLevel,
+    connect_options::RpcLogOptions, connect_options::TransportMode,
+    layers::ConfigLayers, layers::ConfigSource, layers::FieldPath,
+    profile::ProfileInfo, ssh_jump::SshJump, system_config::AuraeSocket,
+    system_config::InheritedSocket, system_config::ParseSocketError,
+    system_config::SocketKind, system_config::SystemConfig,
+    x509_details::AuditRecord, x509_details::ExtendedKeyUsage,
+    x509_details::KeyUsage, x509_details::X509Change,
+    x509_details::X509Details, x509_details::X509Diff,
+    x509_details::AUDIT_SCHEMA_VERSION,
 };
 use anyhow::{anyhow, Context, Result};
-use serde::Deserialize;
-use std::fs::File;
+use secure_path::open_resolved;
+use serde::{Deserialize, Serialize};
 use std::io::prelude::*;
-use std::path::Path;
-use x509_details::X509Details;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use x509_certificate::DigestAlgorithm;
 
 mod auth_config;
-mod cert_material;
+#[cfg(feature = "config-binary")]
+mod binary;
+mod bundle;
+pub(crate) mod ca_fetch;
+mod cache;
+pub(crate) mod cert_material;
 mod client_cert_details;
+mod connect_options;
+mod duration;
+mod layers;
+mod listen_fds;
+mod pid;
+mod profile;
+mod secure_path;
+pub(crate) mod ssh_jump;
 mod system_config;
-mod x509_details;
+mod unknown_field;
+pub(crate) mod x509_details;
+
+/// Where in-cluster certs are mounted, see [`AuraeConfig::in_cluster()`].
+const IN_CLUSTER_MOUNT: &str = "/var/run/secrets/aurae";
+pub(crate) const IN_CLUSTER_SOCKET_ENV: &str = "AURAE_SOCKET";
+/// Overrides the search paths of [`AuraeConfig::try_default()`].
+const CONFIG_ENV: &str = "AURAE_CONFIG";
+/// Where auraed listens by default.
+const DEFAULT_SOCKET: &str = "/var/run/aurae/aurae.sock";
 
 /// Configuration for AuraeScript client
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(deny_unknown_fields)]
 pub struct AuraeConfig {
     /// Authentication material
     pub auth: AuthConfig,
     /// System configuration
     pub system: SystemConfig,
+    /// Connection tuning
+    #[serde(default)]
+    pub connect: ConnectOptions,
 }
 
 impl AuraeConfig {
     /// Attempt to easy-load Aurae configuration from well-known locations.
     pub fn try_default() -> Result<Self> {
+        match std::env::var(CONFIG_ENV) {
+            Ok(path) if path == "-" => {
+                return Self::from_reader(std::io::stdin().lock())
+                    .context("failed to read config from stdin");
+            }
+            Ok(path) => return Self::parse_from_toml_file(path),
+            Err(_) => {}
+        }
+
         let home = std::env::var("HOME")
             .expect("missing $HOME environmental variable");
 
@@ -73,15 +134,98 @@ impl AuraeConfig {
             }
         }
 
+        let config = Self::default();
+        if [
+            &config.auth.ca_crt,
+            &config.auth.client_crt,
+            &config.auth.client_key,
+        ]
+        .into_iter()
+        .all(|path| Path::new(path).exists())
+        {
+            return Ok(config);
+        }
+
         Err(anyhow!("unable to find valid config file"))
     }
 
+    /// Assemble configuration from the conventional in-cluster mounts, for
+    /// Aurae tooling running as a Kubernetes pod.
+    ///
+    /// The certs are expected under `/var/run/secrets/aurae/` as `ca.crt`,
+    /// `client.crt` and `client.key`. The socket is taken from the
+    /// `AURAE_SOCKET` environment variable (typically set through the
+    /// downward API), falling back to the `socket` file in the same mount.
+    pub fn in_cluster() -> Result<Self> {
+        Self::in_cluster_at(Path::new(IN_CLUSTER_MOUNT))
+    }
+
+    fn in_cluster_at(mount: &Path) -> Result<Self> {
+        let ca_crt = mount.join("ca.crt");
+        let client_crt = mount.join("client.crt");
+        let client_key = mount.join("client.key");
+        let socket_file = mount.join("socket");
+
+        let socket = match std::env::var(IN_CLUSTER_SOCKET_ENV) {
+            Ok(socket) => Some(socket),
+            Err(_) => std::fs::read_to_string(&socket_file)
+                .ok()
+                .map(|socket| socket.trim().to_string()),
+        };
+
+        let mut missing: Vec<String> = [&ca_crt, &client_crt, &client_key]
+            .into_iter()
+            .filter(|path| !path.exists())
+            .map(|path| path.display().to_string())
+            .collect();
+
+        if socket.is_none() {
+            missing.push(format!(
+                "${IN_CLUSTER_SOCKET_ENV} or {}",
+                socket_file.display()
+            ));
+        }
+
+        let socket = match socket {
+            Some(socket) if missing.is_empty() => socket,
+            _ => {
+                return Err(anyhow!(
+                    "incomplete in-cluster aurae config, missing: {}",
+                    missing.join(", ")
+                ))
+            }
+        };
+
+        let auth = AuthConfig {
+            ca_crt: ca_crt.to_string_lossy().into(),
+            client_crt: client_crt.to_string_lossy().into(),
+            client_key: client_key.to_string_lossy().into(),
+            enforce_secure_paths: false,
+            strict: false,
+            expected_server_cn: None,
+            expected_server_spiffe: None,
+            expected_server_trust_domain: None,
+            client_key_passphrase: None,
+            ca_fetch: CaFetchOptions::default(),
+            pkcs11: None,
+        };
+        let system = SystemConfig { socket: socket.parse()?, ssh_jump: None };
+        Ok(Self { auth, system, connect: ConnectOptions::default() })
+    }
+
     /// Attempt to parse a config file into memory.
+    ///
+    /// Relative cert paths are taken relative to the directory `path` is in
+    /// (not the target of a symlink), rather than the working directory.
+    ///
+    /// If the parsed config enables `auth.enforce_secure_paths`, the config
+    /// file itself is held to the same ownership rules as the cert paths.
     pub fn parse_from_toml_file<P: AsRef<Path>>(
         path: P,
     ) -> Result<AuraeConfig> {
+        let config_dir = config_dir(path.as_ref())?;
+        let (mut file, resolved) = open_resolved(path)?;
         let mut config_toml = String::new();
-        let mut file = File::open(path)?;
 
         if file
             .read_to_string(&mut config_toml)
@@ -91,11 +235,92 @@ impl AuraeConfig {
             return Err(anyhow!("empty config"));
         }
 
+        let mut config = AuraeConfig::parse_from_toml(&config_toml)?;
+        config.auth.resolve_relative_to(&config_dir);
+        if config.auth.enforce_secure_paths {
+            resolved.check()?;
+        }
+
+        Ok(config)
+    }
+
+    /// Read a TOML config from a stream, such as stdin.
+    ///
+    /// A stream has no name to detect the format from, so it is always taken
+    /// to be TOML. Relative cert paths are left relative to the working
+    /// directory.
+    pub fn from_reader(mut reader: impl Read) -> Result<AuraeConfig> {
+        let mut config_toml = String::new();
+        if reader
+            .read_to_string(&mut config_toml)
+            .with_context(|| "could not read AuraeConfig toml")?
+            == 0
+        {
+            return Err(anyhow!("empty config"));
+        }
+
         AuraeConfig::parse_from_toml(&config_toml)
     }
 
+    /// Unknown keys are rejected, with a suggestion when they look like a
+    /// typo of a valid one.
     pub fn parse_from_toml(config_toml: &str) -> Result<AuraeConfig> {
-        Ok(toml::from_str(config_toml)?)
+        toml::from_str(config_toml)
+            .map_err(|e| match unknown_field::did_you_mean(e.message()) {
+                Some(hint) => anyhow::Error::new(e).context(hint),
+                None => anyhow::Error::new(e),
+            })
+            .context("invalid aurae config")
+    }
+
+    /// Reads a config written by [`AuraeConfig::to_bytes`], for images that
+    /// bake their config in at build time instead of parsing TOML at
+    /// startup. Bytes from a client with a different binary format version
+    /// are rejected.
+    #[cfg(feature = "config-binary")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<AuraeConfig> {
+        binary::decode(bytes)
+    }
+
+    /// The compact binary form of this config, see
+    /// [`AuraeConfig::from_bytes`]. Like [`AuraeConfig::checksum`], it
+    /// leaves out `auth.client_key_passphrase`.
+    #[cfg(feature = "config-binary")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        binary::encode(self)
+    }
+
+    /// A SHA-256 over the settings of this config, as hex, for asserting
+    /// that every client of a fleet runs the intended config.
+    ///
+    /// The hash covers the config as loaded, with relative cert paths
+    /// resolved and defaults filled in, so configs that only differ in
+    /// formatting, key order or spelled out defaults hash the same. Map keys
+    /// are sorted before hashing. `auth.client_key_passphrase` is left out,
+    /// so the checksum can be shared without giving anything away, and so
+    /// is the descriptor of an inherited socket.
+    pub fn checksum(&self) -> String {
+        let value = serde_json::to_value(self)
+            .expect("aurae config always serializes to JSON");
+        let canonical = sorted(value).to_string();
+        DigestAlgorithm::Sha256
+            .digest_data(canonical.as_bytes())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    /// Whether [`AuraeConfig::checksum`] is `expected`, ignoring case and
+    /// surrounding whitespace.
+    pub fn verify_checksum(&self, expected: &str) -> bool {
+        self.checksum().eq_ignore_ascii_case(expected.trim())
+    }
+
+    /// The JSON Schema of the config file, for validating configs in CI and
+    /// for editor completion. Field docs become the descriptions.
+    #[cfg(feature = "schema")]
+    pub fn json_schema() -> schemars::schema::RootSchema {
+        schemars::schema_for!(AuraeConfig)
     }
 
     /// Create a new AuraeConfig from given options
@@ -105,7 +330,12 @@ impl AuraeConfig {
     /// * `ca_crt` - Path to ca cert
     /// * `client_crt` - Path to client cert
     /// * `client_key` - Path to client key
-    /// * `socket` - Address to auraed
+    /// * `socket` - Address to auraed, read like the `system.socket` of a
+    ///   config file, see [`SystemConfig::socket`]: `127.0.0.1:8443` and
+    ///   `[::1]:8443` connect over TCP, `auraed.example.com:8443` as an
+    ///   `https` URI, and anything else that is not a URI, such as
+    ///   `./weird:path`, is a unix socket path. Strings that fail to parse as
+    ///   a socket are taken as a path as well.
     ///
     /// Note: A new client is required for every independent execution of this process.
     pub fn from_options<
@@ -125,9 +355,99 @@ impl AuraeConfig {
             client_key.into(),
             socket.into(),
         );
-        let auth = AuthConfig { ca_crt, client_crt, client_key };
-        let system = SystemConfig { socket: AuraeSocket::Path(socket.into()) };
-        Self { auth, system }
+        let auth = AuthConfig {
+            ca_crt,
+            client_crt,
+            client_key,
+            enforce_secure_paths: false,
+            strict: false,
+            expected_server_cn: None,
+            expected_server_spiffe: None,
+            expected_server_trust_domain: None,
+            client_key_passphrase: None,
+            ca_fetch: CaFetchOptions::default(),
+            pkcs11: None,
+        };
+        let socket =
+            socket.parse().unwrap_or_else(|_| AuraeSocket::Path(socket.into()));
+        let system = SystemConfig { socket, ssh_jump: None };
+        Self { auth, system, connect: ConnectOptions::default() }
+    }
+}
+
+/// The conventional layout, with the socket at `/var/run/aurae/aurae.sock`
+/// and the certs `hack/certgen` and `hack/certgen-client` generate in
+/// `${HOME}/.aurae/pki/`. Nothing is read or checked, so fields can be
+/// overridden before connecting. Without `$HOME` the cert paths start with
+/// `~/`, and are not expanded.
+impl Default for AuraeConfig {
+    fn default() -> Self {
+        let home = std::env::var("HOME").unwrap_or_else(|_| "~".into());
+        let pki = Path::new(&home).join(".aurae/pki");
+        let cert = |name: &str| pki.join(name).to_string_lossy().into_owned();
+        let auth = AuthConfig {
+            ca_crt: cert("ca.crt"),
+            client_crt: cert("_signed.client.nova.crt"),
+            client_key: cert("client.nova.key"),
+            enforce_secure_paths: false,
+            strict: false,
+            expected_server_cn: None,
+            expected_server_spiffe: None,
+            expected_server_trust_domain: None,
+            client_key_passphrase: None,
+            ca_fetch: CaFetchOptions::default(),
+            pkcs11: None,
+        };
+        let system = SystemConfig {
+            socket: AuraeSocket::Path(DEFAULT_SOCKET.into()),
+            ssh_jump: None,
+        };
+        Self { auth, system, connect: ConnectOptions::default() }
+    }
+}
+
+/// `value` with the keys of every object in sorted order, whether or not
+/// `serde_json` preserves insertion order.
+fn sorted(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            entries
+                .into_iter()
+                .map(|(key, value)| (key, sorted(value)))
+                .collect::<serde_json::Map<_, _>>()
+                .into()
+        }
+        serde_json::Value::Array(values) => {
+            values.into_iter().map(sorted).collect::<Vec<_>>().into()
+        }
+        value => value,
+    }
+}
+
+/// The absolute directory a config file at `path` is in.
+fn config_dir(path: &Path) -> Result<PathBuf> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    if dir.is_absolute() {
+        return Ok(dir.to_path_buf());
+    }
+    let cwd = std::env::current_dir()
+        .context("failed to resolve the config directory")?;
+    Ok(cwd.join(dir))
+}
+
+/// Parses a whole TOML config document, e.g. one embedded in a test or
+/// passed through an environment variable.
+impl FromStr for AuraeConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(config_toml: &str) -> Result<Self> {
+        Self::parse_from_toml(config_toml)
     }
 }
 
@@ -135,7 +455,8 @@ impl AuraeConfig {
 mod tests {
     use super::*;
     use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
-    use std::str::FromStr;
+    use std::num::NonZeroUsize;
+    use std::time::Duration;
 
     fn get_input(socket: &str) -> String {
         const INPUT: &str = r#"
@@ -193,6 +514,100 @@ socket = "#;
         assert_eq!(addr.scope_id(), 0);
     }
 
+    #[test]
+    fn relative_cert_paths_are_resolved_against_the_config_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("config");
+        std::fs::write(
+            &path,
+            r#"
+[auth]
+ca_crt = "./certs/ca.crt"
+client_crt = "/etc/aurae/client.crt"
+client_key = "~/.aurae/client.key"
+
+[system]
+socket = "/var/run/aurae/aurae.sock"
+"#,
+        )
+        .unwrap();
+
+        let config = AuraeConfig::parse_from_toml_file(&path).unwrap();
+        assert_eq!(Path::new(&config.auth.ca_crt), dir.join("./certs/ca.crt"));
+        assert_eq!(config.auth.client_crt, "/etc/aurae/client.crt");
+        assert_eq!(config.auth.client_key, "~/.aurae/client.key");
+    }
+
+    #[test]
+    fn ca_urls_are_not_resolved_as_paths() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("config");
+        std::fs::write(
+            &path,
+            r#"
+[auth]
+ca_crt = "https://pki.example.com/aurae/ca.crt"
+client_crt = "./client.crt"
+client_key = "./client.key"
+
+[auth.ca_fetch]
+pinned_sha256 = "ab:cd"
+cache_ttl = "90s"
+
+[system]
+socket = "/var/run/aurae/aurae.sock"
+"#,
+        )
+        .unwrap();
+
+        let config = AuraeConfig::parse_from_toml_file(&path).unwrap();
+        assert_eq!(config.auth.ca_crt, "https://pki.example.com/aurae/ca.crt");
+        assert_eq!(
+            config.auth.ca_fetch.pinned_sha256.as_deref(),
+            Some("ab:cd")
+        );
+        assert_eq!(config.auth.ca_fetch.cache_ttl, Duration::from_secs(90));
+        assert_eq!(config.auth.ca_fetch.timeout, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn client_identity_may_be_left_out() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("config");
+        std::fs::write(
+            &path,
+            r#"
+[auth]
+ca_crt = "ca.crt"
+
+[system]
+socket = "/var/run/aurae/aurae.sock"
+"#,
+        )
+        .unwrap();
+
+        let config = AuraeConfig::parse_from_toml_file(&path).unwrap();
+        assert!(!config.auth.has_client_identity());
+        assert_eq!(config.auth.client_crt, "");
+        assert_eq!(config.auth.ca_crt, dir.join("ca.crt").to_string_lossy());
+    }
+
+    #[test]
+    fn in_cluster_reports_missing_mounts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mount = tmp.path();
+        std::fs::write(mount.join("ca.crt"), "").unwrap();
+
+        let err = AuraeConfig::in_cluster_at(&mount).unwrap_err().to_string();
+
+        assert!(!err.contains("ca.crt"));
+        assert!(err.contains("client.crt"));
+        assert!(err.contains("client.key"));
+    }
+
     #[test]
     fn can_parse_toml_config_socket_ipv4() {
         let input = get_input("127.1.2.3:1234");
@@ -208,4 +623,203 @@ socket = "#;
         assert_eq!(*addr.ip(), Ipv4Addr::from_str("127.1.2.3").unwrap());
         assert_eq!(addr.port(), 1234);
     }
+
+    #[test]
+    fn can_parse_config_from_str() {
+        let config: AuraeConfig =
+            get_input("/var/run/aurae/aurae.sock").parse().unwrap();
+
+        assert_eq!(config.auth.ca_crt, "~/.aurae/pki/ca.crt");
+    }
+
+    #[test]
+    fn can_read_config_from_a_stream() {
+        let input = get_input("/var/run/aurae/aurae.sock");
+        let config = AuraeConfig::from_reader(input.as_bytes()).unwrap();
+
+        assert_eq!(config.auth.ca_crt, "~/.aurae/pki/ca.crt");
+        assert!(AuraeConfig::from_reader(std::io::empty()).is_err());
+    }
+
+    #[test]
+    fn from_options_reads_host_port_sockets_as_tcp() {
+        let socket = |socket| {
+            AuraeConfig::from_options("ca", "crt", "key", socket).system.socket
+        };
+
+        assert!(matches!(
+            socket("127.0.0.1:8443"),
+            AuraeSocket::Addr(addr) if addr.to_string() == "127.0.0.1:8443"
+        ));
+        assert!(matches!(
+            socket("[::1]:8443"),
+            AuraeSocket::Addr(addr) if addr.to_string() == "[::1]:8443"
+        ));
+        assert!(matches!(
+            socket("auraed.example.com:8443"),
+            AuraeSocket::Uri(uri) if uri.scheme_str() == Some("https")
+        ));
+        assert!(matches!(
+            socket("./weird:path"),
+            AuraeSocket::Path(path) if path.to_str() == Some("./weird:path")
+        ));
+    }
+
+    #[test]
+    fn default_uses_the_conventional_paths() {
+        let config = AuraeConfig::default();
+
+        assert!(matches!(
+            config.system.socket,
+            AuraeSocket::Path(path) if path.to_str() == Some(DEFAULT_SOCKET)
+        ));
+        assert!(config.auth.ca_crt.ends_with("/.aurae/pki/ca.crt"));
+        assert!(config
+            .auth
+            .client_key
+            .ends_with("/.aurae/pki/client.nova.key"));
+    }
+
+    #[test]
+    fn checksum_ignores_formatting_and_spelled_out_defaults() {
+        let input = get_input("/var/run/aurae/aurae.sock");
+        let config = AuraeConfig::from_str(&input).unwrap();
+        let spelled_out = AuraeConfig::from_str(&format!(
+            "{input}\n[connect]\nresolve_interval = \"30s\"\nread_only = false\n"
+        ))
+        .unwrap();
+        let changed = AuraeConfig::from_str(&format!(
+            "{input}\n[connect]\nread_only = true\n"
+        ))
+        .unwrap();
+
+        let checksum = config.checksum();
+        assert_eq!(checksum.len(), 64);
+        assert_eq!(spelled_out.checksum(), checksum);
+        assert_ne!(changed.checksum(), checksum);
+        assert!(config.verify_checksum(&checksum.to_uppercase()));
+        assert!(!changed.verify_checksum(&checksum));
+    }
+
+    #[test]
+    fn zero_resolve_interval_is_rejected() {
+        let input = format!(
+            "{}\n[connect]\nresolve_interval = \"0s\"\n",
+            get_input("/var/run/aurae/aurae.sock")
+        );
+        let err = AuraeConfig::from_str(&input).unwrap_err();
+        assert!(format!("{err:#}").contains("must not be 0"), "{err:#}");
+    }
+
+    #[test]
+    fn zero_max_concurrent_rpcs_is_rejected() {
+        let input = format!(
+            "{}\n[connect]\nmax_concurrent_rpcs = 0\n",
+            get_input("/var/run/aurae/aurae.sock")
+        );
+        assert!(AuraeConfig::from_str(&input).is_err());
+
+        let input = input.replace("= 0", "= 8");
+        let config = AuraeConfig::from_str(&input).unwrap();
+        assert_eq!(config.connect.max_concurrent_rpcs, NonZeroUsize::new(8));
+    }
+
+    #[test]
+    fn missing_table_is_reported() {
+        let err =
+            AuraeConfig::from_str("[auth]\nca_crt = \"ca.crt\"").unwrap_err();
+
+        assert!(format!("{err:#}").contains("missing field"));
+    }
+
+    #[test]
+    fn misspelled_key_gets_a_suggestion() {
+        let input = get_input("/var/run/aurae/aurae.sock")
+            .replace("client_crt", "client_cert");
+
+        let err = format!("{:#}", AuraeConfig::from_str(&input).unwrap_err());
+
+        assert!(err.contains("did you mean `client_crt`?"), "{err}");
+        assert!(err.contains("line 4"), "{err}");
+    }
+
+    #[test]
+    fn malformed_toml_is_reported() {
+        let err = AuraeConfig::from_str("[auth").unwrap_err();
+
+        assert!(format!("{err:#}").starts_with("invalid aurae config"));
+    }
+
+    /// Check `value` against the parts of `schema` config files use:
+    /// references, alternatives, enums, object properties and scalar types.
+    #[cfg(feature = "schema")]
+    fn conforms(
+        value: &serde_json::Value,
+        schema: &serde_json::Value,
+        root: &serde_json::Value,
+    ) -> bool {
+        use serde_json::Value;
+
+        if let Some(Value::String(path)) = schema.get("$ref") {
+            let name = path.trim_start_matches("#/definitions/");
+            return conforms(value, &root["definitions"][name], root);
+        }
+        for key in ["anyOf", "oneOf"] {
+            if let Some(Value::Array(options)) = schema.get(key) {
+                return options
+                    .iter()
+                    .any(|schema| conforms(value, schema, root));
+            }
+        }
+        if let Some(Value::Array(allowed)) = schema.get("enum") {
+            return allowed.contains(value);
+        }
+
+        match schema.get("type").and_then(Value::as_str) {
+            Some("object") => {
+                let Some(object) = value.as_object() else {
+                    return false;
+                };
+                let properties = &schema["properties"];
+                let required = schema["required"].as_array();
+                required.into_iter().flatten().all(|key| {
+                    key.as_str().is_some_and(|key| object.contains_key(key))
+                }) && object.iter().all(|(key, value)| {
+                    properties
+                        .get(key)
+                        .is_some_and(|schema| conforms(value, schema, root))
+                })
+            }
+            Some("string") => value.is_string(),
+            Some("boolean") => value.is_boolean(),
+            Some("integer") => value.is_u64() || value.is_i64(),
+            _ => true,
+        }
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn schema_accepts_a_valid_config() {
+        let schema = serde_json::to_value(AuraeConfig::json_schema()).unwrap();
+        let input = format!(
+            "{}\n[connect]\nload_balance = \"round_robin\"\ntransport_mode = \"grpc_web\"\nresolve_interval = \"30s\"\n\n[connect.retry]\nmax_attempts = 3\n",
+            get_input("/var/run/aurae/aurae.sock")
+        );
+        let _ = AuraeConfig::from_str(&input).unwrap();
+        let config: serde_json::Value = toml::from_str(&input).unwrap();
+
+        assert!(conforms(&config, &schema, &schema), "{schema:#}");
+
+        let mut misspelled = config.clone();
+        misspelled["auth"]["client_cert"] = "client.crt".into();
+        assert!(!conforms(&misspelled, &schema, &schema));
+
+        let mut unknown_mode = config;
+        unknown_mode["connect"]["transport_mode"] = "http3".into();
+        assert!(!conforms(&unknown_mode, &schema, &schema));
+
+        let parsed: schemars::schema::RootSchema =
+            serde_json::from_value(schema.clone()).unwrap();
+        assert_eq!(serde_json::to_value(parsed).unwrap(), schema);
+    }
 }
\ No newline at end of file
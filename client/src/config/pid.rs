@@ -0,0 +1,201 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Finding the socket of a running auraed from its PID, for hosts that run
+//! several daemons side by side.
+
+use super::{AuraeConfig, AuraeSocket, SystemConfig};
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// `__SO_ACCEPTCON` in the flags column of `/proc/net/unix`, set on
+/// listening sockets.
+const SO_ACCEPTCON: u32 = 0x10000;
+/// The name auraed gives its socket inside the runtime dir.
+const SOCKET_NAME: &str = "aurae.sock";
+
+impl AuraeConfig {
+    /// Build a config for the auraed running as `pid`.
+    ///
+    /// The socket is the unix socket the process is listening on, found
+    /// through `/proc/<pid>/fd` and `/proc/<pid>/net/unix`, falling back to
+    /// the `--socket` argument it was started with. The client certs and
+    /// connect options come from the default config, see
+    /// [`AuraeConfig::try_default()`].
+    ///
+    /// Reading the open files of another user's process requires running as
+    /// that user or root.
+    pub fn discover_from_pid(pid: u32) -> Result<Self> {
+        let socket = discover_socket_in(Path::new("/proc"), pid)?;
+        let config = Self::try_default().with_context(|| {
+            format!("found the socket of auraed {pid}, but no config with client certs to connect with")
+        })?;
+
+        Ok(Self { system: SystemConfig { socket, ssh_jump: None }, ..config })
+    }
+}
+
+fn discover_socket_in(proc: &Path, pid: u32) -> Result<AuraeSocket> {
+    let dir = proc.join(pid.to_string());
+
+    let comm = std::fs::read_to_string(dir.join("comm"))
+        .with_context(|| format!("no process with PID {pid}"))?;
+    if comm.trim() != "auraed" {
+        return Err(anyhow!("process {pid} is '{}', not auraed", comm.trim()));
+    }
+
+    if let Some(path) = listening_socket(&dir)? {
+        return Ok(AuraeSocket::Path(path));
+    }
+
+    socket_argument(&dir)?.ok_or_else(|| {
+        anyhow!("auraed {pid} is not listening on a unix socket and was not started with --socket")
+    })
+}
+
+/// The unix socket the process listens on, preferring one called
+/// `aurae.sock` when there are several.
+fn listening_socket(dir: &Path) -> Result<Option<PathBuf>> {
+    let fds = std::fs::read_dir(dir.join("fd")).with_context(|| {
+        format!(
+            "cannot read the open files of {}, try as its owner or root",
+            dir.display()
+        )
+    })?;
+    let inodes: HashSet<u64> = fds
+        .filter_map(|fd| std::fs::read_link(fd.ok()?.path()).ok())
+        .filter_map(|target| {
+            target
+                .to_str()?
+                .strip_prefix("socket:[")?
+                .strip_suffix(']')?
+                .parse()
+                .ok()
+        })
+        .collect();
+
+    let table = std::fs::read_to_string(dir.join("net/unix"))
+        .context("failed to read the unix socket table")?;
+    let listening: Vec<PathBuf> = table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let columns: Vec<_> = line.split_whitespace().collect();
+            let flags = u32::from_str_radix(columns.get(3)?, 16).ok()?;
+            let inode: u64 = columns.get(6)?.parse().ok()?;
+            let path = columns.get(7..).filter(|path| !path.is_empty())?;
+            let path = path.join(" ");
+
+            // abstract sockets have no path to connect to
+            let listening = flags & SO_ACCEPTCON != 0 && !path.starts_with('@');
+            (listening && inodes.contains(&inode)).then(|| PathBuf::from(path))
+        })
+        .collect();
+
+    Ok(match listening.as_slice() {
+        [] => None,
+        [only] => Some(only.clone()),
+        several => several
+            .iter()
+            .find(|path| {
+                path.file_name().is_some_and(|name| name == SOCKET_NAME)
+            })
+            .or_else(|| several.first())
+            .cloned(),
+    })
+}
+
+/// The value of `--socket` (or `-s`) on the command line of the process.
+fn socket_argument(dir: &Path) -> Result<Option<AuraeSocket>> {
+    let cmdline = std::fs::read(dir.join("cmdline"))
+        .context("failed to read the command line")?;
+    let args: Vec<_> =
+        cmdline.split(|b| *b == 0).map(String::from_utf8_lossy).collect();
+
+    let value = args.iter().enumerate().find_map(|(i, arg)| {
+        match arg.strip_prefix("--socket=") {
+            Some(value) => Some(value.to_string()),
+            None if arg == "--socket" || arg == "-s" => {
+                args.get(i + 1).map(|value| value.to_string())
+            }
+            None => None,
+        }
+    });
+
+    value.map(|value| value.parse().map_err(anyhow::Error::from)).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    const UNIX_TABLE: &str = "\
+Num       RefCount Protocol Flags    Type St Inode Path
+0000000000000000: 00000002 00000000 00010000 0001 01 4242 /run/aurae-a/aurae.sock
+0000000000000000: 00000002 00000000 00000000 0001 03 4343 /run/aurae-a/aurae.sock
+0000000000000000: 00000002 00000000 00010000 0001 01 4444 /run/other.sock
+";
+
+    /// A `/proc` holding process 7, removed once dropped.
+    fn fake_process(comm: &str, cmdline: &[&str]) -> tempfile::TempDir {
+        let proc = tempfile::tempdir().unwrap();
+        let dir = proc.path().join("7");
+        std::fs::create_dir_all(dir.join("fd")).unwrap();
+        std::fs::create_dir_all(dir.join("net")).unwrap();
+        std::fs::write(dir.join("comm"), format!("{comm}\n")).unwrap();
+        std::fs::write(dir.join("cmdline"), cmdline.join("\0")).unwrap();
+        std::fs::write(dir.join("net/unix"), UNIX_TABLE).unwrap();
+        proc
+    }
+
+    #[test]
+    fn finds_the_listening_socket_of_the_process() {
+        let proc = fake_process("auraed", &["auraed"]);
+        // a connected socket sharing the path, and a listener of another
+        // process, must both be ignored
+        symlink("socket:[4242]", proc.path().join("7/fd/3")).unwrap();
+        symlink("socket:[4343]", proc.path().join("7/fd/4")).unwrap();
+        symlink("/dev/null", proc.path().join("7/fd/5")).unwrap();
+
+        let socket = discover_socket_in(proc.path(), 7).unwrap();
+
+        assert!(matches!(
+            socket,
+            AuraeSocket::Path(path) if path == Path::new("/run/aurae-a/aurae.sock")
+        ));
+    }
+
+    #[test]
+    fn falls_back_to_the_socket_argument() {
+        let proc =
+            fake_process("auraed", &["auraed", "--socket", "[::1]:8080"]);
+
+        let socket = discover_socket_in(proc.path(), 7).unwrap();
+
+        assert!(matches!(socket, AuraeSocket::Addr(_)));
+    }
+
+    #[test]
+    fn rejects_other_processes() {
+        let proc = fake_process("sshd", &["sshd"]);
+
+        let err = discover_socket_in(proc.path(), 7).unwrap_err();
+
+        assert!(err.to_string().contains("not auraed"));
+        assert!(discover_socket_in(proc.path(), 8).is_err());
+    }
+}
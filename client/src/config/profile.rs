@@ -0,0 +1,155 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Named configs (profiles) kept side by side, like `kubectl` contexts.
+//!
+//! Every `<name>.toml` file in `${HOME}/.aurae/` is a profile called `name`.
+
+use super::{AuraeConfig, AuraeSocket};
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+use x509_certificate::X509Certificate;
+
+/// Summary of a profile, see [`AuraeConfig::list_profiles()`].
+#[derive(Debug, Clone)]
+pub struct ProfileInfo {
+    /// The file name without `.toml`.
+    pub name: String,
+    pub path: PathBuf,
+    pub socket: AuraeSocket,
+    /// Subject common name of the client certificate, if it could be read.
+    pub client_cn: Option<String>,
+}
+
+fn profile_dir() -> Result<PathBuf> {
+    let home = std::env::var("HOME")
+        .map_err(|_| anyhow!("missing $HOME environmental variable"))?;
+    Ok(Path::new(&home).join(".aurae"))
+}
+
+impl AuraeConfig {
+    /// List the profiles in `${HOME}/.aurae/`, sorted by name. Files that do
+    /// not parse as a config are left out.
+    pub fn list_profiles() -> Vec<ProfileInfo> {
+        match profile_dir() {
+            Ok(dir) => Self::list_profiles_in(dir),
+            Err(_) => vec![],
+        }
+    }
+
+    pub(crate) fn list_profiles_in<P: AsRef<Path>>(dir: P) -> Vec<ProfileInfo> {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return vec![];
+        };
+
+        let mut profiles: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .filter_map(|path| {
+                let name = path.file_stem()?.to_str()?.to_string();
+                match Self::parse_from_toml_file(&path) {
+                    Ok(config) => Some(ProfileInfo {
+                        name,
+                        client_cn: client_cn(&config.auth.client_crt),
+                        socket: config.system.socket,
+                        path,
+                    }),
+                    Err(e) => {
+                        debug!("skipping profile '{}': {e}", path.display());
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        profiles
+    }
+
+    /// Load the profile called `name` from `${HOME}/.aurae/<name>.toml`.
+    pub fn load_profile(name: &str) -> Result<Self> {
+        Self::load_profile_in(profile_dir()?, name)
+    }
+
+    pub(crate) fn load_profile_in<P: AsRef<Path>>(
+        dir: P,
+        name: &str,
+    ) -> Result<Self> {
+        if name.is_empty()
+            || name.contains(['/', '\\'])
+            || name.starts_with('.')
+        {
+            return Err(anyhow!("invalid profile name '{name}'"));
+        }
+
+        let path = dir.as_ref().join(format!("{name}.toml"));
+        Self::parse_from_toml_file(&path).map_err(|e| {
+            anyhow!(
+                "failed to load profile '{name}' from '{}': {e}",
+                path.display()
+            )
+        })
+    }
+}
+
+/// Only the client certificate is read, and only its subject is used.
+fn client_cn(client_crt: &str) -> Option<String> {
+    let pem = std::fs::read(client_crt).ok()?;
+    X509Certificate::from_pem(pem).ok()?.subject_common_name()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &str = r#"
+[auth]
+ca_crt = "/nonexistent/ca.crt"
+client_crt = "/nonexistent/client.crt"
+client_key = "/nonexistent/client.key"
+
+[system]
+socket = "#;
+
+    #[test]
+    fn profiles_are_listed_by_name() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(
+            dir.join("staging.toml"),
+            format!("{PROFILE}\"10.0.0.2:8080\""),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("dev.toml"),
+            format!("{PROFILE}\"/var/run/aurae/aurae.sock\""),
+        )
+        .unwrap();
+        std::fs::write(dir.join("config"), "not a profile").unwrap();
+        std::fs::write(dir.join("broken.toml"), "[auth]").unwrap();
+
+        let profiles = AuraeConfig::list_profiles_in(&dir);
+
+        let names: Vec<_> = profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["dev", "staging"]);
+        assert!(matches!(profiles[1].socket, AuraeSocket::Addr(_)));
+        assert!(profiles[0].client_cn.is_none());
+
+        let config = AuraeConfig::load_profile_in(&dir, "staging").unwrap();
+        assert!(matches!(config.system.socket, AuraeSocket::Addr(_)));
+        assert!(AuraeConfig::load_profile_in(&dir, "../staging").is_err());
+    }
+}
@@ -0,0 +1,196 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use anyhow::{anyhow, Context, Result};
+use std::fs::{File, Metadata};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Same limit the Linux kernel applies when walking a symlink chain.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// What a path opened with [`open_resolved`] resolved through, kept to
+/// check it without going back to the file system.
+#[derive(Debug, Clone)]
+pub(crate) struct Resolved {
+    /// The final target of the symlink chain.
+    pub(crate) path: PathBuf,
+    links: Vec<(PathBuf, Metadata)>,
+    /// Of the opened file, not of whatever is at `path` now.
+    metadata: Metadata,
+}
+
+impl Resolved {
+    /// Every link in the chain, the final target, and the directory holding
+    /// the final target must be owned by the current user or root. The
+    /// target and its directory must also not be writable by group or
+    /// others. This guards against a config or cert being planted by another
+    /// local user through a symlink.
+    ///
+    /// The target is judged by the file that was opened, so swapping it
+    /// after the check cannot change what was read.
+    pub(crate) fn check(&self) -> Result<()> {
+        for (link, metadata) in &self.links {
+            check_owner(link, metadata)?;
+        }
+        check_target(&self.path, &self.metadata)
+    }
+}
+
+/// Follow every symlink in the chain of `path` and open the final target,
+/// once. Fails when the target is replaced between being resolved and
+/// opened, so the file returned is the one [`Resolved::check`] judges.
+pub(crate) fn open_resolved<P: AsRef<Path>>(
+    path: P,
+) -> Result<(File, Resolved)> {
+    let original = path.as_ref();
+    let mut current = original.to_path_buf();
+    let mut links = vec![];
+
+    for _ in 0..MAX_SYMLINK_HOPS {
+        let metadata =
+            std::fs::symlink_metadata(&current).with_context(|| {
+                format!("failed to stat '{}'", current.display())
+            })?;
+
+        if !metadata.file_type().is_symlink() {
+            let file = File::open(&current).with_context(|| {
+                format!("failed to open '{}'", current.display())
+            })?;
+            let opened = file.metadata().with_context(|| {
+                format!("failed to stat '{}'", current.display())
+            })?;
+            if (opened.dev(), opened.ino()) != (metadata.dev(), metadata.ino())
+            {
+                return Err(anyhow!(
+                    "'{}' was replaced while it was opened",
+                    current.display()
+                ));
+            }
+            let resolved = Resolved { path: current, links, metadata: opened };
+            return Ok((file, resolved));
+        }
+
+        let target = std::fs::read_link(&current).with_context(|| {
+            format!("failed to read symlink '{}'", current.display())
+        })?;
+        links.push((current.clone(), metadata));
+
+        current = match current.parent() {
+            Some(parent) if target.is_relative() => parent.join(target),
+            _ => target,
+        };
+    }
+
+    Err(anyhow!(
+        "too many levels of symbolic links while resolving '{}'",
+        original.display()
+    ))
+}
+
+fn check_target(path: &Path, metadata: &Metadata) -> Result<()> {
+    check_owner(path, metadata)?;
+    check_not_shared_writable(path, metadata)?;
+
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let parent_metadata = std::fs::metadata(parent).with_context(|| {
+        format!("failed to stat directory '{}'", parent.display())
+    })?;
+
+    check_owner(parent, &parent_metadata)?;
+    check_not_shared_writable(parent, &parent_metadata)
+}
+
+fn check_owner(path: &Path, metadata: &Metadata) -> Result<()> {
+    let euid = nix::unistd::geteuid().as_raw();
+    let owner = metadata.uid();
+
+    if owner != euid && owner != 0 {
+        return Err(anyhow!(
+            "refusing to use '{}': owned by uid {owner}, expected uid {euid} or root",
+            path.display()
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_not_shared_writable(path: &Path, metadata: &Metadata) -> Result<()> {
+    if metadata.mode() & 0o022 != 0 {
+        return Err(anyhow!(
+            "refusing to use '{}': writable by group or others (mode {:o})",
+            path.display(),
+            metadata.mode() & 0o7777
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::Permissions;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    #[test]
+    fn open_resolved_follows_symlink_chain() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let target = dir.join("prod.toml");
+        std::fs::write(&target, "").unwrap();
+        symlink("prod.toml", dir.join("middle")).unwrap();
+        symlink(dir.join("middle"), dir.join("config")).unwrap();
+
+        let (_, resolved) = open_resolved(dir.join("config")).unwrap();
+
+        assert_eq!(resolved.path, target);
+        assert_eq!(resolved.links.len(), 2);
+        resolved.check().unwrap();
+    }
+
+    #[test]
+    fn open_resolved_rejects_symlink_loop() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        symlink(dir.join("b"), dir.join("a")).unwrap();
+        symlink(dir.join("a"), dir.join("b")).unwrap();
+
+        let err = open_resolved(dir.join("a")).unwrap_err();
+
+        assert!(err.to_string().contains("too many levels"));
+    }
+
+    #[test]
+    fn shared_writable_targets_fail_the_check() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target = tmp.path().join("prod.toml");
+        std::fs::write(&target, "").unwrap();
+        let (_, resolved) = open_resolved(&target).unwrap();
+        resolved.check().unwrap();
+
+        // the check judges the file as it was when opened
+        std::fs::set_permissions(&target, Permissions::from_mode(0o666))
+            .unwrap();
+        resolved.check().unwrap();
+
+        let (_, resolved) = open_resolved(&target).unwrap();
+        let err = resolved.check().unwrap_err();
+        assert!(err.to_string().contains("writable by group or others"));
+    }
+}
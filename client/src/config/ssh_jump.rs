@@ -0,0 +1,74 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use anyhow::anyhow;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::path::{Path, PathBuf};
+
+/// An SSH bastion used to reach a remote auraed unix socket.
+///
+/// When set on [`crate::SystemConfig`], the client forwards a local unix
+/// socket to `remote_socket` through `ssh` and connects to that instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct SshJump {
+    /// Host running sshd in front of auraed.
+    pub host: String,
+    /// Port sshd listens on. Defaults to the ssh client configuration.
+    pub port: Option<u16>,
+    /// User to log in as. Defaults to the ssh client configuration.
+    pub user: Option<String>,
+    /// Private key passed to `ssh -i`.
+    pub identity_file: Option<String>,
+    /// Path of the auraed unix socket on the remote host. It cannot hold a
+    /// `:`, which `ssh -L` splits the forward on.
+    #[serde(deserialize_with = "deserialize_forward_path")]
+    pub remote_socket: PathBuf,
+}
+
+impl SshJump {
+    /// The `[user@]host` destination passed to `ssh`.
+    pub(crate) fn destination(&self) -> String {
+        match &self.user {
+            Some(user) => format!("{user}@{}", self.host),
+            None => self.host.clone(),
+        }
+    }
+}
+
+/// Fail for a socket path `ssh -L` would split, forwarding elsewhere than
+/// where it points.
+pub(crate) fn check_forward_path(path: &Path) -> anyhow::Result<()> {
+    match path.to_string_lossy().contains(':') {
+        true => Err(anyhow!(
+            "ssh cannot forward '{}': socket paths must not contain ':'",
+            path.display()
+        )),
+        false => Ok(()),
+    }
+}
+
+fn deserialize_forward_path<'de, D>(
+    deserializer: D,
+) -> Result<PathBuf, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let path = PathBuf::deserialize(deserializer)?;
+    check_forward_path(&path).map_err(D::Error::custom)?;
+    Ok(path)
+}
@@ -0,0 +1,87 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Suggestions for misspelled config keys, which serde only reports as
+//! unknown along with every valid field name.
+
+/// For the message of an "unknown field" error, a hint naming the valid
+/// field closest to the unknown one, if any is close enough to be a typo.
+pub(crate) fn did_you_mean(message: &str) -> Option<String> {
+    // "unknown field `client_cert`, expected one of `ca_crt`, `client_crt`"
+    let rest = message.strip_prefix("unknown field ")?;
+    let mut quoted = rest.split('`').skip(1).step_by(2);
+    let field = quoted.next()?;
+
+    let (distance, closest) = quoted
+        .map(|candidate| (edit_distance(field, candidate), candidate))
+        .min()?;
+
+    // anything further apart is more likely a different key altogether
+    let close_enough = distance <= 3 && distance < field.chars().count();
+    close_enough
+        .then(|| format!("unknown field `{field}`, did you mean `{closest}`?"))
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current[j + 1] =
+                substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggests_the_closest_field() {
+        let hint = did_you_mean(
+            "unknown field `client_cert`, expected one of `ca_crt`, `client_crt`, `client_key`",
+        );
+
+        assert_eq!(
+            hint.as_deref(),
+            Some("unknown field `client_cert`, did you mean `client_crt`?")
+        );
+    }
+
+    #[test]
+    fn unrelated_fields_get_no_suggestion() {
+        assert!(did_you_mean(
+            "unknown field `colour`, expected `socket` or `ssh_jump`"
+        )
+        .is_none());
+        assert!(did_you_mean("missing field `socket`").is_none());
+    }
+
+    #[test]
+    fn edit_distance_counts_single_edits() {
+        assert_eq!(edit_distance("client_crt", "client_crt"), 0);
+        assert_eq!(edit_distance("client_cert", "client_crt"), 1);
+        assert_eq!(edit_distance("sokcet", "socket"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+}
@@ -43,9 +43,14 @@
 \* -------------------------------------------------------------------------- */
 
 use anyhow::anyhow;
+use chrono::{DateTime, SecondsFormat, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use std::marker::PhantomData;
-use x509_certificate::X509Certificate;
+use x509_certificate::{
+    DigestAlgorithm, EcdsaCurve, KeyAlgorithm, X509Certificate,
+};
+use x509_parser::extensions::GeneralName;
 
 /// An in-memory representation of an X509 identity, and its metadata.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -56,8 +61,39 @@ pub struct X509Details {
     pub issuer_common_name: String,
     /// From the SSL spec, the sha256 sum fingerprint of the material.
     pub sha256_fingerprint: String,
-    /// From the SSL spec, the algorithm used for encryption.
+    /// The serial number, as colon separated lowercase hex.
+    #[serde(default)]
+    pub serial_number: String,
+    /// From the SSL spec, the algorithm of the subject key: `RSA`,
+    /// `ECDSA P-256`, `ECDSA P-384` or `ED25519`.
     pub key_algorithm: String,
+    /// Whether the issuer and subject are the same, as for a self-signed CA.
+    pub self_signed: bool,
+    /// Whether the Basic Constraints extension marks the certificate as a
+    /// CA. `false` when the extension is missing.
+    #[serde(default)]
+    pub is_ca: bool,
+    /// Subject alternative names, sorted, as `DNS:<name>`, `URI:<uri>`,
+    /// `IP:<addr>` or `email:<address>`.
+    #[serde(default)]
+    pub subject_alt_names: Vec<String>,
+    /// Start of the validity period, in RFC 2822 format.
+    #[serde(default)]
+    pub not_before: Option<String>,
+    /// End of the validity period, in RFC 2822 format.
+    #[serde(default)]
+    pub not_after: Option<String>,
+    /// Hex encoded sha256 sum of the subject public key.
+    #[serde(default)]
+    pub public_key_sha256: String,
+    /// The Key Usage extension, `None` when the certificate has none and
+    /// its key may be used for anything.
+    #[serde(default)]
+    pub key_usage: Option<KeyUsage>,
+    /// The Extended Key Usage extension, empty when the certificate has
+    /// none and may be used for any purpose.
+    #[serde(default)]
+    pub extended_key_usages: Vec<ExtendedKeyUsage>,
     // Force instantiation through function
     phantom_data: PhantomData<()>,
 }
@@ -67,7 +103,30 @@ pub struct X509Details {
 pub(crate) fn new_x509_details(
     client_cert: Vec<u8>,
 ) -> anyhow::Result<X509Details> {
-    let x509 = X509Certificate::from_pem(client_cert)?;
+    let (_, pem) = x509_parser::pem::parse_x509_pem(&client_cert)
+        .map_err(|e| anyhow!("Client certificate is not valid PEM: {e}"))?;
+    x509_details_from_der(&pem.contents)
+}
+
+/// As [`new_x509_details`], for a DER certificate such as the one a server
+/// presents.
+pub(crate) fn x509_details_from_der(der: &[u8]) -> anyhow::Result<X509Details> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(der)
+        .map_err(|e| anyhow!("Client certificate is not valid X509: {e}"))?;
+    let serial_number = parsed.raw_serial_as_string();
+    let subject_alt_names = alt_names(&parsed);
+    let key_usage = key_usage(&parsed);
+    let is_ca = parsed
+        .basic_constraints()
+        .ok()
+        .flatten()
+        .is_some_and(|constraints| constraints.value.ca);
+    let extended_key_usages = extended_key_usages(&parsed);
+    let validity = parsed.validity();
+    let not_before = validity.not_before.to_rfc2822().ok();
+    let not_after = validity.not_after.to_rfc2822().ok();
+
+    let x509 = X509Certificate::from_der(der)?;
 
     let subject_common_name = x509.subject_common_name().ok_or_else(|| {
         anyhow!("Client certificated is missing subject_common_name")
@@ -79,16 +138,534 @@ pub(crate) fn new_x509_details(
 
     let sha256_fingerprint = x509.sha256_fingerprint()?;
 
-    let key_algorithm = x509
-        .key_algorithm()
-        .ok_or_else(|| anyhow!("Client certificate is missing key_algorithm"))?
-        .to_string();
+    let self_signed = x509.subject_name() == x509.issuer_name();
+
+    let key_algorithm =
+        x509.key_algorithm().map(key_algorithm_name).ok_or_else(|| {
+            anyhow!("Client certificate is missing key_algorithm")
+        })?;
+
+    let public_key_sha256 = DigestAlgorithm::Sha256
+        .digest_data(&x509.public_key_data())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
 
     Ok(X509Details {
         subject_common_name,
         issuer_common_name,
         sha256_fingerprint: format!("{sha256_fingerprint:?}"),
+        serial_number,
         key_algorithm,
+        self_signed,
+        is_ca,
+        subject_alt_names,
+        not_before,
+        not_after,
+        public_key_sha256,
+        key_usage,
+        extended_key_usages,
         phantom_data: PhantomData,
     })
+}
+
+/// The Key Usage bits of a certificate, see [`X509Details::key_usage`].
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct KeyUsage {
+    pub digital_signature: bool,
+    pub non_repudiation: bool,
+    pub key_encipherment: bool,
+    pub data_encipherment: bool,
+    pub key_agreement: bool,
+    pub key_cert_sign: bool,
+    pub crl_sign: bool,
+    pub encipher_only: bool,
+    pub decipher_only: bool,
+}
+
+/// A purpose from the Extended Key Usage extension, see
+/// [`X509Details::extended_key_usages`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtendedKeyUsage {
+    /// `anyExtendedKeyUsage`
+    Any,
+    ServerAuth,
+    ClientAuth,
+    CodeSigning,
+    EmailProtection,
+    TimeStamping,
+    OcspSigning,
+    /// Any other purpose, by its dotted OID.
+    Other(String),
+}
+
+fn key_usage(
+    cert: &x509_parser::certificate::X509Certificate<'_>,
+) -> Option<KeyUsage> {
+    let usage = cert.key_usage().ok()??.value;
+    Some(KeyUsage {
+        digital_signature: usage.digital_signature(),
+        non_repudiation: usage.non_repudiation(),
+        key_encipherment: usage.key_encipherment(),
+        data_encipherment: usage.data_encipherment(),
+        key_agreement: usage.key_agreement(),
+        key_cert_sign: usage.key_cert_sign(),
+        crl_sign: usage.crl_sign(),
+        encipher_only: usage.encipher_only(),
+        decipher_only: usage.decipher_only(),
+    })
+}
+
+fn extended_key_usages(
+    cert: &x509_parser::certificate::X509Certificate<'_>,
+) -> Vec<ExtendedKeyUsage> {
+    let Ok(Some(eku)) = cert.extended_key_usage() else {
+        return vec![];
+    };
+    let eku = eku.value;
+
+    [
+        (eku.any, ExtendedKeyUsage::Any),
+        (eku.server_auth, ExtendedKeyUsage::ServerAuth),
+        (eku.client_auth, ExtendedKeyUsage::ClientAuth),
+        (eku.code_signing, ExtendedKeyUsage::CodeSigning),
+        (eku.email_protection, ExtendedKeyUsage::EmailProtection),
+        (eku.time_stamping, ExtendedKeyUsage::TimeStamping),
+        (eku.ocsp_signing, ExtendedKeyUsage::OcspSigning),
+    ]
+    .into_iter()
+    .filter_map(|(set, usage)| set.then_some(usage))
+    .chain(
+        eku.other.iter().map(|oid| ExtendedKeyUsage::Other(oid.to_id_string())),
+    )
+    .collect()
+}
+
+fn alt_names(
+    cert: &x509_parser::certificate::X509Certificate<'_>,
+) -> Vec<String> {
+    let Ok(Some(san)) = cert.subject_alternative_name() else {
+        return vec![];
+    };
+
+    let mut names: Vec<_> = san
+        .value
+        .general_names
+        .iter()
+        .filter_map(|name| match name {
+            GeneralName::DNSName(dns) => Some(format!("DNS:{dns}")),
+            GeneralName::URI(uri) => Some(format!("URI:{uri}")),
+            GeneralName::RFC822Name(email) => Some(format!("email:{email}")),
+            GeneralName::IPAddress(ip) => {
+                ip_addr(ip).map(|ip| format!("IP:{ip}"))
+            }
+            _ => None,
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+fn ip_addr(bytes: &[u8]) -> Option<std::net::IpAddr> {
+    match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes).ok().map(Into::into),
+        16 => <[u8; 16]>::try_from(bytes).ok().map(Into::into),
+        _ => None,
+    }
+}
+
+impl X509Details {
+    /// Whether the certificate may authenticate a TLS client: its Extended
+    /// Key Usage, if any, includes `clientAuth` (or any purpose), and its
+    /// Key Usage, if any, allows signing or key agreement.
+    pub fn is_client_auth_capable(&self) -> bool {
+        let eku = self.extended_key_usages.is_empty()
+            || self.extended_key_usages.iter().any(|usage| {
+                matches!(
+                    usage,
+                    ExtendedKeyUsage::ClientAuth | ExtendedKeyUsage::Any
+                )
+            });
+        let key_usage = self.key_usage.map_or(true, |usage| {
+            usage.digital_signature || usage.key_agreement
+        });
+        eku && key_usage
+    }
+
+    /// Whether `other` names the same identity: the same subject common
+    /// name and subject alternative names. Fingerprint, validity and key
+    /// may all differ, as they do for a renewed certificate.
+    pub fn same_identity(&self, other: &X509Details) -> bool {
+        self.subject_common_name == other.subject_common_name
+            && self.subject_alt_names == other.subject_alt_names
+    }
+
+    /// What changed from `self` to `other`, e.g. from the current to the
+    /// rotated certificate.
+    pub fn diff(&self, other: &X509Details) -> X509Diff {
+        let mut changes = vec![];
+
+        if self.subject_common_name != other.subject_common_name {
+            changes.push(X509Change::SubjectCommonName {
+                old: self.subject_common_name.clone(),
+                new: other.subject_common_name.clone(),
+            });
+        }
+
+        let old: BTreeSet<_> = self.subject_alt_names.iter().collect();
+        let new: BTreeSet<_> = other.subject_alt_names.iter().collect();
+        changes.extend(
+            old.difference(&new)
+                .map(|name| X509Change::AltNameRemoved((*name).clone())),
+        );
+        changes.extend(
+            new.difference(&old)
+                .map(|name| X509Change::AltNameAdded((*name).clone())),
+        );
+
+        if self.issuer_common_name != other.issuer_common_name {
+            changes.push(X509Change::IssuerCommonName {
+                old: self.issuer_common_name.clone(),
+                new: other.issuer_common_name.clone(),
+            });
+        }
+
+        if self.not_before != other.not_before
+            || self.not_after != other.not_after
+        {
+            changes.push(X509Change::Validity {
+                not_before: other.not_before.clone(),
+                not_after: other.not_after.clone(),
+            });
+        }
+
+        if self.key_algorithm != other.key_algorithm {
+            changes.push(X509Change::KeyAlgorithm {
+                old: self.key_algorithm.clone(),
+                new: other.key_algorithm.clone(),
+            });
+        }
+
+        if self.public_key_sha256 != other.public_key_sha256 {
+            changes.push(X509Change::PublicKey);
+        }
+
+        X509Diff { changes }
+    }
+}
+
+/// The differences between two certificates, see [`X509Details::diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct X509Diff {
+    pub changes: Vec<X509Change>,
+}
+
+impl X509Diff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Whether any change affects the identity, in the sense of
+    /// [`X509Details::same_identity`].
+    pub fn identity_changed(&self) -> bool {
+        self.changes.iter().any(|change| {
+            matches!(
+                change,
+                X509Change::SubjectCommonName { .. }
+                    | X509Change::AltNameAdded(_)
+                    | X509Change::AltNameRemoved(_)
+            )
+        })
+    }
+}
+
+/// A single difference between two certificates.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum X509Change {
+    SubjectCommonName {
+        old: String,
+        new: String,
+    },
+    AltNameAdded(String),
+    AltNameRemoved(String),
+    IssuerCommonName {
+        old: String,
+        new: String,
+    },
+    /// The validity period of the new certificate.
+    Validity {
+        not_before: Option<String>,
+        not_after: Option<String>,
+    },
+    KeyAlgorithm {
+        old: String,
+        new: String,
+    },
+    /// A different key, of the same or another algorithm.
+    PublicKey,
+}
+
+// The Display impl of KeyAlgorithm drops the curve, which is what tells
+// ECDSA identities apart.
+/// Version of the [`AuditRecord`] schema. Fields may be added within a
+/// version; it goes up when one is removed, renamed or changes meaning.
+pub const AUDIT_SCHEMA_VERSION: u32 = 1;
+
+/// A certificate as one flat, stably keyed record for audit logs, see
+/// [`X509Details::to_audit_record`].
+///
+/// Schema version 1, serialized by [`AuditRecord::to_json_line`]:
+///
+/// | key                   | value                                        |
+/// |-----------------------|----------------------------------------------|
+/// | `schema_version`      | `1`                                          |
+/// | `subject_common_name` | string                                       |
+/// | `issuer_common_name`  | string                                       |
+/// | `serial_number`       | uppercase hex bytes separated by `:`         |
+/// | `sha256_fingerprint`  | uppercase hex bytes separated by `:`         |
+/// | `not_before`          | RFC 3339 in UTC, e.g. `2025-01-01T00:00:00Z`, or `null` |
+/// | `not_after`           | as `not_before`                              |
+/// | `key_algorithm`       | as [`X509Details::key_algorithm`]            |
+/// | `self_signed`         | boolean                                      |
+/// | `subject_alt_names`   | array of strings, as [`X509Details::subject_alt_names`] |
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub schema_version: u32,
+    pub subject_common_name: String,
+    pub issuer_common_name: String,
+    pub serial_number: String,
+    pub sha256_fingerprint: String,
+    pub not_before: Option<String>,
+    pub not_after: Option<String>,
+    pub key_algorithm: String,
+    pub self_signed: bool,
+    pub subject_alt_names: Vec<String>,
+}
+
+impl AuditRecord {
+    /// The record as a single line of JSON, for JSON lines audit logs.
+    pub fn to_json_line(&self) -> String {
+        serde_json::to_string(self).expect("audit records serialize to JSON")
+    }
+}
+
+impl X509Details {
+    /// Why this certificate cannot sign others, as a match for a leaf
+    /// certificate configured as a CA: its Basic Constraints do not mark it
+    /// as a CA, or its Key Usage leaves out `keyCertSign`.
+    pub(crate) fn not_a_ca(&self) -> Option<&'static str> {
+        if !self.is_ca {
+            return Some("basicConstraints does not mark it as a CA");
+        }
+        if self.key_usage.is_some_and(|usage| !usage.key_cert_sign) {
+            return Some("its key usage does not include keyCertSign");
+        }
+        None
+    }
+
+    /// These details as an [`AuditRecord`] of the current
+    /// [`AUDIT_SCHEMA_VERSION`].
+    pub fn to_audit_record(&self) -> AuditRecord {
+        let fingerprint = self.sha256_fingerprint.as_str();
+        // formatted as `SHA256:<hex>` by x509-certificate
+        let fingerprint =
+            fingerprint.rsplit_once(':').map_or(fingerprint, |(_, hex)| hex);
+        AuditRecord {
+            schema_version: AUDIT_SCHEMA_VERSION,
+            subject_common_name: self.subject_common_name.clone(),
+            issuer_common_name: self.issuer_common_name.clone(),
+            serial_number: self.serial_number.to_ascii_uppercase(),
+            sha256_fingerprint: colon_hex(fingerprint),
+            not_before: self.not_before.as_deref().and_then(rfc3339),
+            not_after: self.not_after.as_deref().and_then(rfc3339),
+            key_algorithm: self.key_algorithm.clone(),
+            self_signed: self.self_signed,
+            subject_alt_names: self.subject_alt_names.clone(),
+        }
+    }
+}
+
+/// `hex` in uppercase with `:` between the bytes.
+fn colon_hex(hex: &str) -> String {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|byte| String::from_utf8_lossy(byte).to_ascii_uppercase())
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// A validity bound of [`X509Details`], in RFC 2822, as RFC 3339 in UTC.
+fn rfc3339(rfc2822: &str) -> Option<String> {
+    let time = DateTime::parse_from_rfc2822(rfc2822).ok()?;
+    Some(time.with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+fn key_algorithm_name(algorithm: KeyAlgorithm) -> String {
+    match algorithm {
+        KeyAlgorithm::Rsa => "RSA".into(),
+        KeyAlgorithm::Ecdsa(EcdsaCurve::Secp256r1) => "ECDSA P-256".into(),
+        KeyAlgorithm::Ecdsa(EcdsaCurve::Secp384r1) => "ECDSA P-384".into(),
+        KeyAlgorithm::Ed25519 => "ED25519".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{
+        Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose,
+        KeyUsagePurpose,
+    };
+
+    fn cert(cn: &str, alt_names: &[&str], year: i32) -> X509Details {
+        let mut params = CertificateParams::new(
+            alt_names.iter().map(|name| name.to_string()).collect::<Vec<_>>(),
+        );
+        params.distinguished_name.push(DnType::CommonName, cn);
+        params.not_before = rcgen::date_time_ymd(year, 1, 1);
+        params.not_after = rcgen::date_time_ymd(year + 1, 1, 1);
+        details(params)
+    }
+
+    fn details(params: CertificateParams) -> X509Details {
+        let pem = Certificate::from_params(params).unwrap().serialize_pem();
+        new_x509_details(pem.unwrap().into_bytes()).unwrap()
+    }
+
+    fn with_usages(
+        key_usages: Vec<KeyUsagePurpose>,
+        extended_key_usages: Vec<ExtendedKeyUsagePurpose>,
+    ) -> X509Details {
+        let mut params = CertificateParams::new(vec![]);
+        params.distinguished_name.push(DnType::CommonName, "aurae-client");
+        params.key_usages = key_usages;
+        params.extended_key_usages = extended_key_usages;
+        details(params)
+    }
+
+    #[test]
+    fn client_auth_eku_makes_a_client_certificate() {
+        let client = with_usages(
+            vec![KeyUsagePurpose::DigitalSignature],
+            vec![ExtendedKeyUsagePurpose::ClientAuth],
+        );
+        assert_eq!(client.extended_key_usages, [ExtendedKeyUsage::ClientAuth]);
+        assert!(client.key_usage.unwrap().digital_signature);
+        assert!(client.is_client_auth_capable());
+
+        let unrestricted = with_usages(vec![], vec![]);
+        assert_eq!(unrestricted.key_usage, None);
+        assert!(unrestricted.extended_key_usages.is_empty());
+        assert!(unrestricted.is_client_auth_capable());
+    }
+
+    #[test]
+    fn server_only_certificates_cannot_authenticate_clients() {
+        let server = with_usages(
+            vec![KeyUsagePurpose::DigitalSignature],
+            vec![ExtendedKeyUsagePurpose::ServerAuth],
+        );
+        assert_eq!(server.extended_key_usages, [ExtendedKeyUsage::ServerAuth]);
+        assert!(!server.is_client_auth_capable());
+
+        let ca_key = with_usages(
+            vec![KeyUsagePurpose::KeyCertSign],
+            vec![ExtendedKeyUsagePurpose::ClientAuth],
+        );
+        assert!(!ca_key.is_client_auth_capable());
+    }
+
+    #[test]
+    fn renewed_certificate_keeps_identity() {
+        let current = cert("aurae-client", &["client.aurae.io"], 2024);
+        let renewed = cert("aurae-client", &["client.aurae.io"], 2025);
+
+        assert!(current.same_identity(&renewed));
+        let diff = current.diff(&renewed);
+        assert!(!diff.identity_changed());
+        assert!(diff.changes.contains(&X509Change::PublicKey));
+        assert!(diff
+            .changes
+            .iter()
+            .any(|change| matches!(change, X509Change::Validity { .. })));
+    }
+
+    #[test]
+    fn changed_alt_names_change_identity() {
+        let current = cert("aurae-client", &["client.aurae.io"], 2024);
+        let changed = cert("aurae-client", &["other.aurae.io"], 2024);
+
+        assert!(!current.same_identity(&changed));
+        let diff = current.diff(&changed);
+        assert!(diff.identity_changed());
+        assert!(diff.changes.contains(&X509Change::AltNameRemoved(
+            "DNS:client.aurae.io".into()
+        )));
+        assert!(diff
+            .changes
+            .contains(&X509Change::AltNameAdded("DNS:other.aurae.io".into())));
+    }
+
+    #[test]
+    fn identical_certificate_has_no_changes() {
+        let current = cert("aurae-client", &["client.aurae.io"], 2024);
+
+        assert!(current.diff(&current).is_empty());
+    }
+
+    #[test]
+    fn audit_records_keep_their_shape() {
+        let mut params =
+            CertificateParams::new(vec!["auraed.example.com".into()]);
+        params.distinguished_name.push(DnType::CommonName, "auraed");
+        params.not_before = rcgen::date_time_ymd(2025, 1, 1);
+        params.not_after = rcgen::date_time_ymd(2026, 1, 1);
+        let line = details(params).to_audit_record().to_json_line();
+        assert!(!line.contains('\n'));
+
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        let mut keys: Vec<_> = json.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "issuer_common_name",
+                "key_algorithm",
+                "not_after",
+                "not_before",
+                "schema_version",
+                "self_signed",
+                "serial_number",
+                "sha256_fingerprint",
+                "subject_alt_names",
+                "subject_common_name",
+            ]
+        );
+        assert_eq!(json["schema_version"], 1);
+        assert_eq!(json["subject_common_name"], "auraed");
+        assert_eq!(json["issuer_common_name"], "auraed");
+        assert_eq!(json["not_before"], "2025-01-01T00:00:00Z");
+        assert_eq!(json["not_after"], "2026-01-01T00:00:00Z");
+        assert_eq!(json["key_algorithm"], "ECDSA P-256");
+        assert_eq!(json["self_signed"], true);
+        assert_eq!(
+            json["subject_alt_names"],
+            serde_json::json!(["DNS:auraed.example.com"])
+        );
+
+        let colon_hex = |value: &serde_json::Value| {
+            let value = value.as_str().unwrap();
+            value.split(':').all(|byte| {
+                byte.len() == 2
+                    && byte.chars().all(|c| matches!(c, '0'..='9' | 'A'..='F'))
+            }) && !value.is_empty()
+        };
+        assert!(colon_hex(&json["serial_number"]), "{line}");
+        assert!(colon_hex(&json["sha256_fingerprint"]), "{line}");
+        assert_eq!(json["sha256_fingerprint"].as_str().unwrap().len(), 95);
+    }
 }
\ No newline at end of file
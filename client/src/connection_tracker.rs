@@ -0,0 +1,328 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Connection leak detection, and the process wide connection budget.
+//!
+//! Every [`crate::Client`] owns a connection, so creating many short-lived
+//! clients puts a new TLS handshake on auraed for each one. In debug builds,
+//! or when the `diagnostics` feature is enabled, live connections are counted
+//! and a warning naming the creation site is logged once too many are alive
+//! at the same time. It is logged again only after the count has dropped
+//! back, so a leaking loop warns once rather than for every connection. In
+//! release builds the tracker does nothing.
+//!
+//! When `AURAE_MAX_CONNECTIONS` is set, clients that connect themselves also
+//! take a slot of a budget of that many, and give it back once their last
+//! clone is dropped. Connecting with all slots taken waits for one, or fails
+//! with [`ClientError::ConnectionBudgetExhausted`], see
+//! [`crate::ConnectOptions::wait_for_connection_budget`]. A client rebuilding
+//! its connection hands its slot over to the new one rather than waiting
+//! for a second.
+
+use crate::{Client, ClientError, ConnectOptions};
+use std::panic::Location;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+const ENABLED: bool = cfg!(any(debug_assertions, feature = "diagnostics"));
+
+/// Number of simultaneously live connections above which we warn.
+const LIVE_CONNECTIONS_WARN_THRESHOLD: usize = 16;
+
+static LIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The most connections clients may hold at once across the process.
+const MAX_CONNECTIONS_ENV: &str = "AURAE_MAX_CONNECTIONS";
+
+/// Read from [`MAX_CONNECTIONS_ENV`] on first use, `None` when it is unset.
+static BUDGET: OnceLock<Result<Option<ConnectionBudget>, String>> =
+    OnceLock::new();
+
+/// Slots of the budget currently taken.
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+
+/// Held by a [`crate::Client`] (and shared by its clones) for as long as its
+/// connection is alive.
+#[derive(Debug)]
+pub(crate) struct ConnectionTracker {
+    slot: Mutex<Option<BudgetSlot>>,
+    /// The counter this connection is counted in while alive.
+    live: &'static AtomicUsize,
+}
+
+impl ConnectionTracker {
+    /// `slot` is `None` for clients that were handed a channel instead of
+    /// connecting themselves.
+    pub(crate) fn new(
+        created_at: &'static Location<'static>,
+        slot: Option<BudgetSlot>,
+    ) -> Self {
+        Self::counted_in(&LIVE_CONNECTIONS, created_at, slot)
+    }
+
+    fn counted_in(
+        live: &'static AtomicUsize,
+        created_at: &'static Location<'static>,
+        slot: Option<BudgetSlot>,
+    ) -> Self {
+        if ENABLED {
+            let live = live.fetch_add(1, Ordering::Relaxed) + 1;
+            if crosses_threshold(live) {
+                warn!(
+                    "{live} aurae client connections are alive at once, newest created at {created_at}; consider reusing a single client"
+                );
+            }
+        }
+
+        Self { slot: Mutex::new(slot), live }
+    }
+
+    /// Give up the budget slot, for a connection about to replace this one.
+    pub(crate) fn take_slot(&self) -> Option<BudgetSlot> {
+        self.slot.lock().expect("connection tracker lock poisoned").take()
+    }
+
+    /// Take back a slot given up by [`ConnectionTracker::take_slot`], when
+    /// the replacement failed.
+    pub(crate) fn restore_slot(&self, slot: Option<BudgetSlot>) {
+        if slot.is_some() {
+            *self.slot.lock().expect("connection tracker lock poisoned") = slot;
+        }
+    }
+}
+
+/// Whether `live` connections are one past the threshold, so the warning
+/// fires once per crossing rather than for every connection above it.
+fn crosses_threshold(live: usize) -> bool {
+    live == LIVE_CONNECTIONS_WARN_THRESHOLD + 1
+}
+
+/// A budget slot on its way from a connection being replaced to the one
+/// replacing it, shared through [`crate::connector::ConnectState`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SlotHandover(Arc<Mutex<Option<BudgetSlot>>>);
+
+impl SlotHandover {
+    pub(crate) fn give(&self, slot: Option<BudgetSlot>) {
+        *self.0.lock().expect("slot handover lock poisoned") = slot;
+    }
+
+    pub(crate) fn take(&self) -> Option<BudgetSlot> {
+        self.0.lock().expect("slot handover lock poisoned").take()
+    }
+}
+
+impl Drop for ConnectionTracker {
+    fn drop(&mut self) {
+        if ENABLED {
+            let _ = self.live.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ConnectionBudget {
+    max: usize,
+    permits: Arc<Semaphore>,
+}
+
+impl ConnectionBudget {
+    fn new(max: usize) -> Self {
+        Self { max, permits: Arc::new(Semaphore::new(max)) }
+    }
+
+    fn from_env() -> Result<Option<Self>, String> {
+        let Ok(max) = std::env::var(MAX_CONNECTIONS_ENV) else {
+            return Ok(None);
+        };
+        match max.trim().parse::<usize>() {
+            Ok(max) if max > 0 => Ok(Some(Self::new(max))),
+            _ => Err(format!(
+                "{MAX_CONNECTIONS_ENV} must be a positive number, not {max:?}"
+            )),
+        }
+    }
+
+    /// Take a slot, waiting for one up to `connect.overall_timeout` if
+    /// `connect.wait_for_connection_budget` is set.
+    async fn acquire(
+        &self,
+        connect: &ConnectOptions,
+    ) -> Result<OwnedSemaphorePermit, ClientError> {
+        let exhausted =
+            || ClientError::ConnectionBudgetExhausted { max: self.max };
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+        if !connect.wait_for_connection_budget {
+            return Err(exhausted());
+        }
+
+        let acquire = self.permits.clone().acquire_owned();
+        let permit = match connect.overall_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, acquire)
+                .await
+                .map_err(|_| exhausted())?,
+            None => acquire.await,
+        };
+        Ok(permit.expect("connection budget semaphore is never closed"))
+    }
+}
+
+/// A slot of the connection budget, counted in
+/// [`Client::connections_in_use`] until dropped.
+#[derive(Debug)]
+pub(crate) struct BudgetSlot {
+    /// `None` when no budget is configured.
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl BudgetSlot {
+    /// Take a slot of the process wide budget, for a client about to
+    /// connect with `connect`, or the one in `handover`.
+    pub(crate) async fn acquire(
+        connect: &ConnectOptions,
+        handover: &SlotHandover,
+    ) -> Result<Self, ClientError> {
+        let budget = BUDGET
+            .get_or_init(ConnectionBudget::from_env)
+            .as_ref()
+            .map_err(|e| ClientError::Other(anyhow::anyhow!("{e}")))?;
+        Self::acquire_in(budget.as_ref(), connect, handover).await
+    }
+
+    async fn acquire_in(
+        budget: Option<&ConnectionBudget>,
+        connect: &ConnectOptions,
+        handover: &SlotHandover,
+    ) -> Result<Self, ClientError> {
+        if let Some(slot) = handover.take() {
+            return Ok(slot);
+        }
+        let _permit = match budget {
+            Some(budget) => Some(budget.acquire(connect).await?),
+            None => None,
+        };
+        let _ = IN_USE.fetch_add(1, Ordering::SeqCst);
+        Ok(Self { _permit })
+    }
+}
+
+impl Drop for BudgetSlot {
+    fn drop(&mut self) {
+        let _ = IN_USE.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Client {
+    /// Clients in this process that connected themselves and are still
+    /// alive, counting a client and its clones once. This is what
+    /// `AURAE_MAX_CONNECTIONS` caps. Clients from [`Client::from_channel`]
+    /// are not counted.
+    pub fn connections_in_use() -> usize {
+        IN_USE.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn tracker_counts_live_connections() {
+        // other tests create clients, and with them trackers, concurrently
+        static LIVE: AtomicUsize = AtomicUsize::new(0);
+        let counted = usize::from(ENABLED);
+
+        let first =
+            ConnectionTracker::counted_in(&LIVE, Location::caller(), None);
+        let second =
+            ConnectionTracker::counted_in(&LIVE, Location::caller(), None);
+        assert_eq!(LIVE.load(Ordering::Relaxed), 2 * counted);
+
+        drop(first);
+        assert_eq!(LIVE.load(Ordering::Relaxed), counted);
+        drop(second);
+        assert_eq!(LIVE.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn warns_once_per_threshold_crossing() {
+        let warned: Vec<_> = (0..=4 * LIVE_CONNECTIONS_WARN_THRESHOLD)
+            .filter(|&live| crosses_threshold(live))
+            .collect();
+
+        assert_eq!(warned, [LIVE_CONNECTIONS_WARN_THRESHOLD + 1]);
+    }
+
+    #[tokio::test]
+    async fn budget_caps_connections() {
+        let budget = ConnectionBudget::new(2);
+        let fail = ConnectOptions {
+            wait_for_connection_budget: false,
+            ..ConnectOptions::default()
+        };
+
+        let first = budget.acquire(&fail).await.unwrap();
+        let _second = budget.acquire(&fail).await.unwrap();
+        assert!(matches!(
+            budget.acquire(&fail).await,
+            Err(ClientError::ConnectionBudgetExhausted { max: 2 })
+        ));
+
+        let wait = ConnectOptions {
+            overall_timeout: Some(Duration::from_millis(50)),
+            ..ConnectOptions::default()
+        };
+        assert!(matches!(
+            budget.acquire(&wait).await,
+            Err(ClientError::ConnectionBudgetExhausted { max: 2 })
+        ));
+
+        let waiting = budget.acquire(&ConnectOptions::default());
+        drop(first);
+        let _third = waiting.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rebuilt_connections_take_over_the_slot() {
+        let budget = ConnectionBudget::new(1);
+        // waits for a slot without a timeout
+        let connect = ConnectOptions::default();
+        let none = SlotHandover::default();
+        let slot = BudgetSlot::acquire_in(Some(&budget), &connect, &none)
+            .await
+            .unwrap();
+        let tracker = ConnectionTracker::new(Location::caller(), Some(slot));
+
+        let handover = SlotHandover::default();
+        handover.give(tracker.take_slot());
+        let rebuilt = tokio::time::timeout(
+            Duration::from_secs(1),
+            BudgetSlot::acquire_in(Some(&budget), &connect, &handover),
+        )
+        .await
+        .expect("the rebuild waited for a second slot")
+        .unwrap();
+
+        drop(tracker);
+        assert_eq!(budget.permits.available_permits(), 0);
+        drop(rebuilt);
+        assert_eq!(budget.permits.available_permits(), 1);
+    }
+}
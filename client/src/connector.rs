@@ -0,0 +1,1191 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Establishes the byte stream a [`tonic::transport::Channel`] runs over.
+//!
+//! Connecting happens in two explicit phases, each with its own timeout:
+//! opening the transport (unix or TCP socket), then the TLS handshake on top
+//! of it. tonic only sees the finished stream.
+//!
+//! The transport is opened by a [`Connector`], the built in [`UnixConnector`]
+//! and [`TcpConnector`] unless one is passed to
+//! [`Client::new_with_connector`](crate::Client::new_with_connector).
+
+use crate::config::x509_details::x509_details_from_der;
+use crate::config::{ConnectOptions, IpFamily};
+use crate::connection_tracker::SlotHandover;
+use crate::cooldown::{CooldownPolicy, Cooldowns, CoolingDown};
+use crate::diagnostics::{
+    ConnectTimings, ConnectionState, LastError, ServerIdentity, TlsParams,
+};
+use crate::events::{ConnectionEvent, Events};
+use crate::goaway::GoAwaySniffer;
+use crate::tls::TlsConnect;
+use crate::AuraeSocket;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpSocket, TcpStream, UnixStream};
+use tonic::transport::Uri;
+use tracing::{debug, debug_span, field, info, warn, Instrument, Span};
+
+pub(crate) trait Io:
+    AsyncRead + AsyncWrite + Send + Unpin + 'static
+{
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Io for T {}
+
+pub(crate) type BoxedIo = Box<dyn Io>;
+
+/// Opens the transport of each connection, the stream a client runs TLS
+/// and HTTP/2 over, for transports the client cannot dial itself, such as
+/// QUIC or a tunnel, and for tests.
+///
+/// The client applies `connect.tcp_connect_timeout` around
+/// [`Connector::connect`], and does the TLS handshake, the checks of the
+/// server identity and the reconnects itself.
+#[tonic::async_trait]
+pub trait Connector: Debug + Send + Sync + 'static {
+    /// Open a connection to `socket`, the configured `system.socket` or,
+    /// under round robin, the address of a replica.
+    async fn connect(&self, socket: &AuraeSocket) -> io::Result<Connection>;
+}
+
+/// A stream opened by a [`Connector`], and where it leads.
+pub struct Connection {
+    stream: BoxedIo,
+    info: ConnectInfo,
+}
+
+impl Connection {
+    pub fn new<S>(stream: S, info: ConnectInfo) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        Self { stream: Box::new(stream), info }
+    }
+}
+
+impl Debug for Connection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Connection").field("info", &self.info).finish()
+    }
+}
+
+/// Connects to unix socket paths.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnixConnector;
+
+#[tonic::async_trait]
+impl Connector for UnixConnector {
+    async fn connect(&self, socket: &AuraeSocket) -> io::Result<Connection> {
+        let AuraeSocket::Path(path) = socket else {
+            return Err(unsupported("UnixConnector", socket));
+        };
+        let stream = UnixStream::connect(path).await?;
+        Ok(Connection::new(stream, ConnectInfo::Unix { path: path.clone() }))
+    }
+}
+
+/// Connects to TCP addresses and URIs, with the TCP options of the
+/// `connect` section it was created from. Addresses that fail to connect
+/// cool down per `connect.endpoint_cooldown`, tracked by each connector
+/// and its clones.
+#[derive(Debug, Clone)]
+pub struct TcpConnector {
+    tcp: TcpOptions,
+    cooldowns: Cooldowns,
+}
+
+impl TcpConnector {
+    pub fn new(options: &ConnectOptions) -> Self {
+        Self { tcp: TcpOptions::from(options), cooldowns: Cooldowns::default() }
+    }
+}
+
+#[tonic::async_trait]
+impl Connector for TcpConnector {
+    async fn connect(&self, socket: &AuraeSocket) -> io::Result<Connection> {
+        let stream = match socket {
+            AuraeSocket::Addr(addr) => {
+                if let Some(remaining) = self.cooldowns.remaining(*addr) {
+                    return Err(cooling_down(*addr, remaining));
+                }
+                connect_tracked(*addr, self.tcp, &self.cooldowns).await?
+            }
+            AuraeSocket::Uri(uri) => {
+                let (host, port) = host_port(uri)?;
+                connect_host(&host, port, self.tcp, &self.cooldowns).await?
+            }
+            _ => return Err(unsupported("TcpConnector", socket)),
+        };
+        let info = tcp_info(&stream)?;
+        Ok(Connection::new(stream, info))
+    }
+}
+
+/// Takes the stream of an inherited socket, which only connects once.
+#[derive(Debug)]
+struct InheritedConnector;
+
+#[tonic::async_trait]
+impl Connector for InheritedConnector {
+    async fn connect(&self, socket: &AuraeSocket) -> io::Result<Connection> {
+        let AuraeSocket::Inherited(inherited) = socket else {
+            return Err(unsupported("InheritedConnector", socket));
+        };
+        let fd = inherited.take().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!(
+                    "the inherited socket (fd {}) was already used and cannot be reconnected",
+                    inherited.fd()
+                ),
+            )
+        })?;
+        let (stream, info) = inherited_stream(fd)?;
+        Ok(Connection { stream, info })
+    }
+}
+
+fn unsupported(connector: &str, socket: &AuraeSocket) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{connector} cannot connect to {} sockets", socket.kind()),
+    )
+}
+
+/// A step of establishing a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectPhase {
+    /// Opening the unix or TCP socket.
+    Transport,
+    /// The TLS handshake on an open socket.
+    TlsHandshake,
+    /// The whole connect, including HTTP/2 setup.
+    Overall,
+}
+
+impl Display for ConnectPhase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ConnectPhase::Transport => "transport connect",
+            ConnectPhase::TlsHandshake => "TLS handshake",
+            ConnectPhase::Overall => "connect",
+        })
+    }
+}
+
+/// Returned from the connector (inside a [`tonic::transport::Error`]) when a
+/// phase exceeds its timeout.
+#[derive(Debug, thiserror::Error)]
+#[error("{phase} timed out after {timeout:?}")]
+pub(crate) struct PhaseTimeout {
+    pub(crate) phase: ConnectPhase,
+    pub(crate) timeout: Duration,
+}
+
+/// Returned from the connector, like [`PhaseTimeout`], when resolving a
+/// host takes longer than `connect.dns_timeout`.
+#[derive(Debug, thiserror::Error)]
+#[error("resolving {host} timed out after {timeout:?}")]
+pub(crate) struct DnsTimeout {
+    pub(crate) host: String,
+    pub(crate) timeout: Duration,
+}
+
+/// Addresses host names and ports resolved to, and when. Shared by every
+/// client of the process, as the answers are the same for all of them.
+/// Each lookup uses an entry for as long as its `connect.dns_cache_ttl`.
+static DNS_CACHE: Mutex<BTreeMap<(String, u16), (Instant, Vec<SocketAddr>)>> =
+    Mutex::new(BTreeMap::new());
+
+/// Phase timeouts copied out of [`ConnectOptions`].
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct PhaseTimeouts {
+    transport: Option<Duration>,
+    tls_handshake: Option<Duration>,
+}
+
+impl From<&ConnectOptions> for PhaseTimeouts {
+    fn from(options: &ConnectOptions) -> Self {
+        Self {
+            transport: options.tcp_connect_timeout,
+            tls_handshake: options.tls_handshake_timeout,
+        }
+    }
+}
+
+/// How TCP connections are opened, copied out of [`ConnectOptions`].
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct TcpOptions {
+    family: IpFamily,
+    bind_address: Option<SocketAddr>,
+    nodelay: bool,
+    send_buffer_size: Option<u32>,
+    recv_buffer_size: Option<u32>,
+    dns_timeout: Option<Duration>,
+    dns_cache_ttl: Duration,
+    cooldown: Option<CooldownPolicy>,
+}
+
+impl From<&ConnectOptions> for TcpOptions {
+    fn from(options: &ConnectOptions) -> Self {
+        Self {
+            family: options.ip_family,
+            bind_address: options.bind_address,
+            nodelay: options.tcp_nodelay,
+            send_buffer_size: options.send_buffer_size,
+            recv_buffer_size: options.recv_buffer_size,
+            dns_timeout: options.dns_timeout,
+            dns_cache_ttl: options.dns_cache_ttl,
+            cooldown: CooldownPolicy::new(options),
+        }
+    }
+}
+
+/// Where a connection ended up, see [`crate::Client::connect_info`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectInfo {
+    /// A unix socket, opened at `path`.
+    Unix { path: PathBuf },
+    /// A TCP connection from the `local` address to the resolved `peer`.
+    Tcp { local: SocketAddr, peer: SocketAddr },
+    /// A connection opened by a custom [`Connector`], as it describes it.
+    Custom { description: String },
+}
+
+impl Display for ConnectInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectInfo::Unix { path } => write!(f, "unix:{}", path.display()),
+            ConnectInfo::Tcp { local, peer } => write!(f, "{local} -> {peer}"),
+            ConnectInfo::Custom { description } => f.write_str(description),
+        }
+    }
+}
+
+/// What a client's connectors report back: the most recent connect, the
+/// number of open connections, the state they leave the connection in,
+/// the endpoints cooling down and the lifecycle [`Events`]. Also carries
+/// the custom [`Connector`] they open transports with, if there is one.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectState {
+    connector: Option<Arc<dyn Connector>>,
+    pub(crate) cooldowns: Cooldowns,
+    /// The budget slot of the connection a rebuild replaces.
+    pub(crate) handover: SlotHandover,
+    last: Arc<Mutex<Option<LastConnect>>>,
+    open: Arc<AtomicUsize>,
+    health: Arc<Mutex<Health>>,
+    pub(crate) events: Events,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    state: ConnectionState,
+    last_error: Option<LastError>,
+    rtt: Option<Rtt>,
+    last_success: Option<Instant>,
+}
+
+/// Round trips measured by the liveness monitor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Rtt {
+    pub(crate) latest: Duration,
+    /// Weighs each new sample by 1/8, as TCP's smoothed RTT does.
+    pub(crate) average: Duration,
+}
+
+impl Rtt {
+    fn record(previous: Option<Self>, sample: Duration) -> Self {
+        let average = match previous {
+            Some(Self { average, .. }) => (average * 7 + sample) / 8,
+            None => sample,
+        };
+        Self { latest: sample, average }
+    }
+}
+
+/// Everything recorded about the most recent connect.
+#[derive(Debug, Clone)]
+pub(crate) struct LastConnect {
+    pub(crate) info: ConnectInfo,
+    pub(crate) timings: ConnectTimings,
+    /// `None` without TLS, or when the handshake did not complete.
+    pub(crate) tls: Option<TlsParams>,
+    pub(crate) server: Option<ServerIdentity>,
+}
+
+impl ConnectState {
+    /// A fresh state opening transports with `connector`.
+    pub(crate) fn with_connector(connector: Arc<dyn Connector>) -> Self {
+        Self { connector: Some(connector), ..Self::default() }
+    }
+
+    /// A fresh state publishing to the same subscribers, with the same
+    /// connector.
+    pub(crate) fn sharing_events(&self) -> Self {
+        Self {
+            connector: self.connector.clone(),
+            events: self.events.clone(),
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn get(&self) -> Option<ConnectInfo> {
+        self.last().map(|last| last.info)
+    }
+
+    pub(crate) fn last(&self) -> Option<LastConnect> {
+        self.last.lock().expect("connect info lock poisoned").clone()
+    }
+
+    /// Connections that are currently open.
+    pub(crate) fn open(&self) -> usize {
+        self.open.load(Ordering::SeqCst)
+    }
+
+    /// The state the connection is in, and the most recent failure.
+    pub(crate) fn health(&self) -> (ConnectionState, Option<LastError>) {
+        let health = self.health.lock().expect("health lock poisoned");
+        (health.state, health.last_error.clone())
+    }
+
+    pub(crate) fn rtt(&self) -> Option<Rtt> {
+        self.health.lock().expect("health lock poisoned").rtt
+    }
+
+    pub(crate) fn record_rtt(&self, sample: Duration) {
+        let mut health = self.health.lock().expect("health lock poisoned");
+        health.rtt = Some(Rtt::record(health.rtt, sample));
+    }
+
+    pub(crate) fn last_success(&self) -> Option<Instant> {
+        self.health.lock().expect("health lock poisoned").last_success
+    }
+
+    pub(crate) fn record_success(&self) {
+        self.health.lock().expect("health lock poisoned").last_success =
+            Some(Instant::now());
+    }
+
+    /// Publish `event` to subscribers, tracking the state it moves the
+    /// connection to.
+    pub(crate) fn publish(&self, event: ConnectionEvent) {
+        {
+            let mut health = self.health.lock().expect("health lock poisoned");
+            match event {
+                ConnectionEvent::Connected => {
+                    health.state = ConnectionState::Connected;
+                }
+                ConnectionEvent::Disconnected if self.open() == 0 => {
+                    health.state = ConnectionState::Disconnected;
+                }
+                ConnectionEvent::Reconnecting { attempt } => {
+                    health.state = ConnectionState::Reconnecting { attempt };
+                }
+                _ => {}
+            }
+        }
+        self.events.publish(event);
+    }
+
+    /// Remember `error` as the most recent failure, leaving the state as
+    /// is. Used for failed reconnect attempts and health checks.
+    pub(crate) fn record_error(&self, error: &dyn Display) {
+        self.health.lock().expect("health lock poisoned").last_error =
+            Some(LastError::now(error));
+    }
+
+    /// The connection could not be established, and is not being retried.
+    pub(crate) fn failed(&self, error: &dyn Display) {
+        let mut health = self.health.lock().expect("health lock poisoned");
+        health.state = ConnectionState::Failed;
+        health.last_error = Some(LastError::now(error));
+    }
+
+    fn set(&self, info: ConnectInfo, transport: Duration) {
+        *self.last.lock().expect("connect info lock poisoned") =
+            Some(LastConnect {
+                info,
+                timings: ConnectTimings::new(transport, None),
+                tls: None,
+                server: None,
+            });
+    }
+
+    fn set_tls(
+        &self,
+        handshake: Duration,
+        tls: TlsParams,
+        server: Option<ServerIdentity>,
+    ) {
+        if let Some(last) =
+            self.last.lock().expect("connect info lock poisoned").as_mut()
+        {
+            last.timings = last.timings.with_tls_handshake(handshake);
+            last.tls = Some(tls);
+            last.server = server;
+        }
+    }
+
+    pub(crate) fn set_warm_up(&self, warm_up: Duration) {
+        if let Some(last) =
+            self.last.lock().expect("connect info lock poisoned").as_mut()
+        {
+            last.timings = last.timings.with_warm_up(warm_up);
+        }
+    }
+}
+
+/// Publishes [`ConnectionEvent::Draining`] when the server sends GOAWAY,
+/// and [`ConnectionEvent::Disconnected`] once hyper drops the stream of a
+/// closed connection.
+struct Observed {
+    inner: BoxedIo,
+    state: ConnectState,
+    goaway: GoAwaySniffer,
+    /// The address reached over TCP, reported with a GOAWAY.
+    peer: Option<SocketAddr>,
+}
+
+impl Drop for Observed {
+    fn drop(&mut self) {
+        let _ = self.state.open.fetch_sub(1, Ordering::SeqCst);
+        self.state.publish(ConnectionEvent::Disconnected);
+    }
+}
+
+impl AsyncRead for Observed {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let read = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = read {
+            if self.goaway.feed(&buf.filled()[before..]) {
+                let peer = self.peer;
+                debug!("server sent GOAWAY, connection is draining");
+                self.state.publish(ConnectionEvent::Draining { peer });
+            }
+        }
+        read
+    }
+}
+
+impl AsyncWrite for Observed {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+pub(crate) async fn connect(
+    socket: AuraeSocket,
+    tls: Option<TlsConnect>,
+    timeouts: PhaseTimeouts,
+    tcp: TcpOptions,
+    state: ConnectState,
+) -> io::Result<BoxedIo> {
+    let span = debug_span!(
+        "connect",
+        transport = %socket.kind(),
+        addr = field::Empty,
+    );
+    let (stream, info) =
+        connect_traced(socket, tls, timeouts, tcp, state.clone())
+            .instrument(span)
+            .await?;
+    let peer = match info {
+        ConnectInfo::Tcp { peer, .. } => Some(peer),
+        ConnectInfo::Unix { .. } | ConnectInfo::Custom { .. } => None,
+    };
+
+    let _ = state.open.fetch_add(1, Ordering::SeqCst);
+    state.publish(ConnectionEvent::Connected);
+    Ok(Box::new(Observed {
+        inner: stream,
+        state,
+        goaway: GoAwaySniffer::default(),
+        peer,
+    }))
+}
+
+async fn connect_traced(
+    socket: AuraeSocket,
+    tls: Option<TlsConnect>,
+    timeouts: PhaseTimeouts,
+    tcp: TcpOptions,
+    state: ConnectState,
+) -> io::Result<(BoxedIo, ConnectInfo)> {
+    let started = Instant::now();
+    let host_ip = host_ip(&socket);
+    let tcp_connector;
+    let connector: &dyn Connector = match (&state.connector, &socket) {
+        (Some(custom), _) => custom.as_ref(),
+        (None, AuraeSocket::Path(_)) => &UnixConnector,
+        (None, AuraeSocket::Addr(_) | AuraeSocket::Uri(_)) => {
+            tcp_connector =
+                TcpConnector { tcp, cooldowns: state.cooldowns.clone() };
+            &tcp_connector
+        }
+        (None, AuraeSocket::Inherited(_)) => &InheritedConnector,
+    };
+    let Connection { stream, info } = with_timeout(
+        ConnectPhase::Transport,
+        timeouts.transport,
+        connector.connect(&socket),
+    )
+    .await?;
+
+    let _ = Span::current().record("addr", field::display(&info));
+    debug!("transport connected");
+    state.set(info.clone(), started.elapsed());
+
+    let Some(tls) = tls else {
+        return Ok((stream, info));
+    };
+
+    let started = Instant::now();
+    let stream = with_timeout(
+        ConnectPhase::TlsHandshake,
+        timeouts.tls_handshake,
+        tls.handshake(host_ip, stream),
+    )
+    .await?;
+
+    let end_entity = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .map(|cert| cert.0.as_slice())
+        .unwrap_or_default();
+    state.set_tls(
+        started.elapsed(),
+        TlsParams::from(stream.get_ref().1),
+        ServerIdentity::from_der(end_entity),
+    );
+    tls.expected
+        .check(end_entity)
+        .map_err(|e| io::Error::new(io::ErrorKind::PermissionDenied, e))?;
+    tls.expected
+        .check_trust_domain(end_entity)
+        .map_err(|e| io::Error::new(io::ErrorKind::PermissionDenied, e))?;
+    if tls.audit_log {
+        match x509_details_from_der(end_entity) {
+            Ok(details) => info!(
+                target: "aurae::audit",
+                peer = %info,
+                record = %details.to_audit_record().to_json_line(),
+                "connected to auraed"
+            ),
+            Err(e) => warn!("failed to audit the server certificate: {e}"),
+        }
+    }
+
+    Ok((Box::new(stream), info))
+}
+
+/// Resolve `host` and connect to the first address `tcp.family` selects
+/// that accepts the connection, skipping those cooling down.
+async fn connect_host(
+    host: &str,
+    port: u16,
+    tcp: TcpOptions,
+    cooldowns: &Cooldowns,
+) -> io::Result<TcpStream> {
+    let addrs = resolve(host, port, tcp).await?;
+
+    let mut last_err = None;
+    for addr in addrs {
+        if let Some(remaining) = cooldowns.remaining(addr) {
+            debug!("skipping {addr}, cooling down for {remaining:?}");
+            last_err = Some(cooling_down(addr, remaining));
+            continue;
+        }
+        match connect_tracked(addr, tcp, cooldowns).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                debug!("failed to connect to {addr}: {e}");
+                last_err = Some(e);
+            }
+        }
+    }
+
+    Err(last_err.expect("resolve returns at least one address"))
+}
+
+/// Connect to `addr` as [`connect_addr`] does, cooling it down when that
+/// fails and `tcp.cooldown` is set.
+async fn connect_tracked(
+    addr: SocketAddr,
+    tcp: TcpOptions,
+    cooldowns: &Cooldowns,
+) -> io::Result<TcpStream> {
+    let Some(policy) = tcp.cooldown else {
+        return connect_addr(addr, tcp).await;
+    };
+    let connected = connect_addr(addr, tcp).await;
+    match &connected {
+        Ok(_) => cooldowns.recovered(addr),
+        Err(_) => cooldowns.failed(addr, policy),
+    }
+    connected
+}
+
+fn cooling_down(addr: SocketAddr, remaining: Duration) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionRefused,
+        CoolingDown { addr, remaining },
+    )
+}
+
+/// Connect to `addr` with the socket options of `tcp`.
+async fn connect_addr(
+    addr: SocketAddr,
+    tcp: TcpOptions,
+) -> io::Result<TcpStream> {
+    let stream = tcp_socket(addr, tcp)?.connect(addr).await?;
+    stream.set_nodelay(tcp.nodelay)?;
+    Ok(stream)
+}
+
+/// A socket for connecting to `addr`, bound to `tcp.bind_address` when set
+/// and with the buffer sizes of `tcp`, which only take full effect when set
+/// before connecting.
+fn tcp_socket(addr: SocketAddr, tcp: TcpOptions) -> io::Result<TcpSocket> {
+    if let Some(bind) = tcp.bind_address {
+        if bind.is_ipv4() != addr.is_ipv4() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("connect.bind_address {bind} cannot reach {addr}, which is of the other address family"),
+            ));
+        }
+    }
+
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    if let Some(size) = tcp.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = tcp.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(bind) = tcp.bind_address {
+        socket.bind(bind).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("failed to bind to connect.bind_address {bind}: {e}"),
+            )
+        })?;
+    }
+    Ok(socket)
+}
+
+/// The addresses of `host` that `tcp.family` selects, in the order to try
+/// them. Fails rather than returning an empty list.
+pub(crate) async fn resolve(
+    host: &str,
+    port: u16,
+    tcp: TcpOptions,
+) -> io::Result<Vec<SocketAddr>> {
+    resolve_with(host, port, tcp, |host, port| async move {
+        Ok(tokio::net::lookup_host((host.as_str(), port)).await?.collect())
+    })
+    .await
+}
+
+/// As [`resolve`], looking up hosts missing from the [`DNS_CACHE`] with
+/// `lookup`, within `tcp.dns_timeout`.
+async fn resolve_with<F, Fut>(
+    host: &str,
+    port: u16,
+    tcp: TcpOptions,
+    lookup: F,
+) -> io::Result<Vec<SocketAddr>>
+where
+    F: FnOnce(String, u16) -> Fut,
+    Fut: Future<Output = io::Result<Vec<SocketAddr>>>,
+{
+    let family = tcp.family;
+    let key = (host.to_string(), port);
+    let cached = match tcp.dns_cache_ttl.is_zero() {
+        true => None,
+        false => DNS_CACHE
+            .lock()
+            .expect("dns cache lock poisoned")
+            .get(&key)
+            .filter(|(at, _)| at.elapsed() < tcp.dns_cache_ttl)
+            .map(|(_, addrs)| addrs.clone()),
+    };
+
+    let resolved = match cached {
+        Some(addrs) => addrs,
+        None => {
+            let lookup = lookup(host.to_string(), port);
+            let addrs = match tcp.dns_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, lookup)
+                    .await
+                    .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        DnsTimeout { host: host.into(), timeout },
+                    )
+                })??,
+                None => lookup.await?,
+            };
+            if !tcp.dns_cache_ttl.is_zero() {
+                let _ = DNS_CACHE
+                    .lock()
+                    .expect("dns cache lock poisoned")
+                    .insert(key, (Instant::now(), addrs.clone()));
+            }
+            addrs
+        }
+    };
+    let addrs = family.select(resolved);
+
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{host} did not resolve to any address allowed by ip_family {family:?}"),
+        ));
+    }
+
+    Ok(addrs)
+}
+
+/// Wrap an inherited socket in the stream type matching its address family.
+/// Only unix and TCP sockets are supported.
+fn inherited_stream(socket: OwnedFd) -> io::Result<(BoxedIo, ConnectInfo)> {
+    let fd = socket.as_raw_fd();
+    let unix = std::os::unix::net::UnixStream::from(socket);
+
+    // Fails when the descriptor is not a unix socket.
+    if unix.local_addr().is_ok() {
+        unix.set_nonblocking(true)?;
+        let stream = UnixStream::from_std(unix)?;
+        let path = PathBuf::from(format!("/proc/self/fd/{fd}"));
+        return Ok((Box::new(stream), ConnectInfo::Unix { path }));
+    }
+
+    let tcp = std::net::TcpStream::from(OwnedFd::from(unix));
+    tcp.set_nonblocking(true)?;
+    let stream = TcpStream::from_std(tcp)?;
+    let info = tcp_info(&stream).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "inherited fd {fd} is not a connected unix or TCP socket: {e}"
+            ),
+        )
+    })?;
+    Ok((Box::new(stream), info))
+}
+
+fn tcp_info(stream: &TcpStream) -> io::Result<ConnectInfo> {
+    Ok(ConnectInfo::Tcp {
+        local: stream.local_addr()?,
+        peer: stream.peer_addr()?,
+    })
+}
+
+/// The IP address `socket` names, if it names one rather than a host name
+/// or a path.
+fn host_ip(socket: &AuraeSocket) -> Option<IpAddr> {
+    match socket {
+        AuraeSocket::Addr(addr) => Some(addr.ip()),
+        AuraeSocket::Uri(uri) => {
+            host_port(uri).ok().and_then(|(host, _)| host.parse().ok())
+        }
+        AuraeSocket::Path(_) | AuraeSocket::Inherited(_) => None,
+    }
+}
+
+/// The host and port to dial for `uri`, defaulting to the scheme's port.
+pub(crate) fn host_port(uri: &Uri) -> io::Result<(String, u16)> {
+    let Some(host) = uri.host() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{uri} has no host"),
+        ));
+    };
+
+    let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+        Some("http") => 80,
+        _ => 443,
+    });
+
+    // Bracketed IPv6 hosts need their brackets removed before resolving.
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    Ok((host.to_string(), port))
+}
+
+async fn with_timeout<T>(
+    phase: ConnectPhase,
+    timeout: Option<Duration>,
+    fut: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    let Some(timeout) = timeout else {
+        return fut.await;
+    };
+
+    tokio::time::timeout(timeout, fut).await.map_err(|_| {
+        io::Error::new(io::ErrorKind::TimedOut, PhaseTimeout { phase, timeout })
+    })?
+}
+
+/// Find an error of type `E`, such as a [`PhaseTimeout`], anywhere in the
+/// source chain of `err`.
+pub(crate) fn find_cause<E: std::error::Error + 'static>(
+    err: &(dyn std::error::Error + 'static),
+) -> Option<&E> {
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if let Some(cause) = err.downcast_ref::<E>() {
+            return Some(cause);
+        }
+
+        if let Some(cause) = err
+            .downcast_ref::<io::Error>()
+            .and_then(|err| err.get_ref())
+            .and_then(|inner| inner.downcast_ref::<E>())
+        {
+            return Some(cause);
+        }
+
+        current = err.source();
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InheritedSocket;
+
+    #[test]
+    fn rtt_averages_samples() {
+        let state = ConnectState::default();
+        assert_eq!(state.rtt(), None);
+
+        state.record_rtt(Duration::from_millis(8));
+        state.record_rtt(Duration::from_millis(16));
+        let rtt = state.rtt().unwrap();
+        assert_eq!(rtt.latest, Duration::from_millis(16));
+        assert_eq!(rtt.average, Duration::from_millis(9));
+    }
+
+    #[test]
+    fn host_port_defaults_to_scheme_port() {
+        let uri = Uri::from_static("https://auraed.example.com");
+        assert_eq!(
+            host_port(&uri).unwrap(),
+            ("auraed.example.com".to_string(), 443)
+        );
+
+        let uri = Uri::from_static("http://[fe80::2]:8080");
+        assert_eq!(host_port(&uri).unwrap(), ("fe80::2".to_string(), 8080));
+    }
+
+    #[test]
+    fn only_ip_sockets_have_a_host_ip() {
+        let ip = |socket: &str| host_ip(&socket.parse().unwrap());
+
+        assert_eq!(ip("10.0.0.3:8080"), Some("10.0.0.3".parse().unwrap()));
+        assert_eq!(
+            ip("https://[fe80::2]:8080"),
+            Some("fe80::2".parse().unwrap())
+        );
+        assert_eq!(ip("https://auraed.example.com"), None);
+        assert_eq!(ip("/var/run/aurae/aurae.sock"), None);
+    }
+
+    #[tokio::test]
+    async fn connect_records_unix_socket_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("aurae.sock");
+        let _listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let state = ConnectState::default();
+        let mut events = state.events.subscribe();
+
+        let stream = connect(
+            AuraeSocket::Path(path.clone()),
+            None,
+            PhaseTimeouts::default(),
+            TcpOptions::default(),
+            state.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(state.get(), Some(ConnectInfo::Unix { path: path.clone() }));
+        assert_eq!(events.try_recv().unwrap(), ConnectionEvent::Connected);
+        assert_eq!(state.open(), 1);
+        drop(stream);
+        assert_eq!(events.try_recv().unwrap(), ConnectionEvent::Disconnected);
+        assert_eq!(state.open(), 0);
+    }
+
+    /// Hands out in-memory streams, keeping the sockets it was asked for.
+    #[derive(Debug, Default)]
+    struct InMemory {
+        asked: Mutex<Vec<String>>,
+    }
+
+    #[tonic::async_trait]
+    impl Connector for InMemory {
+        async fn connect(
+            &self,
+            socket: &AuraeSocket,
+        ) -> io::Result<Connection> {
+            self.asked.lock().unwrap().push(socket.endpoint());
+            let (ours, _theirs) = tokio::io::duplex(64);
+            let info = ConnectInfo::Custom { description: "in memory".into() };
+            Ok(Connection::new(ours, info))
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_connectors_open_the_transport() {
+        let connector = Arc::new(InMemory::default());
+        let state = ConnectState::with_connector(connector.clone());
+        let socket: AuraeSocket = "auraed.example.com:8443".parse().unwrap();
+
+        // rebuilt clients keep the connector
+        let rebuilt = state.sharing_events();
+        let stream = connect(
+            socket.clone(),
+            None,
+            PhaseTimeouts::default(),
+            TcpOptions::default(),
+            rebuilt.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(*connector.asked.lock().unwrap(), [socket.endpoint()]);
+        let description = "in memory".to_string();
+        assert_eq!(rebuilt.get(), Some(ConnectInfo::Custom { description }));
+        drop(stream);
+
+        let err = UnixConnector.connect(&socket).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn inherited_socket_connects_once() {
+        let (ours, _theirs) = std::os::unix::net::UnixStream::pair().unwrap();
+        let socket = AuraeSocket::Inherited(InheritedSocket::new(ours.into()));
+        let state = ConnectState::default();
+
+        let stream = connect(
+            socket.clone(),
+            None,
+            PhaseTimeouts::default(),
+            TcpOptions::default(),
+            state.clone(),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(state.get(), Some(ConnectInfo::Unix { .. })));
+        drop(stream);
+
+        let err = connect(
+            socket,
+            None,
+            PhaseTimeouts::default(),
+            TcpOptions::default(),
+            state,
+        )
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+    }
+
+    #[tokio::test]
+    async fn connections_leave_from_the_bind_address() {
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bind: SocketAddr = "127.0.0.2:0".parse().unwrap();
+        let tcp = TcpOptions { bind_address: Some(bind), ..Default::default() };
+        let state = ConnectState::default();
+
+        let _stream = connect(
+            AuraeSocket::Addr(addr),
+            None,
+            PhaseTimeouts::default(),
+            tcp,
+            state.clone(),
+        )
+        .await
+        .unwrap();
+        let (_, peer) = listener.accept().await.unwrap();
+
+        assert_eq!(peer.ip(), bind.ip());
+        assert!(matches!(
+            state.get(),
+            Some(ConnectInfo::Tcp { local, .. }) if local.ip() == bind.ip()
+        ));
+    }
+
+    #[tokio::test]
+    async fn unusable_bind_addresses_fail_the_connect() {
+        let addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        // TEST-NET-1, not assigned to any local interface
+        let unassigned = TcpOptions {
+            bind_address: Some("192.0.2.1:0".parse().unwrap()),
+            ..Default::default()
+        };
+        let err = connect_addr(addr, unassigned).await.unwrap_err();
+        assert!(err.to_string().contains("connect.bind_address"), "{err}");
+
+        let v6 = TcpOptions {
+            bind_address: Some("[::1]:0".parse().unwrap()),
+            ..Default::default()
+        };
+        let err = connect_addr(addr, v6).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn socket_options_are_applied() {
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tcp = TcpOptions::from(&ConnectOptions {
+            send_buffer_size: Some(64 * 1024),
+            recv_buffer_size: Some(128 * 1024),
+            ..Default::default()
+        });
+
+        let socket = tcp_socket(addr, tcp).unwrap();
+        // Linux doubles the sizes to leave room for bookkeeping
+        assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+        assert!(socket.recv_buffer_size().unwrap() >= 128 * 1024);
+
+        let stream = connect_addr(addr, tcp).await.unwrap();
+        assert!(stream.nodelay().unwrap());
+
+        let tcp = TcpOptions { nodelay: false, ..tcp };
+        let stream = connect_addr(addr, tcp).await.unwrap();
+        assert!(!stream.nodelay().unwrap());
+    }
+
+    #[tokio::test]
+    async fn with_timeout_reports_phase() {
+        let err = with_timeout(
+            ConnectPhase::TlsHandshake,
+            Some(Duration::from_millis(10)),
+            std::future::pending::<io::Result<()>>(),
+        )
+        .await
+        .unwrap_err();
+
+        let timeout = find_cause::<PhaseTimeout>(&err).unwrap();
+        assert_eq!(timeout.phase, ConnectPhase::TlsHandshake);
+        assert_eq!(timeout.timeout, Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn slow_dns_times_out() {
+        let tcp = TcpOptions {
+            dns_timeout: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+
+        let err = resolve_with("slow.aurae.test", 8080, tcp, |_, _| async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(vec![])
+        })
+        .await
+        .unwrap_err();
+
+        let timeout = find_cause::<DnsTimeout>(&err).unwrap();
+        assert_eq!(timeout.host, "slow.aurae.test");
+        assert_eq!(timeout.timeout, Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn dns_answers_are_cached_for_the_ttl() {
+        let lookups = AtomicUsize::new(0);
+        let lookup = |_: String, port: u16| {
+            let _ = lookups.fetch_add(1, Ordering::SeqCst);
+            async move { Ok(vec![SocketAddr::from(([10, 0, 0, 3], port))]) }
+        };
+        let cached = TcpOptions {
+            dns_cache_ttl: Duration::from_secs(60),
+            ..Default::default()
+        };
+
+        for _ in 0..2 {
+            let addrs = resolve_with("cached.aurae.test", 8080, cached, lookup)
+                .await
+                .unwrap();
+            assert_eq!(addrs, [SocketAddr::from(([10, 0, 0, 3], 8080))]);
+        }
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        // without a TTL every connect looks the host up again
+        let _ = resolve_with(
+            "cached.aurae.test",
+            8080,
+            TcpOptions::default(),
+            lookup,
+        )
+        .await
+        .unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,197 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Cooling down TCP endpoints that failed to connect, see
+//! [`ConnectOptions::endpoint_cooldown`].
+
+use crate::config::ConnectOptions;
+use crate::diagnostics::millis;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Returned from the connector (inside a [`tonic::transport::Error`]) when
+/// every address it could connect to is cooling down.
+#[derive(Debug, thiserror::Error)]
+#[error("{addr} is cooling down after failing to connect, for another {remaining:?}")]
+pub(crate) struct CoolingDown {
+    pub(crate) addr: SocketAddr,
+    pub(crate) remaining: Duration,
+}
+
+/// How long endpoints cool down, copied out of [`ConnectOptions`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CooldownPolicy {
+    base: Duration,
+    max: Duration,
+}
+
+impl CooldownPolicy {
+    /// `None` unless `connect.endpoint_cooldown` is set.
+    pub(crate) fn new(options: &ConnectOptions) -> Option<Self> {
+        options.endpoint_cooldown.map(|base| Self {
+            base,
+            max: options.max_endpoint_cooldown.max(base),
+        })
+    }
+
+    /// The cooldown after `failures` failed connects in a row, doubling
+    /// from the base with each one.
+    fn after(&self, failures: u32) -> Duration {
+        let doublings = failures.saturating_sub(1).min(31);
+        self.base.saturating_mul(1 << doublings).min(self.max)
+    }
+}
+
+/// An endpoint of [`crate::Diagnostics::cooldowns`], which is skipped when
+/// connecting until its cooldown is over.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EndpointCooldown {
+    pub addr: SocketAddr,
+    /// Failed connects in a row, each doubling the cooldown.
+    pub failures: u32,
+    #[serde(serialize_with = "millis")]
+    pub remaining: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    failures: u32,
+    until: Instant,
+}
+
+/// The endpoints a client failed to connect to, shared by its connectors.
+/// An endpoint stays here once its cooldown is over, so the next failure
+/// cools it down for longer, and leaves on its first successful connect.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Cooldowns {
+    entries: Arc<Mutex<HashMap<SocketAddr, Entry>>>,
+}
+
+impl Cooldowns {
+    /// How much longer `addr` is cooling down, `None` when it can be
+    /// connected to.
+    pub(crate) fn remaining(&self, addr: SocketAddr) -> Option<Duration> {
+        let entries = self.entries.lock().expect("cooldowns lock poisoned");
+        let remaining =
+            entries.get(&addr)?.until.saturating_duration_since(Instant::now());
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    /// Whether no endpoint is cooling down or waiting to recover.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.lock().expect("cooldowns lock poisoned").is_empty()
+    }
+
+    pub(crate) fn failed(&self, addr: SocketAddr, policy: CooldownPolicy) {
+        let mut entries = self.entries.lock().expect("cooldowns lock poisoned");
+        let failures = entries.get(&addr).map_or(0, |entry| entry.failures) + 1;
+        let cooldown = policy.after(failures);
+        debug!("cooling down {addr} for {cooldown:?} after {failures} failed connects");
+        let _ = entries
+            .insert(addr, Entry { failures, until: Instant::now() + cooldown });
+    }
+
+    pub(crate) fn recovered(&self, addr: SocketAddr) {
+        let mut entries = self.entries.lock().expect("cooldowns lock poisoned");
+        if entries.remove(&addr).is_some() {
+            debug!("{addr} recovered from its cooldown");
+        }
+    }
+
+    /// The endpoints still cooling down, by address.
+    pub(crate) fn cooling(&self) -> Vec<EndpointCooldown> {
+        let now = Instant::now();
+        let entries = self.entries.lock().expect("cooldowns lock poisoned");
+        let mut cooling: Vec<_> = entries
+            .iter()
+            .filter(|(_, entry)| entry.until > now)
+            .map(|(addr, entry)| EndpointCooldown {
+                addr: *addr,
+                failures: entry.failures,
+                remaining: entry.until - now,
+            })
+            .collect();
+        cooling.sort_by_key(|cooldown| cooldown.addr);
+        cooling
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connector::{self, ConnectState, PhaseTimeouts, TcpOptions};
+    use crate::AuraeSocket;
+
+    #[test]
+    fn cooldowns_double_up_to_the_max() {
+        let policy = CooldownPolicy::new(&ConnectOptions {
+            endpoint_cooldown: Some(Duration::from_secs(1)),
+            max_endpoint_cooldown: Duration::from_secs(5),
+            ..ConnectOptions::default()
+        })
+        .unwrap();
+
+        let cooldowns: Vec<_> =
+            (1..=5).map(|failures| policy.after(failures).as_secs()).collect();
+
+        assert_eq!(cooldowns, [1, 2, 4, 5, 5]);
+        assert!(CooldownPolicy::new(&ConnectOptions::default()).is_none());
+    }
+
+    #[tokio::test]
+    async fn failing_endpoints_cool_down_until_they_connect() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let cooldown = Duration::from_millis(200);
+        let tcp = TcpOptions::from(&ConnectOptions {
+            endpoint_cooldown: Some(cooldown),
+            ..ConnectOptions::default()
+        });
+        let state = ConnectState::default();
+        let connect = || {
+            connector::connect(
+                AuraeSocket::Addr(addr),
+                None,
+                PhaseTimeouts::default(),
+                tcp,
+                state.clone(),
+            )
+        };
+
+        let refused = connect().await.err().unwrap();
+        assert_eq!(refused.kind(), std::io::ErrorKind::ConnectionRefused);
+        let cooling = state.cooldowns.cooling();
+        assert_eq!(cooling.len(), 1);
+        assert_eq!((cooling[0].addr, cooling[0].failures), (addr, 1));
+
+        // skipped without dialing, even though it listens again
+        let _listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        let skipped = connect().await.err().unwrap();
+        assert!(connector::find_cause::<CoolingDown>(&skipped).is_some());
+
+        tokio::time::sleep(cooldown).await;
+        assert!(state.cooldowns.cooling().is_empty());
+        assert!(!state.cooldowns.is_empty());
+
+        let _stream = connect().await.unwrap();
+        assert!(state.cooldowns.is_empty());
+    }
+}
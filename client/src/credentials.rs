@@ -0,0 +1,277 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Bearer tokens from a [`CredentialProvider`], refreshed before they
+//! expire, see [`Client::with_credential_provider`].
+
+use crate::{Client, ClientError};
+use anyhow::anyhow;
+use secrecy::{ExposeSecret, SecretString};
+use std::fmt;
+use std::sync::{Arc, RwLock, Weak};
+use std::time::{Duration, SystemTime};
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::{Request, Status};
+use tracing::{debug, warn};
+
+const AUTHORIZATION_HEADER: &str = "authorization";
+
+/// How long before it expires a credential is replaced.
+const REFRESH_BEFORE_EXPIRY: Duration = Duration::from_secs(60);
+
+/// Least time between scheduled refreshes, so a provider issuing
+/// credentials shorter lived than [`REFRESH_BEFORE_EXPIRY`] is not called
+/// in a loop. Calls still refresh them as needed.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Pause before a failed scheduled refresh is tried again.
+const REFRESH_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// A bearer token, sent as `authorization: Bearer <token>`.
+#[derive(Debug, Clone)]
+pub struct Credential {
+    pub token: SecretString,
+    /// When auraed stops accepting the token. `None` for tokens that do not
+    /// expire, which are fetched once.
+    pub expires_at: Option<SystemTime>,
+}
+
+/// A source of bearer tokens, such as an OAuth or STS token endpoint.
+#[tonic::async_trait]
+pub trait CredentialProvider: Send + Sync {
+    /// Obtain a fresh credential. Only called again once the last one is
+    /// about to expire, never concurrently for the same client.
+    async fn fetch(&self) -> Result<Credential, ClientError>;
+}
+
+/// The latest credential of a provider, shared by a client and its clones.
+pub(crate) struct Credentials {
+    provider: Arc<dyn CredentialProvider>,
+    current: RwLock<Option<Current>>,
+    /// Held while fetching, so concurrent calls refresh once.
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+struct Current {
+    header: MetadataValue<Ascii>,
+    expires_at: Option<SystemTime>,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let expires_at = self.read().as_ref().map(|current| current.expires_at);
+        f.debug_struct("Credentials")
+            .field("expires_at", &expires_at)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Credentials {
+    fn new(provider: Arc<dyn CredentialProvider>) -> Self {
+        Self {
+            provider,
+            current: RwLock::new(None),
+            refreshing: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Option<Current>> {
+        self.current.read().expect("credentials lock poisoned")
+    }
+
+    /// Whether there is no credential yet, or it is about to expire.
+    fn needs_refresh(&self) -> bool {
+        match &*self.read() {
+            None => true,
+            Some(current) => current.expires_at.is_some_and(|at| {
+                SystemTime::now() + REFRESH_BEFORE_EXPIRY >= at
+            }),
+        }
+    }
+
+    /// Fetch a new credential when [`Credentials::needs_refresh`].
+    async fn refresh(&self) -> Result<(), ClientError> {
+        if !self.needs_refresh() {
+            return Ok(());
+        }
+        let _refreshing = self.refreshing.lock().await;
+        // another call may have refreshed while this one waited
+        if !self.needs_refresh() {
+            return Ok(());
+        }
+
+        let credential = self.provider.fetch().await?;
+        let mut header: MetadataValue<Ascii> =
+            format!("Bearer {}", credential.token.expose_secret())
+                .parse()
+                .map_err(|_| {
+                    anyhow!("credential token is not a valid header value")
+                })?;
+        header.set_sensitive(true);
+        *self.current.write().expect("credentials lock poisoned") =
+            Some(Current { header, expires_at: credential.expires_at });
+        debug!(expires_at = ?credential.expires_at, "refreshed credential");
+        Ok(())
+    }
+
+    /// Send the latest credential with `req`.
+    pub(crate) fn apply<T>(&self, req: &mut Request<T>) {
+        if let Some(current) = &*self.read() {
+            let _ = req
+                .metadata_mut()
+                .insert(AUTHORIZATION_HEADER, current.header.clone());
+        }
+    }
+
+    /// How long until the scheduled refresh, `None` for a credential that
+    /// does not expire.
+    fn next_refresh(&self) -> Option<Duration> {
+        let expires_at = self.read().as_ref()?.expires_at?;
+        let due = expires_at
+            .checked_sub(REFRESH_BEFORE_EXPIRY)
+            .and_then(|due| due.duration_since(SystemTime::now()).ok())
+            .unwrap_or_default();
+        Some(due.max(MIN_REFRESH_INTERVAL))
+    }
+}
+
+/// Refresh `credentials` ahead of expiry until the last client using them
+/// is dropped, so calls rarely have to wait for the provider.
+async fn refresh_on_schedule(credentials: Weak<Credentials>) {
+    loop {
+        let Some(due) = credentials.upgrade().and_then(|c| c.next_refresh())
+        else {
+            return;
+        };
+        tokio::time::sleep(due).await;
+
+        let Some(credentials) = credentials.upgrade() else {
+            return;
+        };
+        if let Err(e) = credentials.refresh().await {
+            warn!("failed to refresh credential, retrying: {e}");
+            tokio::time::sleep(REFRESH_RETRY_DELAY).await;
+        }
+    }
+}
+
+impl Client {
+    /// A handle to the same connection whose RPCs carry a bearer token from
+    /// `provider`, on top of the mTLS identity. The first credential is
+    /// fetched before this returns, and a failure to fetch it is returned.
+    /// Later ones are fetched in the background shortly before the current
+    /// one expires, and by any RPC that finds it about to expire, which
+    /// fails with `UNAUTHENTICATED` if the provider does.
+    ///
+    /// Clones of the handle share the credential. Interceptors run after
+    /// the `authorization` header is set, and can replace it.
+    pub async fn with_credential_provider(
+        &self,
+        provider: Arc<dyn CredentialProvider>,
+    ) -> crate::client::Result<Self> {
+        let credentials = Arc::new(Credentials::new(provider));
+        credentials.refresh().await?;
+        let _ = tokio::spawn(refresh_on_schedule(Arc::downgrade(&credentials)));
+        Ok(self.with_credentials(credentials))
+    }
+
+    /// Make sure the credential sent with the next RPC is not about to
+    /// expire. Used by the generated service clients.
+    pub(crate) async fn refresh_credentials(&self) -> Result<(), Status> {
+        let Some(credentials) = self.credentials() else {
+            return Ok(());
+        };
+        credentials.refresh().await.map_err(|e| {
+            Status::unauthenticated(format!(
+                "failed to refresh credential: {e}"
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{serve_in_memory, Discovery};
+    use proto::discovery::discovery_service_server::DiscoveryServiceServer;
+    use proto::discovery::{DiscoverRequest, DiscoverResponse};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tonic::transport::Server;
+
+    /// Issues `token-1`, `token-2`, ..., each valid for `lifetime`.
+    struct Rotating {
+        issued: AtomicUsize,
+        lifetime: Duration,
+    }
+
+    #[tonic::async_trait]
+    impl CredentialProvider for Rotating {
+        async fn fetch(&self) -> Result<Credential, ClientError> {
+            let n = self.issued.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(Credential {
+                token: SecretString::new(format!("token-{n}")),
+                expires_at: Some(SystemTime::now() + self.lifetime),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn expiring_tokens_are_rotated() {
+        use crate::discovery::discovery_service::DiscoveryServiceClient;
+
+        let recorder = Discovery::answering(DiscoverResponse::default());
+        let client = serve_in_memory(
+            Server::builder()
+                .add_service(DiscoveryServiceServer::new(recorder.clone())),
+        );
+        // always within REFRESH_BEFORE_EXPIRY, so every call refreshes
+        let provider = Arc::new(Rotating {
+            issued: AtomicUsize::new(0),
+            lifetime: Duration::from_secs(30),
+        });
+        let client = client.with_credential_provider(provider).await.unwrap();
+
+        let _ = client.discover(DiscoverRequest {}).await.unwrap();
+        let _ = client.discover(DiscoverRequest {}).await.unwrap();
+
+        assert_eq!(
+            recorder.sent(AUTHORIZATION_HEADER),
+            [Some("Bearer token-2".into()), Some("Bearer token-3".into())]
+        );
+    }
+
+    #[tokio::test]
+    async fn long_lived_tokens_are_reused() {
+        let provider = Arc::new(Rotating {
+            issued: AtomicUsize::new(0),
+            lifetime: Duration::from_secs(3600),
+        });
+        let credentials = Credentials::new(provider.clone());
+
+        credentials.refresh().await.unwrap();
+        credentials.refresh().await.unwrap();
+
+        assert_eq!(provider.issued.load(Ordering::SeqCst), 1);
+        let mut req = Request::new(());
+        credentials.apply(&mut req);
+        assert_eq!(
+            req.metadata().get(AUTHORIZATION_HEADER).unwrap(),
+            "Bearer token-1"
+        );
+        assert!(
+            credentials.next_refresh().unwrap() > Duration::from_secs(3000)
+        );
+    }
+}
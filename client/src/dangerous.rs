@@ -0,0 +1,144 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Connecting without verifying the server certificate, to tell PKI
+//! problems apart from transport ones while debugging.
+//!
+//! Only compiled with the `dangerous-no-verify` feature, and only active
+//! with both `connect.dangerous_no_verify` and
+//! `AURAE_I_KNOW_THIS_IS_INSECURE=1` set. Release builds also need
+//! `AURAE_ALLOW_INSECURE_RELEASE=1`, so a binary shipped with the feature
+//! left on still verifies servers.
+
+use crate::config::ConnectOptions;
+use anyhow::Result;
+use tokio_rustls::rustls::ClientConfig;
+
+/// Environment variable that must be `1` for verification to be skipped.
+#[cfg(feature = "dangerous-no-verify")]
+const CONFIRM_ENV: &str = "AURAE_I_KNOW_THIS_IS_INSECURE";
+
+/// Environment variable that must also be `1` in release builds.
+#[cfg(feature = "dangerous-no-verify")]
+const RELEASE_ENV: &str = "AURAE_ALLOW_INSECURE_RELEASE";
+
+/// Whether server certificate verification is to be skipped. Fails when it
+/// is requested without the confirming environment variables.
+#[cfg(feature = "dangerous-no-verify")]
+pub(crate) fn no_verify_requested(options: &ConnectOptions) -> Result<bool> {
+    if !options.dangerous_no_verify {
+        return Ok(false);
+    }
+    confirmed(cfg!(debug_assertions), |name| std::env::var(name).ok())?;
+    Ok(true)
+}
+
+#[cfg(feature = "dangerous-no-verify")]
+fn confirmed(
+    debug_build: bool,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<()> {
+    let set = |name| env(name).is_some_and(|value| value == "1");
+    if !set(CONFIRM_ENV) {
+        return Err(anyhow::anyhow!(
+            "connect.dangerous_no_verify is set, but {CONFIRM_ENV}=1 is not, refusing to connect without verifying the server"
+        ));
+    }
+    if !debug_build && !set(RELEASE_ENV) {
+        return Err(anyhow::anyhow!(
+            "connect.dangerous_no_verify is refused in release builds unless {RELEASE_ENV}=1 is set as well"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "dangerous-no-verify"))]
+pub(crate) fn no_verify_requested(_options: &ConnectOptions) -> Result<bool> {
+    Ok(false)
+}
+
+/// Accept any server certificate on `config`.
+#[cfg(feature = "dangerous-no-verify")]
+pub(crate) fn skip_server_verification(
+    config: &mut ClientConfig,
+) -> Result<()> {
+    config
+        .dangerous()
+        .set_certificate_verifier(std::sync::Arc::new(verifier::AcceptAny));
+    Ok(())
+}
+
+#[cfg(not(feature = "dangerous-no-verify"))]
+pub(crate) fn skip_server_verification(
+    _config: &mut ClientConfig,
+) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "built without the dangerous-no-verify feature, server certificates are always verified"
+    ))
+}
+
+#[cfg(feature = "dangerous-no-verify")]
+mod verifier {
+    use std::time::SystemTime;
+    use tokio_rustls::rustls::client::{
+        ServerCertVerified, ServerCertVerifier,
+    };
+    use tokio_rustls::rustls::{Certificate, Error, ServerName};
+    use tracing::warn;
+
+    pub(super) struct AcceptAny;
+
+    impl ServerCertVerifier for AcceptAny {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &Certificate,
+            _intermediates: &[Certificate],
+            server_name: &ServerName,
+            _scts: &mut dyn Iterator<Item = &[u8]>,
+            _ocsp_response: &[u8],
+            _now: SystemTime,
+        ) -> Result<ServerCertVerified, Error> {
+            warn!(
+                ?server_name,
+                "INSECURE: accepting the server certificate without verification (dangerous_no_verify)"
+            );
+            Ok(ServerCertVerified::assertion())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "dangerous-no-verify"))]
+mod tests {
+    use super::*;
+
+    fn env<'a>(set: &'a [&str]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| set.iter().any(|set| *set == name).then(|| "1".into())
+    }
+
+    #[test]
+    fn no_verify_needs_confirmation() {
+        assert!(confirmed(true, env(&[])).is_err());
+        assert!(confirmed(true, env(&[CONFIRM_ENV])).is_ok());
+        assert!(!no_verify_requested(&ConnectOptions::default()).unwrap());
+    }
+
+    #[test]
+    fn release_builds_need_the_release_override() {
+        let err = confirmed(false, env(&[CONFIRM_ENV])).unwrap_err();
+        assert!(err.to_string().contains(RELEASE_ENV), "{err}");
+        assert!(confirmed(false, env(&[CONFIRM_ENV, RELEASE_ENV])).is_ok());
+        assert!(confirmed(false, env(&[RELEASE_ENV])).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Deadlines carried over from the caller's own request, see
+//! [`Client::with_deadline_instant`].
+
+use crate::client::ClientError;
+use crate::Client;
+use std::future::Future;
+use std::time::Instant;
+use tonic::Status;
+
+impl Client {
+    /// Fail with [`ClientError::DeadlineExceeded`] when the deadline of this
+    /// handle has passed.
+    ///
+    /// The generated service clients call this before every RPC and fail
+    /// with `DEADLINE_EXCEEDED` instead of sending it. Callers issuing
+    /// their own requests can use it to get the same guarantee.
+    pub fn check_deadline(&self) -> Result<(), ClientError> {
+        match self.deadline() {
+            Some(deadline) if deadline <= Instant::now() => {
+                Err(ClientError::DeadlineExceeded)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Used by the generated service clients.
+    pub(crate) fn deadline_guard(&self) -> Result<(), Status> {
+        self.check_deadline()
+            .map_err(|e| Status::deadline_exceeded(e.to_string()))
+    }
+
+    /// Run `call`, failing it with `DEADLINE_EXCEEDED` if it is still
+    /// running at the deadline. Used by the generated service clients.
+    pub(crate) async fn until_deadline<T>(
+        &self,
+        call: impl Future<Output = Result<T, Status>>,
+    ) -> Result<T, Status> {
+        let Some(deadline) = self.deadline() else {
+            return call.await;
+        };
+
+        tokio::time::timeout_at(deadline.into(), call).await.unwrap_or_else(
+            |_| {
+                Err(Status::deadline_exceeded(
+                    "the call deadline passed while waiting for auraed",
+                ))
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tonic::transport::Endpoint;
+    use tonic::Code;
+
+    fn client() -> Client {
+        let channel = Endpoint::from_static("http://[::1]:8080").connect_lazy();
+        Client::from_channel(channel, None)
+    }
+
+    #[tokio::test]
+    async fn passed_deadline_fails_without_a_call() {
+        let client = client()
+            .with_deadline_instant(Instant::now() - Duration::from_secs(1));
+
+        assert!(matches!(
+            client.check_deadline(),
+            Err(ClientError::DeadlineExceeded)
+        ));
+        assert_eq!(
+            client.deadline_guard().unwrap_err().code(),
+            Code::DeadlineExceeded
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_carry_the_time_left() {
+        let client = client()
+            .with_deadline_instant(Instant::now() + Duration::from_secs(30));
+        assert!(client.check_deadline().is_ok());
+
+        let req = client.request(()).unwrap();
+        let timeout = req.metadata().get("grpc-timeout").unwrap();
+        let micros: u64 =
+            timeout.to_str().unwrap().trim_end_matches('u').parse().unwrap();
+        assert!(micros > 29_000_000 && micros <= 30_000_000, "{timeout:?}");
+    }
+
+    #[tokio::test]
+    async fn slow_calls_are_cut_off() {
+        let client = client()
+            .with_deadline_instant(Instant::now() + Duration::from_millis(20));
+
+        let err = client
+            .until_deadline(std::future::pending::<Result<(), Status>>())
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.code(), Code::DeadlineExceeded);
+    }
+}
@@ -0,0 +1,184 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Deployment artifacts rendered from an [`AuraeConfig`], so they stay in
+//! sync with the config the client runs with.
+
+use crate::config::{ca_fetch, IN_CLUSTER_SOCKET_ENV};
+use crate::{AuraeConfig, AuraeSocket};
+use std::path::Path;
+
+/// The `[Service]` settings a systemd service using `config` needs:
+///
+/// - `AURAE_SOCKET` set to the configured socket,
+/// - an `ExecStartPre` check per cert file, so a missing or unreadable one
+///   fails the start instead of the first connect,
+/// - a cache directory, when the CA is fetched from a URL,
+/// - restarting on failure, `connect.retry.backoff` apart.
+///
+/// `ExecStart` is left to the service. Install the result as a drop-in
+/// (`/etc/systemd/system/<service>.service.d/aurae.conf`), see
+/// [`install_unit`], or append it to a unit file.
+pub fn systemd_unit(config: &AuraeConfig) -> String {
+    let mut unit = String::from(
+        "# Rendered from the aurae client config, render it again after changing the config.\n[Service]\n",
+    );
+    let mut line = |line: String| {
+        unit.push_str(&line);
+        unit.push('\n');
+    };
+
+    let socket = match &config.system.socket {
+        AuraeSocket::Path(path) => Some(path.display().to_string()),
+        AuraeSocket::Addr(addr) => Some(addr.to_string()),
+        AuraeSocket::Uri(uri) => Some(uri.to_string()),
+        // handed over by the parent process, nothing to point at
+        AuraeSocket::Inherited(_) => None,
+    };
+    if let Some(socket) = socket {
+        line(format!(
+            "Environment={}",
+            quote(&format!("{IN_CLUSTER_SOCKET_ENV}={socket}"))
+        ));
+    }
+
+    let auth = &config.auth;
+    for path in [&auth.ca_crt, &auth.client_crt, &auth.client_key] {
+        if path.is_empty() || ca_fetch::is_url(path) {
+            continue;
+        }
+        line(format!("ExecStartPre=/usr/bin/test -r {}", quote(path)));
+    }
+
+    if ca_fetch::is_url(&auth.ca_crt) && auth.ca_fetch.cache_dir.is_none() {
+        // the fetched CA is cached under $XDG_CACHE_HOME/aurae
+        line("CacheDirectory=aurae".into());
+        line("Environment=XDG_CACHE_HOME=%C".into());
+    }
+
+    line("Restart=on-failure".into());
+    line(format!(
+        "RestartSec={}ms",
+        config.connect.retry.backoff.as_millis().max(1)
+    ));
+    unit
+}
+
+/// Write [`systemd_unit`] for `config` to `path`, creating its directory.
+/// Run `systemctl daemon-reload` afterwards.
+pub fn install_unit(
+    config: &AuraeConfig,
+    path: impl AsRef<Path>,
+) -> std::io::Result<()> {
+    let path = path.as_ref();
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, systemd_unit(config))
+}
+
+/// `value` as one double quoted word of a unit file, with `%` escaped so
+/// systemd does not expand it as a specifier.
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '%' => quoted.push_str("%%"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(ca_crt: &str) -> AuraeConfig {
+        AuraeConfig::parse_from_toml(&format!(
+            r#"
+[auth]
+ca_crt = "{ca_crt}"
+client_crt = "/etc/aurae/pki/client 1.crt"
+client_key = "/etc/aurae/pki/client.key"
+
+[system]
+socket = "/var/run/aurae/aurae.sock"
+
+[connect.retry]
+backoff = "2s"
+"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn unit_references_the_config() {
+        let unit = systemd_unit(&config("/etc/aurae/pki/ca.crt"));
+
+        assert!(unit.contains("\n[Service]\n"), "{unit}");
+        assert!(
+            unit.contains(
+                r#"Environment="AURAE_SOCKET=/var/run/aurae/aurae.sock""#
+            ),
+            "{unit}"
+        );
+        for path in [
+            r#""/etc/aurae/pki/ca.crt""#,
+            r#""/etc/aurae/pki/client 1.crt""#,
+            r#""/etc/aurae/pki/client.key""#,
+        ] {
+            assert!(
+                unit.contains(&format!("ExecStartPre=/usr/bin/test -r {path}")),
+                "{unit}"
+            );
+        }
+        assert!(unit.contains("RestartSec=2000ms"), "{unit}");
+        assert!(!unit.contains("CacheDirectory"), "{unit}");
+    }
+
+    #[test]
+    fn fetched_ca_gets_a_cache_directory() {
+        let unit = systemd_unit(&config("https://pki.example.com/ca.crt"));
+
+        assert!(unit.contains("CacheDirectory=aurae"), "{unit}");
+        assert!(!unit.contains("pki.example.com"), "{unit}");
+    }
+
+    #[test]
+    fn specifiers_and_quotes_are_escaped() {
+        assert_eq!(quote(r#"50%"off""#), r#""50%%\"off\"""#);
+    }
+
+    #[test]
+    fn unit_is_installed_with_its_directory() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("demo.service.d/aurae.conf");
+        let config = config("/etc/aurae/pki/ca.crt");
+
+        install_unit(&config, &path).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            systemd_unit(&config)
+        );
+    }
+}
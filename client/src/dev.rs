@@ -0,0 +1,117 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Development helpers, enabled with the `dev-certs` feature.
+//!
+//! Nothing in this module is suitable for production use.
+
+use crate::AuraeConfig;
+use anyhow::Result;
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DnType,
+    ExtendedKeyUsagePurpose, IsCa,
+};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// The domain name the client verifies the server certificate against.
+const SERVER_NAME: &str = "server.unsafe.aurae.io";
+const DEV_SOCKET: &str = "/var/run/aurae/aurae.sock";
+
+/// Mint a throwaway CA, a server cert for `server.unsafe.aurae.io`, and a
+/// client cert signed by that CA, writing them to `out_dir`.
+///
+/// Writes `ca.crt`, `server.crt`, `server.key`, `client.crt` and
+/// `client.key`, then returns a config pointing at the client material and
+/// the default auraed socket. Keys are written with mode 0600.
+pub fn generate_cert_set<P: AsRef<Path>>(out_dir: P) -> Result<AuraeConfig> {
+    let out_dir = out_dir.as_ref();
+    std::fs::create_dir_all(out_dir)?;
+
+    let mut ca_params = CertificateParams::new(vec![]);
+    ca_params.distinguished_name.push(DnType::CommonName, "unsafe.aurae.io");
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = Certificate::from_params(ca_params)?;
+
+    let mut server_params = CertificateParams::new(vec![SERVER_NAME.into()]);
+    server_params.distinguished_name.push(DnType::CommonName, SERVER_NAME);
+    server_params.extended_key_usages =
+        vec![ExtendedKeyUsagePurpose::ServerAuth];
+    let server = Certificate::from_params(server_params)?;
+
+    let mut client_params = CertificateParams::new(vec![]);
+    client_params.distinguished_name.push(DnType::CommonName, "aurae-dev");
+    client_params.extended_key_usages =
+        vec![ExtendedKeyUsagePurpose::ClientAuth];
+    let client = Certificate::from_params(client_params)?;
+
+    let ca_crt = out_dir.join("ca.crt");
+    let client_crt = out_dir.join("client.crt");
+    let client_key = out_dir.join("client.key");
+
+    write(&ca_crt, ca.serialize_pem()?, 0o644)?;
+    write(
+        &out_dir.join("server.crt"),
+        server.serialize_pem_with_signer(&ca)?,
+        0o644,
+    )?;
+    write(
+        &out_dir.join("server.key"),
+        server.serialize_private_key_pem(),
+        0o600,
+    )?;
+    write(&client_crt, client.serialize_pem_with_signer(&ca)?, 0o644)?;
+    write(&client_key, client.serialize_private_key_pem(), 0o600)?;
+
+    Ok(AuraeConfig::from_options(
+        ca_crt.to_string_lossy(),
+        client_crt.to_string_lossy(),
+        client_key.to_string_lossy(),
+        DEV_SOCKET,
+    ))
+}
+
+fn write(path: &Path, contents: String, mode: u32) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn generated_client_material_is_loadable() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("certs");
+
+        let config = generate_cert_set(&dir).unwrap();
+        let material = config.auth.to_cert_material().await.unwrap();
+        let details = material.get_client_cert_details().unwrap();
+
+        assert_eq!(details.subject_common_name, "aurae-dev");
+        assert_eq!(details.issuer_common_name, "unsafe.aurae.io");
+        assert!(!details.self_signed);
+        assert!(material.get_server_ca_details().unwrap().self_signed);
+    }
+}
@@ -0,0 +1,447 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! A one-shot dump of everything known about a client's connection, for
+//! attaching to a bug report.
+
+use crate::config::{SocketKind, X509Details};
+use crate::connector::{ConnectInfo, ConnectState};
+use crate::cooldown::EndpointCooldown;
+use crate::{AuraeSocket, Client};
+use serde::{Serialize, Serializer};
+use std::fmt::Display;
+use std::time::{Duration, SystemTime};
+use tokio_rustls::rustls::ClientConnection;
+use x509_parser::extensions::GeneralName;
+
+/// See [`Client::diagnostics`].
+///
+/// Only public certificate details are included, never key material.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostics {
+    /// How the configured socket is reached, `None` for clients from
+    /// [`Client::from_channel`].
+    pub transport: Option<SocketKind>,
+    /// The configured socket, `None` for clients from
+    /// [`Client::from_channel`].
+    pub endpoint: Option<String>,
+    pub state: ConnectionState,
+    pub security_level: SecurityLevel,
+    pub last_error: Option<LastError>,
+    /// Where the most recent connect ended up.
+    pub connected_to: Option<ConnectInfo>,
+    pub timings: Option<ConnectTimings>,
+    /// How long ago an RPC last succeeded, see [`Client::last_success`].
+    #[serde(serialize_with = "millis_option")]
+    pub since_last_success: Option<Duration>,
+    pub tls: Option<TlsParams>,
+    /// The certificate this client authenticates with.
+    pub client_identity: Option<X509Details>,
+    /// The certificate the server presented on the most recent connect.
+    pub server_identity: Option<ServerIdentity>,
+    /// The root CA the server certificate is verified against.
+    pub server_ca: Option<X509Details>,
+    /// TCP addresses skipped when connecting after failing to, see
+    /// [`crate::ConnectOptions::endpoint_cooldown`].
+    pub cooldowns: Vec<EndpointCooldown>,
+}
+
+impl Diagnostics {
+    /// Pretty printed JSON, ready to paste into an issue.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self)
+            .expect("diagnostics always serialize to JSON")
+    }
+}
+
+/// Whether the client currently holds an open connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    /// The client never connected itself, as for [`Client::from_channel`].
+    #[default]
+    Unknown,
+    Connected,
+    /// The last connection closed, it is re-established on the next RPC.
+    Disconnected,
+    /// The connection is being re-established, `attempt` counts from 1.
+    Reconnecting {
+        attempt: u32,
+    },
+    /// Connecting failed and is not retried, see
+    /// [`ClientState::last_error`]. [`Client::ensure_connected`] tries
+    /// again.
+    Failed,
+}
+
+/// How a connection is secured, strongest first, see
+/// [`Client::security_level`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityLevel {
+    /// TLS with a verified server and a client certificate.
+    Mtls,
+    /// TLS with a verified server, authenticating with a bearer token from
+    /// [`Client::with_credential_provider`] instead of a client certificate.
+    TokenOverTls,
+    /// TLS with a verified server, presenting no client identity.
+    ServerTlsOnly,
+    /// No TLS, or TLS without verifying the server under
+    /// `connect.dangerous_no_verify`, which anyone on the path can
+    /// intercept.
+    Insecure,
+    /// A channel built by the caller, see [`Client::from_channel`], whose
+    /// security the client cannot see.
+    Unknown,
+}
+
+/// See [`Client::state`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientState {
+    pub connection: ConnectionState,
+    /// The most recent failed connect, reconnect or health check. Kept
+    /// after the connection recovers, compare `at` to tell how long ago.
+    pub last_error: Option<LastError>,
+}
+
+/// A failure recorded in [`ClientState::last_error`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LastError {
+    pub at: SystemTime,
+    pub message: String,
+}
+
+impl LastError {
+    pub(crate) fn now(error: &dyn Display) -> Self {
+        Self { at: SystemTime::now(), message: error.to_string() }
+    }
+}
+
+/// How long the phases of the most recent connect took.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ConnectTimings {
+    #[serde(serialize_with = "millis")]
+    pub transport: Duration,
+    /// `None` without TLS, or when the handshake did not complete.
+    #[serde(serialize_with = "millis_option")]
+    pub tls_handshake: Option<Duration>,
+    /// `None` unless `connect.warm_up` is set.
+    #[serde(serialize_with = "millis_option")]
+    pub warm_up: Option<Duration>,
+}
+
+impl ConnectTimings {
+    pub(crate) fn new(
+        transport: Duration,
+        tls_handshake: Option<Duration>,
+    ) -> Self {
+        Self { transport, tls_handshake, warm_up: None }
+    }
+
+    pub(crate) fn with_tls_handshake(self, tls_handshake: Duration) -> Self {
+        Self { tls_handshake: Some(tls_handshake), ..self }
+    }
+
+    pub(crate) fn with_warm_up(self, warm_up: Duration) -> Self {
+        Self { warm_up: Some(warm_up), ..self }
+    }
+}
+
+/// What the TLS handshake negotiated.
+#[derive(Debug, Clone, Serialize)]
+pub struct TlsParams {
+    pub protocol_version: Option<String>,
+    pub cipher_suite: Option<String>,
+    pub alpn_protocol: Option<String>,
+}
+
+impl From<&ClientConnection> for TlsParams {
+    fn from(session: &ClientConnection) -> Self {
+        Self {
+            protocol_version: session
+                .protocol_version()
+                .map(|version| format!("{version:?}")),
+            cipher_suite: session
+                .negotiated_cipher_suite()
+                .map(|suite| format!("{:?}", suite.suite())),
+            alpn_protocol: session
+                .alpn_protocol()
+                .map(|alpn| String::from_utf8_lossy(alpn).into_owned()),
+        }
+    }
+}
+
+/// The end entity certificate the server presented.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerIdentity {
+    pub subject_common_name: Option<String>,
+    pub issuer_common_name: Option<String>,
+    /// DNS names from the subject alternative names.
+    pub dns_names: Vec<String>,
+    /// URIs, such as a SPIFFE ID, from the subject alternative names.
+    pub uris: Vec<String>,
+    pub not_after: Option<String>,
+    pub sha256_fingerprint: Option<String>,
+}
+
+impl ServerIdentity {
+    pub(crate) fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+
+        let mut dns_names = vec![];
+        let mut uris = vec![];
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(dns) => {
+                        dns_names.push(dns.to_string())
+                    }
+                    GeneralName::URI(uri) => uris.push(uri.to_string()),
+                    _ => {}
+                }
+            }
+        }
+
+        let common_name = |name: &x509_parser::x509::X509Name<'_>| {
+            name.iter_common_name()
+                .next()
+                .and_then(|cn| cn.as_str().ok())
+                .map(String::from)
+        };
+
+        Some(Self {
+            subject_common_name: common_name(cert.subject()),
+            issuer_common_name: common_name(cert.issuer()),
+            dns_names,
+            uris,
+            not_after: cert.validity().not_after.to_rfc2822().ok(),
+            sha256_fingerprint: x509_certificate::X509Certificate::from_der(
+                der,
+            )
+            .ok()
+            .and_then(|cert| cert.sha256_fingerprint().ok())
+            .map(|fingerprint| format!("{fingerprint:?}")),
+        })
+    }
+}
+
+impl Client {
+    /// Everything a maintainer would ask for about this client's
+    /// connection: the transport and endpoint, both identities, what TLS
+    /// negotiated, how long connecting took and whether it is connected.
+    ///
+    /// Serializes to JSON with [`Diagnostics::to_json`]. Never includes
+    /// private key material.
+    pub fn diagnostics(&self) -> Diagnostics {
+        collect(
+            self.socket(),
+            self.connect_state(),
+            self.security_level(),
+            self.client_cert_details(),
+            self.server_ca_details(),
+        )
+    }
+
+    /// The connection state of this client and its clones, and the last
+    /// error seen while connecting, for status displays that poll. See
+    /// [`Client::events`] to be told about changes instead.
+    pub fn state(&self) -> ClientState {
+        let (connection, last_error) = self.connect_state().health();
+        ClientState { connection, last_error }
+    }
+
+    /// How the connections of this client are secured, for showing a
+    /// security badge. Follows the TLS config connections are made with
+    /// and whether a credential provider is set, so it is known before
+    /// the first connect.
+    pub fn security_level(&self) -> SecurityLevel {
+        let Some(certs) = self.cert_store() else {
+            return match self.socket() {
+                Some(_) => SecurityLevel::Insecure,
+                None => SecurityLevel::Unknown,
+            };
+        };
+        let tls = certs.tls();
+        if !tls.debug.verify_server {
+            SecurityLevel::Insecure
+        } else if !tls.debug.client_chain.is_empty() {
+            SecurityLevel::Mtls
+        } else if self.credentials().is_some() {
+            SecurityLevel::TokenOverTls
+        } else {
+            SecurityLevel::ServerTlsOnly
+        }
+    }
+}
+
+fn collect(
+    socket: Option<&AuraeSocket>,
+    connect_state: &ConnectState,
+    security_level: SecurityLevel,
+    client_identity: Option<X509Details>,
+    server_ca: Option<X509Details>,
+) -> Diagnostics {
+    let last = connect_state.last();
+    let (state, last_error) = connect_state.health();
+
+    Diagnostics {
+        transport: socket.map(AuraeSocket::kind),
+        endpoint: socket.map(AuraeSocket::endpoint),
+        state,
+        security_level,
+        last_error,
+        timings: last.as_ref().map(|last| last.timings),
+        since_last_success: connect_state.last_success().map(|at| at.elapsed()),
+        tls: last.as_ref().and_then(|last| last.tls.clone()),
+        server_identity: last.as_ref().and_then(|last| last.server.clone()),
+        connected_to: last.map(|last| last.info),
+        client_identity,
+        server_ca,
+        cooldowns: connect_state.cooldowns.cooling(),
+    }
+}
+
+pub(crate) fn millis<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+pub(crate) fn millis_option<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => millis(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connector::{self, PhaseTimeouts, TcpOptions};
+    use crate::events::ConnectionEvent;
+    use crate::testing::{
+        client_material, serve_health_at, serve_in_memory, test_ca, Serving,
+    };
+    use crate::AuraeConfig;
+    use proto::grpc::health::health_server::HealthServer;
+
+    #[tokio::test]
+    async fn diagnostics_follow_the_connection() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("aurae.sock");
+        let _listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let socket = AuraeSocket::Path(path.clone());
+        let state = ConnectState::default();
+
+        let before =
+            collect(Some(&socket), &state, SecurityLevel::Insecure, None, None);
+        assert_eq!(before.state, ConnectionState::Unknown);
+
+        let stream = connector::connect(
+            socket.clone(),
+            None,
+            PhaseTimeouts::default(),
+            TcpOptions::default(),
+            state.clone(),
+        )
+        .await
+        .unwrap();
+        let connected =
+            collect(Some(&socket), &state, SecurityLevel::Insecure, None, None);
+        assert_eq!(connected.state, ConnectionState::Connected);
+        assert!(connected.timings.unwrap().tls_handshake.is_none());
+
+        let json = connected.to_json();
+        assert!(json.contains(r#""transport": "unix""#));
+        assert!(json.contains(&path.display().to_string()));
+        assert!(!json.contains("PRIVATE KEY"));
+
+        drop(stream);
+        let closed =
+            collect(Some(&socket), &state, SecurityLevel::Insecure, None, None);
+        assert_eq!(closed.state, ConnectionState::Disconnected);
+    }
+
+    #[test]
+    fn failed_reconnects_keep_the_attempt_and_error() {
+        let state = ConnectState::default();
+        state.publish(ConnectionEvent::Connected);
+        state.publish(ConnectionEvent::Disconnected);
+        state.publish(ConnectionEvent::Reconnecting { attempt: 3 });
+        state.record_error(&"connection refused");
+
+        let (connection, last_error) = state.health();
+        assert_eq!(connection, ConnectionState::Reconnecting { attempt: 3 });
+        assert_eq!(last_error.unwrap().message, "connection refused");
+
+        state.publish(ConnectionEvent::Connected);
+        let (connection, last_error) = state.health();
+        assert_eq!(connection, ConnectionState::Connected);
+        assert!(last_error.is_some());
+
+        state.failed(&"certificate expired");
+        assert_eq!(state.health().0, ConnectionState::Failed);
+    }
+
+    #[tokio::test]
+    async fn security_levels_follow_the_auth_mode() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let ca = test_ca("security ca");
+        let material = client_material(&ca, "dashboard");
+        let file = |name: &str, contents: &[u8]| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        let socket = dir.join("aurae.sock");
+        serve_health_at(&ca, &socket);
+        let config = AuraeConfig::from_options(
+            file("ca.crt", &material.server_root_ca_cert),
+            file("client.crt", &material.client_cert),
+            file("client.key", &material.client_key),
+            socket.to_string_lossy(),
+        );
+
+        let mtls = Client::new(config).await.unwrap();
+        assert_eq!(mtls.security_level(), SecurityLevel::Mtls);
+        let json = mtls.diagnostics().to_json();
+        assert!(json.contains(r#""security_level": "mtls""#), "{json}");
+
+        let caller_built = serve_in_memory(
+            tonic::transport::Server::builder()
+                .add_service(HealthServer::new(Serving)),
+        );
+        assert_eq!(caller_built.security_level(), SecurityLevel::Unknown);
+    }
+
+    #[test]
+    fn successes_show_up_in_the_dump() {
+        let state = ConnectState::default();
+        assert!(collect(None, &state, SecurityLevel::Unknown, None, None)
+            .since_last_success
+            .is_none());
+
+        state.record_success();
+        let json =
+            collect(None, &state, SecurityLevel::Unknown, None, None).to_json();
+        assert!(json.contains(r#""since_last_success": "#), "{json}");
+        assert!(!json.contains(r#""since_last_success": null"#), "{json}");
+    }
+}
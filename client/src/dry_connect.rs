@@ -0,0 +1,421 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Every local step of connecting, run against a config to find what would
+//! keep it from connecting, without opening a socket.
+
+use crate::cert_bundle::check_key_matches;
+use crate::cert_store::{check_ca, check_client_auth};
+use crate::config::{ca_fetch, cert_material, CertMaterial};
+use crate::namespace::Namespace;
+use crate::tls::{self, TlsConnect, TlsOptions};
+use crate::{AuraeConfig, ClientError};
+use anyhow::anyhow;
+use serde::Serialize;
+use std::fmt;
+use std::time::SystemTime;
+
+/// See [`AuraeConfig::dry_connect`].
+#[derive(Debug, Clone, Serialize)]
+pub struct DryConnectReport {
+    /// Every step, in the order connecting runs them.
+    pub steps: Vec<DryConnectStep>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DryConnectStep {
+    pub name: &'static str,
+    pub outcome: DryConnectOutcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DryConnectOutcome {
+    Passed,
+    Failed {
+        message: String,
+    },
+    /// The step could not run, because a step it builds on failed or it
+    /// would need the network.
+    Skipped {
+        reason: String,
+    },
+}
+
+impl DryConnectReport {
+    /// Whether no step failed. Skipped steps are not failures.
+    pub fn is_ok(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &DryConnectStep> {
+        self.steps.iter().filter(|step| {
+            matches!(step.outcome, DryConnectOutcome::Failed { .. })
+        })
+    }
+
+    /// The report when no step failed, otherwise a [`ClientError::Other`]
+    /// listing every failed step, for `?` in a CI check.
+    pub fn into_result(self) -> Result<Self, ClientError> {
+        if self.is_ok() {
+            return Ok(self);
+        }
+        let failures = self
+            .failures()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("; ");
+        Err(ClientError::Other(anyhow!("dry connect failed: {failures}")))
+    }
+
+    /// Pretty printed JSON, for CI artifacts.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self)
+            .expect("dry connect reports always serialize to JSON")
+    }
+}
+
+/// One line per step.
+impl fmt::Display for DryConnectReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "{step}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DryConnectStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.outcome {
+            DryConnectOutcome::Passed => write!(f, "{}: ok", self.name),
+            DryConnectOutcome::Failed { message } => {
+                write!(f, "{}: failed: {message}", self.name)
+            }
+            DryConnectOutcome::Skipped { reason } => {
+                write!(f, "{}: skipped: {reason}", self.name)
+            }
+        }
+    }
+}
+
+impl AuraeConfig {
+    /// Run every step of connecting that needs no network: parse the
+    /// endpoint and connect options, read the cert files, check the key,
+    /// chain, validity period and key usage of the client certificate, and
+    /// build the TLS config. Nothing is connected to, and a CA fetched from
+    /// a URL is only taken from the cache of an earlier fetch.
+    ///
+    /// Every step runs, rather than stopping at the first failure; a step
+    /// that builds on a failed one is skipped. Problems with the config are
+    /// failed steps in the report, see [`DryConnectReport::into_result`] to
+    /// turn them into an error.
+    pub async fn dry_connect(&self) -> Result<DryConnectReport, ClientError> {
+        let AuraeConfig { auth, system, connect } = self;
+        let mut steps = Steps::default();
+
+        let _ = steps.run(
+            "endpoint",
+            system.socket.clone().normalized().map_err(anyhow::Error::from),
+        );
+        let options = steps.run(
+            "connect options",
+            TlsOptions::new(connect).and_then(|options| {
+                let _ = connect
+                    .default_namespace
+                    .as_deref()
+                    .map(Namespace::new)
+                    .transpose()?;
+                Ok(options)
+            }),
+        );
+
+        let ca = match ca_fetch::is_url(&auth.ca_crt) {
+            true => match ca_fetch::cached(&auth.ca_crt, &auth.ca_fetch).await {
+                Some(pem) => steps.run("read server root CA", Ok(pem)),
+                None => steps.skip(
+                    "read server root CA",
+                    format!(
+                        "'{}' was never fetched, and a dry connect does not fetch it",
+                        auth.ca_crt
+                    ),
+                ),
+            },
+            false => steps.run(
+                "read server root CA",
+                cert_material::read_ca_file(auth).await,
+            ),
+        };
+        const SERVER_TLS_ONLY: &str =
+            "no client identity is set, server TLS only";
+        let identity = auth.has_client_identity();
+        let (client_cert, client_key) = match identity {
+            true => (
+                steps.run(
+                    "read client certificate",
+                    cert_material::read_client_cert(auth).await,
+                ),
+                steps.run(
+                    "read client key",
+                    cert_material::read_client_key(auth).await,
+                ),
+            ),
+            false => {
+                let _: Option<()> = steps
+                    .skip("read client certificate", SERVER_TLS_ONLY.into());
+                let _: Option<()> =
+                    steps.skip("read client key", SERVER_TLS_ONLY.into());
+                (Some(Vec::new()), Some(Vec::new()))
+            }
+        };
+
+        let material = match (ca, client_cert, client_key) {
+            (
+                Some(server_root_ca_cert),
+                Some(client_cert),
+                Some(client_key),
+            ) => Some(CertMaterial {
+                server_root_ca_cert,
+                client_cert,
+                client_key,
+            }),
+            _ => None,
+        };
+        let material = material.as_ref();
+        const NO_MATERIAL: &str = "needs all three cert files";
+        // the checks of the client identity
+        let (client, no_client) = match identity {
+            true => (material, NO_MATERIAL),
+            false => (None, SERVER_TLS_ONLY),
+        };
+
+        let _ = steps.after("client key matches", client, no_client, |m| {
+            check_key_matches(m)
+        });
+        let _ = steps.after(
+            "certificate chain and validity",
+            client,
+            no_client,
+            |m| {
+                tls::verify_client_chain(
+                    m,
+                    SystemTime::now(),
+                    connect.clock_skew_tolerance,
+                )
+            },
+        );
+        let _ = steps.after("server root CA", material, NO_MATERIAL, |m| {
+            check_ca(auth, &m.get_server_ca_bundle_details()?)
+        });
+        let details =
+            steps.after("client certificate usage", client, no_client, |m| {
+                let details = m.get_client_cert_details()?;
+                check_client_auth(auth, &details)?;
+                Ok(details)
+            });
+        let _ = steps.after(
+            "client key algorithm",
+            options.as_ref().zip(details.as_ref()),
+            "needs valid connect options and client certificate",
+            |(options, details)| options.check_key_algorithm(details),
+        );
+        let _ = steps.after(
+            "TLS config",
+            material.zip(options.as_ref()),
+            "needs all three cert files and valid connect options",
+            |(m, options)| TlsConnect::new(m, auth, options),
+        );
+
+        Ok(DryConnectReport { steps: steps.0 })
+    }
+}
+
+#[derive(Default)]
+struct Steps(Vec<DryConnectStep>);
+
+impl Steps {
+    fn run<T>(
+        &mut self,
+        name: &'static str,
+        result: anyhow::Result<T>,
+    ) -> Option<T> {
+        let (outcome, value) = match result {
+            Ok(value) => (DryConnectOutcome::Passed, Some(value)),
+            Err(e) => {
+                (DryConnectOutcome::Failed { message: format!("{e:#}") }, None)
+            }
+        };
+        self.0.push(DryConnectStep { name, outcome });
+        value
+    }
+
+    fn skip<T>(&mut self, name: &'static str, reason: String) -> Option<T> {
+        self.0.push(DryConnectStep {
+            name,
+            outcome: DryConnectOutcome::Skipped { reason },
+        });
+        None
+    }
+
+    /// Run `step` on the result of earlier steps, or skip it with `reason`
+    /// when one of them failed.
+    fn after<I, T>(
+        &mut self,
+        name: &'static str,
+        input: Option<I>,
+        reason: &str,
+        step: impl FnOnce(I) -> anyhow::Result<T>,
+    ) -> Option<T> {
+        match input {
+            Some(input) => self.run(name, step(input)),
+            None => self.skip(name, reason.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AuraeSocket;
+    use rcgen::{
+        BasicConstraints, Certificate, CertificateParams, DnType,
+        ExtendedKeyUsagePurpose, IsCa,
+    };
+    use std::path::{Path, PathBuf};
+
+    fn temp_file(dir: &Path, name: &str, contents: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// A config with a CA and a client certificate it signed, and the key
+    /// of that certificate unless `foreign_key` is set, in `dir`.
+    fn config(dir: &Path, foreign_key: bool) -> AuraeConfig {
+        let mut params = CertificateParams::default();
+        params.distinguished_name.push(DnType::CommonName, "test ca");
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = Certificate::from_params(params).unwrap();
+
+        let mut params = CertificateParams::default();
+        params.distinguished_name.push(DnType::CommonName, "test client");
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let client = Certificate::from_params(params).unwrap();
+        let other = Certificate::from_params(Default::default()).unwrap();
+        let key = match foreign_key {
+            true => &other,
+            false => &client,
+        };
+
+        let ca_crt =
+            temp_file(dir, "ca.crt", ca.serialize_pem().unwrap().as_bytes());
+        let client_crt = temp_file(
+            dir,
+            "client.crt",
+            client.serialize_pem_with_signer(&ca).unwrap().as_bytes(),
+        );
+        let client_key = temp_file(
+            dir,
+            "client.key",
+            key.serialize_private_key_pem().as_bytes(),
+        );
+
+        AuraeConfig::parse_from_toml(&format!(
+            r#"
+[auth]
+ca_crt = "{}"
+client_crt = "{}"
+client_key = "{}"
+
+[system]
+socket = "auraed.example.com:8080"
+"#,
+            ca_crt.display(),
+            client_crt.display(),
+            client_key.display(),
+        ))
+        .unwrap()
+    }
+
+    fn outcome<'a>(
+        report: &'a DryConnectReport,
+        name: &str,
+    ) -> &'a DryConnectOutcome {
+        &report.steps.iter().find(|step| step.name == name).unwrap().outcome
+    }
+
+    #[tokio::test]
+    async fn a_consistent_config_passes_every_step() {
+        let tmp = tempfile::tempdir().unwrap();
+        let report = config(tmp.path(), false).dry_connect().await.unwrap();
+
+        assert!(report.is_ok(), "{report}");
+        assert!(report
+            .steps
+            .iter()
+            .all(|step| step.outcome == DryConnectOutcome::Passed));
+        assert!(report.into_result().is_ok());
+    }
+
+    #[tokio::test]
+    async fn every_problem_is_reported() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut config = config(tmp.path(), true);
+        config.system.socket =
+            AuraeSocket::Uri("https://auraed.example.com/v1".parse().unwrap());
+        config.connect.pinned_server_sha256 = vec!["not a pin".into()];
+
+        let report = config.dry_connect().await.unwrap();
+
+        let failed: Vec<_> = report.failures().map(|step| step.name).collect();
+        assert_eq!(
+            failed,
+            ["endpoint", "connect options", "client key matches"]
+        );
+        assert!(matches!(
+            outcome(&report, "TLS config"),
+            DryConnectOutcome::Skipped { .. }
+        ));
+        assert_eq!(
+            outcome(&report, "certificate chain and validity"),
+            &DryConnectOutcome::Passed
+        );
+
+        let e = report.into_result().unwrap_err().to_string();
+        assert!(e.contains("not a pin"), "{e}");
+        assert!(e.contains("client key matches: failed"), "{e}");
+    }
+
+    #[tokio::test]
+    async fn an_unfetched_ca_url_is_skipped() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut config = config(tmp.path(), false);
+        config.auth.ca_crt = "https://pki.example.com/ca.crt".into();
+        config.auth.ca_fetch.cache_dir =
+            Some(tmp.path().join("cache").display().to_string());
+
+        let report = config.dry_connect().await.unwrap();
+
+        assert!(report.is_ok(), "{report}");
+        assert!(matches!(
+            outcome(&report, "read server root CA"),
+            DryConnectOutcome::Skipped { .. }
+        ));
+        assert!(matches!(
+            outcome(&report, "certificate chain and validity"),
+            DryConnectOutcome::Skipped { .. }
+        ));
+    }
+}
@@ -0,0 +1,240 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Checking every endpoint a config reaches auraed at, see
+//! [`Client::check_all_endpoints`].
+
+use crate::cert_store::CertStore;
+use crate::connector::{self, ConnectState, TcpOptions};
+use crate::diagnostics::{millis_option, ServerIdentity};
+use crate::grpc_web::Transport;
+use crate::namespace::Namespace;
+use crate::ssh_tunnel::SshTunnel;
+use crate::tls::TlsOptions;
+use crate::{AuraeConfig, AuraeSocket, Client, ConnectOptions};
+use futures_util::future::join_all;
+use proto::grpc::health::health_client::HealthClient;
+use proto::grpc::health::HealthCheckRequest;
+use serde::Serialize;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tonic::Code;
+
+/// Limit on connecting to and health checking a single endpoint, unless
+/// `connect.overall_timeout` is set.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How one endpoint fared in [`Client::check_all_endpoints`].
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointStatus {
+    /// The configured socket, with any password masked.
+    pub endpoint: String,
+    /// The replica checked, for a URI socket resolving to several.
+    pub addr: Option<SocketAddr>,
+    /// Why the endpoint is unusable, `None` when it answered the health
+    /// check.
+    pub error: Option<String>,
+    /// How long connecting took, including the TLS handshake.
+    #[serde(serialize_with = "millis_option")]
+    pub connect_time: Option<Duration>,
+    /// How long the health check took once connected.
+    #[serde(serialize_with = "millis_option")]
+    pub health_check_time: Option<Duration>,
+    /// The certificate the server presented.
+    pub server_identity: Option<ServerIdentity>,
+}
+
+impl EndpointStatus {
+    fn new(endpoint: &str, addr: Option<SocketAddr>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            addr,
+            error: None,
+            connect_time: None,
+            health_check_time: None,
+            server_identity: None,
+        }
+    }
+
+    fn failed(
+        endpoint: &str,
+        addr: Option<SocketAddr>,
+        error: &dyn Display,
+    ) -> Self {
+        Self { error: Some(error.to_string()), ..Self::new(endpoint, addr) }
+    }
+
+    /// Whether the endpoint answered the health check.
+    pub fn is_reachable(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl Client {
+    /// Connect to and health check every endpoint of `config` at once, for
+    /// verifying a failover setup before relying on it: each address a URI
+    /// socket resolves to, or the one socket otherwise (through the SSH
+    /// jump host, if one is set). Every endpoint gets a status, whether or
+    /// not the others could be reached, and each check is limited to
+    /// `connect.overall_timeout`, 10 seconds if unset.
+    ///
+    /// When nothing can be checked at all, such as when the certs cannot be
+    /// read or the host does not resolve, a single failed status for the
+    /// configured socket is returned.
+    pub async fn check_all_endpoints(
+        config: &AuraeConfig,
+    ) -> Vec<EndpointStatus> {
+        let AuraeConfig { auth, system, connect } = config.clone();
+        let endpoint = system.socket.endpoint();
+        let failed = |error: &dyn Display| {
+            vec![EndpointStatus::failed(&endpoint, None, error)]
+        };
+
+        let tls = match TlsOptions::new(&connect) {
+            Ok(tls) => tls,
+            Err(e) => return failed(&e),
+        };
+        if let Some(Err(e)) =
+            connect.default_namespace.as_deref().map(Namespace::new)
+        {
+            return failed(&e);
+        }
+        let certs = match CertStore::load(auth, tls).await {
+            Ok(certs) => Arc::new(certs),
+            Err(e) => return failed(&e),
+        };
+
+        let tunnel = match &system.ssh_jump {
+            Some(jump) => match SshTunnel::open(jump).await {
+                Ok(tunnel) => Some(tunnel),
+                Err(e) => return failed(&e),
+            },
+            None => None,
+        };
+        let socket = match &tunnel {
+            Some(tunnel) => AuraeSocket::Path(tunnel.local_socket().into()),
+            None => match system.socket.normalized() {
+                Ok(socket) => socket,
+                Err(e) => return failed(&e),
+            },
+        };
+
+        let targets = match socket {
+            AuraeSocket::Uri(uri) => {
+                let resolved = async {
+                    let (host, port) = connector::host_port(&uri)?;
+                    connector::resolve(&host, port, TcpOptions::from(&connect))
+                        .await
+                };
+                match resolved.await {
+                    Ok(addrs) => addrs
+                        .into_iter()
+                        .map(|addr| (Some(addr), AuraeSocket::Addr(addr)))
+                        .collect(),
+                    Err(e) => return failed(&e),
+                }
+            }
+            socket => vec![(None, socket)],
+        };
+
+        join_all(targets.into_iter().map(|(addr, socket)| {
+            check(&endpoint, addr, socket, certs.clone(), &connect)
+        }))
+        .await
+    }
+}
+
+/// Connect to `socket` and health check it, within the check timeout.
+async fn check(
+    endpoint: &str,
+    addr: Option<SocketAddr>,
+    socket: AuraeSocket,
+    certs: Arc<CertStore>,
+    connect: &ConnectOptions,
+) -> EndpointStatus {
+    let timeout = connect.overall_timeout.unwrap_or(CHECK_TIMEOUT);
+    let state = ConnectState::default();
+    let mut status = EndpointStatus::new(endpoint, addr);
+
+    let checked = tokio::time::timeout(timeout, async {
+        let started = Instant::now();
+        let channel =
+            Client::connect_chan(socket, Some(certs), connect, state.clone())
+                .await
+                .map_err(|e| e.to_string())?;
+        status.connect_time = Some(started.elapsed());
+
+        let started = Instant::now();
+        let mut health =
+            HealthClient::new(Transport::new(channel, connect.transport_mode));
+        match health.check(HealthCheckRequest { service: String::new() }).await
+        {
+            Ok(_) => {}
+            // served without the health service, but answering
+            Err(rpc) if rpc.code() == Code::Unimplemented => {}
+            Err(rpc) => {
+                return Err(format!("health check failed: {}", rpc.message()))
+            }
+        }
+        status.health_check_time = Some(started.elapsed());
+        Ok(())
+    })
+    .await;
+
+    status.error = match checked {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e),
+        Err(_) => Some(format!(
+            "connecting and health checking timed out after {timeout:?}"
+        )),
+    };
+    status.server_identity = state.last().and_then(|last| last.server);
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn unreadable_certs_fail_the_one_socket() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let config = AuraeConfig::parse_from_toml(&format!(
+            r#"
+[auth]
+ca_crt = "{0}/ca.crt"
+client_crt = "{0}/client.crt"
+client_key = "{0}/client.key"
+
+[system]
+socket = "{0}/aurae.sock"
+"#,
+            dir.display()
+        ))
+        .unwrap();
+
+        let statuses = Client::check_all_endpoints(&config).await;
+
+        assert_eq!(statuses.len(), 1);
+        assert!(!statuses[0].is_reachable());
+        assert_eq!(
+            statuses[0].endpoint,
+            format!("{}/aurae.sock", dir.display())
+        );
+    }
+}
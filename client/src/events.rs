@@ -0,0 +1,101 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Connection lifecycle events, see [`crate::Client::events`].
+
+use std::net::SocketAddr;
+use tokio::sync::broadcast;
+
+/// Events kept for subscribers that fall behind. Older events are dropped
+/// for them, and they see a `Lagged` error instead.
+const EVENT_BUFFER: usize = 32;
+
+/// A change in the state of a client's connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// A connection was established, including the TLS handshake.
+    Connected,
+    /// An established connection closed.
+    Disconnected,
+    /// The connection is being re-established, `attempt` counts from 1.
+    Reconnecting { attempt: u32 },
+    /// The certs were reloaded, new connections use the client certificate
+    /// with this fingerprint.
+    CertReloaded { fingerprint: String },
+    /// The connections reached `max_connection_age` and were replaced.
+    /// RPCs still running on the old ones finish there.
+    Rotated,
+    /// No RPC was made for `idle_timeout`, so the connections were closed.
+    /// The next RPC connects again, publishing [`ConnectionEvent::Connected`].
+    ClosedIdle,
+    /// The addresses behind a URI socket changed on re-resolving it, and
+    /// new RPCs now go to connections to `addrs`. RPCs still running on the
+    /// old connections finish there.
+    Migrated { addrs: Vec<SocketAddr> },
+    /// The server sent GOAWAY on the connection to `peer` (`None` over a
+    /// unix socket), e.g. on the way to a restart. RPCs running on it
+    /// finish there, new RPCs go to a replacement connection.
+    Draining { peer: Option<SocketAddr> },
+    /// The client moved to a new config, see
+    /// [`crate::Client::switch_config`].
+    ConfigSwitched,
+    /// The connections were replaced on request, see
+    /// [`crate::Client::reconnect_now`].
+    Reconnected,
+    /// Reconnect attempts kept failing for `connect.reconnect_deadline`, so
+    /// the client stopped trying until [`crate::Client::reconnect_now`].
+    GaveUp,
+}
+
+/// Publisher of [`ConnectionEvent`]s. Publishing never waits on subscribers.
+#[derive(Debug, Clone)]
+pub(crate) struct Events(broadcast::Sender<ConnectionEvent>);
+
+impl Default for Events {
+    fn default() -> Self {
+        Self(broadcast::channel(EVENT_BUFFER).0)
+    }
+}
+
+impl Events {
+    pub(crate) fn publish(&self, event: ConnectionEvent) {
+        // an error only means there is nobody subscribed
+        let _ = self.0.send(event);
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.0.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lagging_subscriber_does_not_block_publishing() {
+        let events = Events::default();
+        let mut rx = events.subscribe();
+
+        for attempt in 0..(EVENT_BUFFER as u32 * 2) {
+            events.publish(ConnectionEvent::Reconnecting { attempt });
+        }
+
+        assert!(matches!(
+            rx.try_recv(),
+            Err(broadcast::error::TryRecvError::Lagged(_))
+        ));
+    }
+}
@@ -0,0 +1,111 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Spotting the HTTP/2 GOAWAY frame auraed sends when it starts draining a
+//! connection, e.g. ahead of a restart.
+//!
+//! hyper keeps the GOAWAY to itself: the connection stays up for the
+//! streams already running on it, and only closes once they are done. The
+//! bytes the server sends are watched instead, so the balancer can move new
+//! RPCs to a fresh connection right away.
+
+/// The frame type of GOAWAY, see RFC 9113 section 6.8.
+const GOAWAY: u8 = 0x7;
+const FRAME_HEADER_LEN: usize = 9;
+
+/// Follows the frames of the server side of an HTTP/2 connection, as read
+/// after TLS.
+#[derive(Debug, Default)]
+pub(crate) struct GoAwaySniffer {
+    header: [u8; FRAME_HEADER_LEN],
+    filled: usize,
+    /// Payload bytes of the current frame still to skip.
+    skip: usize,
+    seen: bool,
+}
+
+impl GoAwaySniffer {
+    /// Follow the frames through the next bytes read. True only the first
+    /// time a GOAWAY frame header comes by.
+    pub(crate) fn feed(&mut self, mut bytes: &[u8]) -> bool {
+        let mut goaway = false;
+
+        while !bytes.is_empty() && !self.seen {
+            if self.skip > 0 {
+                let skipped = self.skip.min(bytes.len());
+                self.skip -= skipped;
+                bytes = &bytes[skipped..];
+                continue;
+            }
+
+            let take = (FRAME_HEADER_LEN - self.filled).min(bytes.len());
+            self.header[self.filled..self.filled + take]
+                .copy_from_slice(&bytes[..take]);
+            self.filled += take;
+            bytes = &bytes[take..];
+            if self.filled < FRAME_HEADER_LEN {
+                break;
+            }
+
+            self.filled = 0;
+            let [a, b, c, kind, ..] = self.header;
+            self.skip = u32::from_be_bytes([0, a, b, c]) as usize;
+            if kind == GOAWAY {
+                self.seen = true;
+                goaway = true;
+            }
+        }
+
+        goaway
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(kind: u8, payload: &[u8]) -> Vec<u8> {
+        let len = (payload.len() as u32).to_be_bytes();
+        let mut frame = vec![len[1], len[2], len[3], kind, 0, 0, 0, 0, 0];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn finds_goaway_after_other_frames() {
+        let settings = frame(0x4, &[0, 3, 0, 0, 0, 100]);
+        // a DATA frame whose payload happens to look like a GOAWAY header
+        let data = frame(0x0, &frame(GOAWAY, &[]));
+        let goaway = frame(GOAWAY, &[0; 8]);
+
+        let mut sniffer = GoAwaySniffer::default();
+        assert!(!sniffer.feed(&settings));
+        assert!(!sniffer.feed(&data));
+        assert!(sniffer.feed(&goaway));
+        assert!(!sniffer.feed(&goaway));
+    }
+
+    #[test]
+    fn follows_frames_split_across_reads() {
+        let mut bytes = frame(0x6, &[0; 8]);
+        bytes.extend(frame(GOAWAY, &[0; 8]));
+
+        let mut sniffer = GoAwaySniffer::default();
+        let found: Vec<_> =
+            bytes.chunks(4).map(|chunk| sniffer.feed(chunk)).collect();
+        assert_eq!(found.iter().filter(|found| **found).count(), 1);
+        assert!(found[..4].iter().all(|found| !found));
+    }
+}
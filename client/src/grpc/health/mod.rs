@@ -13,4 +13,5 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 #[allow(clippy::module_inception)]
-pub mod health;
\ No newline at end of file
+pub mod health;
+mod serving;
\ No newline at end of file
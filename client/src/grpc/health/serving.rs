@@ -0,0 +1,136 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use super::health::HealthClient;
+use crate::client::Client;
+use proto::grpc::health::{
+    health_check_response::ServingStatus, HealthCheckRequest,
+};
+use std::time::Duration;
+
+/// Pause before re-establishing a watch stream that ended or failed.
+const WATCH_RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+impl Client {
+    /// Block until `service` (or the server as a whole, for `None`) reports
+    /// `SERVING` on the gRPC health `Watch` stream, or until `timeout` elapses.
+    ///
+    /// Returns the last status observed, so anything other than
+    /// [`ServingStatus::Serving`] means the timeout was hit. If the stream
+    /// ends or errors, it is re-established for as long as the timeout allows.
+    pub async fn await_serving(
+        &self,
+        service: Option<&str>,
+        timeout: Duration,
+    ) -> ServingStatus {
+        let req = HealthCheckRequest {
+            service: service.unwrap_or_default().to_string(),
+        };
+        let mut last = ServingStatus::Unknown;
+
+        let _ = tokio::time::timeout(timeout, async {
+            loop {
+                if let Ok(res) = self.watch(req.clone()).await {
+                    let mut stream = res.into_inner();
+                    while let Ok(Some(res)) = stream.message().await {
+                        last = res.status();
+                        if last == ServingStatus::Serving {
+                            return;
+                        }
+                    }
+                }
+
+                tokio::time::sleep(WATCH_RETRY_INTERVAL).await;
+            }
+        })
+        .await;
+
+        last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::serve_in_memory;
+    use futures_util::stream::BoxStream;
+    use futures_util::StreamExt;
+    use proto::grpc::health::health_server::{Health, HealthServer};
+    use proto::grpc::health::HealthCheckResponse;
+    use std::sync::Arc;
+    use tokio::sync::watch;
+    use tonic::transport::Server;
+    use tonic::{Request, Response, Status};
+
+    /// Streams the status `status` holds to every watcher, then each change
+    /// to it.
+    struct Flipping {
+        status: Arc<watch::Sender<ServingStatus>>,
+    }
+
+    #[tonic::async_trait]
+    impl Health for Flipping {
+        async fn check(
+            &self,
+            _request: Request<HealthCheckRequest>,
+        ) -> Result<Response<HealthCheckResponse>, Status> {
+            Err(Status::unimplemented("check"))
+        }
+
+        type WatchStream =
+            BoxStream<'static, Result<HealthCheckResponse, Status>>;
+
+        async fn watch(
+            &self,
+            _request: Request<HealthCheckRequest>,
+        ) -> Result<Response<Self::WatchStream>, Status> {
+            let rx = self.status.subscribe();
+            let stream = futures_util::stream::unfold(
+                (rx, true),
+                |(mut rx, first)| async move {
+                    if !first && rx.changed().await.is_err() {
+                        return None;
+                    }
+                    let status = *rx.borrow_and_update();
+                    let res = HealthCheckResponse { status: status.into() };
+                    Some((Ok(res), (rx, false)))
+                },
+            );
+            Ok(Response::new(stream.boxed()))
+        }
+    }
+
+    #[tokio::test]
+    async fn returns_once_the_service_is_serving() {
+        let (status, _) = watch::channel(ServingStatus::NotServing);
+        let status = Arc::new(status);
+        let client = serve_in_memory(Server::builder().add_service(
+            HealthServer::new(Flipping { status: status.clone() }),
+        ));
+
+        let waiting = tokio::spawn(async move {
+            client.await_serving(None, Duration::from_secs(10)).await
+        });
+        while status.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished(), "returned while NOT_SERVING");
+
+        status.send_replace(ServingStatus::Serving);
+
+        assert_eq!(waiting.await.unwrap(), ServingStatus::Serving);
+    }
+}
@@ -0,0 +1,74 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Framing RPCs as gRPC-Web, for auraed behind a gRPC-Web proxy.
+
+use crate::config::TransportMode;
+use tonic::body::{boxed, BoxBody};
+use tonic::codegen::{http, BoxFuture, StdError};
+use tonic::transport::Channel;
+use tonic_web::{GrpcWebCall, GrpcWebClientLayer};
+use tower::{Layer, Service, ServiceExt};
+
+/// A channel that sends RPCs in the client's [`TransportMode`].
+#[derive(Debug, Clone)]
+pub(crate) struct Transport {
+    channel: Channel,
+    mode: TransportMode,
+}
+
+impl Transport {
+    pub(crate) fn new(channel: Channel, mode: TransportMode) -> Self {
+        Self { channel, mode }
+    }
+}
+
+impl Service<http::Request<BoxBody>> for Transport {
+    type Response = http::Response<BoxBody>;
+    type Error = StdError;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        // readiness of the channel is awaited in `call`
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<BoxBody>) -> Self::Future {
+        let channel = self.channel.clone();
+        match self.mode {
+            TransportMode::Grpc => Box::pin(async move {
+                let response = channel.oneshot(req).await?;
+                Ok(response.map(boxed))
+            }),
+            TransportMode::GrpcWeb => Box::pin(async move {
+                // the layer re-frames the request body, the channel only
+                // takes boxed ones
+                let channel = tower::service_fn(
+                    move |req: http::Request<GrpcWebCall<BoxBody>>| {
+                        channel.clone().oneshot(req.map(boxed))
+                    },
+                );
+                let response = GrpcWebClientLayer::new()
+                    .layer(channel)
+                    .oneshot(req)
+                    .await?;
+                Ok(response.map(boxed))
+            }),
+        }
+    }
+}
@@ -0,0 +1,211 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Hedging read RPCs across replicas, see
+//! [`ConnectOptions::hedge_delay`].
+
+use crate::concurrency::RpcChannel;
+use crate::config::ConnectOptions;
+use crate::retry::RetryBudget;
+use crate::Client;
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tonic::Status;
+use tracing::debug;
+
+/// The unary RPCs of the services shipped with this client that only read,
+/// and so may run on several replicas at once.
+pub(crate) const HEDGED_METHODS: &[&str] = &[
+    "/aurae.cells.v0.CellService/List",
+    "/aurae.discovery.v0.DiscoveryService/Discover",
+    "/aurae.vms.v0.VmService/List",
+    "/grpc.health.v1.Health/Check",
+    "/runtime.v1.RuntimeService/Version",
+    "/runtime.v1.RuntimeService/PodSandboxStatus",
+    "/runtime.v1.RuntimeService/ListPodSandbox",
+    "/runtime.v1.RuntimeService/ListContainers",
+    "/runtime.v1.RuntimeService/ContainerStatus",
+    "/runtime.v1.RuntimeService/ContainerStats",
+    "/runtime.v1.RuntimeService/ListContainerStats",
+    "/runtime.v1.RuntimeService/PodSandboxStats",
+    "/runtime.v1.RuntimeService/ListPodSandboxStats",
+    "/runtime.v1.RuntimeService/Status",
+    "/runtime.v1.RuntimeService/ListMetricDescriptors",
+    "/runtime.v1.RuntimeService/ListPodSandboxMetrics",
+    "/runtime.v1.ImageService/ListImages",
+    "/runtime.v1.ImageService/ImageStatus",
+    "/runtime.v1.ImageService/ImageFsInfo",
+];
+
+/// `connect.hedge_delay`, `connect.max_hedges` and
+/// `connect.hedged_methods`.
+#[derive(Debug, Clone)]
+pub(crate) struct HedgePolicy {
+    delay: Duration,
+    max_hedges: usize,
+    methods: Arc<Vec<String>>,
+}
+
+impl HedgePolicy {
+    /// `None` when `options` do not hedge.
+    pub(crate) fn new(options: &ConnectOptions) -> Option<Self> {
+        let delay = options.hedge_delay?;
+        let max_hedges = usize::try_from(options.max_hedges).ok()?;
+        (max_hedges > 0).then(|| Self {
+            delay,
+            max_hedges,
+            methods: Arc::new(options.hedged_methods.clone()),
+        })
+    }
+
+    /// Whether calls of the full method path `method` are hedged. Only the
+    /// methods listed are, so one missing from the list runs once.
+    fn hedges(&self, method: &str) -> bool {
+        self.methods.iter().any(|listed| listed == method)
+    }
+}
+
+impl Client {
+    /// Run the unary `call` of `method` on a channel from the balancer,
+    /// hedging it on other replicas under `connect.hedge_delay` when it is
+    /// one of `connect.hedged_methods` and not a mutating method. Used by
+    /// the generated service clients, inside their retries.
+    pub(crate) async fn hedged<Req, Res, F, Fut>(
+        &self,
+        method: &str,
+        req: Req,
+        call: F,
+    ) -> Result<Res, Status>
+    where
+        Req: Clone,
+        F: Fn(Req, RpcChannel) -> Fut,
+        Fut: Future<Output = Result<Res, Status>>,
+    {
+        let balancer = self.balancer();
+        let policy = balancer.hedge_policy().filter(|policy| {
+            policy.hedges(method) && !self.read_only().is_mutating(method)
+        });
+        let Some(policy) = policy else {
+            return call(req, self.channel()).await;
+        };
+
+        let channels = balancer
+            .pick_with_spares(policy.max_hedges)
+            .into_iter()
+            .map(|channel| self.rpc_channel(channel))
+            .collect();
+        race(policy.delay, self.retry_budget(), channels, req, call).await
+    }
+}
+
+/// Run `call` on the first of `channels`, and on the next one each `delay`
+/// while none has answered and `budget` allows, returning the first
+/// success. The calls still running are dropped with it, which cancels
+/// them. Fails with the last failure once every call sent has failed.
+async fn race<C, Req, Res, F, Fut>(
+    delay: Duration,
+    budget: &RetryBudget,
+    channels: Vec<C>,
+    req: Req,
+    call: F,
+) -> Result<Res, Status>
+where
+    Req: Clone,
+    F: Fn(Req, C) -> Fut,
+    Fut: Future<Output = Result<Res, Status>>,
+{
+    let mut spares = channels.into_iter();
+    let first = spares.next().expect("the balancer has a channel");
+    let mut in_flight = FuturesUnordered::new();
+    in_flight.push(call(req.clone(), first));
+
+    let hedge_at = tokio::time::sleep(delay);
+    tokio::pin!(hedge_at);
+    let mut hedging = !spares.as_slice().is_empty();
+    loop {
+        tokio::select! {
+            Some(res) = in_flight.next() => match res {
+                Ok(res) => return Ok(res),
+                Err(status) if in_flight.is_empty() => return Err(status),
+                Err(status) => debug!("hedged call failed: {status}"),
+            },
+            () = &mut hedge_at, if hedging => {
+                match spares.next() {
+                    Some(channel) if budget.try_withdraw() => {
+                        in_flight.push(call(req.clone(), channel));
+                        hedge_at.as_mut().reset(Instant::now() + delay);
+                        hedging = !spares.as_slice().is_empty();
+                    }
+                    _ => hedging = false,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RetryOptions;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn the_faster_replica_wins() {
+        let budget = RetryBudget::new(RetryOptions::default());
+        let started = Mutex::new(vec![]);
+        let replicas = vec![
+            ("slow", Duration::from_secs(5)),
+            ("fast", Duration::from_millis(10)),
+        ];
+
+        let begun = Instant::now();
+        let winner = race(
+            Duration::from_millis(50),
+            &budget,
+            replicas,
+            (),
+            |(), (name, latency)| {
+                started.lock().unwrap().push(name);
+                async move {
+                    tokio::time::sleep(latency).await;
+                    Ok::<_, Status>(name)
+                }
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(winner, "fast");
+        assert_eq!(*started.lock().unwrap(), ["slow", "fast"]);
+        assert!(begun.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn only_listed_methods_are_hedged() {
+        let options = ConnectOptions {
+            hedge_delay: Some(Duration::from_millis(10)),
+            ..ConnectOptions::default()
+        };
+        let policy = HedgePolicy::new(&options).unwrap();
+
+        assert!(policy.hedges("/aurae.cells.v0.CellService/List"));
+        assert!(!policy.hedges("/aurae.cells.v0.CellService/Allocate"));
+        // neither listed nor known to mutate
+        assert!(!policy.hedges("/aurae.example.v0.Service/Do"));
+    }
+}
@@ -0,0 +1,221 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! The hello a client can send right after connecting, trading what it
+//! supports for what the daemon supports, see [`ConnectOptions::hello`].
+
+use crate::config::{CompressionMode, ConnectOptions};
+use crate::discovery::discovery_service::DiscoveryServiceClient;
+use crate::Client;
+use proto::discovery::{self, HelloRequest, HelloResponse};
+use serde::Serialize;
+use tonic::Code;
+use tracing::{debug, warn};
+
+/// Protocol features this client understands: rich error details, retry
+/// pushback headers and draining on GOAWAY.
+const FEATURES: &[&str] = &["status-details", "retry-pushback", "goaway-drain"];
+
+/// The largest message this client decodes, tonic's default.
+const MAX_MESSAGE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// What a client tells auraed about itself in its hello.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientInfo {
+    /// The version of this crate.
+    pub version: String,
+    /// Protocol features the client understands, e.g. `status-details`.
+    pub features: Vec<String>,
+    /// Message encodings the client accepts, e.g. `gzip`.
+    pub compression: Vec<String>,
+    /// The largest message the client decodes, in bytes.
+    pub max_message_size: u64,
+}
+
+impl ClientInfo {
+    /// This client, accepting what `connect.compression` turns on.
+    pub(crate) fn new(options: &ConnectOptions) -> Self {
+        let compression =
+            options.compression.name().into_iter().map(Into::into).collect();
+        Self {
+            version: env!("CARGO_PKG_VERSION").into(),
+            features: FEATURES
+                .iter()
+                .map(|feature| feature.to_string())
+                .collect(),
+            compression,
+            max_message_size: MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+impl From<ClientInfo> for discovery::ClientInfo {
+    fn from(info: ClientInfo) -> Self {
+        Self {
+            version: info.version,
+            features: info.features,
+            compression: info.compression,
+            max_message_size: info.max_message_size,
+        }
+    }
+}
+
+/// What auraed answered the hello with, see
+/// [`Client::server_capabilities`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServerCapabilities {
+    /// The auraed version, e.g. `0.1.0`.
+    pub version: String,
+    /// Protocol features the daemon supports.
+    pub features: Vec<String>,
+    /// Message encodings the daemon accepts. Empty when it only accepts
+    /// uncompressed messages.
+    pub compression: Vec<String>,
+    /// The largest message the daemon decodes, in bytes.
+    pub max_message_size: u64,
+}
+
+impl ServerCapabilities {
+    /// Whether the daemon supports the protocol feature `feature`.
+    pub fn supports_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|supported| supported == feature)
+    }
+
+    /// Whether the daemon accepts messages encoded with `encoding`, such as
+    /// `gzip`.
+    pub fn accepts_compression(&self, encoding: &str) -> bool {
+        self.compression.iter().any(|accepted| accepted == encoding)
+    }
+}
+
+impl From<HelloResponse> for ServerCapabilities {
+    fn from(res: HelloResponse) -> Self {
+        Self {
+            version: res.version,
+            features: res.features,
+            compression: res.compression,
+            max_message_size: res.max_message_size,
+        }
+    }
+}
+
+impl Client {
+    /// What auraed said it supports in answer to the hello sent right after
+    /// connecting. Shared by this client and its clones, for gating
+    /// features without a round trip. `None` unless `connect.hello` is set,
+    /// and when the daemon does not implement hello or the hello failed.
+    pub fn server_capabilities(&self) -> Option<&ServerCapabilities> {
+        self.session().get()
+    }
+
+    /// Send `info` in a hello and keep the answer. Never fails the connect:
+    /// daemons without hello are left without capabilities.
+    pub(crate) async fn send_hello(&self, info: ClientInfo) {
+        // uncompressed, as the daemon has yet to say it accepts compression
+        let uncompressed = self.with_compression(CompressionMode::None);
+        let wanted = info.compression.clone();
+        let req = HelloRequest { client: Some(info.into()) };
+
+        let capabilities = match uncompressed.hello(req).await {
+            Ok(res) => ServerCapabilities::from(res.into_inner()),
+            Err(status) if status.code() == Code::Unimplemented => {
+                debug!("auraed does not implement hello");
+                return;
+            }
+            Err(status) => {
+                warn!(
+                    "hello failed, continuing without server capabilities: {}",
+                    status.message()
+                );
+                return;
+            }
+        };
+
+        for encoding in &wanted {
+            if !capabilities.accepts_compression(encoding) {
+                warn!("connect.compression is {encoding}, which auraed does not accept, sending uncompressed");
+            }
+        }
+        debug!(?capabilities, "auraed answered the hello");
+        let _ = self.session().set(capabilities);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{serve_in_memory, Discovery, Serving};
+    use proto::discovery::discovery_service_server::DiscoveryServiceServer;
+    use proto::grpc::health::health_server::HealthServer;
+    use tonic::transport::Server;
+
+    #[tokio::test]
+    async fn hello_answers_are_kept() {
+        let client = serve_in_memory(Server::builder().add_service(
+            DiscoveryServiceServer::new(Discovery::echoing_hello()),
+        ));
+        assert!(client.server_capabilities().is_none());
+
+        let options = ConnectOptions {
+            compression: CompressionMode::Gzip,
+            ..ConnectOptions::default()
+        };
+        client.send_hello(ClientInfo::new(&options)).await;
+
+        let capabilities =
+            client.clone().server_capabilities().cloned().unwrap();
+        assert_eq!(capabilities.version, env!("CARGO_PKG_VERSION"));
+        assert!(capabilities.supports_feature("status-details"));
+        assert!(capabilities.accepts_compression("gzip"));
+        assert_eq!(capabilities.max_message_size, 4 * 1024 * 1024);
+    }
+
+    #[tokio::test]
+    async fn only_accepted_encodings_are_compressed_with() {
+        let options = ConnectOptions {
+            compression: CompressionMode::Gzip,
+            ..ConnectOptions::default()
+        };
+        let serving = |discovery| {
+            serve_in_memory(
+                Server::builder()
+                    .add_service(DiscoveryServiceServer::new(discovery)),
+            )
+            .with_compression(CompressionMode::Gzip)
+        };
+
+        let accepting = serving(Discovery::echoing_hello());
+        assert_eq!(accepting.compression_encoding(), None);
+        accepting.send_hello(ClientInfo::new(&options)).await;
+        assert!(accepting.compression_encoding().is_some());
+
+        // auraed answers without any encodings
+        let refusing = serving(Discovery::echoing_hello());
+        refusing.send_hello(ClientInfo::new(&ConnectOptions::default())).await;
+        assert!(refusing.server_capabilities().is_some());
+        assert_eq!(refusing.compression_encoding(), None);
+    }
+
+    #[tokio::test]
+    async fn daemons_without_hello_leave_no_capabilities() {
+        let client = serve_in_memory(
+            Server::builder().add_service(HealthServer::new(Serving)),
+        );
+
+        client.send_hello(ClientInfo::new(&ConnectOptions::default())).await;
+
+        assert!(client.server_capabilities().is_none());
+    }
+}
@@ -0,0 +1,166 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Acting as one of several client identities, see [`Client::as_identity`].
+
+use crate::config::AuthConfig;
+use crate::Client;
+use anyhow::anyhow;
+use std::collections::BTreeMap;
+use tokio::sync::OnceCell;
+
+/// Named client identities a [`Client`] can issue calls as, e.g. the service
+/// accounts a controller acts on behalf of. Attach them with
+/// [`Client::with_identities`].
+#[derive(Debug, Clone, Default)]
+pub struct IdentitySet {
+    identities: BTreeMap<String, AuthConfig>,
+}
+
+impl IdentitySet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the identity `auth` as `name`, replacing any by the same name.
+    /// Only the cert paths and server checks of `auth` are used, the
+    /// connection settings come from the client.
+    pub fn with_identity(
+        mut self,
+        name: impl Into<String>,
+        auth: AuthConfig,
+    ) -> Self {
+        let _ = self.identities.insert(name.into(), auth);
+        self
+    }
+
+    /// The identity names, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.identities.keys().map(String::as_str)
+    }
+}
+
+/// The identities of a client and their connections, made on first use.
+#[derive(Debug)]
+pub(crate) struct IdentityPool {
+    identities: BTreeMap<String, (AuthConfig, OnceCell<Client>)>,
+}
+
+impl IdentityPool {
+    pub(crate) fn new(set: IdentitySet) -> Self {
+        let identities = set
+            .identities
+            .into_iter()
+            .map(|(name, auth)| (name, (auth, OnceCell::new())))
+            .collect();
+        Self { identities }
+    }
+
+    /// The same identities without any of their connections, for a client
+    /// that moved to another endpoint.
+    pub(crate) fn fresh(&self) -> Self {
+        let identities = self
+            .identities
+            .iter()
+            .map(|(name, (auth, _))| {
+                (name.clone(), (auth.clone(), OnceCell::new()))
+            })
+            .collect();
+        Self { identities }
+    }
+}
+
+impl Client {
+    /// A handle that issues its calls as the identity `name` from
+    /// [`Client::with_identities`].
+    ///
+    /// TLS identities are per connection, so each identity gets its own
+    /// connection to the same socket with the same connect options, made on
+    /// the first call for it: that first call reads and checks the certs and
+    /// runs a full TLS handshake (and, through a jump host, opens another
+    /// SSH forward). Later calls for the same name reuse the connection,
+    /// which stays open for as long as this client or one of its clones is
+    /// alive. The handle keeps this handle's metadata, read only mode and
+    /// deadline, but cannot switch identities itself.
+    pub async fn as_identity(&self, name: &str) -> crate::client::Result<Self> {
+        let pool = self.identities().ok_or_else(|| {
+            anyhow!("client has no identities, see Client::with_identities")
+        })?;
+        let (auth, connection) =
+            pool.identities.get(name).ok_or_else(|| {
+                anyhow!(
+                    "no identity named '{name}', known identities are: {}",
+                    pool.identities
+                        .keys()
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?;
+
+        let connection = connection
+            .get_or_try_init(|| self.connect_as(auth.clone()))
+            .await?;
+        Ok(self.with_settings_on(connection.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::transport::Endpoint;
+
+    fn auth(name: &str) -> AuthConfig {
+        AuthConfig {
+            ca_crt: "ca.crt".into(),
+            client_crt: format!("{name}.crt"),
+            client_key: format!("{name}.key"),
+            enforce_secure_paths: false,
+            strict: false,
+            expected_server_cn: None,
+            expected_server_spiffe: None,
+            expected_server_trust_domain: None,
+            client_key_passphrase: None,
+            ca_fetch: Default::default(),
+            pkcs11: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_identities_are_reported() {
+        let channel = Endpoint::from_static("http://[::1]:8080").connect_lazy();
+        let client = Client::from_channel(channel, None);
+
+        let err = client.as_identity("builder").await.unwrap_err();
+        assert!(err.to_string().contains("no identities"), "{err}");
+
+        let identities = IdentitySet::new()
+            .with_identity("reader", auth("reader"))
+            .with_identity("builder", auth("builder"))
+            .with_identity("reader", auth("reader-2"));
+        assert_eq!(
+            identities.names().collect::<Vec<_>>(),
+            ["builder", "reader"]
+        );
+
+        let client = client.with_identities(identities);
+        let err = client.as_identity("admin").await.unwrap_err();
+        assert!(err.to_string().contains("builder, reader"), "{err}");
+
+        // a caller built channel has no config to connect the identity with
+        let err = client.as_identity("builder").await.unwrap_err();
+        assert!(err.to_string().contains("no identity to switch"), "{err}");
+    }
+}
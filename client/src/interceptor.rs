@@ -0,0 +1,152 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Caller interceptors run on the RPCs of a [`crate::Client`] handle.
+
+use std::fmt;
+use std::sync::Arc;
+use tonic::{Request, Status};
+
+/// Where an interceptor runs in an [`InterceptorChain`]. Stages run in the
+/// order they are declared here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InterceptorStage {
+    /// Credentials, e.g. a bearer token on top of the mTLS identity.
+    Auth,
+    /// Headers such as a user agent or correlation ID.
+    Metadata,
+    /// Observing the request as it is about to be sent.
+    Logging,
+    /// Runs last, once per attempt when the RPC is retried.
+    Retry,
+}
+
+type Interceptor =
+    Arc<dyn Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync>;
+
+/// An ordered set of tonic style interceptors, set on a handle with
+/// [`crate::Client::with_interceptors`].
+///
+/// Interceptors run by [`InterceptorStage`], then in the order they were
+/// added within a stage, after the handle's own metadata and deadline have
+/// been set. They see the metadata and extensions of each attempt, not the
+/// message. An error fails the RPC with that status without sending it, and
+/// skips the interceptors after it.
+#[derive(Clone, Default)]
+pub struct InterceptorChain {
+    interceptors: Vec<(InterceptorStage, Interceptor)>,
+}
+
+impl fmt::Debug for InterceptorChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.interceptors.iter().map(|(stage, _)| stage))
+            .finish()
+    }
+}
+
+impl InterceptorChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `interceptor` at `stage`, after any already in that stage.
+    pub fn with<F>(mut self, stage: InterceptorStage, interceptor: F) -> Self
+    where
+        F: Fn(Request<()>) -> Result<Request<()>, Status>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let at = self.interceptors.partition_point(|(s, _)| *s <= stage);
+        self.interceptors.insert(at, (stage, Arc::new(interceptor)));
+        self
+    }
+
+    /// Run every interceptor on `req`, in order.
+    pub(crate) fn apply<T>(
+        &self,
+        req: Request<T>,
+    ) -> Result<Request<T>, Status> {
+        if self.interceptors.is_empty() {
+            return Ok(req);
+        }
+
+        let (metadata, extensions, message) = req.into_parts();
+        let mut req = Request::from_parts(metadata, extensions, ());
+        for (_, interceptor) in &self.interceptors {
+            req = interceptor(req)?;
+        }
+        let (metadata, extensions, ()) = req.into_parts();
+        Ok(Request::from_parts(metadata, extensions, message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn recording(
+        seen: &Arc<Mutex<Vec<&'static str>>>,
+        name: &'static str,
+    ) -> impl Fn(Request<()>) -> Result<Request<()>, Status> {
+        let seen = seen.clone();
+        move |mut req| {
+            seen.lock().unwrap().push(name);
+            let _ = req.metadata_mut().append("x-seen", name.parse().unwrap());
+            Ok(req)
+        }
+    }
+
+    #[test]
+    fn interceptors_run_by_stage_then_in_order_added() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let chain = InterceptorChain::new()
+            .with(InterceptorStage::Retry, recording(&seen, "retry"))
+            .with(InterceptorStage::Logging, recording(&seen, "logging"))
+            .with(InterceptorStage::Metadata, recording(&seen, "user-agent"))
+            .with(InterceptorStage::Auth, recording(&seen, "auth"))
+            .with(InterceptorStage::Metadata, recording(&seen, "correlation"));
+
+        let req = chain.apply(Request::new("message")).unwrap();
+
+        let order = ["auth", "user-agent", "correlation", "logging", "retry"];
+        assert_eq!(*seen.lock().unwrap(), order);
+        let headers: Vec<_> = req
+            .metadata()
+            .get_all("x-seen")
+            .iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(headers, order);
+        assert_eq!(*req.get_ref(), "message");
+    }
+
+    #[test]
+    fn failing_interceptor_stops_the_chain() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let chain = InterceptorChain::new()
+            .with(InterceptorStage::Logging, recording(&seen, "logging"))
+            .with(InterceptorStage::Auth, |_| {
+                Err(Status::unauthenticated("no token"))
+            });
+
+        let err = chain.apply(Request::new(())).unwrap_err();
+
+        assert_eq!(err.code(), tonic::Code::Unauthenticated);
+        assert!(seen.lock().unwrap().is_empty());
+    }
+}
@@ -12,14 +12,104 @@
  * Copyright 2022 - 2024, the aurae contributors                              *
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
+pub use crate::api::AuraeApi;
+pub use crate::cert_bundle::CertBundle;
+pub use crate::cert_store::{CertWatcherHandle, SighupReloadHandle};
 pub use crate::client::{Client, ClientError};
-pub use config::{AuraeConfig, AuraeSocket, AuthConfig, SystemConfig};
+pub use crate::concurrency::{is_load_shed, InFlightSnapshot, Priority};
+pub use crate::connector::{
+    ConnectInfo, ConnectPhase, Connection, Connector, TcpConnector,
+    UnixConnector,
+};
+pub use crate::cooldown::EndpointCooldown;
+pub use crate::credentials::{Credential, CredentialProvider};
+pub use crate::diagnostics::{
+    ClientState, ConnectTimings, ConnectionState, Diagnostics, LastError,
+    SecurityLevel, ServerIdentity, TlsParams,
+};
+pub use crate::dry_connect::{
+    DryConnectOutcome, DryConnectReport, DryConnectStep,
+};
+pub use crate::endpoints::EndpointStatus;
+pub use crate::events::ConnectionEvent;
+pub use crate::hello::{ClientInfo, ServerCapabilities};
+pub use crate::identity::IdentitySet;
+pub use crate::interceptor::{InterceptorChain, InterceptorStage};
+pub use crate::liveness::LivenessHandle;
+pub use crate::multi::{EndpointId, MultiClient};
+pub use crate::operation::{AbortOutcome, Operation};
+pub use crate::prepared::PreparedClient;
+pub use crate::resumable::{ResumableStream, Resume, StreamEvent};
+pub use crate::server_info::ServerInfo;
+pub use crate::status_details::{ErrorDetail, StatusDetails};
+pub use crate::tls::{PresentedCert, TlsDebugInfo};
+pub use crate::tower_service::RpcService;
+pub use crate::watch::{AuraeEvent, ContainerEventKind, EventFilter};
+pub use config::{
+    AuditRecord, AuraeConfig, AuraeSocket, AuthConfig, CaFetchOptions,
+    CompressionMode, ConfigLayers, ConfigSource, ConnectOptions,
+    ExtendedKeyUsage, FieldPath, InheritedSocket, IpFamily, KeyAlgorithm,
+    KeyUsage, LbPolicy, MethodPolicy, ParseSocketError, Pkcs11Key, ProfileInfo,
+    RetryOptions, RpcLogLevel, RpcLogOptions, SocketKind, SshJump,
+    SystemConfig, TransportMode, X509Change, X509Details, X509Diff,
+    AUDIT_SCHEMA_VERSION,
+};
 
+mod api;
+mod balancer;
+mod bootstrap;
 pub mod cells;
+mod cert_bundle;
+mod cert_store;
 mod client;
+mod clock;
+mod concurrency;
 mod config;
+mod connection_tracker;
+mod connector;
+mod cooldown;
+mod credentials;
 pub mod cri;
+mod dangerous;
+mod deadline;
+pub mod deploy;
+#[cfg(feature = "dev-certs")]
+pub mod dev;
+mod diagnostics;
 pub mod discovery;
+mod dry_connect;
+mod endpoints;
+mod events;
+mod goaway;
 pub mod grpc;
+mod grpc_web;
+mod hedge;
+mod hello;
+mod identity;
+mod interceptor;
+mod liveness;
+mod metadata;
+mod method_policy;
+pub mod migrate;
+mod multi;
+mod namespace;
 pub mod observe;
-pub mod vms;
\ No newline at end of file
+mod operation;
+mod payload_log;
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
+mod prepared;
+mod read_only;
+mod ready;
+mod resumable;
+mod retry;
+mod rpc_log;
+mod server_info;
+mod ssh_tunnel;
+mod status_details;
+#[cfg(test)]
+mod testing;
+mod tls;
+mod tower_service;
+pub mod vms;
+mod watch;
\ No newline at end of file
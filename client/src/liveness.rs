@@ -0,0 +1,134 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Periodic health checks of a client's connection, see
+//! [`Client::start_liveness_monitor`].
+
+use crate::events::ConnectionEvent;
+use crate::Client;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, warn};
+
+/// Health checks prove the connection, not that the client gets work done,
+/// so they leave [`Client::last_success`] alone.
+const HEALTH_SERVICE: &str = "/grpc.health.v1.Health/";
+
+/// Consecutive failed probes after which the connection is rebuilt.
+const FAILURES_BEFORE_RECONNECT: u32 = 3;
+
+/// Keeps a monitor started by [`Client::start_liveness_monitor`] running.
+/// Dropping the handle stops it.
+#[derive(Debug)]
+pub struct LivenessHandle {
+    task: JoinHandle<()>,
+}
+
+impl Drop for LivenessHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Client {
+    /// Run a health check every `interval`, keeping [`Client::state`] up to
+    /// date for this client and its clones.
+    ///
+    /// Probes never overlap: a slow probe delays the next one rather than
+    /// running alongside it. Failures are recorded as the last error, and
+    /// after three failures in a row the connections are rebuilt (as a
+    /// [`ConnectionEvent::Reconnecting`] attempt), which also covers
+    /// connections that stopped answering without closing. The monitor holds
+    /// a clone of the client and runs until the returned handle is dropped.
+    ///
+    /// Each successful probe is also a round trip measurement, see
+    /// [`Client::rtt`].
+    pub fn start_liveness_monitor(&self, interval: Duration) -> LivenessHandle {
+        let task = tokio::spawn(monitor(self.clone(), interval));
+        LivenessHandle { task }
+    }
+
+    /// The round trip time of the most recent successful liveness probe,
+    /// `None` unless [`Client::start_liveness_monitor`] is running on this
+    /// client or a clone.
+    ///
+    /// A probe is a unary health check RPC on the current connection, so
+    /// the time includes the server handling it, not only the network. It
+    /// costs one small request and response per interval, no extra
+    /// connection. tonic does not expose HTTP/2 PING frames, which would
+    /// measure the network alone.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.connect_state().rtt().map(|rtt| rtt.latest)
+    }
+
+    /// A moving average of the probes measured by [`Client::rtt`], which
+    /// smooths out single slow probes: each one weighs in by 1/8.
+    pub fn rtt_average(&self) -> Option<Duration> {
+        self.connect_state().rtt().map(|rtt| rtt.average)
+    }
+
+    /// When an RPC of this client or a clone last succeeded, `None` if none
+    /// has yet. Health checks, including the probes of
+    /// [`Client::start_liveness_monitor`], do not count, so a connected
+    /// client whose `last_success` is old is idle rather than working.
+    ///
+    /// A streaming RPC counts when the stream opens.
+    pub fn last_success(&self) -> Option<Instant> {
+        self.connect_state().last_success()
+    }
+
+    /// How long ago [`Client::last_success`] was.
+    pub fn since_last_success(&self) -> Option<Duration> {
+        self.last_success().map(|at| at.elapsed())
+    }
+
+    /// Used by the generated service clients.
+    pub(crate) fn record_success(&self, path: &str) {
+        if !path.starts_with(HEALTH_SERVICE) {
+            self.connect_state().record_success();
+        }
+    }
+}
+
+async fn monitor(client: Client, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let state = client.connect_state().clone();
+    let mut failures = 0;
+
+    loop {
+        let _ = ticks.tick().await;
+
+        let sent = Instant::now();
+        let Err(e) = client.health_check().await else {
+            state.record_rtt(sent.elapsed());
+            failures = 0;
+            continue;
+        };
+        failures += 1;
+        debug!(failures, "liveness probe failed: {e}");
+        state.record_error(&e);
+
+        if failures % FAILURES_BEFORE_RECONNECT == 0 {
+            let attempt = failures / FAILURES_BEFORE_RECONNECT;
+            state.publish(ConnectionEvent::Reconnecting { attempt });
+            if let Err(e) = client.reconnect().await {
+                warn!("liveness monitor failed to reconnect: {e}");
+                state.record_error(&e);
+            }
+        }
+    }
+}
@@ -0,0 +1,126 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Extra metadata attached to the RPCs of a [`crate::Client`] handle.
+
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use tonic::metadata::{AsciiMetadataKey, AsciiMetadataValue};
+use tonic::Request;
+
+/// Headers added to every request made through a handle.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CallMetadata(
+    Arc<Vec<(AsciiMetadataKey, AsciiMetadataValue)>>,
+);
+
+impl CallMetadata {
+    /// The current headers followed by `pairs`. Names must be valid ASCII
+    /// header names, and values visible ASCII.
+    pub(crate) fn extend(&self, pairs: Vec<(String, String)>) -> Result<Self> {
+        let mut metadata = (*self.0).clone();
+        for (name, value) in pairs {
+            let key = AsciiMetadataKey::from_bytes(name.as_bytes())
+                .map_err(|_| anyhow!("invalid metadata name '{name}'"))?;
+            if key.as_str().ends_with("-bin") {
+                return Err(anyhow!(
+                    "metadata name '{name}' is reserved for binary values"
+                ));
+            }
+            let value =
+                AsciiMetadataValue::try_from(value.as_str()).map_err(|_| {
+                    anyhow!("invalid value for metadata '{name}': '{value}'")
+                })?;
+            metadata.push((key, value));
+        }
+        Ok(Self(Arc::new(metadata)))
+    }
+
+    pub(crate) fn request<T>(&self, message: T) -> Request<T> {
+        let mut req = Request::new(message);
+        let metadata = req.metadata_mut();
+        for (key, value) in self.0.iter() {
+            let _ = metadata.append(key.clone(), value.clone());
+        }
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::discovery_service::DiscoveryServiceClient;
+    use crate::testing::{serve_in_memory, Discovery};
+    use proto::discovery::discovery_service_server::DiscoveryServiceServer;
+    use proto::discovery::{DiscoverRequest, DiscoverResponse};
+    use tonic::transport::Server;
+
+    #[tokio::test]
+    async fn metadata_reaches_the_server() {
+        let recorder = Discovery::answering(DiscoverResponse::default());
+        let client = serve_in_memory(
+            Server::builder()
+                .add_service(DiscoveryServiceServer::new(recorder.clone())),
+        );
+        let tenant = client
+            .with_metadata(vec![("x-tenant-id".into(), "acme".into())])
+            .unwrap();
+        let traced = tenant
+            .with_metadata(vec![("baggage".into(), "team=runtime".into())])
+            .unwrap();
+
+        let _ = client.discover(DiscoverRequest {}).await.unwrap();
+        let _ = tenant.discover(DiscoverRequest {}).await.unwrap();
+        let _ = traced.discover(DiscoverRequest {}).await.unwrap();
+
+        assert_eq!(
+            recorder.sent("x-tenant-id"),
+            [None, Some("acme".into()), Some("acme".into())]
+        );
+        assert_eq!(
+            recorder.sent("baggage"),
+            [None, None, Some("team=runtime".into())]
+        );
+    }
+
+    #[test]
+    fn request_carries_metadata() {
+        let metadata = CallMetadata::default()
+            .extend(vec![("x-tenant-id".into(), "acme".into())])
+            .unwrap()
+            .extend(vec![("baggage".into(), "team=runtime".into())])
+            .unwrap();
+
+        let req = metadata.request(());
+
+        assert_eq!(req.metadata().get("x-tenant-id").unwrap(), "acme");
+        assert_eq!(req.metadata().get("baggage").unwrap(), "team=runtime");
+    }
+
+    #[test]
+    fn invalid_metadata_is_rejected() {
+        let metadata = CallMetadata::default();
+
+        assert!(metadata
+            .extend(vec![("bad name".into(), "value".into())])
+            .is_err());
+        assert!(metadata
+            .extend(vec![("x-tenant-id".into(), "caf\u{e9}".into())])
+            .is_err());
+        assert!(metadata
+            .extend(vec![("x-trace-bin".into(), "value".into())])
+            .is_err());
+    }
+}
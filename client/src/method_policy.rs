@@ -0,0 +1,138 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Allow and deny lists of the RPCs a client makes, see
+//! [`crate::ConnectOptions::method_policy`].
+
+use crate::config::MethodPolicy;
+use crate::{Client, ClientError};
+
+impl MethodPolicy {
+    /// Deny wins over allow, an empty allow list allows everything.
+    pub(crate) fn permits(&self, method: &str) -> bool {
+        let matches = |globs: &[String]| {
+            globs.iter().any(|glob| glob_matches(glob, method))
+        };
+        !matches(&self.deny) && (self.allow.is_empty() || matches(&self.allow))
+    }
+}
+
+/// Whether `text` matches `glob`, where `*` matches any run of characters
+/// and everything else only itself.
+fn glob_matches(glob: &str, text: &str) -> bool {
+    let mut parts = glob.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let mut parts = parts.peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    // no `*` at all
+    rest.is_empty()
+}
+
+impl Client {
+    /// Check the full method path of an RPC (`/package.Service/Method`)
+    /// against `connect.method_policy`, before it hits the wire. Like
+    /// [`Client::check_read_only`], this is done by the generated service
+    /// clients for every RPC.
+    pub fn check_method_policy(&self, method: &str) -> Result<(), ClientError> {
+        match self.method_policy().permits(method) {
+            true => Ok(()),
+            false => {
+                Err(ClientError::MethodNotPermitted { method: method.into() })
+            }
+        }
+    }
+
+    /// Used by the generated service clients: the method policy, then the
+    /// read only mode. Rejected calls fail with `PERMISSION_DENIED`.
+    pub(crate) fn method_guard(
+        &self,
+        method: &str,
+    ) -> Result<(), tonic::Status> {
+        self.check_method_policy(method)
+            .and_then(|()| self.check_read_only(method))
+            .map_err(|e| tonic::Status::permission_denied(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(allow: &[&str], deny: &[&str]) -> MethodPolicy {
+        MethodPolicy {
+            allow: allow.iter().map(|glob| glob.to_string()).collect(),
+            deny: deny.iter().map(|glob| glob.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn globs_match_runs_of_characters() {
+        assert!(glob_matches(
+            "/aurae.cells.v0.*",
+            "/aurae.cells.v0.CellService/Allocate"
+        ));
+        assert!(glob_matches("*/Free", "/aurae.vms.v0.VmService/Free"));
+        assert!(glob_matches("/*.v0.*/List", "/aurae.vms.v0.VmService/List"));
+        assert!(!glob_matches("/*.v0.*/List", "/aurae.vms.v0.VmService/Free"));
+        assert!(glob_matches(
+            "/aurae.vms.v0.VmService/List",
+            "/aurae.vms.v0.VmService/List"
+        ));
+        assert!(!glob_matches(
+            "/aurae.vms.v0.VmService/List",
+            "/aurae.vms.v0.VmService/ListAll"
+        ));
+    }
+
+    #[test]
+    fn allow_lists_restrict_to_their_methods() {
+        let observe_only = policy(&["/aurae.observe.v0.*"], &[]);
+        assert!(observe_only
+            .permits("/aurae.observe.v0.ObserveService/GetSubProcessStream"));
+        assert!(!observe_only.permits("/aurae.cells.v0.CellService/List"));
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let no_free = policy(&["/aurae.cells.v0.*"], &["*/Free"]);
+        assert!(no_free.permits("/aurae.cells.v0.CellService/Allocate"));
+        assert!(!no_free.permits("/aurae.cells.v0.CellService/Free"));
+
+        let no_deletes = policy(&[], &["/runtime.v1.*/Remove*"]);
+        assert!(
+            !no_deletes.permits("/runtime.v1.RuntimeService/RemoveContainer")
+        );
+        assert!(no_deletes.permits("/runtime.v1.RuntimeService/ListContainers"));
+    }
+
+    #[test]
+    fn empty_policy_permits_everything() {
+        assert!(
+            MethodPolicy::default().permits("/aurae.cells.v0.CellService/Free")
+        );
+    }
+}
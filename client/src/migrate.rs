@@ -0,0 +1,156 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Moving a config off the throwaway certs of [`crate::dev`] onto certs
+//! issued by a real CA, backing `aurae cert migrate`.
+
+use crate::client::Result;
+use crate::{AuraeConfig, CertBundle, Client};
+use anyhow::{anyhow, Context};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Check that `new_certs` connect to the auraed of `old`, then write them to
+/// `out_dir` and return `old` pointing at them.
+///
+/// Nothing is written unless a connection with `new_certs` answers a
+/// health check, and the files of `old` are never touched, so clients still
+/// running with `old` keep working until they are moved over. `old` is
+/// tried alongside, for the error to tell a rejected cert from an
+/// unreachable daemon.
+///
+/// Writes `ca.crt`, `client.crt` and `client.key` (mode 0600). The key is
+/// written in the clear, so `auth.client_key_passphrase` is cleared. A
+/// self-signed client certificate is rejected, as it was not issued by a
+/// CA.
+pub async fn to_ca_issued<P: AsRef<Path>>(
+    old: &AuraeConfig,
+    new_certs: CertBundle,
+    out_dir: P,
+) -> Result<AuraeConfig> {
+    if new_certs.client_cert_details().self_signed {
+        return Err(anyhow!(
+            "the new client certificate is self-signed, not issued by a CA"
+        )
+        .into());
+    }
+
+    let ca_pem = new_certs.ca_pem().to_vec();
+    let cert_pem = new_certs.client_cert_pem().to_vec();
+    let key_pem = new_certs.client_key_pem().to_vec();
+    let (new, old_works) = tokio::join!(
+        connect(Client::from_bundle(old.clone(), new_certs)),
+        connect(Client::new(old.clone())),
+    );
+    if let Err(e) = new {
+        let context = match old_works {
+            Ok(()) => {
+                "auraed rejected the new certs, the current ones still connect"
+            }
+            Err(_) => {
+                "the new certs failed to connect, and so do the current ones"
+            }
+        };
+        return Err(anyhow!(e).context(context).into());
+    }
+
+    let out_dir = out_dir.as_ref();
+    std::fs::create_dir_all(out_dir)
+        .with_context(|| format!("failed to create '{}'", out_dir.display()))?;
+    let ca_crt = out_dir.join("ca.crt");
+    let client_crt = out_dir.join("client.crt");
+    let client_key = out_dir.join("client.key");
+    write(&ca_crt, &ca_pem, 0o644)?;
+    write(&client_crt, &cert_pem, 0o644)?;
+    write(&client_key, &key_pem, 0o600)?;
+
+    let mut config = old.clone();
+    config.auth.ca_crt = ca_crt.to_string_lossy().into();
+    config.auth.client_crt = client_crt.to_string_lossy().into();
+    config.auth.client_key = client_key.to_string_lossy().into();
+    config.auth.client_key_passphrase = None;
+    Ok(config)
+}
+
+async fn connect(
+    client: impl std::future::Future<Output = Result<Client>>,
+) -> std::result::Result<(), String> {
+    client.await.map_err(|e| e.to_string())?.health_check().await
+}
+
+/// Write `contents` to `path` through a temporary file, so a reader never
+/// sees half a cert.
+fn write(path: &Path, contents: &[u8], mode: u32) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let written = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(contents))
+        .and_then(|()| std::fs::rename(&tmp, path));
+    written.with_context(|| format!("failed to write '{}'", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{client_material, serve_health_at, test_ca};
+    use std::time::Duration;
+
+    fn bundle(ca: &rcgen::Certificate) -> CertBundle {
+        let material = client_material(ca, "migrated client");
+        CertBundle::from_material(material, Duration::from_secs(300)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn certs_are_written_once_they_connect() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let socket = dir.join("aurae.sock");
+        let pki = test_ca("production ca");
+        serve_health_at(&pki, &socket);
+        // the dev certs are long gone, which must not stop the migration
+        let old = AuraeConfig::from_options(
+            dir.join("dev/ca.crt").to_string_lossy(),
+            dir.join("dev/client.crt").to_string_lossy(),
+            dir.join("dev/client.key").to_string_lossy(),
+            socket.to_string_lossy(),
+        );
+
+        let err =
+            to_ca_issued(&old, bundle(&test_ca("other ca")), dir.join("bad"))
+                .await
+                .unwrap_err();
+        assert!(err.to_string().contains("failed to connect"), "{err}");
+        assert!(!dir.join("bad").exists());
+
+        let migrated =
+            to_ca_issued(&old, bundle(&pki), dir.join("pki")).await.unwrap();
+        assert_eq!(
+            migrated.auth.client_crt,
+            dir.join("pki/client.crt").to_string_lossy()
+        );
+        let material = migrated.auth.to_cert_material().await.unwrap();
+        assert_eq!(
+            material.get_client_cert_details().unwrap().subject_common_name,
+            "migrated client"
+        );
+    }
+}
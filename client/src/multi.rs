@@ -0,0 +1,226 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Running the same RPCs against many daemons at once, for fleet tools.
+
+use crate::{AuraeConfig, Client, ClientError};
+use futures_util::future::join_all;
+use futures_util::stream::{self, StreamExt};
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::time::Duration;
+
+/// Clients [`MultiClient::fan_out`] calls at once, unless changed with
+/// [`MultiClient::with_concurrency`].
+const DEFAULT_CONCURRENCY: usize = 16;
+
+/// How long each call of [`MultiClient::fan_out`] may take, unless changed
+/// with [`MultiClient::with_call_timeout`].
+const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Which client of a [`MultiClient`] a result is from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct EndpointId {
+    /// The position of the client in the list it was created from.
+    pub index: usize,
+    /// The configured socket, `None` for clients from
+    /// [`Client::from_channel`].
+    pub endpoint: Option<String>,
+}
+
+impl Display for EndpointId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.endpoint {
+            Some(endpoint) => f.write_str(endpoint),
+            None => write!(f, "client {}", self.index),
+        }
+    }
+}
+
+/// A client per daemon of a fleet, for running the same RPCs against all
+/// of them, see [`MultiClient::fan_out`].
+#[derive(Debug, Clone)]
+pub struct MultiClient {
+    clients: Vec<(EndpointId, Client)>,
+    concurrency: usize,
+    call_timeout: Duration,
+}
+
+impl MultiClient {
+    /// A client for each of `configs`, connecting to all of them at once.
+    /// Daemons that cannot be reached do not fail this: their clients are
+    /// created as by [`Client::new_allow_disconnected`], and their calls
+    /// fail in [`MultiClient::fan_out`] until they come up. Only a config
+    /// that can never connect is an error.
+    pub async fn connect(
+        configs: Vec<AuraeConfig>,
+    ) -> Result<Self, ClientError> {
+        let clients =
+            join_all(configs.into_iter().map(Client::new_allow_disconnected))
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::from_clients(clients))
+    }
+
+    /// Fan out over clients created by the caller.
+    pub fn from_clients(clients: impl IntoIterator<Item = Client>) -> Self {
+        let clients = clients
+            .into_iter()
+            .enumerate()
+            .map(|(index, client)| {
+                let endpoint = client.socket().map(|socket| socket.endpoint());
+                (EndpointId { index, endpoint }, client)
+            })
+            .collect();
+        Self {
+            clients,
+            concurrency: DEFAULT_CONCURRENCY,
+            call_timeout: DEFAULT_CALL_TIMEOUT,
+        }
+    }
+
+    /// Run at most `concurrency` calls at once, 16 by default.
+    pub fn with_concurrency(self, concurrency: usize) -> Self {
+        Self { concurrency: concurrency.max(1), ..self }
+    }
+
+    /// Fail each call that takes longer than `timeout`, 30 seconds by
+    /// default.
+    pub fn with_call_timeout(self, timeout: Duration) -> Self {
+        Self { call_timeout: timeout, ..self }
+    }
+
+    /// The clients, with the ids their results are reported under.
+    pub fn clients(&self) -> &[(EndpointId, Client)] {
+        &self.clients
+    }
+
+    /// Call `op` with every client, at most the configured concurrency at
+    /// once and each within the call timeout, and collect what each
+    /// returned, in the order of the clients. A call that fails or times
+    /// out only fails its own result, the others run to completion.
+    ///
+    /// `op` usually makes a read RPC, e.g.
+    /// `|client| async move { client.discover(DiscoverRequest {}).await }`.
+    pub async fn fan_out<F, Fut, T, E>(
+        &self,
+        op: F,
+    ) -> Vec<(EndpointId, Result<T, ClientError>)>
+    where
+        F: Fn(Client) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Into<anyhow::Error>,
+    {
+        let timeout = self.call_timeout;
+        let op = &op;
+        let mut results: Vec<_> = stream::iter(self.clients.iter().enumerate())
+            .map(|(position, (id, client))| async move {
+                let result =
+                    match tokio::time::timeout(timeout, op(client.clone()))
+                        .await
+                    {
+                        Ok(Ok(value)) => Ok(value),
+                        Ok(Err(e)) => Err(ClientError::from(e.into())),
+                        Err(_) => Err(ClientError::Other(anyhow::anyhow!(
+                            "{id} did not answer within {timeout:?}"
+                        ))),
+                    };
+                (position, id.clone(), result)
+            })
+            .buffer_unordered(self.concurrency)
+            .collect()
+            .await;
+
+        results.sort_by_key(|(position, _, _)| *position);
+        results.into_iter().map(|(_, id, result)| (id, result)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grpc::health::health::HealthClient;
+    use crate::testing::{serve_in_memory, Serving};
+    use proto::grpc::health::health_server::{Health, HealthServer};
+    use proto::grpc::health::{HealthCheckRequest, HealthCheckResponse};
+    use tonic::transport::{Endpoint, Server};
+    use tonic::{Request, Response, Status};
+
+    /// Answers nothing for a minute.
+    struct Hung;
+
+    #[tonic::async_trait]
+    impl Health for Hung {
+        async fn check(
+            &self,
+            _request: Request<HealthCheckRequest>,
+        ) -> Result<Response<HealthCheckResponse>, Status> {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Err(Status::unavailable("hung"))
+        }
+
+        type WatchStream =
+            futures_util::stream::Empty<Result<HealthCheckResponse, Status>>;
+
+        async fn watch(
+            &self,
+            _request: Request<HealthCheckRequest>,
+        ) -> Result<Response<Self::WatchStream>, Status> {
+            Err(Status::unimplemented("watch"))
+        }
+    }
+
+    #[tokio::test]
+    async fn partial_failures_only_fail_their_own_result() {
+        let serving = || {
+            serve_in_memory(
+                Server::builder().add_service(HealthServer::new(Serving)),
+            )
+        };
+        let fleet = MultiClient::from_clients([
+            serving(),
+            serve_in_memory(
+                Server::builder().add_service(HealthServer::new(Hung)),
+            ),
+            // a daemon that is down
+            Client::from_channel(
+                Endpoint::from_static("http://127.0.0.1:1").connect_lazy(),
+                None,
+            ),
+            serving(),
+        ])
+        .with_concurrency(2)
+        .with_call_timeout(Duration::from_millis(200));
+
+        let results = fleet
+            .fan_out(|client| async move {
+                client
+                    .check(HealthCheckRequest { service: String::new() })
+                    .await
+            })
+            .await;
+
+        let ids: Vec<_> =
+            results.iter().map(|(id, _)| id.to_string()).collect();
+        assert_eq!(ids, ["client 0", "client 1", "client 2", "client 3"]);
+        assert!(results[0].1.is_ok());
+        let hung = results[1].1.as_ref().unwrap_err().to_string();
+        assert!(hung.contains("did not answer within"), "{hung}");
+        assert!(results[2].1.is_err());
+        assert!(results[3].1.is_ok());
+    }
+}
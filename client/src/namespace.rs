@@ -0,0 +1,96 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Scoping the RPCs of a [`crate::Client`] handle to a namespace (tenant),
+//! see [`crate::ConnectOptions::default_namespace`].
+
+use crate::ClientError;
+use anyhow::anyhow;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::Request;
+
+/// The header carrying the namespace of an RPC.
+pub(crate) const NAMESPACE_HEADER: &str = "x-aurae-namespace";
+
+/// A namespace known to be a legal header value.
+#[derive(Debug, Clone)]
+pub(crate) struct Namespace(AsciiMetadataValue);
+
+impl Namespace {
+    pub(crate) fn new(namespace: &str) -> Result<Self, ClientError> {
+        if namespace.is_empty() {
+            return Err(ClientError::Other(anyhow!("namespace is empty")));
+        }
+        AsciiMetadataValue::try_from(namespace).map(Self).map_err(|_| {
+            ClientError::Other(anyhow!(
+                "namespace '{namespace}' is not a valid header value"
+            ))
+        })
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        self.0.to_str().expect("namespaces are visible ASCII")
+    }
+
+    /// Set the header on `req`, unless the handle's metadata already did.
+    pub(crate) fn apply<T>(&self, req: &mut Request<T>) {
+        let metadata = req.metadata_mut();
+        if !metadata.contains_key(NAMESPACE_HEADER) {
+            let _ = metadata.insert(NAMESPACE_HEADER, self.0.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{serve_in_memory, Discovery};
+    use proto::discovery::discovery_service_server::DiscoveryServiceServer;
+    use proto::discovery::{DiscoverRequest, DiscoverResponse};
+    use tonic::transport::Server;
+
+    #[tokio::test]
+    async fn calls_carry_the_namespace() {
+        use crate::discovery::discovery_service::DiscoveryServiceClient;
+
+        let recorder = Discovery::answering(DiscoverResponse::default());
+        let client = serve_in_memory(
+            Server::builder()
+                .add_service(DiscoveryServiceServer::new(recorder.clone())),
+        );
+
+        let _ = client.discover(DiscoverRequest {}).await.unwrap();
+        let tenant = client.with_namespace("tenant-a").unwrap();
+        let _ = tenant.discover(DiscoverRequest {}).await.unwrap();
+        let other = tenant
+            .with_metadata(vec![(NAMESPACE_HEADER.into(), "tenant-b".into())])
+            .unwrap();
+        let _ = other.discover(DiscoverRequest {}).await.unwrap();
+
+        assert_eq!(
+            recorder.sent(NAMESPACE_HEADER),
+            [None, Some("tenant-a".into()), Some("tenant-b".into())]
+        );
+        assert_eq!(tenant.namespace(), Some("tenant-a"));
+    }
+
+    #[test]
+    fn namespaces_must_be_header_values() {
+        assert!(Namespace::new("tenant-a").is_ok());
+        assert!(Namespace::new("").is_err());
+        assert!(Namespace::new("tenant\na").is_err());
+        assert!(Namespace::new("ténant").is_err());
+    }
+}
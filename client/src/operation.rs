@@ -0,0 +1,179 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Long running calls that can be aborted on the server too, see
+//! [`Operation`].
+
+use crate::client::{ClientError, Result};
+use anyhow::anyhow;
+use futures_util::future::BoxFuture;
+use std::future::Future;
+use tokio::task::JoinHandle;
+
+type Cancel = Box<dyn FnOnce() -> BoxFuture<'static, Result<()>> + Send>;
+
+/// A call running in the background, such as starting a workload, that
+/// [`Operation::abort`] stops.
+///
+/// With a companion cancel call set by [`Operation::with_cancel`], such as a
+/// `CellService::stop` for the executable being started, aborting makes
+/// that call and waits for the server to answer it, so the server has
+/// stopped the work once `abort` returns. Without one, aborting drops the
+/// call: the server sees the HTTP/2 stream reset and drops its handler,
+/// which stops work done within the handler but not work it handed off,
+/// and nothing confirms either. Dropping the operation aborts it the same
+/// way.
+pub struct Operation<T> {
+    task: Option<JoinHandle<Result<T>>>,
+    cancel: Option<Cancel>,
+}
+
+/// How an [`Operation::abort`] ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbortOutcome {
+    /// The server answered the companion cancel call.
+    Acknowledged,
+    /// There is no companion cancel call, so the call was dropped.
+    Dropped,
+    /// The call had already finished, there was nothing to abort.
+    Finished,
+}
+
+impl<T: Send + 'static> Operation<T> {
+    /// Start running `call`.
+    pub fn spawn<F>(call: F) -> Self
+    where
+        F: Future<Output = Result<T>> + Send + 'static,
+    {
+        Self { task: Some(tokio::spawn(call)), cancel: None }
+    }
+
+    /// Abort by awaiting `cancel`, a call telling the server to stop the
+    /// work, rather than by only dropping the call.
+    pub fn with_cancel<F, Fut>(mut self, cancel: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.cancel = Some(Box::new(move || Box::pin(cancel())));
+        self
+    }
+
+    /// Wait for the call to finish.
+    pub async fn wait(mut self) -> Result<T> {
+        let task = self.task.take().expect("operation task is only taken once");
+        match task.await {
+            Ok(result) => result,
+            Err(e) => Err(ClientError::Other(anyhow!("operation failed: {e}"))),
+        }
+    }
+
+    /// Stop the call, see [`Operation`]. When the companion cancel call
+    /// fails, the call is dropped all the same and the error is returned.
+    pub async fn abort(mut self) -> Result<AbortOutcome> {
+        let task = self.task.take().expect("operation task is only taken once");
+        if task.is_finished() {
+            return Ok(AbortOutcome::Finished);
+        }
+
+        let outcome = match self.cancel.take() {
+            Some(cancel) => cancel().await.map(|()| AbortOutcome::Acknowledged),
+            None => Ok(AbortOutcome::Dropped),
+        };
+        task.abort();
+        let _ = task.await;
+        outcome
+    }
+}
+
+impl<T> Drop for Operation<T> {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for Operation<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Operation")
+            .field(
+                "running",
+                &self.task.as_ref().is_some_and(|t| !t.is_finished()),
+            )
+            .field("cancel", &self.cancel.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// A call that only ends by being dropped, recording when it is.
+    fn endless(dropped: Arc<AtomicBool>) -> impl Future<Output = Result<()>> {
+        struct SetOnDrop(Arc<AtomicBool>);
+        impl Drop for SetOnDrop {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        async move {
+            let _guard = SetOnDrop(dropped);
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn abort_awaits_the_cancel_call() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let operation =
+            Operation::spawn(endless(dropped.clone())).with_cancel({
+                let cancelled = cancelled.clone();
+                move || async move {
+                    cancelled.store(true, Ordering::SeqCst);
+                    Ok(())
+                }
+            });
+        tokio::task::yield_now().await;
+
+        assert_eq!(
+            operation.abort().await.unwrap(),
+            AbortOutcome::Acknowledged
+        );
+        assert!(cancelled.load(Ordering::SeqCst));
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn abort_without_cancel_call_drops_it() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let operation = Operation::spawn(endless(dropped.clone()));
+        tokio::task::yield_now().await;
+
+        assert_eq!(operation.abort().await.unwrap(), AbortOutcome::Dropped);
+        assert!(dropped.load(Ordering::SeqCst));
+
+        let finished = Operation::spawn(async { Ok(()) });
+        while !finished.task.as_ref().unwrap().is_finished() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(finished.abort().await.unwrap(), AbortOutcome::Finished);
+    }
+}
@@ -0,0 +1,166 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Trace level logging of RPC messages as JSON, see
+//! [`crate::RpcLogOptions::log_payloads`]. Only built with the
+//! `payload-log` feature, without it messages are never serialized.
+
+use crate::Client;
+use serde::Serialize;
+use serde_json::Value;
+
+const ENABLED: bool = cfg!(feature = "payload-log");
+
+/// What the value of a redacted field is replaced with.
+const MASK: &str = "***";
+
+impl Client {
+    /// Log `message`, the `direction` (`request` or `response`) of a call
+    /// to `method`, when payload logging is on. Used by the generated
+    /// service clients.
+    pub(crate) fn log_payload<T: Serialize>(
+        &self,
+        method: &str,
+        direction: &'static str,
+        message: &T,
+    ) {
+        if !ENABLED || !tracing::enabled!(tracing::Level::TRACE) {
+            return;
+        }
+        let Some(options) =
+            self.rpc_log.as_deref().filter(|options| options.log_payloads)
+        else {
+            return;
+        };
+        let payload = redacted(message, &options.redact);
+        tracing::trace!(method, direction, %payload, "rpc payload");
+    }
+}
+
+/// `message` as JSON, with the fields matching any of the `redact` paths
+/// replaced by [`MASK`].
+fn redacted<T: Serialize>(message: &T, redact: &[String]) -> String {
+    let mut value = match serde_json::to_value(message) {
+        Ok(value) => value,
+        Err(e) => return format!("<not serializable: {e}>"),
+    };
+    for path in redact {
+        mask(&mut value, &path.split('.').collect::<Vec<_>>());
+    }
+    value.to_string()
+}
+
+/// Mask the fields of `value` at `path`. Arrays are looked through, so a
+/// path applies to each element.
+fn mask(value: &mut Value, path: &[&str]) {
+    let Some((first, rest)) = path.split_first() else {
+        return;
+    };
+    if let Value::Array(items) = value {
+        for item in items {
+            mask(item, path);
+        }
+        return;
+    }
+    let Value::Object(fields) = value else {
+        return;
+    };
+
+    if *first == "*" {
+        for field in fields.values_mut() {
+            match rest.is_empty() {
+                true => *field = MASK.into(),
+                // any number of fields deeper...
+                false => mask(field, path),
+            }
+        }
+        // ...or none
+        mask(value, rest);
+        return;
+    }
+
+    for (name, field) in fields.iter_mut() {
+        if same_field(name, first) {
+            match rest.is_empty() {
+                true => *field = MASK.into(),
+                false => mask(field, rest),
+            }
+        }
+    }
+}
+
+/// Whether the JSON field `name`, which is lowerCamelCase, is the field
+/// `pattern` names, in either the JSON or the proto (snake_case) spelling.
+fn same_field(name: &str, pattern: &str) -> bool {
+    let fold = |s: &str| {
+        s.chars()
+            .filter(|c| *c != '_')
+            .map(|c| c.to_ascii_lowercase())
+            .collect::<String>()
+    };
+    fold(name) == fold(pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn redact(value: Value, paths: &[&str]) -> Value {
+        let paths: Vec<_> = paths.iter().map(|path| path.to_string()).collect();
+        serde_json::from_str(&redacted(&value, &paths)).unwrap()
+    }
+
+    #[test]
+    fn configured_fields_are_masked() {
+        let message = json!({
+            "token": "t0",
+            "cell": {
+                "name": "web",
+                "apiToken": "t1",
+                "env": [{ "name": "A", "secret": "s1" }, { "name": "B" }],
+            },
+        });
+
+        let redacted =
+            redact(message, &["*.token", "*.secret", "cell.api_token"]);
+
+        assert_eq!(
+            redacted,
+            json!({
+                "token": "***",
+                "cell": {
+                    "name": "web",
+                    "apiToken": "***",
+                    "env": [{ "name": "A", "secret": "***" }, { "name": "B" }],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn paths_only_match_where_they_point() {
+        let message = json!({ "token": "t0", "cell": { "token": "t1" } });
+
+        assert_eq!(
+            redact(message.clone(), &["cell.token"]),
+            json!({ "token": "t0", "cell": { "token": "***" } })
+        );
+        assert_eq!(
+            redact(message, &["cell.*"]),
+            json!({ "token": "t0", "cell": { "token": "***" } })
+        );
+    }
+}
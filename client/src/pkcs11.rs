@@ -0,0 +1,407 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Client keys that never leave a PKCS#11 token, see
+//! [`AuthConfig::pkcs11`](crate::AuthConfig::pkcs11).
+//!
+//! Any module implementing PKCS#11 2.40 works, such as SoftHSM 2
+//! (`libsofthsm2.so`), OpenSC (`opensc-pkcs11.so`), the YubiKey PIV module
+//! (`libykcs11.so`) or the client library of a network HSM. The key must be
+//! an EC key on P-256 or P-384, signed with `CKM_ECDSA`, or an RSA key,
+//! signed with `CKM_SHA256_RSA_PKCS_PSS` and friends as TLS 1.3 requires.
+//! Hashing for ECDSA happens in the client, so tokens without the combined
+//! `CKM_ECDSA_SHA256` mechanisms work too.
+
+use crate::config::Pkcs11Key;
+use anyhow::{anyhow, Context, Result};
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::error::{Error as Pkcs11Error, RvError};
+use cryptoki::mechanism::rsa::{PkcsMgfType, PkcsPssParams};
+use cryptoki::mechanism::{Mechanism, MechanismType};
+use cryptoki::object::{
+    Attribute, AttributeType, KeyType, ObjectClass, ObjectHandle,
+};
+use cryptoki::session::{Session, UserType};
+use cryptoki::types::AuthPin;
+use secrecy::ExposeSecret;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio_rustls::rustls::sign::{Signer, SigningKey};
+use tokio_rustls::rustls::{self, SignatureAlgorithm, SignatureScheme};
+use x509_certificate::{DigestAlgorithm, X509Certificate};
+
+/// `ecParameters` of the supported curves, as DER encoded OIDs.
+const P256: &[u8] =
+    &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const P384: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22];
+
+/// Modules stay loaded and initialized once opened. Finalizing a module
+/// would end the sessions of every other key opened through it, as those of
+/// connections still using the previous identity after a cert reload.
+static MODULES: Mutex<BTreeMap<String, Pkcs11>> = Mutex::new(BTreeMap::new());
+
+/// What [`open`] found on the token.
+pub(crate) struct Opened {
+    pub(crate) key: Arc<dyn SigningKey>,
+    /// The certificate with the ID of the key, as PEM, if the token has
+    /// one.
+    pub(crate) cert_pem: Option<Vec<u8>>,
+}
+
+/// Log in to the token of `config` and look up its key. Blocks on the
+/// token.
+pub(crate) fn open(config: &Pkcs11Key) -> Result<Opened> {
+    let id = parse_hex(&config.key_id).with_context(|| {
+        format!("auth.pkcs11.key_id '{}' is not hex", config.key_id)
+    })?;
+    let module = module(&config.module)?;
+
+    let slot = module
+        .get_slots_with_token()
+        .context("failed to list PKCS#11 slots")?
+        .into_iter()
+        .find(|slot| slot.id() == config.slot)
+        .ok_or_else(|| {
+            anyhow!(
+                "no PKCS#11 token in slot {} of '{}'",
+                config.slot,
+                config.module
+            )
+        })?;
+    let session = module
+        .open_ro_session(slot)
+        .context("failed to open a PKCS#11 session")?;
+    if let Some(pin) = &config.pin {
+        let pin = AuthPin::new(pin.expose_secret().clone());
+        match session.login(UserType::User, Some(&pin)) {
+            Ok(())
+            | Err(Pkcs11Error::Pkcs11(RvError::UserAlreadyLoggedIn, _)) => {}
+            Err(e) => {
+                return Err(e).context("failed to log in to the PKCS#11 token")
+            }
+        }
+    }
+
+    let key =
+        find(&session, ObjectClass::PRIVATE_KEY, &id)?.ok_or_else(|| {
+            anyhow!(
+                "no private key with CKA_ID {} on the PKCS#11 token",
+                config.key_id
+            )
+        })?;
+    let kind = key_kind(&session, key)?;
+    let cert_pem = match find(&session, ObjectClass::CERTIFICATE, &id)? {
+        Some(cert) => Some(cert_pem(&session, cert)?),
+        None => None,
+    };
+
+    let key = TokenKey { session: Arc::new(Mutex::new(session)), key, kind };
+    Ok(Opened { key: Arc::new(key), cert_pem })
+}
+
+fn module(path: &str) -> Result<Pkcs11> {
+    let mut modules = MODULES.lock().expect("pkcs11 module lock poisoned");
+    if let Some(module) = modules.get(path) {
+        return Ok(module.clone());
+    }
+
+    let module = Pkcs11::new(path)
+        .with_context(|| format!("failed to load PKCS#11 module '{path}'"))?;
+    match module.initialize(CInitializeArgs::OsThreads) {
+        // initialized by another library in this process
+        Ok(())
+        | Err(Pkcs11Error::Pkcs11(RvError::CryptokiAlreadyInitialized, _)) => {}
+        Err(e) => {
+            return Err(e).with_context(|| {
+                format!("failed to initialize PKCS#11 module '{path}'")
+            })
+        }
+    }
+    let _ = modules.insert(path.to_string(), module.clone());
+    Ok(module)
+}
+
+fn find(
+    session: &Session,
+    class: ObjectClass,
+    id: &[u8],
+) -> Result<Option<ObjectHandle>> {
+    let found = session
+        .find_objects(&[Attribute::Class(class), Attribute::Id(id.to_vec())])
+        .context("failed to search the PKCS#11 token")?;
+    Ok(found.into_iter().next())
+}
+
+fn key_kind(session: &Session, key: ObjectHandle) -> Result<KeyKind> {
+    let attribute = |kind| {
+        session
+            .get_attributes(key, &[kind])
+            .context("failed to read the PKCS#11 key")
+            .map(|attributes| attributes.into_iter().next())
+    };
+
+    match attribute(AttributeType::KeyType)? {
+        Some(Attribute::KeyType(KeyType::RSA)) => Ok(KeyKind::Rsa),
+        Some(Attribute::KeyType(KeyType::EC)) => {
+            match attribute(AttributeType::EcParams)? {
+                Some(Attribute::EcParams(params)) if params == P256 => {
+                    Ok(KeyKind::EcP256)
+                }
+                Some(Attribute::EcParams(params)) if params == P384 => {
+                    Ok(KeyKind::EcP384)
+                }
+                _ => Err(anyhow!(
+                    "the PKCS#11 key is on a curve other than P-256 or P-384"
+                )),
+            }
+        }
+        Some(Attribute::KeyType(other)) => {
+            Err(anyhow!("PKCS#11 keys of type {other} are not supported"))
+        }
+        _ => Err(anyhow!("the PKCS#11 key has no key type")),
+    }
+}
+
+fn cert_pem(session: &Session, cert: ObjectHandle) -> Result<Vec<u8>> {
+    let value = session
+        .get_attributes(cert, &[AttributeType::Value])
+        .context("failed to read the certificate on the PKCS#11 token")?;
+    let Some(Attribute::Value(der)) = value.into_iter().next() else {
+        return Err(anyhow!("the certificate on the PKCS#11 token is empty"));
+    };
+    let cert = X509Certificate::from_der(der)
+        .context("failed to parse the certificate on the PKCS#11 token")?;
+    let pem = cert
+        .encode_pem()
+        .context("failed to encode the certificate on the PKCS#11 token")?;
+    Ok(pem.into_bytes())
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.trim();
+    if hex.is_empty() || hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy)]
+enum KeyKind {
+    Rsa,
+    EcP256,
+    EcP384,
+}
+
+impl KeyKind {
+    /// The schemes the key can sign with, most preferred first.
+    fn schemes(self) -> &'static [SignatureScheme] {
+        match self {
+            KeyKind::Rsa => &[
+                SignatureScheme::RSA_PSS_SHA256,
+                SignatureScheme::RSA_PSS_SHA384,
+                SignatureScheme::RSA_PSS_SHA512,
+                SignatureScheme::RSA_PKCS1_SHA256,
+            ],
+            KeyKind::EcP256 => &[SignatureScheme::ECDSA_NISTP256_SHA256],
+            KeyKind::EcP384 => &[SignatureScheme::ECDSA_NISTP384_SHA384],
+        }
+    }
+}
+
+/// A private key on a token. Sessions are not safe to share between
+/// threads, hence the lock.
+#[derive(Clone)]
+struct TokenKey {
+    session: Arc<Mutex<Session>>,
+    key: ObjectHandle,
+    kind: KeyKind,
+}
+
+impl SigningKey for TokenKey {
+    fn choose_scheme(
+        &self,
+        offered: &[SignatureScheme],
+    ) -> Option<Box<dyn Signer>> {
+        let scheme = *self
+            .kind
+            .schemes()
+            .iter()
+            .find(|scheme| offered.contains(scheme))?;
+        Some(Box::new(TokenSigner { key: self.clone(), scheme }))
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        match self.kind {
+            KeyKind::Rsa => SignatureAlgorithm::RSA,
+            KeyKind::EcP256 | KeyKind::EcP384 => SignatureAlgorithm::ECDSA,
+        }
+    }
+}
+
+struct TokenSigner {
+    key: TokenKey,
+    scheme: SignatureScheme,
+}
+
+impl Signer for TokenSigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, rustls::Error> {
+        let pss = |hash_alg, mgf, s_len: u64| PkcsPssParams {
+            hash_alg,
+            mgf,
+            s_len: s_len.into(),
+        };
+        let ecdsa = |digest: DigestAlgorithm| {
+            (Mechanism::Ecdsa, digest.digest_data(message))
+        };
+        let (mechanism, data) = match self.scheme {
+            SignatureScheme::ECDSA_NISTP256_SHA256 => {
+                ecdsa(DigestAlgorithm::Sha256)
+            }
+            SignatureScheme::ECDSA_NISTP384_SHA384 => {
+                ecdsa(DigestAlgorithm::Sha384)
+            }
+            SignatureScheme::RSA_PSS_SHA256 => (
+                Mechanism::Sha256RsaPkcsPss(pss(
+                    MechanismType::SHA256,
+                    PkcsMgfType::MGF1_SHA256,
+                    32,
+                )),
+                message.to_vec(),
+            ),
+            SignatureScheme::RSA_PSS_SHA384 => (
+                Mechanism::Sha384RsaPkcsPss(pss(
+                    MechanismType::SHA384,
+                    PkcsMgfType::MGF1_SHA384,
+                    48,
+                )),
+                message.to_vec(),
+            ),
+            SignatureScheme::RSA_PSS_SHA512 => (
+                Mechanism::Sha512RsaPkcsPss(pss(
+                    MechanismType::SHA512,
+                    PkcsMgfType::MGF1_SHA512,
+                    64,
+                )),
+                message.to_vec(),
+            ),
+            SignatureScheme::RSA_PKCS1_SHA256 => {
+                (Mechanism::Sha256RsaPkcs, message.to_vec())
+            }
+            other => {
+                return Err(rustls::Error::General(format!(
+                    "PKCS#11 key cannot sign with {other:?}"
+                )))
+            }
+        };
+
+        let session =
+            self.key.session.lock().expect("pkcs11 session lock poisoned");
+        let signature =
+            session.sign(&mechanism, self.key.key, &data).map_err(|e| {
+                rustls::Error::General(format!("PKCS#11 signing failed: {e}"))
+            })?;
+        match mechanism {
+            Mechanism::Ecdsa => ecdsa_der(&signature),
+            _ => Ok(signature),
+        }
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+}
+
+/// The `r || s` signature `CKM_ECDSA` returns, encoded as the DER
+/// `Ecdsa-Sig-Value` TLS expects. Signatures of P-256 and P-384 keys are
+/// always short enough for single byte lengths, anything else a token
+/// returns is rejected.
+fn ecdsa_der(raw: &[u8]) -> Result<Vec<u8>, rustls::Error> {
+    if raw.is_empty() || raw.len() % 2 != 0 || raw.len() > MAX_ECDSA_BYTES {
+        return Err(rustls::Error::General(format!(
+            "PKCS#11 returned a malformed ECDSA signature of {} bytes",
+            raw.len()
+        )));
+    }
+    let (r, s) = raw.split_at(raw.len() / 2);
+    let mut body = der_integer(r);
+    body.extend(der_integer(s));
+
+    let mut der = vec![0x30, body.len() as u8];
+    der.extend(body);
+    Ok(der)
+}
+
+/// The length of a raw P-384 signature, the longest [`ecdsa_der`] encodes.
+const MAX_ECDSA_BYTES: usize = 2 * 48;
+
+fn der_integer(bytes: &[u8]) -> Vec<u8> {
+    let bytes = match bytes.iter().position(|&b| b != 0) {
+        Some(first) => &bytes[first..],
+        None => &[0],
+    };
+    // a set high bit would make the integer negative
+    let pad = bytes[0] & 0x80 != 0;
+
+    let mut der = vec![0x02, (bytes.len() + usize::from(pad)) as u8];
+    if pad {
+        der.push(0);
+    }
+    der.extend(bytes);
+    der
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_ecdsa_signatures_become_der() {
+        let mut raw = vec![0u8; 64];
+        raw[0] = 0x80; // r needs a leading zero
+        raw[31] = 0x01;
+        raw[62] = 0x12; // s has leading zeros to drop
+        raw[63] = 0x34;
+
+        let der = ecdsa_der(&raw).unwrap();
+
+        let mut r = vec![0x02, 33, 0x00, 0x80];
+        r.extend([0u8; 30]);
+        r.push(0x01);
+        let s = [0x02, 2, 0x12, 0x34];
+        assert_eq!(der[..2], [0x30, (r.len() + s.len()) as u8]);
+        assert_eq!(der[2..2 + r.len()], r[..]);
+        assert_eq!(der[2 + r.len()..], s);
+    }
+
+    #[test]
+    fn malformed_ecdsa_signatures_are_rejected() {
+        assert!(ecdsa_der(&[]).is_err());
+        assert!(ecdsa_der(&[0x01; 63]).is_err());
+        assert!(ecdsa_der(&[0x01; 2 * 66]).is_err());
+
+        // zero integers keep a single zero byte
+        assert_eq!(ecdsa_der(&[0; 2]).unwrap(), [0x30, 6, 2, 1, 0, 2, 1, 0]);
+    }
+
+    #[test]
+    fn key_ids_are_hex() {
+        assert_eq!(parse_hex("01aF"), Some(vec![0x01, 0xaf]));
+        assert_eq!(parse_hex("abc"), None);
+        assert_eq!(parse_hex("zz"), None);
+    }
+}
@@ -0,0 +1,139 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Connecting in two phases: every local step first, so the result can be
+//! shown and approved before a socket is opened.
+
+use crate::cert_store::CertStore;
+use crate::client::{CertSource, Result};
+use crate::config::ca_fetch;
+use crate::connector::ConnectState;
+use crate::namespace::Namespace;
+use crate::tls::{TlsDebugInfo, TlsOptions};
+use crate::{AuraeConfig, Client, X509Details};
+use anyhow::anyhow;
+use std::panic::Location;
+use std::sync::Arc;
+
+/// A config whose certs are loaded and checked and whose TLS config is
+/// built, but which has not connected, see [`Client::prepare`].
+#[derive(Debug)]
+pub struct PreparedClient {
+    config: AuraeConfig,
+    certs: Arc<CertStore>,
+    created_at: &'static Location<'static>,
+}
+
+impl Client {
+    /// Run every local step of [`Client::new`]: read and check the certs,
+    /// build the TLS config and check the rest of `config`, without opening
+    /// a socket. [`PreparedClient::connect`] then connects with the result,
+    /// once the caller approved of what the [`PreparedClient`] shows.
+    ///
+    /// A server root CA fetched from a URL would need the network, so
+    /// `auth.ca_crt` must be a file.
+    #[track_caller]
+    pub fn prepare(
+        config: AuraeConfig,
+    ) -> impl std::future::Future<Output = Result<PreparedClient>> {
+        let created_at = Location::caller();
+        async move {
+            if ca_fetch::is_url(&config.auth.ca_crt) {
+                return Err(anyhow!(
+                    "auth.ca_crt is the URL '{}', which preparing a client does not fetch",
+                    config.auth.ca_crt
+                )
+                .into());
+            }
+            let _ = config
+                .connect
+                .default_namespace
+                .as_deref()
+                .map(Namespace::new)
+                .transpose()?;
+            let _ = config
+                .system
+                .socket
+                .clone()
+                .normalized()
+                .map_err(anyhow::Error::from)?;
+
+            let tls = TlsOptions::new(&config.connect)?;
+            let certs =
+                Arc::new(CertStore::load(config.auth.clone(), tls).await?);
+            Ok(PreparedClient { config, certs, created_at })
+        }
+    }
+}
+
+impl PreparedClient {
+    /// The config being connected with.
+    pub fn config(&self) -> &AuraeConfig {
+        &self.config
+    }
+
+    /// Details of the client certificate the client will authenticate
+    /// with.
+    pub fn client_cert_details(&self) -> Option<X509Details> {
+        self.certs.details()
+    }
+
+    /// Details of the first certificate in the server root CA bundle.
+    pub fn server_ca_details(&self) -> Option<X509Details> {
+        self.certs.ca()
+    }
+
+    /// What the TLS config was built from, as for [`Client::tls_debug`].
+    pub fn tls_debug(&self) -> TlsDebugInfo {
+        (*self.certs.tls().debug).clone()
+    }
+
+    /// Connect as [`Client::new`] does, with the certs and TLS config read
+    /// by [`Client::prepare`]. Any failure now is a network one, or the
+    /// server failing the checks.
+    pub async fn connect(self) -> Result<Client> {
+        Client::connect_config(
+            self.config,
+            CertSource::Prepared(self.certs),
+            self.created_at,
+            ConnectState::default(),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn urls_are_not_fetched() {
+        let config = AuraeConfig::parse_from_toml(
+            r#"
+[auth]
+ca_crt = "https://ca.example.com/ca.crt"
+client_crt = "/nonexistent/client.crt"
+client_key = "/nonexistent/client.key"
+
+[system]
+socket = "/var/run/aurae/aurae.sock"
+"#,
+        )
+        .unwrap();
+
+        let err = Client::prepare(config).await.unwrap_err();
+        assert!(err.to_string().contains("does not fetch"), "{err}");
+    }
+}
@@ -0,0 +1,141 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! A guardrail for sessions that should observe auraed without changing
+//! anything, see [`crate::ConnectOptions::read_only`].
+
+use crate::{Client, ClientError};
+use std::sync::Arc;
+
+/// Method path prefixes of the RPCs shipped with this client that change
+/// state on the server.
+pub(crate) const MUTATING_METHODS: &[&str] = &[
+    "/aurae.cells.v0.CellService/Allocate",
+    "/aurae.cells.v0.CellService/Free",
+    "/aurae.cells.v0.CellService/Start",
+    "/aurae.cells.v0.CellService/Stop",
+    "/aurae.vms.v0.VmService/Allocate",
+    "/aurae.vms.v0.VmService/Free",
+    "/aurae.vms.v0.VmService/Start",
+    "/aurae.vms.v0.VmService/Stop",
+    "/runtime.v1.RuntimeService/RunPodSandbox",
+    "/runtime.v1.RuntimeService/StopPodSandbox",
+    "/runtime.v1.RuntimeService/RemovePodSandbox",
+    "/runtime.v1.RuntimeService/CreateContainer",
+    "/runtime.v1.RuntimeService/StartContainer",
+    "/runtime.v1.RuntimeService/StopContainer",
+    "/runtime.v1.RuntimeService/RemoveContainer",
+    "/runtime.v1.RuntimeService/UpdateContainerResources",
+    "/runtime.v1.RuntimeService/ReopenContainerLog",
+    "/runtime.v1.RuntimeService/ExecSync",
+    "/runtime.v1.RuntimeService/Exec",
+    "/runtime.v1.RuntimeService/Attach",
+    "/runtime.v1.RuntimeService/PortForward",
+    "/runtime.v1.RuntimeService/UpdateRuntimeConfig",
+    "/runtime.v1.RuntimeService/CheckpointContainer",
+    "/runtime.v1.ImageService/PullImage",
+    "/runtime.v1.ImageService/RemoveImage",
+];
+
+/// Whether RPCs are checked, and the prefixes they are checked against.
+#[derive(Debug, Clone)]
+pub(crate) struct ReadOnly {
+    pub(crate) enabled: bool,
+    mutating: Arc<Vec<String>>,
+}
+
+impl ReadOnly {
+    pub(crate) fn new(enabled: bool, mutating: Vec<String>) -> Self {
+        Self { enabled, mutating: Arc::new(mutating) }
+    }
+
+    fn check(&self, method: &str) -> Result<(), ClientError> {
+        if self.enabled && self.is_mutating(method) {
+            return Err(ClientError::ReadOnlyViolation {
+                method: method.into(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether `method` is one of the mutating methods, enabled or not.
+    pub(crate) fn is_mutating(&self, method: &str) -> bool {
+        self.mutating.iter().any(|prefix| method.starts_with(prefix.as_str()))
+    }
+}
+
+impl Default for ReadOnly {
+    fn default() -> Self {
+        let options = crate::ConnectOptions::default();
+        Self::new(options.read_only, options.mutating_methods)
+    }
+}
+
+impl Client {
+    /// Whether this handle rejects mutating RPCs.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only().enabled
+    }
+
+    /// Check the full method path of an RPC (`/package.Service/Method`)
+    /// against the read only mode, before it hits the wire.
+    ///
+    /// The generated service clients call this for every RPC, and fail
+    /// rejected calls with `PERMISSION_DENIED` carrying this error's
+    /// message. Callers issuing their own requests, e.g. over a channel of
+    /// [`Client::from_channel`], can use it to get the same guarantee.
+    pub fn check_read_only(&self, method: &str) -> Result<(), ClientError> {
+        self.read_only().check(method)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_mutating_methods_are_rejected() {
+        let read_only = ReadOnly { enabled: true, ..ReadOnly::default() };
+
+        let err = read_only
+            .check("/aurae.cells.v0.CellService/Allocate")
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ClientError::ReadOnlyViolation { method }
+                if method == "/aurae.cells.v0.CellService/Allocate"
+        ));
+        assert!(read_only.check("/aurae.cells.v0.CellService/List").is_ok());
+        assert!(read_only
+            .check("/aurae.observe.v0.ObserveService/GetSubProcessStream")
+            .is_ok());
+    }
+
+    #[test]
+    fn prefixes_cover_whole_services() {
+        let read_only =
+            ReadOnly::new(true, vec!["/aurae.vms.v0.VmService/".into()]);
+
+        assert!(read_only.check("/aurae.vms.v0.VmService/List").is_err());
+        assert!(read_only.check("/aurae.cells.v0.CellService/Free").is_ok());
+    }
+
+    #[test]
+    fn disabled_mode_allows_everything() {
+        assert!(ReadOnly::default()
+            .check("/aurae.cells.v0.CellService/Free")
+            .is_ok());
+    }
+}
@@ -0,0 +1,219 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Waiting for auraed to come up, for scripts that run while it boots.
+
+use crate::client::Result;
+use crate::connector::{self, ConnectState};
+use crate::grpc::health::health::HealthClient;
+use crate::{AuraeConfig, Client, ClientError};
+use anyhow::anyhow;
+use proto::grpc::health::{
+    health_check_response::ServingStatus, HealthCheckRequest,
+};
+use std::future::Future;
+use std::io;
+use std::panic::Location;
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_rustls::rustls;
+use tonic::Code;
+
+/// The outcome of one connect and health check.
+enum Attempt {
+    Ready(Client),
+    /// Worth trying again, for the given reason.
+    NotYet(String),
+    Failed(ClientError),
+}
+
+impl Client {
+    /// Connect with `config` once auraed is up, trying every
+    /// `poll_interval` for up to `timeout`, for startup scripts that may
+    /// run before the daemon.
+    ///
+    /// Each try connects and runs a health check, returning the client once
+    /// auraed reports itself serving (or does not implement health checks).
+    /// Failures that go away once auraed is up are retried: the socket
+    /// missing or refusing connections, connect timeouts, and health checks
+    /// failing with `UNAVAILABLE` or reporting `NOT_SERVING`. Anything else,
+    /// such as unreadable certs or a server certificate that fails
+    /// verification, is returned at once. After `timeout`, the error says
+    /// why the last try failed.
+    #[track_caller]
+    pub fn ping_until_ready(
+        config: AuraeConfig,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> impl Future<Output = Result<Self>> {
+        let created_at = Location::caller();
+        async move {
+            let deadline = Instant::now() + timeout;
+            let mut last = None;
+            loop {
+                let attempt = attempt(config.clone(), created_at);
+                match tokio::time::timeout_at(deadline, attempt).await {
+                    Ok(Attempt::Ready(client)) => return Ok(client),
+                    Ok(Attempt::Failed(e)) => return Err(e),
+                    Ok(Attempt::NotYet(reason)) => last = Some(reason),
+                    Err(_) => {}
+                }
+                if Instant::now() + poll_interval >= deadline {
+                    break;
+                }
+                tokio::time::sleep(poll_interval).await;
+            }
+
+            let last = last.unwrap_or_else(|| "connecting hung".into());
+            Err(anyhow!("auraed was not ready within {timeout:?}: {last}")
+                .into())
+        }
+    }
+}
+
+async fn attempt(
+    config: AuraeConfig,
+    created_at: &'static Location<'static>,
+) -> Attempt {
+    let client =
+        match Client::new_at(config, created_at, ConnectState::default()).await
+        {
+            Ok(client) => client,
+            Err(e) if is_transient(&e) => {
+                return Attempt::NotYet(e.to_string())
+            }
+            Err(e) => return Attempt::Failed(e),
+        };
+
+    let req = HealthCheckRequest { service: String::new() };
+    match client.check(req).await {
+        Ok(res) => match res.get_ref().status() {
+            ServingStatus::Serving => Attempt::Ready(client),
+            status => Attempt::NotYet(format!("auraed reports {status:?}")),
+        },
+        Err(status) if status.code() == Code::Unimplemented => {
+            Attempt::Ready(client)
+        }
+        Err(status)
+            if matches!(
+                status.code(),
+                Code::Unavailable | Code::DeadlineExceeded
+            ) =>
+        {
+            Attempt::NotYet(format!(
+                "health check failed: {}",
+                status.message()
+            ))
+        }
+        Err(status) => Attempt::Failed(
+            anyhow!("health check failed: {}", status.message()).into(),
+        ),
+    }
+}
+
+/// Whether connecting failed in a way that may go away once auraed is up.
+fn is_transient(e: &ClientError) -> bool {
+    let e = match e {
+        ClientError::ConnectTimeout { .. } | ClientError::DnsTimeout { .. } => {
+            return true
+        }
+        ClientError::ConnectionError(e) => e,
+        _ => return false,
+    };
+    if connector::find_cause::<rustls::Error>(e).is_some() {
+        return false;
+    }
+    match connector::find_cause::<io::Error>(e) {
+        Some(e) => matches!(
+            e.kind(),
+            io::ErrorKind::NotFound
+                | io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::TimedOut
+        ),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{client_material, serve_health_at, test_ca};
+    use std::path::Path;
+
+    /// A config for the socket in `dir`, with the certs of a client of
+    /// `ca` written there.
+    fn config(dir: &Path, ca: &rcgen::Certificate) -> AuraeConfig {
+        std::fs::create_dir_all(dir).unwrap();
+        let material = client_material(ca, "boot script");
+        let file = |name: &str, contents: &[u8]| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        AuraeConfig::from_options(
+            file("ca.crt", &material.server_root_ca_cert),
+            file("client.crt", &material.client_cert),
+            file("client.key", &material.client_key),
+            dir.join("aurae.sock").to_string_lossy(),
+        )
+    }
+
+    #[tokio::test]
+    async fn waits_for_auraed_to_come_up() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let ca = test_ca("test ca");
+        let config = config(dir, &ca);
+        let socket = dir.join("aurae.sock");
+
+        let started = Instant::now();
+        let _late = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            serve_health_at(&ca, &socket);
+        });
+        let client = Client::ping_until_ready(
+            config,
+            Duration::from_secs(10),
+            Duration::from_millis(50),
+        )
+        .await
+        .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        client.health_check().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn untrusted_servers_fail_without_waiting() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let config = config(dir, &test_ca("our ca"));
+        serve_health_at(&test_ca("their ca"), &dir.join("aurae.sock"));
+
+        let started = Instant::now();
+        let err = Client::ping_until_ready(
+            config,
+            Duration::from_secs(30),
+            Duration::from_millis(50),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, ClientError::TlsVerification { .. }), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
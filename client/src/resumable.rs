@@ -0,0 +1,366 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Server streams that survive transient disconnects.
+//!
+//! [`Client::resumable`] re-opens a server stream whenever it fails with
+//! `UNAVAILABLE` or `RESOURCE_EXHAUSTED`, backing off exponentially while it
+//! keeps failing, and tells the consumer it happened with
+//! [`StreamEvent::Reconnected`].
+//!
+//! Delivery across a reconnect depends on the RPC:
+//!
+//! - RPCs whose request carries a cursor ([`Resume::resume_after`] returns a
+//!   request) are at-least-once: the stream is re-requested from the last
+//!   message received, and the server may send that position again.
+//!   Consumers should be idempotent.
+//! - RPCs without a cursor are at-most-once: messages sent while the stream
+//!   was down are lost, which is reported with `gap_possible: true`.
+//!
+//! None of the current auraed streaming RPCs accept a cursor.
+
+use crate::Client;
+use futures_util::Stream;
+use proto::cri::{ContainerEventResponse, GetEventsRequest};
+use proto::observe::{
+    GetAuraeDaemonLogStreamRequest, GetAuraeDaemonLogStreamResponse,
+    GetPosixSignalsStreamRequest, GetPosixSignalsStreamResponse,
+    GetSubProcessStreamRequest, GetSubProcessStreamResponse,
+};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tonic::{Code, Response, Status, Streaming};
+
+/// Messages buffered between the connection and a slow consumer.
+const STREAM_BUFFER: usize = 64;
+
+/// Pause before re-opening a stream that failed, doubled for every failure
+/// in a row up to [`MAX_RECONNECT_INTERVAL`].
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(250);
+const MAX_RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// An item from a [`ResumableStream`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent<T> {
+    Message(T),
+    /// The stream dropped and was re-opened. Unless the RPC resumed from a
+    /// cursor, messages may have been missed while it was down.
+    Reconnected {
+        gap_possible: bool,
+    },
+}
+
+/// How a streaming request is re-issued after a disconnect.
+pub trait Resume: Clone + Send + 'static {
+    type Item: Send + 'static;
+
+    /// The request that continues the stream after `last`, the last message
+    /// received (or `None` if there was none). RPCs without a cursor return
+    /// `None`, and are re-issued with the original request.
+    fn resume_after(&self, last: Option<&Self::Item>) -> Option<Self>;
+}
+
+macro_rules! no_cursor {
+    ($($req:ty => $res:ty),* $(,)?) => {
+        $(
+            impl Resume for $req {
+                type Item = $res;
+
+                fn resume_after(&self, _: Option<&Self::Item>) -> Option<Self> {
+                    None
+                }
+            }
+        )*
+    };
+}
+
+no_cursor!(
+    GetAuraeDaemonLogStreamRequest => GetAuraeDaemonLogStreamResponse,
+    GetSubProcessStreamRequest => GetSubProcessStreamResponse,
+    GetPosixSignalsStreamRequest => GetPosixSignalsStreamResponse,
+    GetEventsRequest => ContainerEventResponse,
+);
+
+/// A server stream that is re-opened after transient failures. Returned by
+/// [`Client::resumable`].
+///
+/// The stream ends when the server closes it, or with an error for failures
+/// that a reconnect would not fix (e.g. `PERMISSION_DENIED`).
+#[derive(Debug)]
+pub struct ResumableStream<T> {
+    rx: mpsc::Receiver<Result<StreamEvent<T>, Status>>,
+}
+
+impl<T> ResumableStream<T> {
+    pub async fn next(&mut self) -> Option<Result<StreamEvent<T>, Status>> {
+        self.rx.recv().await
+    }
+}
+
+impl<T> Stream for ResumableStream<T> {
+    type Item = Result<StreamEvent<T>, Status>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Client {
+    /// Open a server stream with `open`, re-opening it on transient errors.
+    ///
+    /// ```ignore
+    /// let mut logs = client.resumable(
+    ///     GetAuraeDaemonLogStreamRequest {},
+    ///     |client, req| async move { client.get_aurae_daemon_log_stream(req).await },
+    /// );
+    /// ```
+    pub fn resumable<Req, F, Fut>(
+        &self,
+        req: Req,
+        open: F,
+    ) -> ResumableStream<Req::Item>
+    where
+        Req: Resume,
+        F: Fn(Client, Req) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Response<Streaming<Req::Item>>, Status>>
+            + Send
+            + 'static,
+    {
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        let _ = tokio::spawn(forward(self.clone(), req, open, tx));
+        ResumableStream { rx }
+    }
+}
+
+async fn forward<Req, F, Fut>(
+    client: Client,
+    req: Req,
+    open: F,
+    tx: mpsc::Sender<Result<StreamEvent<Req::Item>, Status>>,
+) where
+    Req: Resume,
+    F: Fn(Client, Req) -> Fut,
+    Fut: Future<Output = Result<Response<Streaming<Req::Item>>, Status>>,
+{
+    let mut resume = req.resume_after(None);
+    let mut dropped = false;
+    let mut backoff = RECONNECT_INTERVAL;
+
+    loop {
+        let (current, gap_possible) = match &resume {
+            Some(resume) => (resume.clone(), false),
+            None => (req.clone(), true),
+        };
+
+        let mut stream = match open(client.clone(), current).await {
+            Ok(res) => res.into_inner(),
+            Err(status) if is_transient(&status) => {
+                dropped = true;
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_RECONNECT_INTERVAL);
+                if tx.is_closed() {
+                    return;
+                }
+                continue;
+            }
+            Err(status) => {
+                let _ = tx.send(Err(status)).await;
+                return;
+            }
+        };
+
+        if dropped {
+            dropped = false;
+            let event = StreamEvent::Reconnected { gap_possible };
+            if tx.send(Ok(event)).await.is_err() {
+                return;
+            }
+        }
+
+        loop {
+            match stream.message().await {
+                Ok(Some(item)) => {
+                    backoff = RECONNECT_INTERVAL;
+                    if let Some(next) = req.resume_after(Some(&item)) {
+                        resume = Some(next);
+                    }
+                    if tx.send(Ok(StreamEvent::Message(item))).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(status) if is_transient(&status) => {
+                    dropped = true;
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_RECONNECT_INTERVAL);
+                    break;
+                }
+                Err(status) => {
+                    let _ = tx.send(Err(status)).await;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Failures a later attempt can get past: the connection dropping, or the
+/// server shedding load. `INTERNAL`, `UNKNOWN` and `CANCELLED` may be bugs
+/// or a caller's cancellation, which re-opening would only repeat.
+fn is_transient(status: &Status) -> bool {
+    matches!(status.code(), Code::Unavailable | Code::ResourceExhausted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observe::observe_service::ObserveServiceClient;
+    use crate::testing::serve_in_memory;
+    use proto::observe::observe_service_server::{
+        ObserveService, ObserveServiceServer,
+    };
+    use proto::observe::LogItem;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tonic::transport::Server;
+    use tonic::Request;
+
+    type Items<T> =
+        Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + 'static>>;
+
+    /// Sends the line `open <n>` on the `n`th daemon log stream, then fails
+    /// it with `failure` until `fail_times` streams have failed.
+    struct FlakyLogs {
+        opened: Arc<AtomicUsize>,
+        fail_times: usize,
+        failure: Code,
+    }
+
+    #[tonic::async_trait]
+    impl ObserveService for FlakyLogs {
+        type GetAuraeDaemonLogStreamStream =
+            Items<GetAuraeDaemonLogStreamResponse>;
+
+        async fn get_aurae_daemon_log_stream(
+            &self,
+            _request: Request<GetAuraeDaemonLogStreamRequest>,
+        ) -> Result<Response<Self::GetAuraeDaemonLogStreamStream>, Status>
+        {
+            let n = self.opened.fetch_add(1, Ordering::SeqCst);
+            let line = GetAuraeDaemonLogStreamResponse {
+                item: Some(LogItem {
+                    line: format!("open {n}"),
+                    ..LogItem::default()
+                }),
+            };
+            let mut items = vec![Ok(line)];
+            if n < self.fail_times {
+                items.push(Err(Status::new(self.failure, "stream broke")));
+            }
+            Ok(Response::new(Box::pin(futures_util::stream::iter(items))))
+        }
+
+        type GetSubProcessStreamStream = Items<GetSubProcessStreamResponse>;
+
+        async fn get_sub_process_stream(
+            &self,
+            _request: Request<GetSubProcessStreamRequest>,
+        ) -> Result<Response<Self::GetSubProcessStreamStream>, Status> {
+            Err(Status::unimplemented("get_sub_process_stream"))
+        }
+
+        type GetPosixSignalsStreamStream = Items<GetPosixSignalsStreamResponse>;
+
+        async fn get_posix_signals_stream(
+            &self,
+            _request: Request<GetPosixSignalsStreamRequest>,
+        ) -> Result<Response<Self::GetPosixSignalsStreamStream>, Status>
+        {
+            Err(Status::unimplemented("get_posix_signals_stream"))
+        }
+    }
+
+    fn logs(
+        fail_times: usize,
+        failure: Code,
+    ) -> ResumableStream<GetAuraeDaemonLogStreamResponse> {
+        let client = serve_in_memory(Server::builder().add_service(
+            ObserveServiceServer::new(FlakyLogs {
+                opened: Arc::default(),
+                fail_times,
+                failure,
+            }),
+        ));
+        client.resumable(
+            GetAuraeDaemonLogStreamRequest {},
+            |client, req| async move {
+                client.get_aurae_daemon_log_stream(req).await
+            },
+        )
+    }
+
+    fn line(
+        event: Option<
+            Result<StreamEvent<GetAuraeDaemonLogStreamResponse>, Status>,
+        >,
+    ) -> String {
+        match event {
+            Some(Ok(StreamEvent::Message(res))) => res.item.unwrap().line,
+            other => panic!("expected a message, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn unavailable_streams_are_reopened() {
+        let mut logs = logs(1, Code::Unavailable);
+
+        assert_eq!(line(logs.next().await), "open 0");
+        assert!(matches!(
+            logs.next().await,
+            Some(Ok(StreamEvent::Reconnected { gap_possible: true }))
+        ));
+        assert_eq!(line(logs.next().await), "open 1");
+        assert!(logs.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn other_failures_end_the_stream() {
+        let mut logs = logs(1, Code::Internal);
+
+        assert_eq!(line(logs.next().await), "open 0");
+        match logs.next().await {
+            Some(Err(status)) => assert_eq!(status.code(), Code::Internal),
+            other => panic!("expected the failure, got {other:?}"),
+        }
+        assert!(logs.next().await.is_none());
+    }
+
+    #[test]
+    fn only_connection_and_load_failures_are_transient() {
+        assert!(is_transient(&Status::unavailable("connection reset")));
+        assert!(is_transient(&Status::resource_exhausted("shed")));
+        assert!(!is_transient(&Status::internal("bug")));
+        assert!(!is_transient(&Status::unknown("unknown")));
+        assert!(!is_transient(&Status::cancelled("cancelled")));
+        assert!(!is_transient(&Status::permission_denied("nope")));
+        assert!(!is_transient(&Status::invalid_argument("bad pid")));
+    }
+}
@@ -0,0 +1,567 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Retries of unary RPCs, throttled by a budget shared across a client.
+
+use crate::clock::{Clock, TokioClock};
+use crate::config::RetryOptions;
+use crate::status_details::StatusDetails;
+use crate::Client;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tonic::{Code, Status};
+use tracing::debug;
+
+/// Upper bound on retries saved up from successful calls, so a long quiet
+/// period cannot fund a burst of retries.
+const MAX_EARNED_RETRIES: f64 = 100.0;
+/// Milliseconds to wait before retrying, from the gRPC retry design.
+const PUSHBACK_HEADER: &str = "grpc-retry-pushback-ms";
+/// Seconds to wait before retrying, as set by HTTP proxies.
+const RETRY_AFTER_HEADER: &str = "retry-after";
+
+/// Token bucket of retries, see [`RetryOptions`].
+#[derive(Debug)]
+pub(crate) struct RetryBudget {
+    options: RetryOptions,
+    state: Mutex<BudgetState>,
+    /// Refills the budget and times the backoff.
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug)]
+struct BudgetState {
+    /// Retries earned by successful calls.
+    earned: f64,
+    /// Retries from the `min_retries_per_sec` allowance, at most one second
+    /// worth.
+    reserve: f64,
+    refilled_at: Instant,
+}
+
+impl RetryBudget {
+    pub(crate) fn new(options: RetryOptions) -> Self {
+        Self::with_clock(options, Arc::new(TokioClock))
+    }
+
+    /// A budget reading the time from `clock`, for tests that check backoff
+    /// schedules without sleeping through them.
+    pub(crate) fn with_clock(
+        options: RetryOptions,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let reserve = f64::from(options.min_retries_per_sec);
+        Self {
+            options,
+            state: Mutex::new(BudgetState {
+                earned: 0.0,
+                reserve,
+                refilled_at: clock.now(),
+            }),
+            clock,
+        }
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().expect("retry budget lock poisoned");
+        state.earned =
+            (state.earned + self.options.retry_ratio).min(MAX_EARNED_RETRIES);
+    }
+
+    /// Take one retry from the budget, if there is one left.
+    pub(crate) fn try_withdraw(&self) -> bool {
+        self.try_withdraw_at(self.clock.now())
+    }
+
+    fn try_withdraw_at(&self, now: Instant) -> bool {
+        let mut state = self.state.lock().expect("retry budget lock poisoned");
+
+        let per_sec = f64::from(self.options.min_retries_per_sec);
+        let elapsed = now.saturating_duration_since(state.refilled_at);
+        state.reserve =
+            (state.reserve + elapsed.as_secs_f64() * per_sec).min(per_sec);
+        state.refilled_at = now;
+
+        if state.earned >= 1.0 {
+            state.earned -= 1.0;
+            true
+        } else if state.reserve >= 1.0 {
+            state.reserve -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Decides whether a failed call that is not retried by its code or
+/// pushback is retried anyway, see [`Client::with_retry_predicate`].
+#[derive(Clone)]
+pub(crate) struct RetryPredicate(
+    Arc<dyn Fn(&Status, &StatusDetails) -> bool + Send + Sync>,
+);
+
+impl fmt::Debug for RetryPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RetryPredicate")
+    }
+}
+
+/// What the server asked for in the pushback headers of a failed call.
+#[derive(Debug, PartialEq, Eq)]
+enum Pushback {
+    Wait(Duration),
+    /// A malformed or negative `grpc-retry-pushback-ms`, which means the call
+    /// must not be retried.
+    Stop,
+}
+
+impl Pushback {
+    fn of(status: &Status) -> Option<Self> {
+        let metadata = status.metadata();
+        if let Some(value) = metadata.get(PUSHBACK_HEADER) {
+            let ms = value.to_str().ok().and_then(|ms| ms.trim().parse().ok());
+            return Some(match ms {
+                Some(ms) => Pushback::Wait(Duration::from_millis(ms)),
+                None => Pushback::Stop,
+            });
+        }
+
+        // HTTP dates are not supported, only a number of seconds.
+        let seconds = metadata.get(RETRY_AFTER_HEADER)?.to_str().ok()?;
+        let seconds = seconds.trim().parse().ok()?;
+        Some(Pushback::Wait(Duration::from_secs(seconds)))
+    }
+}
+
+impl Client {
+    /// A handle to the same connection that also retries failures for which
+    /// `predicate` returns `true`, given the call's status and its decoded
+    /// `google.rpc.Status` details (empty when the server sent none).
+    ///
+    /// Retries stay within `connect.retry.max_attempts` and the retry
+    /// budget, and wait the usual backoff. Failures whose details carry a
+    /// `google.rpc.RetryInfo` are retried after its delay (capped at
+    /// `connect.retry.max_pushback`) without asking `predicate`, and a
+    /// negative `grpc-retry-pushback-ms` still stops retries. Mutating
+    /// methods are not retried, whatever `predicate` or the details say.
+    pub fn with_retry_predicate<F>(&self, predicate: F) -> Self
+    where
+        F: Fn(&Status, &StatusDetails) -> bool + Send + Sync + 'static,
+    {
+        let predicate = RetryPredicate(Arc::new(predicate));
+        self.with_retry_predicate_set(Some(predicate))
+    }
+
+    /// Run a unary `call` of `method`, retrying `UNAVAILABLE`, pushed back
+    /// failures, failures with a `google.rpc.RetryInfo` and those accepted
+    /// by the retry predicate, while attempts and the retry budget allow.
+    /// Mutating methods are called once, as a failed call may still have
+    /// taken effect. Used by the generated service clients.
+    pub(crate) async fn with_retries<Req, Res, F, Fut>(
+        &self,
+        method: &str,
+        req: Req,
+        call: F,
+    ) -> Result<Res, Status>
+    where
+        Req: Clone,
+        F: Fn(Req) -> Fut,
+        Fut: Future<Output = Result<Res, Status>>,
+    {
+        let mutating = self.read_only().is_mutating(method);
+        retry(self.retry_budget(), self.retry_predicate(), mutating, req, call)
+            .await
+    }
+}
+
+async fn retry<Req, Res, F, Fut>(
+    budget: &RetryBudget,
+    predicate: Option<&RetryPredicate>,
+    mutating: bool,
+    req: Req,
+    call: F,
+) -> Result<Res, Status>
+where
+    Req: Clone,
+    F: Fn(Req) -> Fut,
+    Fut: Future<Output = Result<Res, Status>>,
+{
+    let options = &budget.options;
+    let mut attempt = 1;
+
+    loop {
+        match call(req.clone()).await {
+            Ok(res) => {
+                budget.record_success();
+                return Ok(res);
+            }
+            Err(status) if !mutating && attempt < options.max_attempts => {
+                let details = StatusDetails::of(&status).unwrap_or_default();
+                let delay = match (
+                    status.code(),
+                    Pushback::of(&status),
+                    details.retry_delay,
+                ) {
+                    (_, Some(Pushback::Stop), _) => {
+                        debug!("server asked not to retry: {status}");
+                        return Err(status);
+                    }
+                    (
+                        Code::Unavailable | Code::ResourceExhausted,
+                        Some(Pushback::Wait(wait)),
+                        _,
+                    ) => wait.min(options.max_pushback),
+                    (_, _, Some(delay)) => delay.min(options.max_pushback),
+                    (Code::Unavailable, None, None) => {
+                        options.backoff * attempt
+                    }
+                    _ if predicate.is_some_and(|predicate| {
+                        (predicate.0)(&status, &details)
+                    }) =>
+                    {
+                        options.backoff * attempt
+                    }
+                    _ => return Err(status),
+                };
+
+                if !budget.try_withdraw() {
+                    debug!("retry budget exhausted, not retrying: {status}");
+                    return Err(status);
+                }
+
+                budget.clock.sleep(delay).await;
+                attempt += 1;
+            }
+            Err(status) => return Err(status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn budget(min_retries_per_sec: u32) -> RetryBudget {
+        RetryBudget::new(RetryOptions {
+            max_attempts: 3,
+            retry_ratio: 0.1,
+            min_retries_per_sec,
+            ..RetryOptions::default()
+        })
+    }
+
+    #[test]
+    fn sustained_failures_taper_off_to_the_minimum_rate() {
+        let budget = budget(5);
+        let start = Instant::now();
+
+        // 100 failing calls per second, each wanting a retry, for 10 seconds
+        let mut allowed_per_sec = Vec::new();
+        for sec in 0..10 {
+            let allowed = (0..100)
+                .filter(|i| {
+                    let now = start
+                        + Duration::from_secs(sec)
+                        + Duration::from_millis(i * 10);
+                    budget.try_withdraw_at(now)
+                })
+                .count();
+            allowed_per_sec.push(allowed);
+        }
+
+        // the first second also spends the initial reserve, after that only
+        // the per second allowance is available, never 100 retries
+        assert!(allowed_per_sec[0] <= 10);
+        assert!(allowed_per_sec[1..]
+            .iter()
+            .all(|allowed| (4..=6).contains(allowed)));
+    }
+
+    #[test]
+    fn successes_earn_retries() {
+        let budget = budget(0);
+        let now = Instant::now();
+        assert!(!budget.try_withdraw_at(now));
+
+        for _ in 0..25 {
+            budget.record_success();
+        }
+
+        assert!(budget.try_withdraw_at(now));
+        assert!(budget.try_withdraw_at(now));
+        assert!(!budget.try_withdraw_at(now));
+    }
+
+    /// A server that fails the first call with `status`, then succeeds.
+    async fn pushed_back_once(
+        status: Status,
+        backoff: Duration,
+    ) -> (u32, Duration) {
+        let budget = RetryBudget::new(RetryOptions {
+            max_attempts: 3,
+            backoff,
+            max_pushback: Duration::from_millis(50),
+            ..RetryOptions::default()
+        });
+        let calls = AtomicU32::new(0);
+        let started = Instant::now();
+
+        let res = retry(&budget, None, false, (), |()| {
+            let status = status.clone();
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match call {
+                    0 => Err(status),
+                    _ => Ok(()),
+                }
+            }
+        })
+        .await;
+
+        assert!(res.is_ok(), "{res:?}");
+        (calls.load(Ordering::SeqCst), started.elapsed())
+    }
+
+    fn with_header(
+        code: Code,
+        header: &'static str,
+        value: &'static str,
+    ) -> Status {
+        let mut status = Status::new(code, "slow down");
+        let _ = status.metadata_mut().insert(header, value.parse().unwrap());
+        status
+    }
+
+    #[tokio::test]
+    async fn pushback_replaces_the_backoff() {
+        let status =
+            with_header(Code::ResourceExhausted, PUSHBACK_HEADER, "10");
+
+        let (calls, elapsed) =
+            pushed_back_once(status, Duration::from_secs(60)).await;
+
+        assert_eq!(calls, 2);
+        assert!(elapsed >= Duration::from_millis(10), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn pushback_is_capped() {
+        let status =
+            with_header(Code::ResourceExhausted, RETRY_AFTER_HEADER, "3600");
+
+        let (calls, elapsed) =
+            pushed_back_once(status, Duration::from_secs(60)).await;
+
+        assert_eq!(calls, 2);
+        assert!(elapsed >= Duration::from_millis(50), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+    }
+
+    #[test]
+    fn pushback_headers_are_parsed() {
+        let wait = |code, header, value| {
+            Pushback::of(&with_header(code, header, value))
+        };
+
+        assert_eq!(
+            wait(Code::Unavailable, PUSHBACK_HEADER, "250"),
+            Some(Pushback::Wait(Duration::from_millis(250)))
+        );
+        assert_eq!(
+            wait(Code::Unavailable, RETRY_AFTER_HEADER, "2"),
+            Some(Pushback::Wait(Duration::from_secs(2)))
+        );
+        assert_eq!(
+            wait(Code::Unavailable, PUSHBACK_HEADER, "-1"),
+            Some(Pushback::Stop)
+        );
+        assert_eq!(
+            wait(
+                Code::Unavailable,
+                RETRY_AFTER_HEADER,
+                "Wed, 21 Oct 2015 07:28:00 GMT"
+            ),
+            None
+        );
+        assert_eq!(Pushback::of(&Status::unavailable("down")), None);
+    }
+
+    #[tokio::test]
+    async fn exhausted_without_pushback_is_not_retried() {
+        let budget = RetryBudget::new(RetryOptions {
+            max_attempts: 3,
+            ..RetryOptions::default()
+        });
+        let calls = AtomicU32::new(0);
+
+        let res: Result<(), _> = retry(&budget, None, false, (), |()| {
+            let _ = calls.fetch_add(1, Ordering::SeqCst);
+            async { Err(Status::resource_exhausted("quota")) }
+        })
+        .await;
+
+        assert_eq!(res.unwrap_err().code(), Code::ResourceExhausted);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retry_info_sets_the_delay() {
+        let status = crate::status_details::with_retry_info(
+            Code::Aborted,
+            Duration::from_millis(10),
+        );
+
+        let (calls, elapsed) =
+            pushed_back_once(status, Duration::from_secs(60)).await;
+
+        assert_eq!(calls, 2);
+        assert!(elapsed >= Duration::from_millis(10), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn the_predicate_retries_other_failures() {
+        let budget = RetryBudget::new(RetryOptions {
+            max_attempts: 3,
+            backoff: Duration::from_millis(1),
+            ..RetryOptions::default()
+        });
+        let predicate = RetryPredicate(Arc::new(|status: &Status, _: &_| {
+            status.message() == "conflict"
+        }));
+        let calls = AtomicU32::new(0);
+
+        let res: Result<(), _> =
+            retry(&budget, Some(&predicate), false, (), |()| {
+                let call = calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match call {
+                        0 => Err(Status::aborted("conflict")),
+                        _ => Err(Status::aborted("gone")),
+                    }
+                }
+            })
+            .await;
+
+        assert_eq!(res.unwrap_err().message(), "gone");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn mutating_methods_are_not_retried() {
+        let budget = budget(5);
+        let calls = AtomicU32::new(0);
+
+        let res: Result<(), _> = retry(&budget, None, true, (), |()| {
+            let _ = calls.fetch_add(1, Ordering::SeqCst);
+            async { Err(Status::unavailable("down")) }
+        })
+        .await;
+
+        assert_eq!(res.unwrap_err().code(), Code::Unavailable);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn details_and_the_predicate_do_not_retry_mutating_methods() {
+        let budget = RetryBudget::new(RetryOptions {
+            max_attempts: 3,
+            backoff: Duration::from_millis(1),
+            ..RetryOptions::default()
+        });
+        let predicate = RetryPredicate(Arc::new(|_: &_, _: &_| true));
+        let status = crate::status_details::with_retry_info(
+            Code::Aborted,
+            Duration::from_millis(1),
+        );
+        let calls = AtomicU32::new(0);
+
+        let res: Result<(), _> =
+            retry(&budget, Some(&predicate), true, (), |()| {
+                let _ = calls.fetch_add(1, Ordering::SeqCst);
+                std::future::ready(Err(status.clone()))
+            })
+            .await;
+
+        assert_eq!(res.unwrap_err().code(), Code::Aborted);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn backoff_grows_linearly_up_to_max_attempts() {
+        let clock = Arc::new(MockClock::new());
+        let budget = RetryBudget::with_clock(
+            RetryOptions {
+                max_attempts: 4,
+                backoff: Duration::from_millis(100),
+                ..RetryOptions::default()
+            },
+            clock.clone(),
+        );
+        let calls = AtomicU32::new(0);
+
+        let res: Result<(), _> = retry(&budget, None, false, (), |()| {
+            let _ = calls.fetch_add(1, Ordering::SeqCst);
+            async { Err(Status::unavailable("down")) }
+        })
+        .await;
+
+        assert_eq!(res.unwrap_err().code(), Code::Unavailable);
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+        assert_eq!(
+            clock.sleeps(),
+            [100, 200, 300].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn the_reserve_refills_with_the_clock() {
+        let clock = Arc::new(MockClock::new());
+        let budget = RetryBudget::with_clock(
+            RetryOptions { min_retries_per_sec: 1, ..RetryOptions::default() },
+            clock.clone(),
+        );
+
+        assert!(budget.try_withdraw());
+        assert!(!budget.try_withdraw());
+        clock.advance(Duration::from_secs(1));
+        assert!(budget.try_withdraw());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn paused_tokio_time_drives_the_default_clock() {
+        let status = with_header(Code::Unavailable, RETRY_AFTER_HEADER, "20");
+        let budget = RetryBudget::new(RetryOptions {
+            max_attempts: 2,
+            max_pushback: Duration::from_secs(60),
+            ..RetryOptions::default()
+        });
+        let started = tokio::time::Instant::now();
+
+        let res: Result<(), _> = retry(&budget, None, false, (), |()| {
+            std::future::ready(Err(status.clone()))
+        })
+        .await;
+
+        assert!(res.is_err());
+        assert_eq!(started.elapsed(), Duration::from_secs(20));
+    }
+}
@@ -0,0 +1,358 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Opt-in logging of every RPC through `tracing`, see
+//! [`crate::RpcLogOptions`].
+//!
+//! One event is emitted per call, once its status is known: when the
+//! trailers arrive, when the call fails before a response, or when the
+//! response is dropped early (logged as `Cancelled`).
+//!
+//! With the `metrics` feature, every call is also recorded through the
+//! `metrics` facade, whether or not it is logged, into histograms labelled
+//! with the full method path and the status code:
+//!
+//! - `aurae_client_rpc_duration_seconds`
+//! - `aurae_client_rpc_request_bytes`
+//! - `aurae_client_rpc_response_bytes`
+//!
+//! Method paths are those of the RPCs, never request content, so the label
+//! sets stay bounded by the services called.
+
+use crate::config::{RpcLogLevel, RpcLogOptions};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tonic::body::{boxed, BoxBody};
+use tonic::codegen::{http, Body, BoxFuture, Bytes, StdError};
+use tonic::{Code, Status};
+use tower::{Service, ServiceExt};
+
+/// Whether calls are recorded as metrics, see the module docs.
+const METRICS: bool = cfg!(feature = "metrics");
+
+/// A service that logs the calls made through it, when `options` is set.
+#[derive(Debug, Clone)]
+pub(crate) struct Logged<S> {
+    inner: S,
+    options: Option<Arc<RpcLogOptions>>,
+}
+
+impl<S> Logged<S> {
+    pub(crate) fn new(inner: S, options: Option<Arc<RpcLogOptions>>) -> Self {
+        Self { inner, options }
+    }
+}
+
+impl<S, B> Service<http::Request<BoxBody>> for Logged<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<B>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    S::Error: Into<StdError>,
+    B: Body<Data = Bytes> + Send + 'static,
+{
+    type Response = http::Response<LoggedBody<B>>;
+    type Error = StdError;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        // readiness of the inner service is awaited in `call`
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<BoxBody>) -> Self::Future {
+        let inner = self.inner.clone();
+        let options = self.options.clone();
+        if options.is_none() && !METRICS {
+            return Box::pin(async move {
+                let response = inner.oneshot(req).await.map_err(Into::into)?;
+                Ok(response.map(|inner| LoggedBody { inner, call: None }))
+            });
+        }
+
+        let request_bytes = Arc::new(AtomicU64::new(0));
+        let counted = METRICS
+            || options
+                .as_ref()
+                .is_some_and(|options| options.summarize_payloads);
+        let req = match counted {
+            true => {
+                let bytes = request_bytes.clone();
+                req.map(|inner| boxed(Counted { inner, bytes }))
+            }
+            false => req,
+        };
+        let mut call = Call {
+            method: req.uri().path().to_string(),
+            options,
+            started: Instant::now(),
+            request_bytes,
+            response_bytes: 0,
+            code: None,
+        };
+
+        Box::pin(async move {
+            match inner.oneshot(req).await.map_err(Into::into) {
+                Ok(response) => {
+                    // a trailers only response carries the status here
+                    call.code = Status::from_header_map(response.headers())
+                        .map(|status| status.code());
+                    Ok(response
+                        .map(|inner| LoggedBody { inner, call: Some(call) }))
+                }
+                Err(e) => {
+                    let code = e
+                        .downcast_ref::<Status>()
+                        .map_or(Code::Unknown, Status::code);
+                    call.finish(code);
+                    Err(e)
+                }
+            }
+        })
+    }
+}
+
+/// What is known about a call until it is logged.
+#[derive(Debug)]
+struct Call {
+    method: String,
+    /// `None` when the call is only recorded as metrics.
+    options: Option<Arc<RpcLogOptions>>,
+    started: Instant,
+    request_bytes: Arc<AtomicU64>,
+    response_bytes: u64,
+    code: Option<Code>,
+}
+
+macro_rules! rpc_event {
+    ($level:expr, $($fields:tt)*) => {
+        match $level {
+            RpcLogLevel::Trace => tracing::trace!($($fields)*),
+            RpcLogLevel::Debug => tracing::debug!($($fields)*),
+            RpcLogLevel::Info => tracing::info!($($fields)*),
+        }
+    };
+}
+
+impl Call {
+    fn finish(self, code: Code) {
+        #[cfg(feature = "metrics")]
+        self.record(code);
+
+        let Some(options) = &self.options else {
+            return;
+        };
+        let (request_bytes, response_bytes) = match options.summarize_payloads {
+            true => (
+                Some(self.request_bytes.load(Ordering::Relaxed)),
+                Some(self.response_bytes),
+            ),
+            false => (None, None),
+        };
+
+        rpc_event!(
+            options.level,
+            method = %self.method,
+            code = ?code,
+            duration_ms = self.started.elapsed().as_secs_f64() * 1000.0,
+            request_bytes,
+            response_bytes,
+            "rpc"
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    fn record(&self, code: Code) {
+        let labels =
+            [("method", self.method.clone()), ("status", format!("{code:?}"))];
+        metrics::histogram!("aurae_client_rpc_duration_seconds", &labels)
+            .record(self.started.elapsed().as_secs_f64());
+        metrics::histogram!("aurae_client_rpc_request_bytes", &labels)
+            .record(self.request_bytes.load(Ordering::Relaxed) as f64);
+        metrics::histogram!("aurae_client_rpc_response_bytes", &labels)
+            .record(self.response_bytes as f64);
+    }
+}
+
+/// A request body counting the bytes sent.
+struct Counted {
+    inner: BoxBody,
+    bytes: Arc<AtomicU64>,
+}
+
+impl Body for Counted {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(data))) = &poll {
+            let _ = self.bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+/// A response body that logs its call once the trailers arrive, or when
+/// it is dropped before that.
+#[derive(Debug)]
+pub(crate) struct LoggedBody<B> {
+    inner: B,
+    call: Option<Call>,
+}
+
+impl<B: Body<Data = Bytes> + Unpin> Body for LoggedBody<B> {
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_data(cx);
+        if let (Poll::Ready(Some(Ok(data))), Some(call)) =
+            (&poll, &mut self.call)
+        {
+            call.response_bytes += data.len() as u64;
+        }
+        poll
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        let poll = Pin::new(&mut self.inner).poll_trailers(cx);
+        if let Poll::Ready(result) = &poll {
+            if let Some(call) = self.call.take() {
+                let code = match result {
+                    Ok(Some(trailers)) => Status::from_header_map(trailers)
+                        .map(|status| status.code())
+                        .or(call.code),
+                    Ok(None) => call.code,
+                    Err(_) => Some(Code::Unknown),
+                };
+                call.finish(code.unwrap_or(Code::Unknown));
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+impl<B> Drop for LoggedBody<B> {
+    fn drop(&mut self) {
+        if let Some(call) = self.call.take() {
+            let code = call.code.unwrap_or(Code::Cancelled);
+            call.finish(code);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::sync::atomic::AtomicUsize;
+    use tonic::body::empty_body;
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Dispatch, Event, Metadata, Subscriber};
+
+    /// Counts the events emitted while it is the default subscriber.
+    struct CountEvents(Arc<AtomicUsize>);
+
+    impl Subscriber for CountEvents {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _span: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {
+            let _ = self.0.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[tokio::test]
+    async fn one_event_per_call() {
+        let service = tower::service_fn(|_req: http::Request<BoxBody>| async {
+            let mut response = http::Response::new(empty_body());
+            let _ = response
+                .headers_mut()
+                .insert("grpc-status", http::HeaderValue::from_static("0"));
+            Ok::<_, Infallible>(response)
+        });
+        let options = RpcLogOptions { enabled: true, ..Default::default() };
+        let logged = Logged::new(service, Some(Arc::new(options)));
+
+        let events = Arc::new(AtomicUsize::new(0));
+        let _default = tracing::dispatcher::set_default(&Dispatch::new(
+            CountEvents(events.clone()),
+        ));
+
+        for _ in 0..2 {
+            let mut response = logged
+                .clone()
+                .oneshot(http::Request::new(empty_body()))
+                .await
+                .unwrap();
+            while response.body_mut().data().await.is_some() {}
+            let _ = response.body_mut().trailers().await.unwrap();
+        }
+        // dropped without reading the response
+        let _ = logged
+            .clone()
+            .oneshot(http::Request::new(empty_body()))
+            .await
+            .unwrap();
+
+        assert_eq!(events.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,376 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! What the daemon a client is connected to runs and serves, so tooling
+//! can gate features on it instead of running into `UNIMPLEMENTED`.
+
+use crate::discovery::discovery_service::DiscoveryServiceClient;
+use crate::grpc::health::health::HealthClient;
+use crate::{Client, ClientError};
+use proto::discovery::{DiscoverRequest, DiscoverResponse};
+use proto::grpc::health::{
+    health_check_response::ServingStatus, HealthCheckRequest,
+};
+use serde::Serialize;
+use std::time::Duration;
+use tonic::Code;
+use tracing::{debug, warn};
+
+/// The version of the Aurae API packages this client was built against.
+pub(crate) const API_VERSION: &str = "v0";
+
+/// Limit on the API version check of a new client when
+/// `connect.overall_timeout` is unset.
+pub(crate) const API_VERSION_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The services of the APIs shipped with this client, probed over the
+/// health service on daemons that do not report what they serve.
+const KNOWN_SERVICES: &[&str] = &[
+    "aurae.cells.v0.CellService",
+    "aurae.discovery.v0.DiscoveryService",
+    "aurae.observe.v0.ObserveService",
+    "aurae.vms.v0.VmService",
+    "runtime.v1.RuntimeService",
+    "runtime.v1.ImageService",
+];
+
+/// See [`Client::server_info`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServerInfo {
+    /// The auraed version, e.g. `0.1.0`.
+    pub version: String,
+    /// The version of the Aurae API packages served, e.g. `v0`. `None` for
+    /// daemons that predate reporting it.
+    pub api_version: Option<String>,
+    /// Full names of the gRPC services served, e.g.
+    /// `aurae.cells.v0.CellService`.
+    pub services: Vec<String>,
+}
+
+impl ServerInfo {
+    /// Whether the daemon serves `service`, given by its full name.
+    pub fn supports(&self, service: &str) -> bool {
+        self.services.iter().any(|served| served == service)
+    }
+
+    /// Whether the daemon serves the service of the full method path
+    /// `method` (`/package.Service/Method`).
+    pub fn supports_method(&self, method: &str) -> bool {
+        method
+            .trim_start_matches('/')
+            .split_once('/')
+            .is_some_and(|(service, _)| self.supports(service))
+    }
+}
+
+/// Whether a client built against API `client` can talk to a daemon serving
+/// API `server`. Aurae API packages make no compatibility promises across
+/// versions, so only the same version is.
+fn api_compatible(client: &str, server: &str) -> bool {
+    client == server
+}
+
+impl Client {
+    /// The version, API version and services of the daemon, from the
+    /// discovery service. Daemons that predate reporting their services are
+    /// asked about those shipped with this client over the health service
+    /// instead, which only knows the services it was told are serving.
+    ///
+    /// Queried once and shared by this client and its clones. A connection
+    /// rebuilt by [`Client::ensure_connected`] or
+    /// [`Client::switch_config`] queries again.
+    pub async fn server_info(&self) -> Result<ServerInfo, ClientError> {
+        self.server_info_cell()
+            .get_or_try_init(|| self.query_server_info())
+            .await
+            .cloned()
+    }
+
+    async fn query_server_info(&self) -> Result<ServerInfo, ClientError> {
+        let discovered = self.discover_server().await?;
+        let services = match discovered.services.is_empty() {
+            true => self.probe_services().await,
+            false => discovered.services,
+        };
+        Ok(ServerInfo {
+            version: discovered.version,
+            api_version: Some(discovered.api_version)
+                .filter(|version| !version.is_empty()),
+            services,
+        })
+    }
+
+    /// What the daemon answers to a discover.
+    async fn discover_server(&self) -> Result<DiscoverResponse, ClientError> {
+        let discovered =
+            self.discover(DiscoverRequest {}).await.map_err(|status| {
+                ClientError::Other(
+                    anyhow::Error::new(status)
+                        .context("failed to query the server version"),
+                )
+            })?;
+        Ok(discovered.into_inner())
+    }
+
+    /// Fail with [`ClientError::VersionIncompatible`] when the daemon serves
+    /// another API version than [`API_VERSION`], so that is reported up
+    /// front rather than by the first RPC that no longer matches, or only
+    /// warn about it with `allow_skew`. Daemons whose version cannot be
+    /// queried within `timeout`, or that do not report it, are assumed
+    /// compatible.
+    ///
+    /// Only the API version is asked for, older daemons are not probed for
+    /// their services as by [`Client::server_info`].
+    pub(crate) async fn check_api_version(
+        &self,
+        allow_skew: bool,
+        timeout: Duration,
+    ) -> Result<(), ClientError> {
+        let discovered = match tokio::time::timeout(
+            timeout,
+            self.discover_server(),
+        )
+        .await
+        {
+            Ok(Ok(discovered)) => discovered,
+            Ok(Err(e)) => {
+                debug!("skipping the API version check: {e}");
+                return Ok(());
+            }
+            Err(_) => {
+                debug!(
+                        "skipping the API version check, auraed did not answer within {timeout:?}"
+                    );
+                return Ok(());
+            }
+        };
+        if discovered.api_version.is_empty() {
+            return Ok(());
+        }
+        let server_version = discovered.api_version;
+        if api_compatible(API_VERSION, &server_version) {
+            return Ok(());
+        }
+
+        let incompatible = ClientError::VersionIncompatible {
+            client_version: API_VERSION.into(),
+            server_version,
+        };
+        match allow_skew {
+            true => {
+                warn!("{incompatible}");
+                Ok(())
+            }
+            false => Err(incompatible),
+        }
+    }
+
+    /// Those of [`KNOWN_SERVICES`] the health service reports as serving.
+    async fn probe_services(&self) -> Vec<String> {
+        let mut services = vec![];
+        for service in KNOWN_SERVICES {
+            let req = HealthCheckRequest { service: service.to_string() };
+            match self.check(req).await {
+                Ok(res) if res.get_ref().status() == ServingStatus::Serving => {
+                    services.push(service.to_string());
+                }
+                // without a health service nothing can be told apart
+                Err(status) if status.code() == Code::Unimplemented => break,
+                _ => {}
+            }
+        }
+        services
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{serve_in_memory, Discovery};
+    use proto::discovery::discovery_service_server::DiscoveryService;
+    use proto::discovery::discovery_service_server::DiscoveryServiceServer;
+    use proto::discovery::{HelloRequest, HelloResponse};
+    use proto::grpc::health::health_server::{Health, HealthServer};
+    use proto::grpc::health::HealthCheckResponse;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tonic::transport::Server;
+    use tonic::{Request, Response, Status};
+
+    fn discovery(services: &[&str], api_version: &str) -> Discovery {
+        Discovery::answering(DiscoverResponse {
+            healthy: true,
+            version: "0.1.0".into(),
+            targets: vec![],
+            api_version: api_version.into(),
+            services: services
+                .iter()
+                .map(|service| service.to_string())
+                .collect(),
+        })
+    }
+
+    /// Reports only the cell service as serving, counting the checks.
+    #[derive(Clone, Default)]
+    struct CellsOnly(Arc<AtomicUsize>);
+
+    #[tonic::async_trait]
+    impl Health for CellsOnly {
+        async fn check(
+            &self,
+            request: Request<HealthCheckRequest>,
+        ) -> Result<Response<HealthCheckResponse>, Status> {
+            let _ = self.0.fetch_add(1, Ordering::SeqCst);
+            match request.get_ref().service.as_str() {
+                "aurae.cells.v0.CellService" => {
+                    Ok(Response::new(HealthCheckResponse {
+                        status: ServingStatus::Serving.into(),
+                    }))
+                }
+                _ => Err(Status::not_found("unknown service")),
+            }
+        }
+
+        type WatchStream =
+            futures_util::stream::Empty<Result<HealthCheckResponse, Status>>;
+
+        async fn watch(
+            &self,
+            _request: Request<HealthCheckRequest>,
+        ) -> Result<Response<Self::WatchStream>, Status> {
+            Err(Status::unimplemented("watch"))
+        }
+    }
+
+    #[tokio::test]
+    async fn reported_services_are_queried_once() {
+        let discovery = discovery(&["aurae.cells.v0.CellService"], "v0");
+        let client = serve_in_memory(
+            Server::builder()
+                .add_service(DiscoveryServiceServer::new(discovery.clone())),
+        );
+
+        let info = client.server_info().await.unwrap();
+        assert_eq!(info.version, "0.1.0");
+        assert_eq!(info.api_version.as_deref(), Some("v0"));
+        assert!(info.supports_method("/aurae.cells.v0.CellService/Allocate"));
+        assert!(!info.supports("aurae.vms.v0.VmService"));
+
+        assert_eq!(client.clone().server_info().await.unwrap(), info);
+        assert_eq!(discovery.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn older_daemons_are_probed_over_health() {
+        let discovery = discovery(&[], "");
+        let client = serve_in_memory(
+            Server::builder()
+                .add_service(DiscoveryServiceServer::new(discovery))
+                .add_service(HealthServer::new(CellsOnly::default())),
+        );
+
+        let info = client.server_info().await.unwrap();
+        assert_eq!(info.api_version, None);
+        assert_eq!(info.services, ["aurae.cells.v0.CellService"]);
+    }
+
+    #[test]
+    fn only_the_same_api_version_is_compatible() {
+        assert!(api_compatible("v0", "v0"));
+        assert!(!api_compatible("v0", "v1"));
+        assert!(!api_compatible("v1", "v1beta1"));
+    }
+
+    #[tokio::test]
+    async fn incompatible_api_versions_fail_unless_skew_is_allowed() {
+        let serving = |api_version| {
+            serve_in_memory(Server::builder().add_service(
+                DiscoveryServiceServer::new(discovery(
+                    &["aurae.cells.v1.CellService"],
+                    api_version,
+                )),
+            ))
+        };
+
+        let client = serving("v1");
+        match client.check_api_version(false, API_VERSION_CHECK_TIMEOUT).await {
+            Err(ClientError::VersionIncompatible {
+                client_version,
+                server_version,
+            }) => {
+                assert_eq!(client_version, API_VERSION);
+                assert_eq!(server_version, "v1");
+            }
+            other => panic!("expected VersionIncompatible, got {other:?}"),
+        }
+        assert!(client
+            .check_api_version(true, API_VERSION_CHECK_TIMEOUT)
+            .await
+            .is_ok());
+
+        assert!(serving(API_VERSION)
+            .check_api_version(false, API_VERSION_CHECK_TIMEOUT)
+            .await
+            .is_ok());
+        assert!(serving("")
+            .check_api_version(false, API_VERSION_CHECK_TIMEOUT)
+            .await
+            .is_ok());
+    }
+
+    /// Never answers a discover.
+    struct Hanging;
+
+    #[tonic::async_trait]
+    impl DiscoveryService for Hanging {
+        async fn discover(
+            &self,
+            _request: Request<DiscoverRequest>,
+        ) -> Result<Response<DiscoverResponse>, Status> {
+            std::future::pending().await
+        }
+
+        async fn hello(
+            &self,
+            _request: Request<HelloRequest>,
+        ) -> Result<Response<HelloResponse>, Status> {
+            Err(Status::unimplemented("hello"))
+        }
+    }
+
+    #[tokio::test]
+    async fn the_api_version_check_neither_probes_nor_hangs() {
+        let health = CellsOnly::default();
+        let client = serve_in_memory(
+            Server::builder()
+                .add_service(DiscoveryServiceServer::new(discovery(&[], "")))
+                .add_service(HealthServer::new(health.clone())),
+        );
+        assert!(client
+            .check_api_version(false, API_VERSION_CHECK_TIMEOUT)
+            .await
+            .is_ok());
+        assert_eq!(health.0.load(Ordering::SeqCst), 0);
+
+        let client = serve_in_memory(
+            Server::builder().add_service(DiscoveryServiceServer::new(Hanging)),
+        );
+        let check = client.check_api_version(false, Duration::from_millis(50));
+        tokio::time::timeout(Duration::from_secs(5), check)
+            .await
+            .expect("the check waited past its timeout")
+            .unwrap();
+    }
+}
@@ -0,0 +1,255 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Unix socket forwarding over an `ssh` child process.
+
+use crate::config::ssh_jump::check_forward_path;
+use crate::config::SshJump;
+use anyhow::{anyhow, Context, Result};
+use std::fs::Permissions;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::process::{Child, Command};
+
+/// How long to wait for `ssh` to establish the forward.
+const TUNNEL_READY_TIMEOUT: Duration = Duration::from_secs(10);
+const TUNNEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A running `ssh -L` forward from a local unix socket to the remote auraed
+/// socket. The `ssh` process is killed and the local socket removed on drop.
+///
+/// The local socket lives in a fresh directory only the current user can
+/// enter, so no other local user can bind it first and receive the traffic
+/// meant for auraed.
+#[derive(Debug)]
+pub(crate) struct SshTunnel {
+    local_socket: PathBuf,
+    _child: Child,
+    _dir: TempDir,
+}
+
+impl SshTunnel {
+    pub(crate) async fn open(jump: &SshJump) -> Result<Self> {
+        check_forward_path(&jump.remote_socket)?;
+        let dir = tempfile::Builder::new()
+            .prefix("aurae-ssh-")
+            .tempdir()
+            .context("failed to create a directory for the ssh tunnel")?;
+        std::fs::set_permissions(dir.path(), Permissions::from_mode(0o700))
+            .context("failed to restrict the ssh tunnel directory")?;
+        let local_socket = dir.path().join("auraed.sock");
+        check_forward_path(&local_socket)?;
+
+        let mut child = command(jump, &local_socket)
+            .spawn()
+            .context("failed to spawn ssh for jump host")?;
+        wait_ready(&mut child, &local_socket, &jump.host, TUNNEL_READY_TIMEOUT)
+            .await?;
+
+        Ok(Self { local_socket, _child: child, _dir: dir })
+    }
+
+    /// The local end of the forward.
+    pub(crate) fn local_socket(&self) -> &Path {
+        &self.local_socket
+    }
+}
+
+/// The `ssh` invocation forwarding `local_socket` to the remote socket of
+/// `jump`. The destination follows `--`, so a host starting with `-` cannot
+/// be taken for an option.
+fn command(jump: &SshJump, local_socket: &Path) -> Command {
+    let mut command = Command::new("ssh");
+    let _ = command
+        .args(["-nNT", "-o", "ExitOnForwardFailure=yes"])
+        .arg("-L")
+        .arg(format!(
+            "{}:{}",
+            local_socket.display(),
+            jump.remote_socket.display()
+        ))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .kill_on_drop(true);
+
+    if let Some(port) = jump.port {
+        let _ = command.arg("-p").arg(port.to_string());
+    }
+
+    if let Some(identity_file) = &jump.identity_file {
+        let _ = command.arg("-i").arg(identity_file);
+    }
+
+    let _ = command.arg("--").arg(jump.destination());
+    command
+}
+
+/// Wait for `child` to bind `local_socket`, failing if it exits first, if
+/// `timeout` passes, or if what appears there is not a socket owned by the
+/// current user.
+async fn wait_ready(
+    child: &mut Child,
+    local_socket: &Path,
+    host: &str,
+    timeout: Duration,
+) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Err(anyhow!(
+                "ssh to jump host '{host}' exited early with {status}"
+            ));
+        }
+
+        if let Ok(metadata) = tokio::fs::symlink_metadata(local_socket).await {
+            let euid = nix::unistd::geteuid().as_raw();
+            if !metadata.file_type().is_socket() || metadata.uid() != euid {
+                return Err(anyhow!(
+                    "refusing to use ssh tunnel socket '{}': not a socket owned by uid {euid}",
+                    local_socket.display()
+                ));
+            }
+            return Ok(());
+        }
+
+        if tokio::time::Instant::now() >= deadline {
+            return Err(anyhow!(
+                "timed out waiting for ssh tunnel through '{host}'"
+            ));
+        }
+
+        tokio::time::sleep(TUNNEL_POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    fn jump(host: &str) -> SshJump {
+        SshJump {
+            host: host.into(),
+            port: Some(2222),
+            user: Some("ops".into()),
+            identity_file: Some("/keys/id_ed25519".into()),
+            remote_socket: "/var/run/aurae/aurae.sock".into(),
+        }
+    }
+
+    fn sleeping() -> Child {
+        Command::new("sleep").arg("5").kill_on_drop(true).spawn().unwrap()
+    }
+
+    #[test]
+    fn destination_follows_the_option_separator() {
+        let command = command(&jump("-oProxyCommand=evil"), Path::new("/t/s"));
+        let args: Vec<_> = command
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_str().unwrap())
+            .collect();
+
+        assert_eq!(
+            args,
+            [
+                "-nNT",
+                "-o",
+                "ExitOnForwardFailure=yes",
+                "-L",
+                "/t/s:/var/run/aurae/aurae.sock",
+                "-p",
+                "2222",
+                "-i",
+                "/keys/id_ed25519",
+                "--",
+                "ops@-oProxyCommand=evil",
+            ]
+        );
+    }
+
+    #[test]
+    fn forwarded_paths_cannot_hold_a_colon() {
+        let parse = |remote_socket: &str| {
+            toml::from_str::<SshJump>(&format!(
+                "host = \"bastion\"\nremote_socket = \"{remote_socket}\""
+            ))
+        };
+
+        assert!(parse("/var/run/aurae/aurae.sock").is_ok());
+        let err = parse("/var/run/aurae:2/aurae.sock").unwrap_err();
+        assert!(err.to_string().contains("must not contain ':'"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn ready_once_the_socket_is_bound() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("auraed.sock");
+        let _listener = UnixListener::bind(&socket).unwrap();
+
+        let mut child = sleeping();
+        wait_ready(&mut child, &socket, "bastion", Duration::from_secs(1))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn other_files_at_the_socket_path_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("auraed.sock");
+        std::fs::write(&socket, "").unwrap();
+
+        let mut child = sleeping();
+        let err =
+            wait_ready(&mut child, &socket, "bastion", Duration::from_secs(1))
+                .await
+                .unwrap_err();
+        assert!(err.to_string().contains("refusing"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn times_out_without_a_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("auraed.sock");
+
+        let mut child = sleeping();
+        let err = wait_ready(
+            &mut child,
+            &socket,
+            "bastion",
+            Duration::from_millis(100),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn fails_when_ssh_exits_early() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("auraed.sock");
+
+        let mut child = Command::new("false").spawn().unwrap();
+        let _ = child.wait().await.unwrap();
+        let err =
+            wait_ready(&mut child, &socket, "bastion", Duration::from_secs(1))
+                .await
+                .unwrap_err();
+        assert!(err.to_string().contains("exited early"), "{err}");
+    }
+}
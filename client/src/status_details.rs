@@ -0,0 +1,144 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! The rich error model of `google.rpc.Status`, as sent in the
+//! `grpc-status-details-bin` trailer, decoded far enough for retries.
+
+use prost::Message;
+use std::time::Duration;
+use tonic::Status;
+
+const RETRY_INFO: &str = "type.googleapis.com/google.rpc.RetryInfo";
+
+/// The details of a failed call, see [`Client::with_retry_predicate`].
+///
+/// [`Client::with_retry_predicate`]: crate::Client::with_retry_predicate
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatusDetails {
+    /// Every detail message, still encoded.
+    pub details: Vec<ErrorDetail>,
+    /// The delay of a `google.rpc.RetryInfo` detail, the server's word that
+    /// the call may be retried after waiting this long.
+    pub retry_delay: Option<Duration>,
+}
+
+/// One `google.protobuf.Any` of [`StatusDetails::details`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorDetail {
+    /// Names the message type, such as
+    /// `type.googleapis.com/google.rpc.ErrorInfo`.
+    pub type_url: String,
+    /// The encoded message.
+    pub value: Vec<u8>,
+}
+
+impl StatusDetails {
+    /// `None` when `status` carries no details, or details that do not
+    /// decode as a `google.rpc.Status`.
+    pub fn of(status: &Status) -> Option<Self> {
+        if status.details().is_empty() {
+            return None;
+        }
+        let decoded = RpcStatus::decode(status.details()).ok()?;
+        let retry_delay = decoded
+            .details
+            .iter()
+            .filter(|any| any.type_url == RETRY_INFO)
+            .find_map(|any| RetryInfo::decode(any.value.as_slice()).ok())
+            .and_then(|info| info.retry_delay)
+            .map(|delay| {
+                Duration::new(
+                    delay.seconds.max(0) as u64,
+                    delay.nanos.clamp(0, 999_999_999) as u32,
+                )
+            });
+        let details = decoded
+            .details
+            .into_iter()
+            .map(|any| ErrorDetail { type_url: any.type_url, value: any.value })
+            .collect();
+        Some(Self { details, retry_delay })
+    }
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<Any>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Any {
+    #[prost(string, tag = "1")]
+    type_url: String,
+    #[prost(bytes = "vec", tag = "2")]
+    value: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct RetryInfo {
+    #[prost(message, optional, tag = "1")]
+    retry_delay: Option<ProtoDuration>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoDuration {
+    #[prost(int64, tag = "1")]
+    seconds: i64,
+    #[prost(int32, tag = "2")]
+    nanos: i32,
+}
+
+/// A status whose details ask for a retry after `delay`, as a server would
+/// send it.
+#[cfg(test)]
+pub(crate) fn with_retry_info(code: tonic::Code, delay: Duration) -> Status {
+    let retry_info = RetryInfo {
+        retry_delay: Some(ProtoDuration {
+            seconds: delay.as_secs() as i64,
+            nanos: delay.subsec_nanos() as i32,
+        }),
+    };
+    let status = RpcStatus {
+        code: code as i32,
+        message: "try again later".into(),
+        details: vec![Any {
+            type_url: RETRY_INFO.into(),
+            value: retry_info.encode_to_vec(),
+        }],
+    };
+    Status::with_details(code, "try again later", status.encode_to_vec().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    #[test]
+    fn retry_info_is_decoded() {
+        let status =
+            with_retry_info(Code::Aborted, Duration::from_millis(1500));
+
+        let details = StatusDetails::of(&status).unwrap();
+        assert_eq!(details.retry_delay, Some(Duration::from_millis(1500)));
+        assert_eq!(details.details[0].type_url, RETRY_INFO);
+        assert!(StatusDetails::of(&Status::aborted("no details")).is_none());
+    }
+}
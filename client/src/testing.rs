@@ -0,0 +1,224 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Helpers shared by the unit tests.
+
+use crate::config::CertMaterial;
+use crate::tls::DEFAULT_SERVER_NAME;
+use crate::Client;
+use proto::discovery::discovery_service_server::DiscoveryService;
+use proto::discovery::{
+    DiscoverRequest, DiscoverResponse, HelloRequest, HelloResponse,
+};
+use proto::grpc::health::health_check_response::ServingStatus;
+use proto::grpc::health::health_server::{Health, HealthServer};
+use proto::grpc::health::{HealthCheckRequest, HealthCheckResponse};
+use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tonic::metadata::MetadataMap;
+use tonic::transport::server::Router;
+use tonic::transport::{
+    Channel, Endpoint, Identity, Server, ServerTlsConfig, Uri,
+};
+use tonic::{Request, Response, Status};
+
+/// A client talking to the services of `router` over an in-memory stream,
+/// for tests that need real RPCs against a mock server.
+pub(crate) fn serve_in_memory(router: Router) -> Client {
+    Client::from_channel(in_memory_channel(router), None)
+}
+
+/// A channel to the services of `router` over an in-memory stream.
+pub(crate) fn in_memory_channel(router: Router) -> Channel {
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    let incoming =
+        futures_util::stream::iter([Ok::<_, std::io::Error>(server_io)]);
+    let _server = tokio::spawn(router.serve_with_incoming(incoming));
+
+    let client_io = Arc::new(Mutex::new(Some(client_io)));
+    Endpoint::from_static("http://[::]:50051").connect_with_connector_lazy(
+        tower::service_fn(move |_: Uri| {
+            let io = client_io.lock().unwrap().take();
+            async move {
+                io.ok_or_else(|| {
+                    std::io::Error::other("the in-memory stream is taken")
+                })
+            }
+        }),
+    )
+}
+
+/// Reports every service as serving.
+pub(crate) struct Serving;
+
+#[tonic::async_trait]
+impl Health for Serving {
+    async fn check(
+        &self,
+        _request: Request<HealthCheckRequest>,
+    ) -> std::result::Result<Response<HealthCheckResponse>, Status> {
+        Ok(Response::new(HealthCheckResponse {
+            status: ServingStatus::Serving.into(),
+        }))
+    }
+
+    type WatchStream = futures_util::stream::Empty<
+        std::result::Result<HealthCheckResponse, Status>,
+    >;
+
+    async fn watch(
+        &self,
+        _request: Request<HealthCheckRequest>,
+    ) -> std::result::Result<Response<Self::WatchStream>, Status> {
+        Err(Status::unimplemented("watch"))
+    }
+}
+
+/// A discovery service stand-in that records the metadata of every call.
+/// `discover` answers with `response`, `hello` echoes the client info back
+/// with `echo_hello`. Without, each is unimplemented.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Discovery {
+    pub(crate) response: Option<DiscoverResponse>,
+    pub(crate) echo_hello: bool,
+    calls: Arc<Mutex<Vec<MetadataMap>>>,
+}
+
+impl Discovery {
+    pub(crate) fn answering(response: DiscoverResponse) -> Self {
+        Self { response: Some(response), ..Self::default() }
+    }
+
+    pub(crate) fn echoing_hello() -> Self {
+        Self { echo_hello: true, ..Self::default() }
+    }
+
+    /// The calls answered so far.
+    pub(crate) fn calls(&self) -> usize {
+        self.calls.lock().unwrap().len()
+    }
+
+    /// The value of `header` each call so far was sent with.
+    pub(crate) fn sent(&self, header: &str) -> Vec<Option<String>> {
+        self.calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|metadata| {
+                metadata
+                    .get(header)
+                    .map(|value| value.to_str().unwrap().to_string())
+            })
+            .collect()
+    }
+
+    fn record<T>(&self, request: &Request<T>) {
+        self.calls.lock().unwrap().push(request.metadata().clone());
+    }
+}
+
+#[tonic::async_trait]
+impl DiscoveryService for Discovery {
+    async fn discover(
+        &self,
+        request: Request<DiscoverRequest>,
+    ) -> std::result::Result<Response<DiscoverResponse>, Status> {
+        self.record(&request);
+        self.response
+            .clone()
+            .map(Response::new)
+            .ok_or_else(|| Status::unimplemented("discover"))
+    }
+
+    async fn hello(
+        &self,
+        request: Request<HelloRequest>,
+    ) -> std::result::Result<Response<HelloResponse>, Status> {
+        self.record(&request);
+        if !self.echo_hello {
+            return Err(Status::unimplemented("hello"));
+        }
+        let client = request.into_inner().client.unwrap_or_default();
+        Ok(Response::new(HelloResponse {
+            version: client.version,
+            features: client.features,
+            compression: client.compression,
+            max_message_size: client.max_message_size,
+        }))
+    }
+}
+
+/// A self-signed CA named `cn`.
+pub(crate) fn test_ca(cn: &str) -> rcgen::Certificate {
+    let mut params = CertificateParams::new(vec![]);
+    params.distinguished_name.push(DnType::CommonName, cn);
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    rcgen::Certificate::from_params(params).unwrap()
+}
+
+/// A client certificate named `cn` issued by `ca`, with `ca` as the
+/// server root CA.
+pub(crate) fn client_material(
+    ca: &rcgen::Certificate,
+    cn: &str,
+) -> CertMaterial {
+    let mut params = CertificateParams::default();
+    params.distinguished_name.push(DnType::CommonName, cn);
+    let client = rcgen::Certificate::from_params(params).unwrap();
+    CertMaterial {
+        server_root_ca_cert: ca.serialize_pem().unwrap().into_bytes(),
+        client_cert: client.serialize_pem_with_signer(ca).unwrap().into_bytes(),
+        client_key: client.serialize_private_key_pem().into_bytes(),
+    }
+}
+
+/// An auraed stand-in serving health checks on the unix socket `socket`,
+/// over mutual TLS with a server certificate for [`DEFAULT_SERVER_NAME`]
+/// and the clients of `ca`.
+pub(crate) fn serve_health_at(ca: &rcgen::Certificate, socket: &Path) {
+    let server = rcgen::Certificate::from_params(CertificateParams::new(vec![
+        DEFAULT_SERVER_NAME.into(),
+    ]))
+    .unwrap();
+    let tls = ServerTlsConfig::new()
+        .identity(Identity::from_pem(
+            server.serialize_pem_with_signer(ca).unwrap(),
+            server.serialize_private_key_pem(),
+        ))
+        .client_ca_root(tonic::transport::Certificate::from_pem(
+            ca.serialize_pem().unwrap(),
+        ));
+
+    let _ = std::fs::remove_file(socket);
+    serve_at(
+        Server::builder()
+            .tls_config(tls)
+            .unwrap()
+            .add_service(HealthServer::new(Serving)),
+        socket,
+    );
+}
+
+/// Serve the services of `router` on the unix socket `socket`.
+pub(crate) fn serve_at(router: Router, socket: &Path) {
+    let listener = tokio::net::UnixListener::bind(socket).unwrap();
+    let incoming =
+        futures_util::stream::unfold(listener, |listener| async move {
+            let accepted = listener.accept().await.map(|(stream, _)| stream);
+            Some((accepted, listener))
+        });
+    let _server = tokio::spawn(router.serve_with_incoming(incoming));
+}
@@ -0,0 +1,1707 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Assembly of the rustls client configuration from PEM cert material.
+
+use crate::config::{
+    AuthConfig, CertMaterial, ConnectOptions, KeyAlgorithm, X509Details,
+};
+use crate::ClientError;
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::client::ResolvesClientCert;
+#[cfg(feature = "custom-verifier")]
+use tokio_rustls::rustls::client::{
+    ServerCertVerified, ServerCertVerifier, WebPkiVerifier,
+};
+use tokio_rustls::rustls::server::{
+    AllowAnyAuthenticatedClient, ClientCertVerifier,
+};
+use tokio_rustls::rustls::sign::{CertifiedKey, SigningKey};
+use tokio_rustls::rustls::{
+    Certificate, CertificateError, ClientConfig, Error, PrivateKey,
+    RootCertStore, ServerName, SignatureScheme, DEFAULT_VERSIONS,
+};
+use tokio_rustls::TlsConnector;
+#[cfg(feature = "custom-verifier")]
+use tracing::trace;
+use tracing::{debug, warn};
+use x509_certificate::DigestAlgorithm;
+use x509_parser::extensions::GeneralName;
+
+/// The domain name the server certificate is verified against, unless
+/// `connect.server_name` is set.
+pub(crate) const DEFAULT_SERVER_NAME: &str = "server.unsafe.aurae.io";
+
+/// The TLS settings taken from [`ConnectOptions`], applied again on every
+/// cert reload.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TlsOptions {
+    /// Skip server certificate verification, see [`crate::dangerous`].
+    no_verify: bool,
+    server_name: Option<String>,
+    sni_hostname: Option<String>,
+    /// Lowercase hex, without separators.
+    pins: Vec<String>,
+    accepted_key_algorithms: Option<Vec<KeyAlgorithm>>,
+    pub(crate) clock_skew_tolerance: Duration,
+    pub(crate) rotation_retries: u32,
+    pub(crate) rotation_retry_delay: Duration,
+    strict_hostname: bool,
+    max_chain_depth: Option<usize>,
+    audit_log: bool,
+}
+
+impl TlsOptions {
+    pub(crate) fn new(options: &ConnectOptions) -> Result<Self> {
+        Ok(Self {
+            no_verify: crate::dangerous::no_verify_requested(options)?,
+            server_name: options.server_name.clone(),
+            sni_hostname: options.sni_hostname.clone(),
+            pins: options
+                .pinned_server_sha256
+                .iter()
+                .map(|pin| parse_pin(pin))
+                .collect::<Result<_>>()?,
+            accepted_key_algorithms: options.accepted_key_algorithms.clone(),
+            clock_skew_tolerance: options.clock_skew_tolerance,
+            rotation_retries: options.cert_rotation_retries,
+            rotation_retry_delay: options.cert_rotation_retry_delay,
+            strict_hostname: options.strict_hostname,
+            max_chain_depth: options.max_chain_depth,
+            audit_log: options.audit_log,
+        })
+    }
+
+    /// Reject a client certificate whose key algorithm is not accepted, with
+    /// a [`ClientError::KeyAlgorithmRejected`].
+    pub(crate) fn check_key_algorithm(
+        &self,
+        details: &X509Details,
+    ) -> Result<()> {
+        let Some(accepted) = &self.accepted_key_algorithms else {
+            return Ok(());
+        };
+
+        match KeyAlgorithm::from_details_name(&details.key_algorithm) {
+            Some(algorithm) if accepted.contains(&algorithm) => Ok(()),
+            _ => Err(ClientError::KeyAlgorithmRejected {
+                found: details.key_algorithm.clone(),
+                accepted: accepted.clone(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// A SHA-256 fingerprint as hex, optionally with `:` between the bytes.
+fn parse_pin(pin: &str) -> Result<String> {
+    let hex: String = pin.trim().chars().filter(|c| *c != ':').collect();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!(
+            "connect.pinned_server_sha256 entry '{pin}' is not a SHA-256 fingerprint of 64 hex digits"
+        ));
+    }
+    Ok(hex.to_ascii_lowercase())
+}
+
+/// Everything needed to run the TLS handshake on top of a raw stream.
+#[derive(Clone)]
+pub(crate) struct TlsConnect {
+    pub(crate) connector: TlsConnector,
+    pub(crate) server_name: ServerName,
+    pub(crate) expected: ExpectedIdentity,
+    /// Verify connections to an IP address against that address, see
+    /// [`ConnectOptions::strict_hostname`].
+    strict_hostname: bool,
+    /// See [`ConnectOptions::max_chain_depth`].
+    max_chain_depth: Option<usize>,
+    /// Log the server certificate of each connection, see
+    /// [`ConnectOptions::audit_log`].
+    pub(crate) audit_log: bool,
+    pub(crate) debug: Arc<TlsDebugInfo>,
+}
+
+impl std::fmt::Debug for TlsConnect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsConnect")
+            .field("server_name", &self.server_name)
+            .field("expected", &self.expected)
+            .field("strict_hostname", &self.strict_hostname)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TlsConnect {
+    pub(crate) fn new(
+        material: &CertMaterial,
+        auth: &AuthConfig,
+        options: &TlsOptions,
+    ) -> Result<Self> {
+        Self::build(material, None, auth, options)
+    }
+
+    /// As [`TlsConnect::new`], signing with `key` instead of the client key
+    /// of `material`, which is left empty.
+    #[cfg(feature = "pkcs11")]
+    pub(crate) fn with_signing_key(
+        material: &CertMaterial,
+        key: Arc<dyn SigningKey>,
+        auth: &AuthConfig,
+        options: &TlsOptions,
+    ) -> Result<Self> {
+        Self::build(material, Some(key), auth, options)
+    }
+
+    fn build(
+        material: &CertMaterial,
+        key: Option<Arc<dyn SigningKey>>,
+        auth: &AuthConfig,
+        options: &TlsOptions,
+    ) -> Result<Self> {
+        let verify_as = dns_name(
+            "connect.server_name",
+            options.server_name.as_deref().unwrap_or(DEFAULT_SERVER_NAME),
+        )?;
+        let sni = options
+            .sni_hostname
+            .as_deref()
+            .map(|name| dns_name("connect.sni_hostname", name))
+            .transpose()?;
+
+        let mut config = client_config(material, key)?;
+        if options.no_verify {
+            crate::dangerous::skip_server_verification(&mut config)?;
+        } else {
+            verify_as_server_name(
+                &mut config,
+                material,
+                &verify_as,
+                sni.as_ref(),
+                options,
+            )?;
+        }
+        let debug = TlsDebugInfo {
+            server_name: options
+                .server_name
+                .clone()
+                .unwrap_or_else(|| DEFAULT_SERVER_NAME.into()),
+            sni_hostname: options.sni_hostname.clone(),
+            strict_hostname: options.strict_hostname,
+            verify_server: !options.no_verify,
+            trust_anchors: unique_cas(parse_certs(
+                &material.server_root_ca_cert,
+            )?)
+            .iter()
+            .map(|ca| subject(&ca.0))
+            .collect(),
+            client_chain: parse_certs(&material.client_cert)?
+                .iter()
+                .map(|cert| subject(&cert.0))
+                .collect(),
+            protocol_versions: DEFAULT_VERSIONS
+                .iter()
+                .map(|version| format!("{:?}", version.version))
+                .collect(),
+            alpn_protocols: config
+                .alpn_protocols
+                .iter()
+                .map(|alpn| String::from_utf8_lossy(alpn).into_owned())
+                .collect(),
+            pinned_server_certs: options.pins.len(),
+            expected_server_cn: auth.expected_server_cn.clone(),
+            expected_server_spiffe: auth.expected_server_spiffe.clone(),
+            expected_server_trust_domain: auth
+                .expected_server_trust_domain
+                .clone(),
+            max_chain_depth: options.max_chain_depth,
+        };
+
+        // rustls sends the name the handshake is started with as SNI
+        let server_name = sni.unwrap_or(verify_as);
+
+        Ok(Self {
+            connector: TlsConnector::from(Arc::new(config)),
+            server_name,
+            expected: ExpectedIdentity {
+                cn: auth.expected_server_cn.clone(),
+                spiffe: auth.expected_server_spiffe.clone(),
+                trust_domain: auth
+                    .expected_server_trust_domain
+                    .as_deref()
+                    .map(trust_domain_name),
+                pins: options.pins.clone(),
+            },
+            strict_hostname: options.strict_hostname,
+            max_chain_depth: options.max_chain_depth,
+            audit_log: options.audit_log,
+            debug: Arc::new(debug),
+        })
+    }
+
+    /// Hand shakes with a config the caller built, see
+    /// [`crate::Client::new_with_rustls`]. The server certificate is only
+    /// checked by `config` itself.
+    pub(crate) fn from_rustls(
+        config: Arc<ClientConfig>,
+        server_name: ServerName,
+        client: Option<&X509Details>,
+    ) -> Self {
+        let debug = TlsDebugInfo {
+            server_name: match &server_name {
+                ServerName::DnsName(name) => name.as_ref().to_owned(),
+                ServerName::IpAddress(ip) => ip.to_string(),
+                _ => String::new(),
+            },
+            sni_hostname: None,
+            strict_hostname: false,
+            verify_server: true,
+            trust_anchors: vec![],
+            client_chain: client
+                .map(|details| vec![details.subject_common_name.clone()])
+                .unwrap_or_default(),
+            protocol_versions: vec![],
+            alpn_protocols: config
+                .alpn_protocols
+                .iter()
+                .map(|alpn| String::from_utf8_lossy(alpn).into_owned())
+                .collect(),
+            pinned_server_certs: 0,
+            expected_server_cn: None,
+            expected_server_spiffe: None,
+            expected_server_trust_domain: None,
+            max_chain_depth: None,
+        };
+        Self {
+            connector: TlsConnector::from(config),
+            server_name,
+            expected: ExpectedIdentity::default(),
+            strict_hostname: false,
+            max_chain_depth: None,
+            audit_log: false,
+            debug: Arc::new(debug),
+        }
+    }
+
+    /// Run the handshake on `stream`, a connection to `host_ip` when the
+    /// socket names an IP address rather than a host name or path.
+    ///
+    /// Under `strict_hostname` such a connection is verified against the
+    /// IP address, so the certificate needs a matching IP SAN, and no SNI
+    /// is sent. Otherwise the server name is verified whatever the socket.
+    ///
+    /// A chain longer than `max_chain_depth` fails the handshake, before
+    /// verification with the `custom-verifier` feature, so also when the
+    /// chain is untrusted, and once it passed verification without.
+    pub(crate) async fn handshake<IO: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        host_ip: Option<IpAddr>,
+        stream: IO,
+    ) -> io::Result<TlsStream<IO>> {
+        let strict_ip = host_ip.filter(|_| self.strict_hostname);
+        let server_name = match strict_ip {
+            Some(ip) => ServerName::IpAddress(ip),
+            None => self.server_name.clone(),
+        };
+
+        let stream =
+            self.connector.connect(server_name, stream).await.map_err(|e| {
+                let invalid = match e
+                    .get_ref()
+                    .and_then(|inner| inner.downcast_ref::<Error>())
+                {
+                    Some(Error::InvalidCertificate(invalid)) => invalid,
+                    _ => return e,
+                };
+                let untrusted = match invalid {
+                    CertificateError::Other(other) => {
+                        other.downcast_ref::<UntrustedChain>()
+                    }
+                    _ => None,
+                };
+                let invalid =
+                    untrusted.map_or(invalid, |untrusted| &untrusted.invalid);
+                let denied: Option<Box<dyn std::error::Error + Send + Sync>> =
+                    match (invalid, strict_ip) {
+                        (CertificateError::NotValidForName, Some(ip)) => {
+                            Some(Box::new(MissingIpSan { ip }))
+                        }
+                        (CertificateError::Other(other), _) => other
+                            .downcast_ref::<ChainTooDeep>()
+                            .map(|too_deep| Box::new(too_deep.clone()) as _),
+                        _ => None,
+                    };
+                // chains are only recorded by VerifyAs
+                let untrusted = untrusted.cloned().unwrap_or_else(|| {
+                    UntrustedChain { invalid: invalid.clone(), chain: vec![] }
+                });
+                let denied = denied.unwrap_or_else(|| Box::new(untrusted));
+                io::Error::new(io::ErrorKind::PermissionDenied, denied)
+            })?;
+
+        let depth =
+            stream.get_ref().1.peer_certificates().map_or(0, <[_]>::len);
+        if let Some(max) = self.max_chain_depth.filter(|max| depth > *max) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                ChainTooDeep { depth, max },
+            ));
+        }
+        Ok(stream)
+    }
+}
+
+/// See [`crate::Client::tls_debug`].
+///
+/// Only names and counts, never key material or certificate bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TlsDebugInfo {
+    /// The name the server certificate is verified against.
+    pub server_name: String,
+    /// The name sent as SNI instead of `server_name`.
+    pub sni_hostname: Option<String>,
+    pub strict_hostname: bool,
+    /// `false` only under `connect.dangerous_no_verify`.
+    pub verify_server: bool,
+    /// Subjects of the distinct CAs in the server root CA bundle.
+    pub trust_anchors: Vec<String>,
+    /// Subjects of the client certificate and the intermediates sent with
+    /// it.
+    pub client_chain: Vec<String>,
+    pub protocol_versions: Vec<String>,
+    pub alpn_protocols: Vec<String>,
+    /// How many fingerprints the server certificate may match, see
+    /// `connect.pinned_server_sha256`.
+    pub pinned_server_certs: usize,
+    pub expected_server_cn: Option<String>,
+    pub expected_server_spiffe: Option<String>,
+    pub expected_server_trust_domain: Option<String>,
+    pub max_chain_depth: Option<usize>,
+}
+
+/// The subject of a DER certificate as an RFC 4514 string, empty when it
+/// does not parse.
+fn subject(der: &[u8]) -> String {
+    x509_parser::parse_x509_certificate(der)
+        .map(|(_, cert)| cert.subject().to_string())
+        .unwrap_or_default()
+}
+
+/// Returned from the connector (inside a [`tonic::transport::Error`]) when
+/// `strict_hostname` verifies a connection against an IP address the server
+/// certificate has no SAN for.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("server certificate has no IP SAN for {ip}, which connect.strict_hostname requires")]
+pub(crate) struct MissingIpSan {
+    pub(crate) ip: IpAddr,
+}
+
+/// Returned from the connector (inside a [`tonic::transport::Error`]) when
+/// the server presents more certificates than `connect.max_chain_depth`.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("server presented a chain of {depth} certificates, more than connect.max_chain_depth of {max}")]
+pub(crate) struct ChainTooDeep {
+    pub(crate) depth: usize,
+    pub(crate) max: usize,
+}
+
+/// Returned from the connector (inside a [`tonic::transport::Error`]) when
+/// the server certificate fails verification, with the chain the server
+/// presented.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("server certificate verification failed: {}; presented chain: {}", reason(.invalid), dump(.chain))]
+pub(crate) struct UntrustedChain {
+    pub(crate) invalid: CertificateError,
+    pub(crate) chain: Vec<PresentedCert>,
+}
+
+/// A certificate the server presented, as listed in
+/// [`ClientError::TlsVerification`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PresentedCert {
+    /// RFC 4514, empty when the certificate does not parse.
+    pub subject: String,
+    pub issuer: String,
+    pub not_before: String,
+    pub not_after: String,
+}
+
+impl PresentedCert {
+    /// The end entity certificate followed by the intermediates, in the
+    /// order the server sent them.
+    #[cfg(feature = "custom-verifier")]
+    fn chain(
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+    ) -> Vec<Self> {
+        std::iter::once(end_entity)
+            .chain(intermediates)
+            .map(|cert| Self::from_der(&cert.0))
+            .collect()
+    }
+
+    fn from_der(der: &[u8]) -> Self {
+        match x509_parser::parse_x509_certificate(der) {
+            Ok((_, cert)) => Self {
+                subject: cert.subject().to_string(),
+                issuer: cert.issuer().to_string(),
+                not_before: cert.validity().not_before.to_string(),
+                not_after: cert.validity().not_after.to_string(),
+            },
+            Err(_) => Self {
+                subject: String::new(),
+                issuer: String::new(),
+                not_before: String::new(),
+                not_after: String::new(),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for PresentedCert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.subject.is_empty() && self.issuer.is_empty() {
+            return write!(f, "unparsable certificate");
+        }
+        write!(
+            f,
+            "'{}' issued by '{}', valid {} to {}",
+            self.subject, self.issuer, self.not_before, self.not_after
+        )
+    }
+}
+
+/// `chain` on one line, numbered from the end entity certificate. Chains
+/// are only recorded with the `custom-verifier` feature, without it the
+/// chain is empty.
+pub(crate) fn dump(chain: &[PresentedCert]) -> String {
+    if chain.is_empty() {
+        return "not recorded".into();
+    }
+    chain
+        .iter()
+        .enumerate()
+        .map(|(i, cert)| format!("[{i}] {cert}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Why webpki rejected a chain, in words for the common cases.
+pub(crate) fn reason(invalid: &CertificateError) -> String {
+    match invalid {
+        CertificateError::UnknownIssuer => "not issued by a trusted CA".into(),
+        CertificateError::Expired => "a certificate has expired".into(),
+        CertificateError::NotValidYet => {
+            "a certificate is not valid yet".into()
+        }
+        CertificateError::NotValidForName => {
+            "not valid for the server name".into()
+        }
+        CertificateError::BadSignature => "a signature does not verify".into(),
+        CertificateError::Revoked => "a certificate is revoked".into(),
+        other => format!("{other:?}"),
+    }
+}
+
+fn dns_name(option: &str, name: &str) -> Result<ServerName> {
+    match ServerName::try_from(name) {
+        Ok(name @ ServerName::DnsName(_)) => Ok(name),
+        _ => Err(anyhow!("{option} '{name}' is not a valid DNS name")),
+    }
+}
+
+/// Have `config` verify the server certificate with [`VerifyAs`], against
+/// `verify_as` whatever is sent as SNI. Also used when no SNI override is
+/// set, as only VerifyAs records the chain of a server that fails
+/// verification.
+#[cfg(feature = "custom-verifier")]
+fn verify_as_server_name(
+    config: &mut ClientConfig,
+    material: &CertMaterial,
+    verify_as: &ServerName,
+    _sni: Option<&ServerName>,
+    options: &TlsOptions,
+) -> Result<()> {
+    let roots = root_store(&material.server_root_ca_cert)?;
+    config.dangerous().set_certificate_verifier(Arc::new(VerifyAs {
+        inner: WebPkiVerifier::new(roots, None),
+        name: verify_as.clone(),
+        max_chain_depth: options.max_chain_depth,
+    }));
+    Ok(())
+}
+
+/// Without the `custom-verifier` feature rustls verifies the server
+/// certificate against the name sent as SNI, so the two cannot differ.
+#[cfg(not(feature = "custom-verifier"))]
+fn verify_as_server_name(
+    _config: &mut ClientConfig,
+    _material: &CertMaterial,
+    verify_as: &ServerName,
+    sni: Option<&ServerName>,
+    _options: &TlsOptions,
+) -> Result<()> {
+    match sni {
+        Some(sni) if sni != verify_as => Err(anyhow!(
+            "connect.sni_hostname differs from connect.server_name, which needs the client built with the custom-verifier feature"
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Verifies the server certificate against `name`, which differs from the
+/// name sent as SNI when auraed is reached through an SNI routing proxy.
+/// Handshakes started with an IP address, see [`TlsConnect::handshake`],
+/// are verified against that address. Installed unless `no_verify` is set.
+///
+/// Chains of more than `max_chain_depth` certificates, counting the end
+/// entity and the intermediates the server sent, are rejected before they
+/// are verified. Chains that fail verification are logged and returned as
+/// an [`UntrustedChain`], chains that pass are logged at trace level.
+#[cfg(feature = "custom-verifier")]
+struct VerifyAs {
+    inner: WebPkiVerifier,
+    name: ServerName,
+    max_chain_depth: Option<usize>,
+}
+
+#[cfg(feature = "custom-verifier")]
+impl ServerCertVerifier for VerifyAs {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        sni: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> std::result::Result<ServerCertVerified, Error> {
+        let depth = 1 + intermediates.len();
+        if let Some(max) = self.max_chain_depth.filter(|max| depth > *max) {
+            return Err(Error::InvalidCertificate(CertificateError::Other(
+                Arc::new(ChainTooDeep { depth, max }),
+            )));
+        }
+
+        let name = match sni {
+            ServerName::IpAddress(_) => sni,
+            _ => &self.name,
+        };
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            name,
+            scts,
+            ocsp_response,
+            now,
+        );
+        match verified {
+            Ok(verified) => {
+                if tracing::enabled!(tracing::Level::TRACE) {
+                    let chain = PresentedCert::chain(end_entity, intermediates);
+                    trace!(chain = %dump(&chain), "server certificate verified");
+                }
+                Ok(verified)
+            }
+            Err(Error::InvalidCertificate(invalid)) => {
+                let chain = PresentedCert::chain(end_entity, intermediates);
+                warn!(
+                    reason = %reason(&invalid),
+                    chain = %dump(&chain),
+                    "server certificate failed verification"
+                );
+                Err(Error::InvalidCertificate(CertificateError::Other(
+                    Arc::new(UntrustedChain { invalid, chain }),
+                )))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Identity the server certificate must present once it passed
+/// verification, to tell auraed apart from another holder of a cert from
+/// the same CA (e.g. one listening on a hijacked socket path).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ExpectedIdentity {
+    cn: Option<String>,
+    spiffe: Option<String>,
+    /// Lowercase, without the `spiffe://` scheme.
+    trust_domain: Option<String>,
+    /// Fingerprints the certificate must match one of, when not empty.
+    pins: Vec<String>,
+}
+
+/// Returned from the connector (inside a [`tonic::transport::Error`]) when
+/// the server presents an identity other than the expected one.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("server presented {found}, expected {expected}")]
+pub(crate) struct IdentityMismatch {
+    pub(crate) expected: String,
+    pub(crate) found: String,
+}
+
+/// Returned from the connector (inside a [`tonic::transport::Error`]) when
+/// the SPIFFE ID of the server is not in the expected trust domain.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("server SPIFFE IDs {found:?} are not in trust domain '{expected}'")]
+pub(crate) struct TrustDomainMismatch {
+    pub(crate) expected: String,
+    pub(crate) found: Vec<String>,
+}
+
+/// `auth.expected_server_trust_domain` as compared, also accepting it
+/// written as `spiffe://aurae.io`.
+fn trust_domain_name(domain: &str) -> String {
+    let domain = domain.strip_prefix("spiffe://").unwrap_or(domain);
+    domain.trim_end_matches('/').to_ascii_lowercase()
+}
+
+/// The trust domain of a SPIFFE ID, `None` for other URIs.
+fn spiffe_trust_domain(uri: &str) -> Option<String> {
+    let rest =
+        uri.get(..9)?.eq_ignore_ascii_case("spiffe://").then(|| &uri[9..])?;
+    let domain = rest.split('/').next()?;
+    (!domain.is_empty()).then(|| domain.to_ascii_lowercase())
+}
+
+impl ExpectedIdentity {
+    /// Check that the server certificate has a SPIFFE ID in the expected
+    /// trust domain, if one is set.
+    pub(crate) fn check_trust_domain(
+        &self,
+        der: &[u8],
+    ) -> std::result::Result<(), TrustDomainMismatch> {
+        let Some(expected) = &self.trust_domain else {
+            return Ok(());
+        };
+
+        let ids: Vec<String> = x509_parser::parse_x509_certificate(der)
+            .ok()
+            .and_then(|(_, cert)| {
+                cert.subject_alternative_name().ok().flatten().map(|san| {
+                    san.value
+                        .general_names
+                        .iter()
+                        .filter_map(|name| match name {
+                            GeneralName::URI(uri)
+                                if spiffe_trust_domain(uri).is_some() =>
+                            {
+                                Some(uri.to_string())
+                            }
+                            _ => None,
+                        })
+                        .collect()
+                })
+            })
+            .unwrap_or_default();
+        match ids
+            .iter()
+            .any(|id| spiffe_trust_domain(id).as_ref() == Some(expected))
+        {
+            true => Ok(()),
+            false => Err(TrustDomainMismatch {
+                expected: expected.clone(),
+                found: ids,
+            }),
+        }
+    }
+
+    /// Check the DER encoded end entity certificate of the server.
+    pub(crate) fn check(
+        &self,
+        der: &[u8],
+    ) -> std::result::Result<(), IdentityMismatch> {
+        if !self.pins.is_empty() {
+            let found = DigestAlgorithm::Sha256
+                .digest_data(der)
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>();
+            let Some(index) = self.pins.iter().position(|pin| *pin == found)
+            else {
+                return Err(IdentityMismatch {
+                    expected: format!(
+                        "one of {} pinned SHA-256 fingerprints",
+                        self.pins.len()
+                    ),
+                    found: format!("SHA-256 {found}"),
+                });
+            };
+            debug!(pin = index, fingerprint = %found, "server certificate matched a pin");
+        }
+
+        if self.cn.is_none() && self.spiffe.is_none() {
+            return Ok(());
+        }
+
+        let (_, cert) =
+            x509_parser::parse_x509_certificate(der).map_err(|e| {
+                IdentityMismatch {
+                    expected: self.to_string(),
+                    found: format!("an unparsable certificate ({e})"),
+                }
+            })?;
+
+        if let Some(expected) = &self.cn {
+            let found = cert
+                .subject()
+                .iter_common_name()
+                .next()
+                .and_then(|cn| cn.as_str().ok());
+            if found != Some(expected.as_str()) {
+                return Err(IdentityMismatch {
+                    expected: format!("CN '{expected}'"),
+                    found: match found {
+                        Some(found) => format!("CN '{found}'"),
+                        None => "no CN".into(),
+                    },
+                });
+            }
+        }
+
+        if let Some(expected) = &self.spiffe {
+            let uris: Vec<_> = cert
+                .subject_alternative_name()
+                .ok()
+                .flatten()
+                .map(|san| {
+                    san.value
+                        .general_names
+                        .iter()
+                        .filter_map(|name| match name {
+                            GeneralName::URI(uri) => Some(*uri),
+                            _ => None,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            if !uris.contains(&expected.as_str()) {
+                return Err(IdentityMismatch {
+                    expected: format!("SPIFFE ID '{expected}'"),
+                    found: format!("URI SANs {uris:?}"),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for ExpectedIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.cn, &self.spiffe) {
+            (Some(cn), Some(spiffe)) => {
+                write!(f, "CN '{cn}' and SPIFFE ID '{spiffe}'")
+            }
+            (Some(cn), None) => write!(f, "CN '{cn}'"),
+            (None, Some(spiffe)) => write!(f, "SPIFFE ID '{spiffe}'"),
+            (None, None) => f.write_str("any identity"),
+        }
+    }
+}
+
+/// Assemble the rustls config from the cert material.
+///
+/// Trust always comes from the configured CA bundle, and the client always
+/// authenticates with its certificate (mTLS), as that is the only mode
+/// auraed supports. Each part is checked on its own, so incomplete material
+/// fails here with a specific error rather than during the handshake.
+/// Without a client certificate in `material` no client identity is
+/// presented, see [`AuthConfig::client_crt`]. A `key` signs in place of the
+/// client key of `material`, see [`AuthConfig::pkcs11`].
+fn client_config(
+    material: &CertMaterial,
+    key: Option<Arc<dyn SigningKey>>,
+) -> Result<ClientConfig> {
+    let roots = root_store(&material.server_root_ca_cert)?;
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+
+    let mut config = match (material.client_cert.is_empty(), key) {
+        (true, _) => builder.with_no_client_auth(),
+        (false, Some(key)) => {
+            let chain = parse_certs(&material.client_cert)
+                .context("failed to parse client certificate")?;
+            let identity = CertifiedKey::new(chain, key);
+            builder.with_client_cert_resolver(Arc::new(FixedIdentity(
+                Arc::new(identity),
+            )))
+        }
+        (false, None) => {
+            let (client_cert, client_key) =
+                client_identity(&material.client_cert, &material.client_key)?;
+            builder.with_client_auth_cert(client_cert, client_key).context(
+                "client certificate and key do not form a valid identity",
+            )?
+        }
+    };
+
+    // auraed only speaks HTTP/2
+    config.alpn_protocols = vec![b"h2".to_vec()];
+
+    Ok(config)
+}
+
+/// Presents the same certificate and key on every handshake, for keys that
+/// are not held in memory.
+struct FixedIdentity(Arc<CertifiedKey>);
+
+impl ResolvesClientCert for FixedIdentity {
+    fn resolve(
+        &self,
+        _acceptable_issuers: &[&[u8]],
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+/// The trust store for the CA bundle `ca_pem`. CAs listed more than once are
+/// only added the first time, in the order of the bundle, so the store is
+/// the same however often a CA is repeated.
+fn root_store(ca_pem: &[u8]) -> Result<RootCertStore> {
+    let certs = parse_certs(ca_pem)
+        .context("failed to parse server root CA certificate")?;
+    if certs.is_empty() {
+        return Err(ClientError::NoTrustAnchors.into());
+    }
+
+    let listed = certs.len();
+    let certs = unique_cas(certs);
+    debug!(
+        duplicates = listed - certs.len(),
+        "loaded {} unique server root CAs",
+        certs.len()
+    );
+
+    let mut roots = RootCertStore::empty();
+    for cert in certs {
+        roots.add(&cert).context("invalid server root CA certificate")?;
+    }
+    if roots.is_empty() {
+        return Err(ClientError::NoTrustAnchors.into());
+    }
+    Ok(roots)
+}
+
+/// `certs` without repeats by SHA-256 fingerprint, keeping the first of each.
+fn unique_cas(certs: Vec<Certificate>) -> Vec<Certificate> {
+    let mut seen = HashSet::new();
+    certs
+        .into_iter()
+        .filter(|cert| {
+            seen.insert(DigestAlgorithm::Sha256.digest_data(&cert.0))
+        })
+        .collect()
+}
+
+/// Check that the client certificate in `material` chains to the server
+/// root CA bundle and is valid at `now`, give or take `tolerance`.
+/// Certificates after the first in the client cert file are taken as
+/// intermediates.
+pub(crate) fn verify_client_chain(
+    material: &CertMaterial,
+    now: SystemTime,
+    tolerance: Duration,
+) -> Result<()> {
+    let roots = root_store(&material.server_root_ca_cert)?;
+    let certs = parse_certs(&material.client_cert)
+        .context("failed to parse client certificate")?;
+    let Some((end_entity, intermediates)) = certs.split_first() else {
+        return Err(anyhow!(
+            "client certificate file contains no PEM certificates"
+        ));
+    };
+    let at = validity_time(end_entity, now, tolerance)?;
+
+    let _ = AllowAnyAuthenticatedClient::new(roots)
+        .verify_client_cert(end_entity, intermediates, at)
+        .map_err(|e| {
+            anyhow!(
+                "client certificate is not valid for the server root CA: {e}"
+            )
+        })?;
+    Ok(())
+}
+
+/// The time to verify the chain of `cert` at: `now`, moved into the
+/// validity period of `cert` when it is outside of it by no more than
+/// `tolerance`.
+fn validity_time(
+    cert: &Certificate,
+    now: SystemTime,
+    tolerance: Duration,
+) -> Result<SystemTime> {
+    let (_, x509) = x509_parser::parse_x509_certificate(&cert.0)
+        .context("failed to parse client certificate")?;
+    let time = |time: x509_parser::time::ASN1Time| {
+        let secs = u64::try_from(time.timestamp()).unwrap_or(0);
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    };
+    let not_before = time(x509.validity().not_before);
+    let not_after = time(x509.validity().not_after);
+
+    let (edge, off, problem) =
+        match (not_before.duration_since(now), now.duration_since(not_after)) {
+            (Ok(off), _) if !off.is_zero() => (
+                not_before,
+                off,
+                format!("is not valid for another {}s", off.as_secs()),
+            ),
+            (_, Ok(off)) if !off.is_zero() => {
+                (not_after, off, format!("expired {}s ago", off.as_secs()))
+            }
+            _ => return Ok(now),
+        };
+
+    if off > tolerance {
+        return Err(anyhow!(
+            "client certificate {problem}, beyond the {}s clock skew tolerance",
+            tolerance.as_secs()
+        ));
+    }
+    warn!(
+        "client certificate {problem}, within the {}s clock skew tolerance",
+        tolerance.as_secs()
+    );
+    Ok(edge)
+}
+
+fn client_identity(
+    cert_pem: &[u8],
+    key_pem: &[u8],
+) -> Result<(Vec<Certificate>, PrivateKey)> {
+    let certs =
+        parse_certs(cert_pem).context("failed to parse client certificate")?;
+    if certs.is_empty() {
+        return Err(anyhow!(
+            "client certificate file contains no PEM certificates"
+        ));
+    }
+
+    Ok((certs, parse_private_key(key_pem)?))
+}
+
+fn parse_certs(pem: &[u8]) -> Result<Vec<Certificate>> {
+    Ok(rustls_pemfile::certs(&mut &*pem)?
+        .into_iter()
+        .map(Certificate)
+        .collect())
+}
+
+fn parse_private_key(pem: &[u8]) -> Result<PrivateKey> {
+    let mut reader = pem;
+    loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .context("failed to parse client key")?
+        {
+            Some(
+                rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::ECKey(key),
+            ) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => return Err(anyhow!("no private key found in client key")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{
+        BasicConstraints, CertificateParams, DistinguishedName, DnType, IsCa,
+        SanType, SignatureAlgorithm, PKCS_ECDSA_P256_SHA256, PKCS_ED25519,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::rustls::ServerConfig;
+    use tokio_rustls::TlsAcceptor;
+
+    const NOT_PEM: &[u8] = b"not a certificate";
+
+    /// A CA, server and client all using `alg`, returning the client
+    /// material and the rustls config of a server requiring client certs.
+    fn cert_set(
+        alg: &'static SignatureAlgorithm,
+    ) -> (CertMaterial, ServerConfig) {
+        cert_set_with_sans(alg, vec![])
+    }
+
+    /// As [`cert_set`], with `sans` added to the server certificate.
+    fn cert_set_with_sans(
+        alg: &'static SignatureAlgorithm,
+        sans: Vec<SanType>,
+    ) -> (CertMaterial, ServerConfig) {
+        let params = |names: Vec<String>, cn: &str| {
+            let mut params = CertificateParams::new(names);
+            params.alg = alg;
+            params.distinguished_name.push(DnType::CommonName, cn);
+            params
+        };
+
+        let mut ca_params = params(vec![], "test ca");
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+        let mut server_params =
+            params(vec![DEFAULT_SERVER_NAME.into()], DEFAULT_SERVER_NAME);
+        server_params.subject_alt_names.extend(sans);
+        let server = rcgen::Certificate::from_params(server_params).unwrap();
+        let client =
+            rcgen::Certificate::from_params(params(vec![], "test client"))
+                .unwrap();
+
+        let material = CertMaterial {
+            server_root_ca_cert: ca.serialize_pem().unwrap().into_bytes(),
+            client_cert: client
+                .serialize_pem_with_signer(&ca)
+                .unwrap()
+                .into_bytes(),
+            client_key: client.serialize_private_key_pem().into_bytes(),
+        };
+
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(
+                AllowAnyAuthenticatedClient::new(
+                    root_store(&material.server_root_ca_cert).unwrap(),
+                )
+                .boxed(),
+            )
+            .with_single_cert(
+                vec![Certificate(
+                    server.serialize_der_with_signer(&ca).unwrap(),
+                )],
+                PrivateKey(server.serialize_private_key_der()),
+            )
+            .unwrap();
+
+        (material, server_config)
+    }
+
+    fn auth() -> AuthConfig {
+        AuthConfig {
+            ca_crt: String::new(),
+            client_crt: String::new(),
+            client_key: String::new(),
+            enforce_secure_paths: false,
+            strict: false,
+            expected_server_cn: None,
+            expected_server_spiffe: None,
+            expected_server_trust_domain: None,
+            client_key_passphrase: None,
+            ca_fetch: Default::default(),
+            pkcs11: None,
+        }
+    }
+
+    /// Run a full mutual TLS handshake over loopback and echo a byte,
+    /// returning the SNI the server received.
+    async fn run_handshake(
+        server_config: ServerConfig,
+        tls: TlsConnect,
+    ) -> Option<String> {
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            let mut byte = [0u8];
+            let _ = stream.read_exact(&mut byte).await.unwrap();
+            stream.write_all(&byte).await.unwrap();
+            stream.get_ref().1.server_name().map(String::from)
+        });
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut stream =
+            tls.connector.connect(tls.server_name, stream).await.unwrap();
+        stream.write_all(b"a").await.unwrap();
+        let mut byte = [0u8];
+        let _ = stream.read_exact(&mut byte).await.unwrap();
+
+        assert_eq!(&byte, b"a");
+        server.await.unwrap()
+    }
+
+    async fn handshake(alg: &'static SignatureAlgorithm) -> CertMaterial {
+        let (material, server_config) = cert_set(alg);
+        let tls = TlsConnect::new(&material, &auth(), &TlsOptions::default())
+            .unwrap();
+
+        let sni = run_handshake(server_config, tls).await;
+
+        assert_eq!(sni.as_deref(), Some(DEFAULT_SERVER_NAME));
+        material
+    }
+
+    #[tokio::test]
+    async fn ed25519_identity_completes_handshake() {
+        let material = handshake(&PKCS_ED25519).await;
+
+        let details = material.get_client_cert_details().unwrap();
+        assert_eq!(details.key_algorithm, "ED25519");
+    }
+
+    #[tokio::test]
+    async fn ecdsa_p256_identity_completes_handshake() {
+        let material = handshake(&PKCS_ECDSA_P256_SHA256).await;
+
+        let details = material.get_client_cert_details().unwrap();
+        assert_eq!(details.key_algorithm, "ECDSA P-256");
+    }
+
+    #[test]
+    fn key_algorithms_are_checked_against_the_policy() {
+        let (material, _) = cert_set(&PKCS_ED25519);
+        let details = material.get_client_cert_details().unwrap();
+        let with_algorithm = |name: &str| X509Details {
+            key_algorithm: name.into(),
+            ..(*details).clone()
+        };
+        let options = TlsOptions {
+            accepted_key_algorithms: Some(vec![
+                KeyAlgorithm::Ecdsa,
+                KeyAlgorithm::Ed25519,
+            ]),
+            ..Default::default()
+        };
+
+        for name in ["ED25519", "ECDSA P-256", "ECDSA P-384"] {
+            options.check_key_algorithm(&with_algorithm(name)).unwrap();
+            TlsOptions::default()
+                .check_key_algorithm(&with_algorithm(name))
+                .unwrap();
+        }
+
+        let err =
+            options.check_key_algorithm(&with_algorithm("RSA")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ClientError>(),
+            Some(ClientError::KeyAlgorithmRejected { found, .. }) if found == "RSA"
+        ));
+        TlsOptions::default()
+            .check_key_algorithm(&with_algorithm("RSA"))
+            .unwrap();
+    }
+
+    #[cfg(feature = "custom-verifier")]
+    #[tokio::test]
+    async fn sni_override_is_sent_while_verifying_the_server_name() {
+        let (material, server_config) = cert_set(&PKCS_ECDSA_P256_SHA256);
+        let options = TlsOptions {
+            sni_hostname: Some("auraed.ingress.example.com".into()),
+            ..Default::default()
+        };
+        let tls = TlsConnect::new(&material, &auth(), &options).unwrap();
+
+        let sni = run_handshake(server_config, tls).await;
+
+        assert_eq!(sni.as_deref(), Some("auraed.ingress.example.com"));
+    }
+
+    #[cfg(not(feature = "custom-verifier"))]
+    #[test]
+    fn sni_overrides_need_the_custom_verifier() {
+        let (material, _) = cert_set(&PKCS_ECDSA_P256_SHA256);
+        let sni = |name: &str| TlsOptions {
+            sni_hostname: Some(name.into()),
+            ..Default::default()
+        };
+
+        let err = TlsConnect::new(
+            &material,
+            &auth(),
+            &sni("auraed.ingress.example.com"),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("custom-verifier"), "{err}");
+        assert!(TlsConnect::new(&material, &auth(), &sni(DEFAULT_SERVER_NAME))
+            .is_ok());
+    }
+
+    /// Handshake with `tls` against a loopback server, telling the handshake
+    /// it connects to 127.0.0.1 when `by_ip` is set, the way the connector
+    /// does for a socket naming that address.
+    async fn loopback_handshake(
+        server_config: ServerConfig,
+        tls: &TlsConnect,
+        by_ip: bool,
+    ) -> io::Result<()> {
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+        let _server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = acceptor.accept(stream).await;
+        });
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let host_ip = Some(addr.ip()).filter(|_| by_ip);
+        tls.handshake(host_ip, stream).await.map(drop)
+    }
+
+    /// Handshake with `tls` against a loopback server presenting `sans`,
+    /// the way the connector does for a socket naming 127.0.0.1.
+    async fn strict_handshake(sans: Vec<SanType>) -> io::Result<()> {
+        let (material, server_config) =
+            cert_set_with_sans(&PKCS_ECDSA_P256_SHA256, sans);
+        let options =
+            TlsOptions { strict_hostname: true, ..Default::default() };
+        let tls = TlsConnect::new(&material, &auth(), &options).unwrap();
+
+        loopback_handshake(server_config, &tls, true).await
+    }
+
+    /// Client material, and a server presenting its certificate followed by
+    /// `intermediates` intermediate CAs between it and the root CA.
+    fn deep_chain(intermediates: usize) -> (CertMaterial, ServerConfig) {
+        let ca_params = |cn: &str| {
+            let mut params = CertificateParams::new(vec![]);
+            params.distinguished_name.push(DnType::CommonName, cn);
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params
+        };
+        let ca = rcgen::Certificate::from_params(ca_params("test ca")).unwrap();
+
+        // each intermediate is signed by the one before it, the first by
+        // the root
+        let mut chain: Vec<(rcgen::Certificate, Vec<u8>)> = vec![];
+        for i in 0..intermediates {
+            let cert = rcgen::Certificate::from_params(ca_params(&format!(
+                "test intermediate {i}"
+            )))
+            .unwrap();
+            let signer = chain.last().map_or(&ca, |(signer, _)| signer);
+            let der = cert.serialize_der_with_signer(signer).unwrap();
+            chain.push((cert, der));
+        }
+
+        let server =
+            rcgen::Certificate::from_params(CertificateParams::new(vec![
+                DEFAULT_SERVER_NAME.into(),
+            ]))
+            .unwrap();
+        let signer = chain.last().map_or(&ca, |(signer, _)| signer);
+        let mut presented = vec![Certificate(
+            server.serialize_der_with_signer(signer).unwrap(),
+        )];
+        presented
+            .extend(chain.into_iter().rev().map(|(_, der)| Certificate(der)));
+
+        let client =
+            rcgen::Certificate::from_params(CertificateParams::new(vec![]))
+                .unwrap();
+        let material = CertMaterial {
+            server_root_ca_cert: ca.serialize_pem().unwrap().into_bytes(),
+            client_cert: client
+                .serialize_pem_with_signer(&ca)
+                .unwrap()
+                .into_bytes(),
+            client_key: client.serialize_private_key_pem().into_bytes(),
+        };
+
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(
+                AllowAnyAuthenticatedClient::new(
+                    root_store(&material.server_root_ca_cert).unwrap(),
+                )
+                .boxed(),
+            )
+            .with_single_cert(
+                presented,
+                PrivateKey(server.serialize_private_key_der()),
+            )
+            .unwrap();
+
+        (material, server_config)
+    }
+
+    #[tokio::test]
+    async fn chains_deeper_than_max_chain_depth_are_rejected() {
+        let (material, server_config) = deep_chain(2);
+        let tls = |max| {
+            let options =
+                TlsOptions { max_chain_depth: Some(max), ..Default::default() };
+            TlsConnect::new(&material, &auth(), &options).unwrap()
+        };
+
+        loopback_handshake(server_config.clone(), &tls(3), false)
+            .await
+            .unwrap();
+
+        let err = loopback_handshake(server_config, &tls(2), false)
+            .await
+            .unwrap_err();
+        let too_deep = err.get_ref().unwrap().downcast_ref::<ChainTooDeep>();
+        let too_deep = too_deep.unwrap();
+        assert_eq!((too_deep.depth, too_deep.max), (3, 2));
+    }
+
+    #[tokio::test]
+    async fn untrusted_chains_are_dumped() {
+        let (_, server_config) = cert_set(&PKCS_ECDSA_P256_SHA256);
+        let (other, _) = cert_set(&PKCS_ECDSA_P256_SHA256);
+        let tls =
+            TlsConnect::new(&other, &auth(), &Default::default()).unwrap();
+
+        let err =
+            loopback_handshake(server_config, &tls, false).await.unwrap_err();
+        let untrusted = err.get_ref().unwrap().downcast_ref::<UntrustedChain>();
+        let untrusted = untrusted.unwrap();
+        assert_eq!(untrusted.invalid, CertificateError::UnknownIssuer);
+        let message = untrusted.to_string();
+        assert!(message.contains("not issued by a trusted CA"), "{message}");
+        if !cfg!(feature = "custom-verifier") {
+            assert!(untrusted.chain.is_empty());
+            assert!(message.ends_with("presented chain: not recorded"));
+            return;
+        }
+
+        assert_eq!(untrusted.chain.len(), 1);
+        assert_eq!(
+            untrusted.chain[0].subject,
+            format!("CN={DEFAULT_SERVER_NAME}")
+        );
+        assert_eq!(untrusted.chain[0].issuer, "CN=test ca");
+        assert!(
+            message.contains("[0] 'CN=server.unsafe.aurae.io' issued by 'CN=test ca', valid "),
+            "{message}"
+        );
+    }
+
+    #[tokio::test]
+    async fn strict_hostname_verifies_ip_connections_against_ip_sans() {
+        let loopback = SanType::IpAddress("127.0.0.1".parse().unwrap());
+        strict_handshake(vec![loopback]).await.unwrap();
+
+        let err = strict_handshake(vec![]).await.unwrap_err();
+        let missing = err.get_ref().unwrap().downcast_ref::<MissingIpSan>();
+        assert_eq!(missing.unwrap().ip.to_string(), "127.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn without_a_client_identity_only_the_server_is_verified() {
+        let mut ca_params = CertificateParams::new(vec![]);
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+        let server =
+            rcgen::Certificate::from_params(CertificateParams::new(vec![
+                DEFAULT_SERVER_NAME.into(),
+            ]))
+            .unwrap();
+        let material = CertMaterial {
+            server_root_ca_cert: ca.serialize_pem().unwrap().into_bytes(),
+            client_cert: vec![],
+            client_key: vec![],
+        };
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(
+                    server.serialize_der_with_signer(&ca).unwrap(),
+                )],
+                PrivateKey(server.serialize_private_key_der()),
+            )
+            .unwrap();
+
+        let tls = TlsConnect::new(&material, &auth(), &TlsOptions::default())
+            .unwrap();
+        assert!(tls.debug.client_chain.is_empty());
+
+        let sni = run_handshake(server_config, tls).await;
+        assert_eq!(sni.as_deref(), Some(DEFAULT_SERVER_NAME));
+    }
+
+    #[test]
+    fn debug_info_describes_the_config_without_secrets() {
+        let (material, _) = cert_set(&PKCS_ECDSA_P256_SHA256);
+        let options = TlsOptions {
+            sni_hostname: Some("auraed.ingress.example.com".into()),
+            pins: vec!["00".repeat(32)],
+            ..Default::default()
+        };
+
+        let tls = TlsConnect::new(&material, &auth(), &options).unwrap();
+        let debug = &*tls.debug;
+
+        assert_eq!(debug.server_name, DEFAULT_SERVER_NAME);
+        assert_eq!(
+            debug.sni_hostname.as_deref(),
+            Some("auraed.ingress.example.com")
+        );
+        assert!(debug.verify_server);
+        assert_eq!(debug.trust_anchors, ["CN=test ca"]);
+        assert_eq!(debug.client_chain, ["CN=test client"]);
+        assert_eq!(debug.alpn_protocols, ["h2"]);
+        assert!(debug.protocol_versions.contains(&"TLSv1_3".to_string()));
+        assert_eq!(debug.pinned_server_certs, 1);
+
+        let json = serde_json::to_string(debug).unwrap();
+        assert!(!json.contains("PRIVATE KEY"), "{json}");
+        assert!(!json.contains("BEGIN CERTIFICATE"), "{json}");
+    }
+
+    #[test]
+    fn names_must_be_dns_names() {
+        let (material, _) = cert_set(&PKCS_ECDSA_P256_SHA256);
+        let options = TlsOptions {
+            sni_hostname: Some("10.0.0.1".into()),
+            ..Default::default()
+        };
+
+        let err = TlsConnect::new(&material, &auth(), &options).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "connect.sni_hostname '10.0.0.1' is not a valid DNS name"
+        );
+    }
+
+    #[test]
+    fn empty_ca_bundle_is_rejected() {
+        let err = root_store(NOT_PEM).unwrap_err();
+
+        assert!(err.to_string().contains("no PEM certificates"));
+        for empty in [&b""[..], b"# the CA goes here\n"] {
+            let err = root_store(empty).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<ClientError>(),
+                Some(ClientError::NoTrustAnchors)
+            ));
+        }
+    }
+
+    #[test]
+    fn validity_is_checked_within_the_clock_skew_tolerance() {
+        let mut params = CertificateParams::default();
+        params.not_before = rcgen::date_time_ymd(2030, 1, 1);
+        params.not_after = rcgen::date_time_ymd(2031, 1, 1);
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        let cert = Certificate(cert.serialize_der().unwrap());
+        let not_before =
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_893_456_000);
+        let not_after =
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_924_992_000);
+        let tolerance = Duration::from_secs(300);
+        let secs = Duration::from_secs;
+
+        let at =
+            validity_time(&cert, not_before - secs(12), tolerance).unwrap();
+        assert_eq!(at, not_before);
+        let at = validity_time(&cert, not_after + secs(12), tolerance).unwrap();
+        assert_eq!(at, not_after);
+        let now = not_before + secs(60);
+        assert_eq!(validity_time(&cert, now, tolerance).unwrap(), now);
+
+        let err = validity_time(&cert, not_before - secs(400), tolerance)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "client certificate is not valid for another 400s, beyond the 300s clock skew tolerance"
+        );
+        let err =
+            validity_time(&cert, not_after + secs(400), tolerance).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "client certificate expired 400s ago, beyond the 300s clock skew tolerance"
+        );
+    }
+
+    #[test]
+    fn overlapping_ca_bundles_are_deduplicated() {
+        let (first, _) = cert_set(&PKCS_ECDSA_P256_SHA256);
+        let (second, _) = cert_set(&PKCS_ED25519);
+        let bundle = [
+            &first.server_root_ca_cert,
+            &second.server_root_ca_cert,
+            &first.server_root_ca_cert,
+        ]
+        .map(|pem| String::from_utf8(pem.clone()).unwrap())
+        .join("\n");
+
+        let certs = unique_cas(parse_certs(bundle.as_bytes()).unwrap());
+        let expected: Vec<_> =
+            [&first.server_root_ca_cert, &second.server_root_ca_cert]
+                .into_iter()
+                .flat_map(|pem| parse_certs(pem).unwrap())
+                .collect();
+
+        assert_eq!(certs, expected);
+        assert_eq!(root_store(bundle.as_bytes()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn missing_client_certificate_is_rejected() {
+        let err = client_identity(NOT_PEM, NOT_PEM).unwrap_err();
+
+        assert!(err.to_string().contains("client certificate"));
+    }
+
+    #[test]
+    fn missing_client_key_is_rejected() {
+        // a syntactically valid PEM block that is not a key
+        let cert =
+            b"-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n";
+
+        let err = client_identity(cert, NOT_PEM).unwrap_err();
+
+        assert!(err.to_string().contains("no private key"));
+    }
+
+    #[test]
+    fn unset_expectation_accepts_any_certificate() {
+        assert!(ExpectedIdentity::default().check(&[]).is_ok());
+    }
+
+    #[test]
+    fn expectation_rejects_missing_certificate() {
+        let expected = ExpectedIdentity {
+            cn: Some("server.unsafe.aurae.io".into()),
+            spiffe: None,
+            trust_domain: None,
+            pins: Vec::new(),
+        };
+
+        let err = expected.check(&[]).unwrap_err();
+
+        assert_eq!(err.expected, "CN 'server.unsafe.aurae.io'");
+    }
+
+    fn with_uri_sans(uris: &[&str]) -> Vec<u8> {
+        leaf("rcgen self signed cert", uris)
+    }
+
+    /// A DER certificate with the CN `cn` and the URI SANs `uris`.
+    fn leaf(cn: &str, uris: &[&str]) -> Vec<u8> {
+        let mut params = CertificateParams::new(vec![]);
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, cn);
+        params.subject_alt_names =
+            uris.iter().map(|uri| SanType::URI(uri.to_string())).collect();
+        rcgen::Certificate::from_params(params)
+            .unwrap()
+            .serialize_der()
+            .unwrap()
+    }
+
+    #[test]
+    fn expected_cn_must_match() {
+        let expected = ExpectedIdentity {
+            cn: Some("auraed.prod".into()),
+            ..ExpectedIdentity::default()
+        };
+
+        assert!(expected.check(&leaf("auraed.prod", &[])).is_ok());
+        let err = expected.check(&leaf("imposter", &[])).unwrap_err();
+        assert_eq!(err.expected, "CN 'auraed.prod'");
+        assert_eq!(err.found, "CN 'imposter'");
+    }
+
+    #[test]
+    fn expected_spiffe_id_must_be_a_uri_san() {
+        let expected = ExpectedIdentity {
+            spiffe: Some("spiffe://aurae.io/auraed".into()),
+            ..ExpectedIdentity::default()
+        };
+
+        let leaf = |uris: &[&str]| leaf("auraed", uris);
+        assert!(expected
+            .check(&leaf(&["https://aurae.io", "spiffe://aurae.io/auraed"]))
+            .is_ok());
+        let err =
+            expected.check(&leaf(&["spiffe://aurae.io/imposter"])).unwrap_err();
+        assert_eq!(err.expected, "SPIFFE ID 'spiffe://aurae.io/auraed'");
+        assert_eq!(err.found, r#"URI SANs ["spiffe://aurae.io/imposter"]"#);
+        assert!(expected.check(&leaf(&[])).is_err());
+    }
+
+    #[test]
+    fn spiffe_ids_of_the_trust_domain_are_accepted() {
+        let expected = ExpectedIdentity {
+            trust_domain: Some(trust_domain_name("spiffe://Aurae.io/")),
+            ..ExpectedIdentity::default()
+        };
+
+        let leaf = with_uri_sans(&[
+            "https://aurae.io/auraed",
+            "spiffe://aurae.io/ns/prod/auraed",
+        ]);
+        assert!(expected.check_trust_domain(&leaf).is_ok());
+        assert!(ExpectedIdentity::default().check_trust_domain(&[]).is_ok());
+    }
+
+    #[test]
+    fn spiffe_ids_of_other_trust_domains_are_rejected() {
+        let expected = ExpectedIdentity {
+            trust_domain: Some(trust_domain_name("aurae.io")),
+            ..ExpectedIdentity::default()
+        };
+
+        let leaf = with_uri_sans(&["spiffe://aurae.io.evil.example/auraed"]);
+        let err = expected.check_trust_domain(&leaf).unwrap_err();
+        assert_eq!(err.expected, "aurae.io");
+        assert_eq!(err.found, vec!["spiffe://aurae.io.evil.example/auraed"]);
+
+        let err = expected.check_trust_domain(&with_uri_sans(&[])).unwrap_err();
+        assert!(err.found.is_empty());
+    }
+
+    #[test]
+    fn any_matching_pin_is_accepted() {
+        // pins are compared against the fingerprint of the DER bytes as is
+        let leaf = b"server certificate";
+        let fingerprint = DigestAlgorithm::Sha256
+            .digest_data(leaf)
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(":");
+        let expected = |pins: &[&str]| ExpectedIdentity {
+            pins: pins.iter().map(|pin| parse_pin(pin).unwrap()).collect(),
+            ..ExpectedIdentity::default()
+        };
+        let retired = "ab".repeat(32);
+
+        assert!(expected(&[&retired, &fingerprint]).check(leaf).is_ok());
+        let err = expected(&[&retired]).check(leaf).unwrap_err();
+        assert_eq!(err.expected, "one of 1 pinned SHA-256 fingerprints");
+    }
+
+    #[test]
+    fn pins_must_be_sha256_fingerprints() {
+        let colons = vec!["AB"; 32].join(":");
+        assert_eq!(parse_pin(&colons).unwrap(), "ab".repeat(32));
+        assert!(parse_pin("abcd").is_err());
+        assert!(parse_pin(&"zz".repeat(32)).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! A [`tower::Service`] over a client's connection, for running RPCs
+//! through middleware from the `tower` ecosystem, see [`Client::service`].
+
+use crate::Client;
+use std::task::{Context, Poll};
+use tonic::body::{boxed, BoxBody};
+use tonic::codegen::{http, BoxFuture, StdError};
+use tonic::Status;
+use tower::{Service, ServiceExt};
+
+/// See [`Client::service`].
+#[derive(Debug, Clone)]
+pub struct RpcService {
+    client: Client,
+}
+
+impl Service<http::Request<BoxBody>> for RpcService {
+    type Response = http::Response<BoxBody>;
+    type Error = StdError;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        // readiness of the channel is awaited in `call`
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<BoxBody>) -> Self::Future {
+        let req = match prepare(&self.client, req) {
+            Ok(req) => req,
+            Err(status) => return Box::pin(async move { Err(status.into()) }),
+        };
+        // picked per call, so calls spread over the balanced channels
+        let channel = self.client.channel();
+        let call = async move {
+            let response =
+                channel.oneshot(req).await.map_err(Status::from_error)?;
+            Ok(response.map(boxed))
+        };
+        let client = self.client.clone();
+        Box::pin(async move { Ok(client.until_deadline(call).await?) })
+    }
+}
+
+/// `req` with the metadata and deadline of `client`'s handle, once its
+/// guards and interceptors let it through.
+fn prepare(
+    client: &Client,
+    mut req: http::Request<BoxBody>,
+) -> Result<http::Request<BoxBody>, Status> {
+    client.method_guard(req.uri().path())?;
+    client.deadline_guard()?;
+    let headers = client.request(())?.into_parts().0.into_headers();
+    req.headers_mut().extend(headers);
+    Ok(req)
+}
+
+impl Client {
+    /// A [`tower::Service`] making RPCs over this handle's connection, to
+    /// build the generated `proto` clients on with `tower` middleware such
+    /// as rate limits, load shedding or timeouts in between:
+    ///
+    /// ```ignore
+    /// use proto::cells::cell_service_client::CellServiceClient;
+    /// use std::time::Duration;
+    /// use tower::ServiceBuilder;
+    ///
+    /// let service = ServiceBuilder::new()
+    ///     .concurrency_limit(16)
+    ///     .timeout(Duration::from_secs(10))
+    ///     .service(client.service());
+    /// let mut cells = CellServiceClient::new(service);
+    /// ```
+    ///
+    /// Layers that hold state per instance, such as
+    /// `tower::limit::RateLimit`, can be shared by the clones a generated
+    /// client makes with `tower::buffer::Buffer`.
+    ///
+    /// Like the RPCs of this client, each call is checked against the
+    /// handle's method policy, read only mode and deadline, and carries its
+    /// metadata and interceptors. Calls are not retried, and compression is
+    /// set on the generated client, e.g. with `send_compressed`.
+    pub fn service(&self) -> RpcService {
+        RpcService { client: self.clone() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::body::empty_body;
+    use tonic::transport::Endpoint;
+    use tonic::Code;
+
+    fn client() -> Client {
+        let channel = Endpoint::from_static("http://[::1]:8080").connect_lazy();
+        Client::from_channel(channel, None)
+    }
+
+    fn request(path: &str) -> http::Request<BoxBody> {
+        http::Request::builder()
+            .uri(format!("http://[::1]:8080{path}"))
+            .body(empty_body())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn calls_carry_the_handle_metadata() {
+        let client = client()
+            .with_metadata(vec![("x-request-id".into(), "1234".into())])
+            .unwrap();
+
+        let req = prepare(&client, request("/aurae.cells.v0.CellService/List"))
+            .unwrap();
+        assert_eq!(req.headers()["x-request-id"], "1234");
+    }
+
+    #[tokio::test]
+    async fn read_only_handles_refuse_mutating_calls() {
+        let mut service = client().with_read_only(true).service();
+
+        let err = service
+            .call(request("/aurae.cells.v0.CellService/Allocate"))
+            .await
+            .unwrap_err();
+        let status = err.downcast_ref::<Status>().unwrap();
+        assert_eq!(status.code(), Code::PermissionDenied);
+    }
+}
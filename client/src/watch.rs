@@ -0,0 +1,215 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! A typed feed of workload lifecycle events, see [`Client::watch_events`].
+
+use crate::cri::runtime_service::RuntimeServiceClient;
+use crate::resumable::StreamEvent;
+use crate::{Client, ClientError};
+use futures_util::future::ready;
+use futures_util::{Stream, StreamExt};
+use proto::cri::{
+    ContainerEventResponse, ContainerEventType, GetEventsRequest,
+};
+use std::time::{Duration, SystemTime};
+
+/// What a container went through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerEventKind {
+    Created,
+    Started,
+    Stopped,
+    Deleted,
+}
+
+/// An item of [`Client::watch_events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuraeEvent {
+    /// A container changed state.
+    Container {
+        container_id: String,
+        /// The pod sandbox the container runs in, if the server sent it.
+        pod_sandbox_id: Option<String>,
+        kind: ContainerEventKind,
+        at: SystemTime,
+    },
+    /// The watch was re-established after the connection dropped. Events
+    /// from while it was down may be missing.
+    Reconnected,
+}
+
+/// Which events [`Client::watch_events`] passes on. Empty fields match
+/// everything, so the default passes every event.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    pub container_ids: Vec<String>,
+    pub pod_sandbox_ids: Vec<String>,
+    pub kinds: Vec<ContainerEventKind>,
+}
+
+impl EventFilter {
+    /// Whether `event` is passed on. [`AuraeEvent::Reconnected`] always is.
+    pub fn matches(&self, event: &AuraeEvent) -> bool {
+        let AuraeEvent::Container {
+            container_id, pod_sandbox_id, kind, ..
+        } = event
+        else {
+            return true;
+        };
+
+        (self.container_ids.is_empty()
+            || self.container_ids.contains(container_id))
+            && (self.pod_sandbox_ids.is_empty()
+                || pod_sandbox_id
+                    .as_ref()
+                    .is_some_and(|id| self.pod_sandbox_ids.contains(id)))
+            && (self.kinds.is_empty() || self.kinds.contains(kind))
+    }
+}
+
+impl Client {
+    /// Follow container lifecycle events, from the CRI `GetContainerEvents`
+    /// stream, passing on those that match `filter`.
+    ///
+    /// The watch is re-opened after transient failures, as with
+    /// [`Client::resumable`], and [`AuraeEvent::Reconnected`] is sent when
+    /// it was. The RPC has no cursor, so events sent while it was down are
+    /// lost. The stream ends when the server closes it or fails with an
+    /// error a reconnect would not fix, which is the last item.
+    ///
+    /// Messages are buffered in a bounded channel. Once it is full, the
+    /// server stream is no longer read until the consumer catches up, so
+    /// nothing is dropped by the client, but a slow consumer holds back the
+    /// stream and auraed decides what happens to events it cannot send.
+    pub fn watch_events(
+        &self,
+        filter: EventFilter,
+    ) -> impl Stream<Item = Result<AuraeEvent, ClientError>> + Send + Unpin
+    {
+        self.resumable(GetEventsRequest {}, |client, req| async move {
+            client.get_container_events(req).await
+        })
+        .filter_map(move |item| {
+            let event: Option<Result<AuraeEvent, ClientError>> = match item {
+                Ok(StreamEvent::Message(msg)) => typed_event(msg).map(Ok),
+                Ok(StreamEvent::Reconnected { .. }) => {
+                    Some(Ok(AuraeEvent::Reconnected))
+                }
+                Err(status) => Some(Err(anyhow::Error::new(status).into())),
+            };
+            ready(event.filter(|event| {
+                event.as_ref().map_or(true, |event| filter.matches(event))
+            }))
+        })
+    }
+}
+
+/// The typed form of `msg`, `None` for event types this client does not
+/// know.
+fn typed_event(msg: ContainerEventResponse) -> Option<AuraeEvent> {
+    let kind = match ContainerEventType::from_i32(msg.container_event_type)? {
+        ContainerEventType::ContainerCreatedEvent => {
+            ContainerEventKind::Created
+        }
+        ContainerEventType::ContainerStartedEvent => {
+            ContainerEventKind::Started
+        }
+        ContainerEventType::ContainerStoppedEvent => {
+            ContainerEventKind::Stopped
+        }
+        ContainerEventType::ContainerDeletedEvent => {
+            ContainerEventKind::Deleted
+        }
+    };
+    // CRI timestamps are nanoseconds since the epoch
+    let at = SystemTime::UNIX_EPOCH
+        + Duration::from_nanos(u64::try_from(msg.created_at).unwrap_or(0));
+
+    Some(AuraeEvent::Container {
+        container_id: msg.container_id,
+        pod_sandbox_id: msg.pod_sandbox_status.map(|status| status.id),
+        kind,
+        at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::cri::PodSandboxStatus;
+
+    fn response(kind: ContainerEventType) -> ContainerEventResponse {
+        ContainerEventResponse {
+            container_id: "nginx".into(),
+            container_event_type: kind as i32,
+            created_at: 1_500_000_000,
+            pod_sandbox_status: Some(PodSandboxStatus {
+                id: "web".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn server_messages_become_typed_events() {
+        let event =
+            typed_event(response(ContainerEventType::ContainerStoppedEvent));
+
+        assert_eq!(
+            event,
+            Some(AuraeEvent::Container {
+                container_id: "nginx".into(),
+                pod_sandbox_id: Some("web".into()),
+                kind: ContainerEventKind::Stopped,
+                at: SystemTime::UNIX_EPOCH + Duration::from_millis(1_500),
+            })
+        );
+
+        let mut unknown = response(ContainerEventType::ContainerCreatedEvent);
+        unknown.container_event_type = 42;
+        assert_eq!(typed_event(unknown), None);
+    }
+
+    #[test]
+    fn filter_matches_on_every_set_field() {
+        let started =
+            typed_event(response(ContainerEventType::ContainerStartedEvent))
+                .unwrap();
+
+        assert!(EventFilter::default().matches(&started));
+        assert!(EventFilter {
+            pod_sandbox_ids: vec!["web".into()],
+            kinds: vec![ContainerEventKind::Started],
+            ..Default::default()
+        }
+        .matches(&started));
+        assert!(!EventFilter {
+            container_ids: vec!["redis".into()],
+            ..Default::default()
+        }
+        .matches(&started));
+        assert!(!EventFilter {
+            kinds: vec![ContainerEventKind::Deleted],
+            ..Default::default()
+        }
+        .matches(&started));
+        assert!(EventFilter {
+            kinds: vec![ContainerEventKind::Deleted],
+            ..Default::default()
+        }
+        .matches(&AuraeEvent::Reconnected));
+    }
+}